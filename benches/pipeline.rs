@@ -0,0 +1,111 @@
+//! Benchmarks of the parsing, filtering, and Parquet writing pipeline.
+//!
+//! Runs on synthetic dumps from `pvstream::bench`. Run with
+//! `cargo bench --features bench`.
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use pvstream::bench::generate_test_dump;
+use pvstream::filter::{Filter, FilterBuilder};
+use pvstream::parse::{ParseMode, parse_line, parse_line_ref};
+use pvstream::stream::{Source, StreamConfig};
+use pvstream::{ParquetOptions, for_each_row, parquet_from_source, stream_from_source};
+use std::hint::black_box;
+
+/// Rows in the generated dumps.
+const ROWS: usize = 100_000;
+
+/// Seed of the generated dumps, fixed to compare runs.
+const SEED: u64 = 1;
+
+/// Writes a generated dump to a temporary file, uncompressed so the
+/// benchmarks don't measure decompression.
+fn dump_file(dump: &str) -> Source {
+    let path = std::env::temp_dir().join(format!("pvstream-bench-{ROWS}-{SEED}.txt"));
+    std::fs::write(&path, dump).unwrap();
+    Source::File(path)
+}
+
+fn filters() -> [(&'static str, Filter); 4] {
+    [
+        ("all", FilterBuilder::new().build()),
+        ("languages", FilterBuilder::new().languages(["de"]).build()),
+        (
+            "line_regex",
+            FilterBuilder::new().line_regex("^de ").build(),
+        ),
+        ("page_title", FilterBuilder::new().page_title("^Ka").build()),
+    ]
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let dump = generate_test_dump(ROWS, SEED);
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(dump.len() as u64));
+
+    group.bench_function("parse_line", |b| {
+        b.iter(|| {
+            for line in dump.lines() {
+                black_box(parse_line(line.to_string()).unwrap());
+            }
+        })
+    });
+    group.bench_function("parse_line_ref", |b| {
+        b.iter(|| {
+            for line in dump.lines() {
+                black_box(parse_line_ref(line, ParseMode::Lenient).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_filter(c: &mut Criterion) {
+    let dump = generate_test_dump(ROWS, SEED);
+    let source = dump_file(&dump);
+    let config = StreamConfig::default();
+    let mut group = c.benchmark_group("filter");
+    group.throughput(Throughput::Bytes(dump.len() as u64));
+
+    for (name, filter) in filters() {
+        group.bench_function(format!("stream_from_source/{name}"), |b| {
+            b.iter(|| {
+                let rows = stream_from_source(&source, &filter, &config).unwrap();
+                black_box(rows.count())
+            })
+        });
+        group.bench_function(format!("for_each_row/{name}"), |b| {
+            b.iter(|| {
+                let mut views = 0;
+                for_each_row(&source, &filter, &config, |row| views += row.views).unwrap();
+                black_box(views)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_parquet(c: &mut Criterion) {
+    let dump = generate_test_dump(ROWS, SEED);
+    let source = dump_file(&dump);
+    let output = std::env::temp_dir().join("pvstream-bench.parquet");
+    let mut group = c.benchmark_group("parquet");
+    group.throughput(Throughput::Bytes(dump.len() as u64));
+    group.sample_size(10);
+
+    group.bench_function("parquet_from_source", |b| {
+        b.iter(|| {
+            parquet_from_source(
+                &source,
+                output.clone(),
+                &FilterBuilder::new().build(),
+                &StreamConfig::default(),
+                &ParquetOptions::default(),
+            )
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_filter, bench_parquet);
+criterion_main!(benches);
@@ -1,5 +1,6 @@
-from collections.abc import Generator
-from typing import Optional
+from collections.abc import Callable, Generator, Iterator
+from datetime import date, datetime, timedelta
+from typing import Any, Literal, Optional, Union
 
 class Pageviews:  # noqa: E302
     domain_code: str
@@ -8,10 +9,111 @@ class Pageviews:  # noqa: E302
     language: str
     domain: Optional[str]
     mobile: bool
+    project: Optional[str]
+    timestamp: Optional[datetime]
+    response_size: Optional[int]
+    hourly_views: Optional[list[int]]
+    page_id: Optional[str]
+    @property
+    def hostname(self) -> Optional[str]: ...
+    def __eq__(self, other: object) -> bool: ...
+    def __hash__(self) -> int: ...
+    def __iter__(self) -> Iterator[Union[str, int]]: ...
+    def to_dict(self) -> dict[str, Any]: ...
+
+class Filter:  # noqa: E302
+    def __init__(self) -> None: ...
+    def line_regex(self, pattern: str) -> Filter: ...
+    def domain_codes(self, codes: list[str]) -> Filter: ...
+    def page_title(self, pattern: str) -> Filter: ...
+    def min_views(self, views: int) -> Filter: ...
+    def max_views(self, views: int) -> Filter: ...
+    def languages(self, languages: list[str]) -> Filter: ...
+    def domains(self, domains: list[str]) -> Filter: ...
+    def mobile(self, mobile: bool) -> Filter: ...
+    def projects(self, projects: list[str]) -> Filter: ...
+    def hostnames(self, hostnames: list[str]) -> Filter: ...
+    def page_title_by_language(self, patterns: dict[str, str]) -> Filter: ...
+    def page_title_exact(self, titles: list[str]) -> Filter: ...
+    def page_titles_file(self, path: str) -> Filter: ...
+    def page_title_prefix(self, prefix: str) -> Filter: ...
+    def page_titles_any(self, patterns: list[str]) -> Filter: ...
+    def ignore_title_case(self, ignore: bool = True) -> Filter: ...
+    def normalize_titles(self, normalize: bool = True) -> Filter: ...
+    def blocklist(self, path: str) -> Filter: ...
+    def rollup_namespaces(self, namespaces: list[str]) -> Filter: ...
+    def residual_rows(self, residual: bool = True) -> Filter: ...
+    def hours(self, hours: list[int]) -> Filter: ...
+    def weekdays(self, weekdays: list[int]) -> Filter: ...
+    def exclude_languages(self, languages: list[str]) -> Filter: ...
+    def exclude_domains(self, domains: list[str]) -> Filter: ...
+    def exclude_page_title(self, pattern: str) -> Filter: ...
+    def negate(self, negate: bool = True) -> Filter: ...
+    def merge_mobile(self, merge: bool = True) -> Filter: ...
+    def min_rank(self, rank: int) -> Filter: ...
+    def max_rank(self, rank: int) -> Filter: ...
+    def page_ids(self, path: str) -> Filter: ...
+    def title_transform(self, pattern: str, replacement: str) -> Filter: ...
+    def custom(
+        self, predicate: Callable[[Pageviews], bool], batch_size: int = 1024
+    ) -> Filter: ...
+    def sample(self, fraction: float, seed: int = 0) -> Filter: ...
+
+class Preview:  # noqa: E302
+    def __len__(self) -> int: ...
+    def __getitem__(self, index: int) -> Pageviews: ...
+    def __iter__(self) -> Iterator[Pageviews]: ...
+    def _repr_html_(self) -> str: ...
+
+class ArrowStream:  # noqa: E302
+    def __arrow_c_stream__(self, requested_schema: Optional[object] = None) -> object: ...
+    def to_pyarrow_table(self) -> Any: ...
+
+class WriteReport:  # noqa: E302
+    rows_written: int
+    rows_filtered: int
+    rows_errored: int
+    rows_dropped: int
+    bytes_read: int
+    elapsed: timedelta
+    errors: dict[str, int]
+
+class Summary:  # noqa: E302
+    rows: int
+    views: int
+    languages: dict[str, tuple[int, int]]
+    domains: dict[str, tuple[int, int]]
+
+class DictIterator:  # noqa: E302
+    def __iter__(self) -> DictIterator: ...
+    def __next__(self) -> dict[str, Any]: ...
+
+class AsyncRowIterator:  # noqa: E302
+    def __aiter__(self) -> AsyncRowIterator: ...
+    async def __anext__(self) -> Pageviews: ...
+    def close(self) -> None: ...
+
+class RowIterator:  # noqa: E302
+    def __iter__(self) -> RowIterator: ...
+    def __next__(self) -> Pageviews: ...
+    def __enter__(self) -> RowIterator: ...
+    def __exit__(self, *args: object) -> bool: ...
+    def close(self) -> None: ...
+    def head(self, n: int = 5) -> Preview: ...
+    def as_dicts(self) -> DictIterator: ...
+    def to_arrow(self, batch_size: Optional[int] = None) -> ArrowStream: ...
+    def to_pandas(self) -> Any: ...
+    def to_polars(self, batch_size: Optional[int] = None) -> Any: ...
+    def top_n(
+        self,
+        n: int,
+        by: Literal["language", "domain", "domain_code"] = "language",
+    ) -> dict[Optional[str], list[Pageviews]]: ...
 
 def stream_from_file(  # noqa: E302
     path: str,
     line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
     domain_codes: Optional[list[str]] = None,
     page_title: Optional[str] = None,
     min_views: Optional[int] = None,
@@ -19,10 +121,30 @@ def stream_from_file(  # noqa: E302
     languages: Optional[list[str]] = None,
     domains: Optional[list[str]] = None,
     mobile: Optional[bool] = None,
-) -> Generator[Pageviews, None, None]: ...
+    prefetch_rows: Optional[int] = None,
+    buffer_bytes: Optional[int] = None,
+    max_download_bytes: Optional[int] = None,
+    max_decompressed_bytes: Optional[int] = None,
+    threads: Optional[int] = None,
+    mirrors: Optional[list[str]] = None,
+    proxy: Optional[str] = None,
+    ca_bundle: Optional[str] = None,
+    connect_timeout: Optional[float] = None,
+    read_timeout: Optional[float] = None,
+    user_agent: Optional[str] = None,
+    progress: Optional[Callable[[dict[str, Any]], None]] = None,
+    cache_dir: Optional[str] = None,
+    on_error: Optional[Literal["raise", "skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    decode_titles: Optional[bool] = None,
+    skip: Optional[int] = None,
+    limit: Optional[int] = None,
+    assume_sorted: Optional[bool] = None,
+) -> RowIterator: ...
 def stream_from_url(  # noqa: E302
     url: str,
     line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
     domain_codes: Optional[list[str]] = None,
     page_title: Optional[str] = None,
     min_views: Optional[int] = None,
@@ -30,12 +152,46 @@ def stream_from_url(  # noqa: E302
     languages: Optional[list[str]] = None,
     domains: Optional[list[str]] = None,
     mobile: Optional[bool] = None,
-) -> Generator[Pageviews, None, None]: ...
+    prefetch_rows: Optional[int] = None,
+    buffer_bytes: Optional[int] = None,
+    max_download_bytes: Optional[int] = None,
+    max_decompressed_bytes: Optional[int] = None,
+    threads: Optional[int] = None,
+    mirrors: Optional[list[str]] = None,
+    proxy: Optional[str] = None,
+    ca_bundle: Optional[str] = None,
+    connect_timeout: Optional[float] = None,
+    read_timeout: Optional[float] = None,
+    user_agent: Optional[str] = None,
+    progress: Optional[Callable[[dict[str, Any]], None]] = None,
+    cache_dir: Optional[str] = None,
+    on_error: Optional[Literal["raise", "skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    decode_titles: Optional[bool] = None,
+    skip: Optional[int] = None,
+    limit: Optional[int] = None,
+    assume_sorted: Optional[bool] = None,
+) -> RowIterator: ...
+def stream_from_url_async(  # noqa: E302
+    url: str,
+    **kwargs: Any,
+) -> AsyncRowIterator: ...
+def dataframe_from_file(  # noqa: E302
+    path: str,
+    library: Literal["pandas", "polars"] = "pandas",
+    **kwargs: Any,
+) -> Any: ...
+def dataframe_from_url(  # noqa: E302
+    url: str,
+    library: Literal["pandas", "polars"] = "pandas",
+    **kwargs: Any,
+) -> Any: ...
 def parquet_from_file(  # noqa: E302
     input_path: str,
     output_path: str,
     batch_size: Optional[int] = None,
     line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
     domain_codes: Optional[list[str]] = None,
     page_title: Optional[str] = None,
     min_views: Optional[int] = None,
@@ -43,12 +199,257 @@ def parquet_from_file(  # noqa: E302
     languages: Optional[list[str]] = None,
     domains: Optional[list[str]] = None,
     mobile: Optional[bool] = None,
-) -> None: ...
+    layout: Optional[Literal["flat", "nested"]] = None,
+    on_error: Optional[Literal["skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    top_k: Optional[int] = None,
+    top_k_per_language: Optional[bool] = None,
+    columns: Optional[list[str]] = None,
+    sort: Optional[Literal["views", "title"]] = None,
+    statistics: Optional[bool] = None,
+    bloom_filters: Optional[bool] = None,
+    encodings: Optional[dict[str, str]] = None,
+    decode_titles: Optional[bool] = None,
+    skip: Optional[int] = None,
+    limit: Optional[int] = None,
+    assume_sorted: Optional[bool] = None,
+) -> WriteReport: ...
 def parquet_from_url(  # noqa: E302
     url: str,
     output_path: str,
     batch_size: Optional[int] = None,
     line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
+    domain_codes: Optional[list[str]] = None,
+    page_title: Optional[str] = None,
+    min_views: Optional[int] = None,
+    max_views: Optional[int] = None,
+    languages: Optional[list[str]] = None,
+    domains: Optional[list[str]] = None,
+    mobile: Optional[bool] = None,
+    layout: Optional[Literal["flat", "nested"]] = None,
+    on_error: Optional[Literal["skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    top_k: Optional[int] = None,
+    top_k_per_language: Optional[bool] = None,
+    columns: Optional[list[str]] = None,
+    sort: Optional[Literal["views", "title"]] = None,
+    statistics: Optional[bool] = None,
+    bloom_filters: Optional[bool] = None,
+    encodings: Optional[dict[str, str]] = None,
+    decode_titles: Optional[bool] = None,
+    skip: Optional[int] = None,
+    limit: Optional[int] = None,
+    assume_sorted: Optional[bool] = None,
+) -> WriteReport: ...
+def parquet_dataset_from_urls(  # noqa: E302
+    urls: list[str],
+    output_dir: str,
+    partition_by: list[Literal["language", "date", "domain"]],
+    batch_size: Optional[int] = None,
+    line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
+    domain_codes: Optional[list[str]] = None,
+    page_title: Optional[str] = None,
+    min_views: Optional[int] = None,
+    max_views: Optional[int] = None,
+    languages: Optional[list[str]] = None,
+    domains: Optional[list[str]] = None,
+    mobile: Optional[bool] = None,
+    layout: Optional[Literal["flat", "nested"]] = None,
+    on_error: Optional[Literal["skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    top_k: Optional[int] = None,
+    top_k_per_language: Optional[bool] = None,
+    columns: Optional[list[str]] = None,
+    sort: Optional[Literal["views", "title"]] = None,
+    statistics: Optional[bool] = None,
+    bloom_filters: Optional[bool] = None,
+    encodings: Optional[dict[str, str]] = None,
+    decode_titles: Optional[bool] = None,
+) -> list[str]: ...
+def parquet_from_urls(  # noqa: E302
+    urls: list[str],
+    output_path: str,
+    concurrency: int = 4,
+    batch_size: Optional[int] = None,
+    line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
+    domain_codes: Optional[list[str]] = None,
+    page_title: Optional[str] = None,
+    min_views: Optional[int] = None,
+    max_views: Optional[int] = None,
+    languages: Optional[list[str]] = None,
+    domains: Optional[list[str]] = None,
+    mobile: Optional[bool] = None,
+    layout: Optional[Literal["flat", "nested"]] = None,
+    on_error: Optional[Literal["skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    top_k: Optional[int] = None,
+    top_k_per_language: Optional[bool] = None,
+    columns: Optional[list[str]] = None,
+    sort: Optional[Literal["views", "title"]] = None,
+    statistics: Optional[bool] = None,
+    bloom_filters: Optional[bool] = None,
+    encodings: Optional[dict[str, str]] = None,
+    decode_titles: Optional[bool] = None,
+) -> WriteReport: ...
+def parquet_from_range(  # noqa: E302
+    start: str,
+    end: str,
+    output_dir: str,
+    merge: bool = False,
+    mirror: Optional[str] = None,
+    concurrency: int = 4,
+    batch_size: Optional[int] = None,
+    line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
+    domain_codes: Optional[list[str]] = None,
+    page_title: Optional[str] = None,
+    min_views: Optional[int] = None,
+    max_views: Optional[int] = None,
+    languages: Optional[list[str]] = None,
+    domains: Optional[list[str]] = None,
+    mobile: Optional[bool] = None,
+    layout: Optional[Literal["flat", "nested"]] = None,
+    on_error: Optional[Literal["skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    top_k: Optional[int] = None,
+    top_k_per_language: Optional[bool] = None,
+    columns: Optional[list[str]] = None,
+    sort: Optional[Literal["views", "title"]] = None,
+    statistics: Optional[bool] = None,
+    bloom_filters: Optional[bool] = None,
+    encodings: Optional[dict[str, str]] = None,
+    decode_titles: Optional[bool] = None,
+) -> dict[str, WriteReport]: ...
+def daily_parquet(  # noqa: E302
+    date: date,
+    output_path: str,
+    mirror: Optional[str] = None,
+    batch_size: Optional[int] = None,
+    line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
+    domain_codes: Optional[list[str]] = None,
+    page_title: Optional[str] = None,
+    min_views: Optional[int] = None,
+    max_views: Optional[int] = None,
+    languages: Optional[list[str]] = None,
+    domains: Optional[list[str]] = None,
+    mobile: Optional[bool] = None,
+    layout: Optional[Literal["flat", "nested"]] = None,
+    on_error: Optional[Literal["skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    top_k: Optional[int] = None,
+    top_k_per_language: Optional[bool] = None,
+    columns: Optional[list[str]] = None,
+    sort: Optional[Literal["views", "title"]] = None,
+    statistics: Optional[bool] = None,
+    bloom_filters: Optional[bool] = None,
+    encodings: Optional[dict[str, str]] = None,
+    decode_titles: Optional[bool] = None,
+) -> WriteReport: ...
+def pageviews_gz_from_file(  # noqa: E302
+    input_path: str,
+    output_path: str,
+    line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
+    domain_codes: Optional[list[str]] = None,
+    page_title: Optional[str] = None,
+    min_views: Optional[int] = None,
+    max_views: Optional[int] = None,
+    languages: Optional[list[str]] = None,
+    domains: Optional[list[str]] = None,
+    mobile: Optional[bool] = None,
+    on_error: Optional[Literal["skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    skip: Optional[int] = None,
+    limit: Optional[int] = None,
+    assume_sorted: Optional[bool] = None,
+) -> None: ...
+def pageviews_gz_from_url(  # noqa: E302
+    url: str,
+    output_path: str,
+    line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
+    domain_codes: Optional[list[str]] = None,
+    page_title: Optional[str] = None,
+    min_views: Optional[int] = None,
+    max_views: Optional[int] = None,
+    languages: Optional[list[str]] = None,
+    domains: Optional[list[str]] = None,
+    mobile: Optional[bool] = None,
+    on_error: Optional[Literal["skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    skip: Optional[int] = None,
+    limit: Optional[int] = None,
+    assume_sorted: Optional[bool] = None,
+) -> None: ...
+def lines_from_file(  # noqa: E302
+    path: str,
+    line_regex: Optional[str] = None,
+) -> Generator[str, None, None]: ...
+def lines_from_url(  # noqa: E302
+    url: str,
+    line_regex: Optional[str] = None,
+) -> Generator[str, None, None]: ...
+def follow(  # noqa: E302
+    start: datetime,
+    until: Optional[datetime] = None,
+    poll_interval: float = 300.0,
+    mirror: Optional[str] = None,
+    line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
+    domain_codes: Optional[list[str]] = None,
+    page_title: Optional[str] = None,
+    min_views: Optional[int] = None,
+    max_views: Optional[int] = None,
+    languages: Optional[list[str]] = None,
+    domains: Optional[list[str]] = None,
+    mobile: Optional[bool] = None,
+    on_error: Optional[Literal["skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    decode_titles: Optional[bool] = None,
+) -> RowIterator: ...
+def sum_views(  # noqa: E302
+    paths: Optional[list[str]] = None,
+    urls: Optional[list[str]] = None,
+    line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
+    domain_codes: Optional[list[str]] = None,
+    page_title: Optional[str] = None,
+    min_views: Optional[int] = None,
+    max_views: Optional[int] = None,
+    languages: Optional[list[str]] = None,
+    domains: Optional[list[str]] = None,
+    mobile: Optional[bool] = None,
+    on_error: Optional[Literal["skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    decode_titles: Optional[bool] = None,
+) -> dict[tuple[str, str], int]: ...
+def summarize(  # noqa: E302
+    paths: Optional[list[str]] = None,
+    urls: Optional[list[str]] = None,
+    line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
+    domain_codes: Optional[list[str]] = None,
+    page_title: Optional[str] = None,
+    min_views: Optional[int] = None,
+    max_views: Optional[int] = None,
+    languages: Optional[list[str]] = None,
+    domains: Optional[list[str]] = None,
+    mobile: Optional[bool] = None,
+    on_error: Optional[Literal["skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    decode_titles: Optional[bool] = None,
+) -> Summary: ...
+def aggregate_views(  # noqa: E302
+    paths: Optional[list[str]] = None,
+    urls: Optional[list[str]] = None,
+    group_by: Literal["language", "domain", "domain_code", "title"] = "language",
+    agg: Literal["sum", "count", "max", "min"] = "sum",
+    line_regex: Optional[str] = None,
+    filter: Optional[Filter] = None,
     domain_codes: Optional[list[str]] = None,
     page_title: Optional[str] = None,
     min_views: Optional[int] = None,
@@ -56,4 +457,27 @@ def parquet_from_url(  # noqa: E302
     languages: Optional[list[str]] = None,
     domains: Optional[list[str]] = None,
     mobile: Optional[bool] = None,
+    on_error: Optional[Literal["skip", "fail"]] = None,
+    error_file: Optional[str] = None,
+    decode_titles: Optional[bool] = None,
+) -> dict[str, int]: ...
+def pageviews_url(  # noqa: E302
+    date: date, hour: int, mirror: Optional[str] = None
+) -> str: ...
+def pageviews_urls_for_day(  # noqa: E302
+    date: date, mirror: Optional[str] = None
+) -> list[str]: ...
+def list_available_hours(  # noqa: E302
+    year: int, month: int, mirror: Optional[str] = None
+) -> list[datetime]: ...
+def refresh_domains(  # noqa: E302
+    cache_path: Optional[str] = None, max_age: float = 86400.0
+) -> None: ...
+def write_fake_pageviews_gz(  # noqa: E302
+    path: str,
+    rows: int = 1000,
+    seed: int = 0,
+    languages: Optional[list[str]] = None,
+    quoted_rows: int = 0,
+    invalid_lines: int = 0,
 ) -> None: ...
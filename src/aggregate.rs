@@ -0,0 +1,852 @@
+use crate::filter::Filter;
+use crate::parse::{Pageviews, PageviewsRef, ParseError};
+use crate::stream::{Source, StreamConfig, StreamError};
+use crate::{RowIterator, for_each_row, stream_from_source};
+use std::cmp::{Ordering, Reverse};
+use std::collections::btree_map::Entry;
+use std::collections::hash_map::Entry as HashEntry;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Total views per `(domain_code, page_title)`, returned by `sum_views`.
+pub type ViewTotals = HashMap<(String, String), u64>;
+
+/// The total of a group grew too large for its integer type.
+///
+/// Totals are summed with checked arithmetic, so they are never silently
+/// wrapped or capped. Totals are `u64` in the aggregations, which holds the
+/// views of every page for far longer than the dumps exist, and `u32` in
+/// rows, like the views of an hour.
+#[derive(Debug, Error)]
+#[error("views of '{group}' overflow the total")]
+pub struct ViewsOverflow {
+    /// The group or page whose total overflowed.
+    pub group: String,
+}
+
+/// A row ranked by views, with ties broken by domain code and title.
+///
+/// Rows with more views rank higher. For equal views, the row which sorts
+/// first alphabetically ranks higher, to keep results deterministic.
+pub(crate) struct Ranked(pub(crate) Pageviews);
+
+impl Ranked {
+    fn sort_key(&self) -> (u32, Reverse<&str>, Reverse<&str>) {
+        (
+            self.0.views,
+            Reverse(&self.0.domain_code),
+            Reverse(&self.0.page_title),
+        )
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.sort_key() == other.sort_key()
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+/// Keeps the `n` rows with most views for each key.
+///
+/// Memory use is bounded by `n` rows per key, regardless of the number of
+/// rows pushed, so the whole file never needs to be in memory at once. Rows
+/// can be pushed from any number of files before collecting the results.
+///
+/// # Example
+///
+/// ```
+/// use pvstream::aggregate::TopN;
+/// use pvstream::parse::parse_line;
+///
+/// let mut top = TopN::new(|row| row.parsed_domain_code.language.clone(), 1);
+/// for line in ["de Berlin 10 0", "de Bonn 5 0", "fr Paris 7 0"] {
+///     top.push(parse_line(line.to_string())?);
+/// }
+///
+/// let top = top.into_sorted();
+/// assert_eq!(top["de"][0].page_title, "Berlin");
+/// assert_eq!(top["fr"][0].page_title, "Paris");
+/// # Ok::<(), pvstream::parse::ParseError>(())
+/// ```
+pub struct TopN<K, F> {
+    key: F,
+    n: usize,
+    heaps: BTreeMap<K, BinaryHeap<Reverse<Ranked>>>,
+}
+
+impl<K, F> TopN<K, F>
+where
+    K: Ord,
+    F: Fn(&Pageviews) -> K,
+{
+    /// Creates an empty aggregation, grouping rows with `key`.
+    pub fn new(key: F, n: usize) -> Self {
+        Self {
+            key,
+            n,
+            heaps: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a row, dropping it if it doesn't make the top `n` for its key.
+    pub fn push(&mut self, row: Pageviews) {
+        if self.n == 0 {
+            return;
+        }
+
+        let heap = self.heaps.entry((self.key)(&row)).or_default();
+        let row = Reverse(Ranked(row));
+        if heap.len() < self.n {
+            heap.push(row);
+        } else if heap.peek().is_some_and(|lowest| row < *lowest) {
+            // `Reverse` flips the order, so a lower value is a higher rank
+            heap.pop();
+            heap.push(row);
+        }
+    }
+
+    /// Returns the top rows for each key, sorted by views, descending.
+    pub fn into_sorted(self) -> BTreeMap<K, Vec<Pageviews>> {
+        self.heaps
+            .into_iter()
+            .map(|(key, heap)| {
+                let rows = heap
+                    .into_sorted_vec()
+                    .into_iter()
+                    .map(|Reverse(Ranked(row))| row)
+                    .collect();
+                (key, rows)
+            })
+            .collect()
+    }
+}
+
+/// Finds the `n` rows with most views for each key.
+///
+/// Convenience wrapper around `TopN` for a single iterator of rows. Handle
+/// or skip parse errors before passing the rows in, e.g. with
+/// `rows.filter_map(Result::ok)`.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::{aggregate::top_n_by, filter::FilterBuilder, stream_from_file};
+/// use std::path::PathBuf;
+///
+/// let rows = stream_from_file(PathBuf::from("pageviews-20240818-080000.gz"), &FilterBuilder::new().build())?;
+/// let top = top_n_by(
+///     rows.filter_map(Result::ok),
+///     |row| row.parsed_domain_code.language.clone(),
+///     1000,
+/// );
+///
+/// for row in &top["en"] {
+///     println!("{} {}", row.views, row.page_title);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn top_n_by<I, K, F>(rows: I, key: F, n: usize) -> BTreeMap<K, Vec<Pageviews>>
+where
+    I: IntoIterator<Item = Pageviews>,
+    K: Ord,
+    F: Fn(&Pageviews) -> K,
+{
+    let mut top = TopN::new(key, n);
+    for row in rows {
+        top.push(row);
+    }
+    top.into_sorted()
+}
+
+/// Keeps only the rows with most views when exporting, see
+/// `ParquetOptions::top_k`.
+///
+/// The rows are ranked like in `TopN`, with a bounded heap, so a whole file
+/// can be reduced to its top rows without holding it in memory.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::aggregate::TopK;
+/// use pvstream::{ParquetOptions, filter::FilterBuilder, parquet_from_source};
+/// use pvstream::stream::{Source, StreamConfig};
+/// use std::path::PathBuf;
+///
+/// // The 10 000 most viewed German pages of the hour
+/// let options = ParquetOptions {
+///     top_k: Some(TopK::global(10_000)),
+///     ..Default::default()
+/// };
+/// parquet_from_source(
+///     &Source::File(PathBuf::from("pageviews-20240818-080000.gz")),
+///     PathBuf::from("top-de.parquet"),
+///     &FilterBuilder::new().languages(["de"]).build(),
+///     &StreamConfig::default(),
+///     &options,
+/// )?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TopK {
+    /// Number of rows to keep, in total or for each language.
+    pub n: usize,
+    /// Keep the top `n` rows of each language instead of the top `n` overall.
+    pub per_language: bool,
+}
+
+impl TopK {
+    /// Keeps the top `n` rows overall.
+    pub fn global(n: usize) -> Self {
+        Self {
+            n,
+            per_language: false,
+        }
+    }
+
+    /// Keeps the top `n` rows of each language.
+    pub fn per_language(n: usize) -> Self {
+        Self {
+            n,
+            per_language: true,
+        }
+    }
+
+    /// Reduces the rows to the top rows, sorted by views, descending, and by
+    /// language first when ranked per language.
+    ///
+    /// The rows are read in full before the first one is returned. Errors are
+    /// passed on ahead of the rows, and a read error ends the rows early, as
+    /// the ranking would be incomplete.
+    pub(crate) fn apply(self, mut rows: RowIterator) -> RowIterator {
+        let per_language = self.per_language;
+        let ranked = std::iter::once_with(move || {
+            let mut top = TopN::new(
+                |row: &Pageviews| per_language.then(|| row.parsed_domain_code.language.clone()),
+                self.n,
+            );
+            let mut errors = Vec::new();
+            for row in rows.by_ref() {
+                match row {
+                    Ok(row) => top.push(row),
+                    Err(err @ ParseError::ReadError(_)) => {
+                        errors.push(err);
+                        return errors.into_iter().map(Err).collect::<Vec<_>>();
+                    }
+                    Err(err) => errors.push(err),
+                }
+            }
+            let top = top.into_sorted().into_values().flatten().map(Ok);
+            errors.into_iter().map(Err).chain(top).collect()
+        });
+        Box::new(ranked.flatten())
+    }
+}
+
+/// Field to group rows by in an `Aggregation`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GroupBy {
+    /// The language code, e.g. `en`.
+    Language,
+    /// The Wikimedia domain, e.g. `wikibooks.org`, or `-` if unknown.
+    Domain,
+    /// The raw domain code, e.g. `en.m.b`.
+    DomainCode,
+    /// The page title, across all wikis.
+    Title,
+}
+
+impl GroupBy {
+    /// Name of the field.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GroupBy::Language => "language",
+            GroupBy::Domain => "domain",
+            GroupBy::DomainCode => "domain_code",
+            GroupBy::Title => "title",
+        }
+    }
+
+    /// Value of the field for a row.
+    pub fn key(&self, row: &Pageviews) -> String {
+        match self {
+            GroupBy::Language => row.parsed_domain_code.language.clone(),
+            GroupBy::Domain => row.parsed_domain_code.domain.unwrap_or("-").to_string(),
+            GroupBy::DomainCode => row.domain_code.clone(),
+            GroupBy::Title => row.page_title.clone(),
+        }
+    }
+}
+
+impl fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "language" => Ok(GroupBy::Language),
+            "domain" => Ok(GroupBy::Domain),
+            "domain_code" => Ok(GroupBy::DomainCode),
+            "title" => Ok(GroupBy::Title),
+            _ => Err(format!(
+                "unknown group '{value}', expected language, domain, domain_code, or title"
+            )),
+        }
+    }
+}
+
+/// How the views of each group are combined in an `Aggregation`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Agg {
+    /// Total views.
+    Sum,
+    /// Number of rows.
+    Count,
+    /// Most views of a single row.
+    Max,
+    /// Fewest views of a single row.
+    Min,
+}
+
+impl Agg {
+    /// Value of a group with a single row.
+    fn first(self, views: u32) -> u64 {
+        match self {
+            Agg::Count => 1,
+            Agg::Sum | Agg::Max | Agg::Min => u64::from(views),
+        }
+    }
+
+    /// Adds a row to the value of a group, `None` if the value overflows.
+    fn add(self, value: u64, views: u32) -> Option<u64> {
+        match self {
+            Agg::Sum => value.checked_add(u64::from(views)),
+            Agg::Count => value.checked_add(1),
+            Agg::Max => Some(value.max(u64::from(views))),
+            Agg::Min => Some(value.min(u64::from(views))),
+        }
+    }
+}
+
+impl FromStr for Agg {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "sum" => Ok(Agg::Sum),
+            "count" => Ok(Agg::Count),
+            "max" => Ok(Agg::Max),
+            "min" => Ok(Agg::Min),
+            _ => Err(format!(
+                "unknown aggregation '{value}', expected sum, count, max, or min"
+            )),
+        }
+    }
+}
+
+/// Combines the views of rows grouped by a field, e.g. the total views of
+/// each language.
+///
+/// Only one value per group is kept, so rows can be pushed from any number
+/// of files before collecting the results.
+///
+/// # Example
+///
+/// ```
+/// use pvstream::aggregate::{Agg, Aggregation, GroupBy};
+/// use pvstream::parse::parse_line;
+///
+/// let mut totals = Aggregation::new(GroupBy::Language, Agg::Sum);
+/// for line in ["de Berlin 10 0", "de.m Berlin 5 0", "fr Paris 7 0"] {
+///     totals.push(&parse_line(line.to_string())?)?;
+/// }
+///
+/// let totals = totals.into_values();
+/// assert_eq!(totals["de"], 15);
+/// assert_eq!(totals["fr"], 7);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Aggregation {
+    group_by: GroupBy,
+    agg: Agg,
+    values: BTreeMap<String, u64>,
+}
+
+impl Aggregation {
+    /// Creates an empty aggregation.
+    pub fn new(group_by: GroupBy, agg: Agg) -> Self {
+        Self {
+            group_by,
+            agg,
+            values: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the views of a row to its group.
+    ///
+    /// Returns `ViewsOverflow` if the value of the group no longer fits in a
+    /// `u64`, leaving the value as it was before the row.
+    pub fn push(&mut self, row: &Pageviews) -> Result<(), ViewsOverflow> {
+        match self.values.entry(self.group_by.key(row)) {
+            Entry::Vacant(entry) => {
+                entry.insert(self.agg.first(row.views));
+            }
+            Entry::Occupied(mut entry) => {
+                let value = self.agg.add(*entry.get(), row.views);
+                *entry.get_mut() = value.ok_or_else(|| ViewsOverflow {
+                    group: entry.key().clone(),
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the value of each group, sorted by group.
+    pub fn into_values(self) -> BTreeMap<String, u64> {
+        self.values
+    }
+}
+
+/// Combines the views of rows grouped by a field.
+///
+/// Convenience wrapper around `Aggregation` for a single iterator of rows.
+/// Handle or skip parse errors before passing the rows in, e.g. with
+/// `rows.filter_map(Result::ok)`.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::aggregate::{Agg, GroupBy, aggregate_views};
+/// use pvstream::{filter::FilterBuilder, stream_from_file};
+/// use std::path::PathBuf;
+///
+/// let rows = stream_from_file(PathBuf::from("pageviews-20240818-080000.gz"), &FilterBuilder::new().build())?;
+/// let totals = aggregate_views(rows.filter_map(Result::ok), GroupBy::Domain, Agg::Sum)?;
+///
+/// for (domain, views) in totals {
+///     println!("{domain}: {views}");
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn aggregate_views<I>(
+    rows: I,
+    group_by: GroupBy,
+    agg: Agg,
+) -> Result<BTreeMap<String, u64>, ViewsOverflow>
+where
+    I: IntoIterator<Item = Pageviews>,
+{
+    let mut aggregation = Aggregation::new(group_by, agg);
+    for row in rows {
+        aggregation.push(&row)?;
+    }
+    Ok(aggregation.into_values())
+}
+
+/// Sums views per `(domain_code, page_title)` across multiple files.
+///
+/// Files are streamed one at a time, and only the running totals are kept in
+/// memory, one entry per distinct page. Use the filters to keep the number of
+/// pages down when summing many hours. Lines that fail to parse are skipped,
+/// while read errors abort the aggregation, as does a total overflowing a
+/// `u64`, see `ViewsOverflow`.
+///
+/// # Example
+///
+/// ```no_run
+/// use chrono::{TimeZone, Utc};
+/// use pvstream::aggregate::sum_views;
+/// use pvstream::filter::FilterBuilder;
+/// use pvstream::stream::{Source, StreamConfig, hours_in_range};
+/// use pvstream::urls::dump_url;
+///
+/// let start = Utc.with_ymd_and_hms(2024, 8, 18, 0, 0, 0).unwrap();
+/// let end = Utc.with_ymd_and_hms(2024, 8, 19, 0, 0, 0).unwrap();
+/// let sources: Vec<_> = hours_in_range(start, end)
+///     .map(|hour| Source::Url(dump_url(&hour)))
+///     .collect();
+///
+/// let filter = FilterBuilder::new().domain_codes(["nn"]).build();
+/// let totals = sum_views(&sources, &filter, &StreamConfig::default())?;
+/// println!("{:?}", totals.get(&("nn".to_string(), "Oslo".to_string())));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn sum_views(
+    sources: &[Source],
+    filter: &Filter,
+    config: &StreamConfig,
+) -> Result<ViewTotals, StreamError> {
+    let mut totals = ViewTotals::new();
+    for source in sources {
+        for row in stream_from_source(source, filter, config)? {
+            let row = match row {
+                Ok(row) => row,
+                Err(err) if config.error_policy.is_fatal(&err) => return Err(err.into()),
+                Err(_) => continue,
+            };
+            let views = u64::from(row.views);
+            match totals.entry((row.domain_code, row.page_title)) {
+                HashEntry::Vacant(entry) => {
+                    entry.insert(views);
+                }
+                HashEntry::Occupied(mut entry) => {
+                    let total = entry.get().checked_add(views);
+                    *entry.get_mut() = total.ok_or_else(|| {
+                        let (domain_code, page_title) = entry.key();
+                        ViewsOverflow {
+                            group: format!("{domain_code} {page_title}"),
+                        }
+                    })?;
+                }
+            }
+        }
+    }
+    Ok(totals)
+}
+
+/// Number of rows and total views of a group in a `Summary`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GroupTotals {
+    pub rows: u64,
+    pub views: u64,
+}
+
+impl GroupTotals {
+    /// Adds a row, which can't overflow once the grand total has room.
+    fn add(&mut self, views: u32) {
+        self.rows += 1;
+        self.views += u64::from(views);
+    }
+}
+
+/// Row counts and views per language and per domain, returned by
+/// `summarize`.
+///
+/// Meant as a quick profile of a file before deciding on the filters of a
+/// full export. Rows from unknown domains are counted under `-`, like in
+/// `GroupBy::Domain`.
+///
+/// # Example
+///
+/// ```
+/// use pvstream::aggregate::Summary;
+/// use pvstream::parse::{PageviewsRef, parse_line};
+///
+/// let mut summary = Summary::new();
+/// for line in ["de Berlin 10 0", "de.m Berlin 5 0", "de.b Kochbuch 1 0"] {
+///     summary.push(&PageviewsRef::from(&parse_line(line.to_string())?))?;
+/// }
+///
+/// assert_eq!(summary.total.rows, 3);
+/// assert_eq!(summary.languages["de"].views, 16);
+/// assert_eq!(summary.domains["wikipedia.org"].rows, 2);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Totals of all rows.
+    pub total: GroupTotals,
+    /// Totals per language code, sorted by language.
+    pub languages: BTreeMap<String, GroupTotals>,
+    /// Totals per Wikimedia domain, sorted by domain.
+    pub domains: BTreeMap<String, GroupTotals>,
+}
+
+impl Summary {
+    /// Creates an empty summary.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a row to the totals.
+    ///
+    /// Returns `ViewsOverflow` if the total views no longer fit in a `u64`,
+    /// leaving the summary as it was before the row.
+    pub fn push(&mut self, row: &PageviewsRef<'_>) -> Result<(), ViewsOverflow> {
+        // The groups never add up to more than the grand total
+        if self.total.views.checked_add(u64::from(row.views)).is_none() {
+            return Err(ViewsOverflow {
+                group: "total".to_string(),
+            });
+        }
+        self.total.add(row.views);
+
+        let language = row.parsed_domain_code.language.as_ref();
+        match self.languages.get_mut(language) {
+            Some(totals) => totals.add(row.views),
+            None => {
+                let totals = self.languages.entry(language.to_string()).or_default();
+                totals.add(row.views);
+            }
+        }
+        let domain = row.parsed_domain_code.domain.unwrap_or("-");
+        match self.domains.get_mut(domain) {
+            Some(totals) => totals.add(row.views),
+            None => {
+                let totals = self.domains.entry(domain.to_string()).or_default();
+                totals.add(row.views);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Counts rows and sums views per language and per domain across multiple
+/// files, in one pass.
+///
+/// Rows are borrowed from the lines they were parsed from, as in
+/// `for_each_row`, and only one entry per language and domain is kept in
+/// memory, so whole files are profiled quickly. Lines that fail to parse are
+/// skipped, unless they are fatal under the `error_policy` of `config`.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::aggregate::summarize;
+/// use pvstream::filter::FilterBuilder;
+/// use pvstream::stream::{Source, StreamConfig};
+/// use std::path::PathBuf;
+///
+/// let source = Source::File(PathBuf::from("pageviews-20240818-080000.gz"));
+/// let filter = FilterBuilder::new().min_views(10).build();
+/// let summary = summarize(&[source], &filter, &StreamConfig::default())?;
+///
+/// for (language, totals) in &summary.languages {
+///     println!("{language}: {} rows, {} views", totals.rows, totals.views);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn summarize(
+    sources: &[Source],
+    filter: &Filter,
+    config: &StreamConfig,
+) -> Result<Summary, StreamError> {
+    let mut summary = Summary::new();
+    let mut overflow = None;
+    for source in sources {
+        for_each_row(source, filter, config, |row| {
+            if overflow.is_none() {
+                overflow = summary.push(&row).err();
+            }
+        })?;
+        if let Some(err) = overflow {
+            return Err(err.into());
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_line;
+
+    fn rows(lines: &[&str]) -> Vec<Pageviews> {
+        lines
+            .iter()
+            .map(|line| parse_line(line.to_string()).unwrap())
+            .collect()
+    }
+
+    fn titles(rows: &[Pageviews]) -> Vec<&str> {
+        rows.iter().map(|row| row.page_title.as_str()).collect()
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut summary = Summary::new();
+        for row in rows(&[
+            "de Berlin 10 0",
+            "de.m Berlin 5 0",
+            "en.d word 2 0",
+            "xx Foo 1 0",
+        ]) {
+            summary.push(&PageviewsRef::from(&row)).unwrap();
+        }
+
+        assert_eq!(summary.total, GroupTotals { rows: 4, views: 18 });
+        assert_eq!(summary.languages["de"], GroupTotals { rows: 2, views: 15 });
+        assert_eq!(summary.languages["en"], GroupTotals { rows: 1, views: 2 });
+        assert_eq!(summary.domains["wikipedia.org"].rows, 3);
+        assert_eq!(summary.domains["wiktionary.org"].views, 2);
+        assert_eq!(summary.languages.len(), 3);
+    }
+
+    #[test]
+    fn test_summary_overflow() {
+        let mut summary = Summary::new();
+        summary.total.views = u64::MAX;
+        let row = parse_line("de Berlin 1 0".to_string()).unwrap();
+
+        assert!(summary.push(&PageviewsRef::from(&row)).is_err());
+        assert_eq!(summary.total.rows, 0);
+        assert!(summary.languages.is_empty());
+    }
+
+    #[test]
+    fn test_top_n_by_language() {
+        let rows = rows(&[
+            "en A 5 0",
+            "en B 50 0",
+            "de C 7 0",
+            "en.m D 20 0",
+            "en E 1 0",
+            "de F 70 0",
+        ]);
+        let top = top_n_by(rows, |row| row.parsed_domain_code.language.clone(), 2);
+
+        assert_eq!(top.len(), 2);
+        assert_eq!(titles(&top["en"]), ["B", "D"]);
+        assert_eq!(titles(&top["de"]), ["F", "C"]);
+    }
+
+    #[test]
+    fn test_top_k() {
+        let lines = ["en A 5 0", "de B 50 0", "en C x 0", "de D 7 0", "en E 20 0"];
+        let results = || -> RowIterator {
+            let rows: Vec<_> = lines
+                .iter()
+                .map(|line| parse_line(line.to_string()))
+                .collect();
+            Box::new(rows.into_iter())
+        };
+
+        let top: Vec<_> = TopK::global(2).apply(results()).collect();
+        assert!(matches!(top[0], Err(ParseError::InvalidField("views", _))));
+        let top: Vec<_> = top.into_iter().skip(1).map(Result::unwrap).collect();
+        assert_eq!(titles(&top), ["B", "E"]);
+
+        let top: Vec<_> = TopK::per_language(1)
+            .apply(results())
+            .filter_map(Result::ok)
+            .collect();
+        assert_eq!(titles(&top), ["B", "E"]);
+
+        let read_error = || ParseError::ReadError(std::io::Error::other("reset"));
+        let rows = results().chain([Err(read_error()), Ok(rows(&["en F 99 0"]).remove(0))]);
+        let top: Vec<_> = TopK::global(2).apply(Box::new(rows)).collect();
+        assert_eq!(top.len(), 2);
+        assert!(matches!(top[1], Err(ParseError::ReadError(_))));
+    }
+
+    #[test]
+    fn test_sum_views() {
+        let source = Source::File("tests/files/pageviews-20240803-060000.gz".into());
+        let filter = crate::filter::FilterBuilder::new()
+            .languages(["ko"])
+            .build();
+        let config = StreamConfig::default();
+
+        let once = sum_views(std::slice::from_ref(&source), &filter, &config).unwrap();
+        let twice = sum_views(&[source.clone(), source], &filter, &config).unwrap();
+
+        assert!(!once.is_empty());
+        assert!(
+            once.keys()
+                .all(|(domain_code, _)| domain_code.starts_with("ko"))
+        );
+        assert_eq!(
+            twice[&("ko".to_string(), "서울_지하철_7호선".to_string())],
+            4
+        );
+        for (key, views) in &once {
+            assert_eq!(twice[key], views * 2);
+        }
+    }
+
+    #[test]
+    fn test_aggregate_views() {
+        let rows = rows(&[
+            "en Rust 5 0",
+            "en.m Rust 3 0",
+            "en.b Rust 1 0",
+            "de Rust 7 0",
+            "commons.m Main_Page 2 0",
+        ]);
+        let aggregate = |group_by, agg| {
+            aggregate_views(rows.clone(), group_by, agg)
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            aggregate(GroupBy::Language, Agg::Sum),
+            [("de".into(), 7), ("en".into(), 11)]
+        );
+        assert_eq!(
+            aggregate(GroupBy::Domain, Agg::Count),
+            [
+                ("commons.wikimedia.org".into(), 1),
+                ("wikibooks.org".into(), 1),
+                ("wikipedia.org".into(), 3),
+            ]
+        );
+        assert_eq!(
+            aggregate(GroupBy::DomainCode, Agg::Max)[..2],
+            [("commons.m".into(), 2), ("de".into(), 7)]
+        );
+        assert_eq!(
+            aggregate(GroupBy::Title, Agg::Min),
+            [("Main_Page".into(), 2), ("Rust".into(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_aggregation_overflow() {
+        let rows = rows(&["en Rust 5 0", "de Rust 7 0"]);
+        let mut totals = Aggregation::new(GroupBy::Language, Agg::Sum);
+        totals.values.insert("en".into(), u64::MAX - 4);
+
+        let err = totals.push(&rows[0]).unwrap_err();
+        assert_eq!(err.group, "en");
+        totals.push(&rows[1]).unwrap();
+
+        // The group keeps its value from before the overflowing row
+        let totals = totals.into_values();
+        assert_eq!(totals["en"], u64::MAX - 4);
+        assert_eq!(totals["de"], 7);
+
+        assert_eq!(Agg::Count.add(u64::MAX, 0), None);
+        assert_eq!(Agg::Max.add(u64::MAX, 5), Some(u64::MAX));
+    }
+
+    #[test]
+    fn test_parse_group_by() {
+        assert_eq!("domain_code".parse(), Ok(GroupBy::DomainCode));
+        assert_eq!("max".parse(), Ok(Agg::Max));
+        assert!("wiki".parse::<GroupBy>().is_err());
+        assert!("mean".parse::<Agg>().is_err());
+    }
+
+    #[test]
+    fn test_top_n_ties() {
+        let rows = rows(&["en C 1 0", "en A 1 0", "en B 1 0", "en D 2 0"]);
+        let top = top_n_by(rows.clone(), |_| (), 3);
+        assert_eq!(titles(&top[&()]), ["D", "A", "B"]);
+
+        let top = top_n_by(rows, |_| (), 0);
+        assert!(top.is_empty());
+    }
+}
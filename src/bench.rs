@@ -0,0 +1,3 @@
+//! Re-exports `testing::generate_test_dump` for the `benches/` harness.
+
+pub use crate::testing::generate_test_dump;
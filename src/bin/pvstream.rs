@@ -0,0 +1,908 @@
+//! Command line interface for pvstream.
+//!
+//! Streams, filters, and converts pageviews files without writing any code.
+//! Inputs can be paths on the local file system or `http(s)://` URLs.
+
+use chrono::{DateTime, NaiveDate, Utc, Weekday};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use pvstream::aggregate::{self, Agg, Aggregation, Summary, TopK, TopN};
+use pvstream::cache::cached_file;
+use pvstream::filter::{
+    Blocklist, Filter, Sample, TitleMatching, TitleTransform, titles_from_file,
+};
+use pvstream::follow::{FollowOptions, follow_hours};
+use pvstream::index::index_from_source;
+use pvstream::join::PageIds;
+use pvstream::parse::{Pageviews, PageviewsRef, ParseError, ParseMode};
+use pvstream::sitematrix::{refresh_domains, refresh_domains_cached};
+use pvstream::sites::Project;
+use pvstream::sort::{self, Sort};
+use pvstream::stream::{ClientConfig, ErrorPolicy, ErrorSink, Source, StreamConfig};
+use pvstream::urls::{Mirror, pageviews_urls_for_day};
+use pvstream::{
+    ColumnEncoding, OutputFormat, ParquetOptions, SchemaLayout, WriteReport,
+    daily_parquet_from_sources, parquet_from_source, stream_from_source,
+};
+use regex::{Regex, RegexSet};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::{self, BufWriter, Write, stdout};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use url::Url;
+
+#[derive(Parser)]
+#[command(name = "pvstream", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Base URL of a mirror to fail over to, can be repeated
+    #[arg(long = "mirror", global = true, value_parser = Mirror::new)]
+    mirrors: Vec<Mirror>,
+    /// Proxy for HTTP requests, e.g. http://proxy.example.com:3128
+    #[arg(long, global = true)]
+    proxy: Option<Url>,
+    /// PEM file with extra root certificates to trust
+    #[arg(long, global = true)]
+    ca_bundle: Option<PathBuf>,
+    /// Seconds to wait for a connection, 0 for no limit
+    #[arg(long, global = true, default_value_t = 10)]
+    connect_timeout: u64,
+    /// Seconds without any data before a download is resumed, 0 for no limit
+    #[arg(long, global = true, default_value_t = 30)]
+    read_timeout: u64,
+    /// User-Agent header of HTTP requests
+    #[arg(long, global = true)]
+    user_agent: Option<String>,
+    /// Directory to cache downloaded files in, reused while unchanged
+    #[arg(long, global = true)]
+    cache_dir: Option<PathBuf>,
+    /// What to do with lines that fail to parse
+    #[arg(long, global = true, value_enum, default_value_t = OnError::Report)]
+    on_error: OnError,
+    /// File to append lines that fail to parse to, instead of reporting them
+    #[arg(long, global = true, conflicts_with = "on_error")]
+    error_file: Option<PathBuf>,
+    /// Decode percent-encoded page titles, with spaces instead of underscores
+    #[arg(long, global = true)]
+    decode_titles: bool,
+    /// Fail to parse lines with domain codes of unknown projects
+    #[arg(long, global = true)]
+    strict_domains: bool,
+    /// Resolve Wikimedia projects launched after this version of pvstream
+    /// from the live sitematrix, saved in --cache-dir for a day if set
+    #[arg(long, global = true)]
+    refresh_domains: bool,
+    /// Stop reading once past the domain codes the filters can match,
+    /// assuming the input is sorted by domain code like the dumps
+    #[arg(long, global = true)]
+    assume_sorted: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print rows matching the filters, in the pageviews file format by default
+    Filter {
+        /// Paths or URLs of pageviews files
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        /// Format of the printed rows
+        #[arg(long, value_enum, default_value_t = Format::Dump)]
+        format: Format,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Convert a pageviews file to Parquet
+    ToParquet {
+        /// Path or URL of a pageviews file
+        input: String,
+        /// Path of the Parquet file to write
+        output: PathBuf,
+        /// Layout of the Parquet columns
+        #[arg(long, value_enum, default_value_t = Layout::Flat)]
+        layout: Layout,
+        /// Rows per row group
+        #[arg(long)]
+        batch_size: Option<usize>,
+        /// Only write the pages with most views
+        #[arg(long)]
+        top_k: Option<usize>,
+        /// Keep the top pages of each language, with `--top-k`
+        #[arg(long, requires = "top_k")]
+        per_language: bool,
+        /// Columns to write, can be repeated, all of them if not given
+        #[arg(long = "column")]
+        columns: Vec<String>,
+        /// Sort the rows, spilling to temporary files if they don't fit in memory
+        #[arg(long, value_enum)]
+        sort: Option<SortBy>,
+        /// Write column statistics, letting query engines skip row groups
+        #[arg(long)]
+        statistics: bool,
+        /// Write bloom filters of page titles and languages
+        #[arg(long)]
+        bloom_filters: bool,
+        /// Dictionary encode a column, e.g. `page_title`, can be repeated
+        #[arg(long = "dictionary")]
+        dictionary: Vec<String>,
+        /// Plain encode a column, e.g. `domain_code`, can be repeated
+        #[arg(long = "plain")]
+        plain: Vec<String>,
+        /// Number of rows to skip before writing any
+        #[arg(long, default_value_t = 0)]
+        skip: usize,
+        /// Maximum number of rows to write, stopping the download early
+        #[arg(long)]
+        limit: Option<usize>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Print rows matching the filters from every hour since a point in time,
+    /// waiting for new hours to be published
+    Follow {
+        /// First hour to print, e.g. `2024-08-18T08:00:00Z`
+        start: DateTime<Utc>,
+        /// Stop before this hour, instead of following the dumps forever
+        #[arg(long)]
+        until: Option<DateTime<Utc>>,
+        /// Seconds to wait between checks for a new hour
+        #[arg(long, default_value_t = 300)]
+        poll_interval: u64,
+        /// Format of the printed rows
+        #[arg(long, value_enum, default_value_t = Format::Dump)]
+        format: Format,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Sum the views per page of all hours of a day, and write them to Parquet
+    Daily {
+        /// Day to sum the views of, e.g. `2024-08-18`
+        date: NaiveDate,
+        /// Path of the Parquet file to write
+        output: PathBuf,
+        /// Only write the pages with most views of the day
+        #[arg(long)]
+        top_k: Option<usize>,
+        /// Columns to write, can be repeated, all of them if not given
+        #[arg(long = "column")]
+        columns: Vec<String>,
+        /// Sort the rows, spilling to temporary files if they don't fit in memory
+        #[arg(long, value_enum)]
+        sort: Option<SortBy>,
+        /// Write column statistics, letting query engines skip row groups
+        #[arg(long)]
+        statistics: bool,
+        /// Write bloom filters of page titles and languages
+        #[arg(long)]
+        bloom_filters: bool,
+        /// Dictionary encode a column, e.g. `page_title`, can be repeated
+        #[arg(long = "dictionary")]
+        dictionary: Vec<String>,
+        /// Plain encode a column, e.g. `domain_code`, can be repeated
+        #[arg(long = "plain")]
+        plain: Vec<String>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Print the views of each group, e.g. the total views per language
+    Aggregate {
+        /// Paths or URLs of pageviews files
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        /// Field to group rows by
+        #[arg(long, value_enum, default_value_t = GroupBy::Language)]
+        by: GroupBy,
+        /// How to combine the views of a group
+        #[arg(long, value_enum, default_value_t = AggArg::Sum)]
+        agg: AggArg,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Print the number of rows and views per language and per domain
+    Summarize {
+        /// Paths or URLs of pageviews files
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Compress a pageviews file as block gzip, with an index for
+    /// `--assume-sorted` to start reading at the matching domain codes
+    Index {
+        /// Path or URL of a pageviews file
+        input: String,
+        /// Path of the block gzipped file to write, indexed in `<output>.idx`
+        output: PathBuf,
+    },
+    /// Print the pages with most views per group
+    Top {
+        /// Paths or URLs of pageviews files
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        /// Number of pages per group
+        #[arg(short, default_value_t = 10)]
+        n: usize,
+        /// Field to group pages by
+        #[arg(long, value_enum, default_value_t = GroupBy::Language)]
+        by: GroupBy,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Space separated, like the dumps
+    Dump,
+    /// Tab separated, in the PostgreSQL text format
+    Tsv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl From<Format> for OutputFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Dump => OutputFormat::Dump,
+            Format::Tsv => OutputFormat::Tsv,
+            Format::Jsonl => OutputFormat::Jsonl,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Layout {
+    Flat,
+    Nested,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OnError {
+    /// Print the errors to stderr and move on
+    Report,
+    /// Skip lines silently
+    Skip,
+    /// Stop at the first line that fails to parse
+    Fail,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GroupBy {
+    Language,
+    Domain,
+    DomainCode,
+    Title,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SortBy {
+    /// Most views first
+    Views,
+    /// Page title, in byte order
+    Title,
+}
+
+impl From<SortBy> for Sort {
+    fn from(sort_by: SortBy) -> Self {
+        Sort::new(match sort_by {
+            SortBy::Views => sort::SortBy::ViewsDescending,
+            SortBy::Title => sort::SortBy::Title,
+        })
+    }
+}
+
+impl From<GroupBy> for aggregate::GroupBy {
+    fn from(group_by: GroupBy) -> Self {
+        match group_by {
+            GroupBy::Language => aggregate::GroupBy::Language,
+            GroupBy::Domain => aggregate::GroupBy::Domain,
+            GroupBy::DomainCode => aggregate::GroupBy::DomainCode,
+            GroupBy::Title => aggregate::GroupBy::Title,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AggArg {
+    Sum,
+    Count,
+    Max,
+    Min,
+}
+
+impl From<AggArg> for Agg {
+    fn from(agg: AggArg) -> Self {
+        match agg {
+            AggArg::Sum => Agg::Sum,
+            AggArg::Count => Agg::Count,
+            AggArg::Max => Agg::Max,
+            AggArg::Min => Agg::Min,
+        }
+    }
+}
+
+/// The same filters as the library, see `Filter` for details.
+#[derive(Args)]
+struct FilterArgs {
+    /// Regex to match lines before parsing
+    #[arg(long, value_parser = Regex::new)]
+    line_regex: Option<Regex>,
+    /// Domain codes to match exactly, can be repeated
+    #[arg(long = "domain-code")]
+    domain_codes: Vec<String>,
+    /// Regex to match the page title
+    #[arg(long, value_parser = Regex::new)]
+    page_title: Option<Regex>,
+    /// Regex to match the page title, matching any of them when repeated
+    #[arg(long = "page-title-any")]
+    page_titles_any: Vec<String>,
+    /// Page titles to match exactly, can be repeated
+    #[arg(long = "title")]
+    titles: Vec<String>,
+    /// File with page titles to match exactly, one per line
+    #[arg(long)]
+    titles_file: Option<PathBuf>,
+    /// Prefix to match the page title with
+    #[arg(long)]
+    title_prefix: Option<String>,
+    /// Compare page titles ignoring case
+    #[arg(long)]
+    ignore_title_case: bool,
+    /// Compare page titles in Unicode NFC
+    #[arg(long)]
+    normalize_titles: bool,
+    /// Minimum number of views
+    #[arg(long)]
+    min_views: Option<u32>,
+    /// Maximum number of views
+    #[arg(long)]
+    max_views: Option<u32>,
+    /// Language codes to match, can be repeated
+    #[arg(long = "language")]
+    languages: Vec<String>,
+    /// Wikimedia domains to match, can be repeated
+    #[arg(long = "domain")]
+    domains: Vec<String>,
+    /// Project families to match, e.g. wiktionary, can be repeated
+    #[arg(long = "project")]
+    projects: Vec<Project>,
+    /// Host names of sites to match, e.g. en.m.wikipedia.org, can be repeated
+    #[arg(long = "hostname")]
+    hostnames: Vec<String>,
+    /// Only mobile (true) or desktop (false) traffic
+    #[arg(long)]
+    mobile: Option<bool>,
+    /// Language codes to exclude, can be repeated
+    #[arg(long = "exclude-language")]
+    exclude_languages: Vec<String>,
+    /// Wikimedia domains to exclude, can be repeated
+    #[arg(long = "exclude-domain")]
+    exclude_domains: Vec<String>,
+    /// Regex of page titles to exclude
+    #[arg(long, value_parser = Regex::new)]
+    exclude_page_title: Option<Regex>,
+    /// Print the rows rejected by the filters instead
+    #[arg(long)]
+    negate: bool,
+    /// File with page titles to exclude
+    #[arg(long)]
+    blocklist: Option<PathBuf>,
+    /// Namespaces to collapse into one row per domain code, can be repeated
+    #[arg(long = "rollup-namespace")]
+    rollup_namespaces: Vec<String>,
+    /// Sum rows rejected by the filters into residual rows
+    #[arg(long)]
+    residual_rows: bool,
+    /// Merge rows of mobile sites into the desktop rows with the same title
+    #[arg(long)]
+    merge_mobile: bool,
+    /// Drop the rows ranked above this by views within their domain code
+    #[arg(long)]
+    min_rank: Option<usize>,
+    /// Keep this many rows with most views of each domain code
+    #[arg(long)]
+    max_rank: Option<usize>,
+    /// Sorted file of domain codes, titles, and IDs for the `page_id` column
+    #[arg(long)]
+    page_ids: Option<PathBuf>,
+    /// Regex and replacement rewriting the page titles, e.g. `'_\(.*\)$' ''`
+    #[arg(long, num_args = 2, value_names = ["REGEX", "REPLACEMENT"])]
+    title_transform: Option<Vec<String>>,
+    /// Hours (UTC) to read files from, can be repeated
+    #[arg(long = "hour", value_parser = clap::value_parser!(u32).range(0..24))]
+    hours: Vec<u32>,
+    /// Weekdays to read files from, e.g. `mon`, can be repeated
+    #[arg(long = "weekday")]
+    weekdays: Vec<Weekday>,
+    /// Fraction of rows to keep, picked by a hash of the domain code and title
+    #[arg(long, value_parser = parse_fraction)]
+    sample: Option<f64>,
+    /// Seed picking which rows `--sample` keeps
+    #[arg(long, default_value_t = 0, requires = "sample")]
+    seed: u64,
+}
+
+/// Parses a fraction between 0 and 1.
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let fraction: f64 = value
+        .parse()
+        .map_err(|e: std::num::ParseFloatError| e.to_string())?;
+    if (0.0..=1.0).contains(&fraction) {
+        Ok(fraction)
+    } else {
+        Err("must be between 0 and 1".to_string())
+    }
+}
+
+/// Prints the number of lines and rows left out of a Parquet file.
+fn warn_skipped(report: &WriteReport) {
+    if report.rows_errored > 0 {
+        eprintln!(
+            "pvstream: skipped {} lines failing to parse",
+            report.rows_errored
+        );
+    }
+    if report.rows_dropped > 0 {
+        eprintln!(
+            "pvstream: dropped {} rows failing to convert to Arrow",
+            report.rows_dropped
+        );
+    }
+}
+
+/// Repeatable options are empty when not given, which means no filter.
+fn non_empty<T>(values: Vec<T>) -> Option<Vec<T>> {
+    (!values.is_empty()).then_some(values)
+}
+
+/// Encodings of the columns given to `--dictionary` and `--plain`.
+fn encodings(dictionary: Vec<String>, plain: Vec<String>) -> HashMap<String, ColumnEncoding> {
+    let dictionary = dictionary
+        .into_iter()
+        .map(|column| (column, ColumnEncoding::Dictionary));
+    let plain = plain
+        .into_iter()
+        .map(|column| (column, ColumnEncoding::Plain));
+    dictionary.chain(plain).collect()
+}
+
+impl FilterArgs {
+    fn into_filter(self) -> Result<Filter, Box<dyn Error>> {
+        let mut page_title_exact: Option<HashSet<String>> =
+            non_empty(self.titles).map(|titles| titles.into_iter().collect());
+        if let Some(path) = &self.titles_file {
+            page_title_exact
+                .get_or_insert_default()
+                .extend(titles_from_file(path)?);
+        }
+        Ok(Filter {
+            line_regex: self.line_regex,
+            domain_codes: non_empty(self.domain_codes),
+            page_title: self.page_title,
+            page_titles_any: non_empty(self.page_titles_any)
+                .map(RegexSet::new)
+                .transpose()?,
+            page_title_exact,
+            page_title_prefix: self.title_prefix,
+            min_views: self.min_views,
+            max_views: self.max_views,
+            languages: non_empty(self.languages),
+            domains: non_empty(self.domains),
+            projects: non_empty(self.projects),
+            hostnames: non_empty(self.hostnames),
+            mobile: self.mobile,
+            blocklist: self
+                .blocklist
+                .map(|path| Blocklist::from_file(&path))
+                .transpose()?,
+            exclude_languages: non_empty(self.exclude_languages),
+            exclude_domains: non_empty(self.exclude_domains),
+            exclude_page_title: self.exclude_page_title,
+            negate: self.negate,
+            rollup_namespaces: non_empty(self.rollup_namespaces),
+            residual_rows: self.residual_rows,
+            merge_mobile: self.merge_mobile,
+            min_rank: self.min_rank,
+            max_rank: self.max_rank,
+            page_ids: self
+                .page_ids
+                .map(|path| PageIds::from_file(&path))
+                .transpose()?,
+            title_transform: self
+                .title_transform
+                .map(|args| TitleTransform::new(&args[0], &args[1]))
+                .transpose()?,
+            hours: non_empty(self.hours),
+            weekdays: non_empty(self.weekdays),
+            sample: self.sample.map(|fraction| Sample {
+                fraction,
+                seed: self.seed,
+            }),
+            ..Default::default()
+        }
+        .with_title_matching(TitleMatching {
+            case_insensitive: self.ignore_title_case,
+            normalize_unicode: self.normalize_titles,
+        }))
+    }
+}
+
+/// Reads inputs starting with `http://`, `https://`, `s3://`, or `gs://` as
+/// URLs, the rest as paths.
+fn source_from_input(input: &str) -> Result<Source, Box<dyn Error>> {
+    if ["http://", "https://", "s3://", "gs://"]
+        .iter()
+        .any(|scheme| input.starts_with(scheme))
+    {
+        Ok(Source::Url(Url::parse(input)?))
+    } else {
+        Ok(Source::File(PathBuf::from(input)))
+    }
+}
+
+/// A timeout in whole seconds, with 0 for no timeout.
+fn seconds(seconds: u64) -> Option<Duration> {
+    (seconds > 0).then(|| Duration::from_secs(seconds))
+}
+
+/// How inputs are read, shared by all subcommands.
+struct ReadOptions {
+    config: StreamConfig,
+    cache_dir: Option<PathBuf>,
+}
+
+impl ReadOptions {
+    /// Opens an input, downloading URLs through the cache if enabled.
+    fn source(&self, input: &str) -> Result<Source, Box<dyn Error>> {
+        match (source_from_input(input)?, &self.cache_dir) {
+            (Source::Url(url), Some(cache_dir)) => {
+                Ok(Source::File(cached_file(&url, cache_dir, &self.config)?))
+            }
+            (source, _) => Ok(source),
+        }
+    }
+}
+
+/// Streams rows from all inputs, reporting lines that fail to parse.
+///
+/// Read errors can't be recovered from, and are returned to the caller, as
+/// are parse errors with `--on-error fail`.
+fn for_each_row(
+    inputs: &[String],
+    filter: &Filter,
+    read: &ReadOptions,
+    mut f: impl FnMut(Pageviews) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    for input in inputs {
+        for_each_row_of(input, filter, read, &mut f)?;
+    }
+    Ok(())
+}
+
+/// Streams rows from a single input, like `for_each_row`.
+fn for_each_row_of(
+    input: &str,
+    filter: &Filter,
+    read: &ReadOptions,
+    mut f: impl FnMut(Pageviews) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let source = read.source(input)?;
+    for row in stream_from_source(&source, filter, &read.config)? {
+        match row {
+            Ok(row) => f(row)?,
+            Err(err) if read.config.error_policy.is_fatal(&err) => return Err(err.into()),
+            Err(err) => eprintln!("pvstream: skipping line: {err}"),
+        }
+    }
+    Ok(())
+}
+
+/// Whether writing to stdout failed because it's piped into a command which
+/// has stopped reading, like `head`.
+fn is_broken_pipe(err: &(dyn Error + 'static)) -> bool {
+    err.downcast_ref::<io::Error>()
+        .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe)
+}
+
+/// How long a sitematrix saved in the cache directory is reused.
+const SITEMATRIX_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    if cli.refresh_domains {
+        let refreshed = match &cli.cache_dir {
+            Some(cache_dir) => {
+                refresh_domains_cached(&cache_dir.join("sitematrix.json"), SITEMATRIX_MAX_AGE)
+            }
+            None => refresh_domains(),
+        };
+        // The hard coded projects still work, so carry on without them.
+        if let Err(err) = refreshed {
+            eprintln!("pvstream: failed to refresh domains: {err}");
+        }
+    }
+    let error_policy = match (cli.error_file, cli.on_error) {
+        (Some(path), _) => ErrorPolicy::CollectTo(ErrorSink::file(&path)?),
+        (None, OnError::Report) => ErrorPolicy::Yield,
+        (None, OnError::Skip) => ErrorPolicy::Skip,
+        (None, OnError::Fail) => ErrorPolicy::FailFast,
+    };
+    let read = ReadOptions {
+        config: StreamConfig {
+            mirrors: cli.mirrors,
+            client: ClientConfig {
+                proxy: cli.proxy,
+                ca_bundle: cli.ca_bundle,
+                connect_timeout: seconds(cli.connect_timeout),
+                read_timeout: seconds(cli.read_timeout),
+                user_agent: cli.user_agent,
+            },
+            error_policy,
+            decode_titles: cli.decode_titles,
+            parse_mode: if cli.strict_domains {
+                ParseMode::Strict
+            } else {
+                ParseMode::Lenient
+            },
+            assume_sorted: cli.assume_sorted,
+            ..Default::default()
+        },
+        cache_dir: cli.cache_dir,
+    };
+    match cli.command {
+        Command::Filter {
+            inputs,
+            format,
+            filter,
+        } => {
+            let filter = filter.into_filter()?;
+            let mut out = BufWriter::new(stdout().lock());
+            let format = OutputFormat::from(format);
+            let printed = for_each_row(&inputs, &filter, &read, |row| {
+                writeln!(out, "{}", format.format(&row))?;
+                Ok(())
+            })
+            .and_then(|()| Ok(out.flush()?));
+            match printed {
+                Err(err) if is_broken_pipe(err.as_ref()) => {}
+                printed => printed?,
+            }
+        }
+        Command::Follow {
+            start,
+            until,
+            poll_interval,
+            format,
+            filter,
+        } => {
+            let filter = filter.into_filter()?;
+            let mirror = Mirror::default();
+            let options = FollowOptions {
+                mirror: mirror.clone(),
+                poll_interval: Duration::from_secs(poll_interval),
+                until,
+                config: read.config.clone(),
+            };
+            let mut out = BufWriter::new(stdout().lock());
+            let format = OutputFormat::from(format);
+            for hour in follow_hours(start, options) {
+                let printed = hour.map_err(Box::from).and_then(|hour| {
+                    for_each_row_of(mirror.dump_url(&hour).as_str(), &filter, &read, |row| {
+                        writeln!(out, "{}", format.format(&row))?;
+                        Ok(())
+                    })
+                });
+                // Rows are printed as soon as each hour is done, and failed
+                // polls and downloads are left behind to keep following
+                match printed.and_then(|()| Ok(out.flush()?)) {
+                    Ok(()) => {}
+                    Err(err) if is_broken_pipe(err.as_ref()) => break,
+                    Err(err)
+                        if err
+                            .downcast_ref::<ParseError>()
+                            .is_some_and(|err| !matches!(err, ParseError::ReadError(_))) =>
+                    {
+                        return Err(err);
+                    }
+                    Err(err) => eprintln!("pvstream: {err}"),
+                }
+            }
+        }
+        Command::ToParquet {
+            input,
+            output,
+            layout,
+            batch_size,
+            top_k,
+            per_language,
+            columns,
+            sort,
+            statistics,
+            bloom_filters,
+            dictionary,
+            plain,
+            skip,
+            limit,
+            filter,
+        } => {
+            let options = ParquetOptions {
+                batch_size,
+                layout: match layout {
+                    Layout::Flat => SchemaLayout::Flat,
+                    Layout::Nested => SchemaLayout::Nested,
+                },
+                top_k: top_k.map(|n| TopK { n, per_language }),
+                columns: non_empty(columns),
+                sort: sort.map(Sort::from),
+                statistics,
+                bloom_filters,
+                encodings: encodings(dictionary, plain),
+                ..Default::default()
+            };
+            let config = StreamConfig {
+                skip,
+                limit,
+                ..read.config.clone()
+            };
+            let report = parquet_from_source(
+                &read.source(&input)?,
+                output,
+                &filter.into_filter()?,
+                &config,
+                &options,
+            )?;
+            warn_skipped(&report);
+        }
+        Command::Daily {
+            date,
+            output,
+            top_k,
+            columns,
+            sort,
+            statistics,
+            bloom_filters,
+            dictionary,
+            plain,
+            filter,
+        } => {
+            let sources = pageviews_urls_for_day(date)
+                .iter()
+                .map(|url| read.source(url.as_str()))
+                .collect::<Result<Vec<_>, _>>()?;
+            let options = ParquetOptions {
+                top_k: top_k.map(TopK::global),
+                columns: non_empty(columns),
+                sort: sort.map(Sort::from),
+                statistics,
+                bloom_filters,
+                encodings: encodings(dictionary, plain),
+                ..Default::default()
+            };
+            let report = daily_parquet_from_sources(
+                &sources,
+                date,
+                output,
+                &filter.into_filter()?,
+                &read.config,
+                &options,
+            )?;
+            warn_skipped(&report);
+        }
+        Command::Aggregate {
+            inputs,
+            by,
+            agg,
+            filter,
+        } => {
+            let mut aggregation = Aggregation::new(by.into(), agg.into());
+            for_each_row(&inputs, &filter.into_filter()?, &read, |row| {
+                aggregation.push(&row)?;
+                Ok(())
+            })?;
+
+            let mut out = BufWriter::new(stdout().lock());
+            for (group, value) in aggregation.into_values() {
+                writeln!(out, "{group}\t{value}")?;
+            }
+            out.flush()?;
+        }
+        Command::Summarize { inputs, filter } => {
+            let mut summary = Summary::new();
+            for_each_row(&inputs, &filter.into_filter()?, &read, |row| {
+                summary.push(&PageviewsRef::from(&row))?;
+                Ok(())
+            })?;
+
+            let mut out = BufWriter::new(stdout().lock());
+            let languages = summary.languages.iter().map(|group| ("language", group));
+            let domains = summary.domains.iter().map(|group| ("domain", group));
+            for (field, (group, totals)) in languages.chain(domains) {
+                writeln!(out, "{field}\t{group}\t{}\t{}", totals.rows, totals.views)?;
+            }
+            let total = summary.total;
+            writeln!(out, "total\t*\t{}\t{}", total.rows, total.views)?;
+            out.flush()?;
+        }
+        Command::Index { input, output } => {
+            let index = index_from_source(&read.source(&input)?, &output, &read.config)?;
+            eprintln!(
+                "pvstream: wrote {} blocks to {}",
+                index.blocks.len(),
+                output.display()
+            );
+        }
+        Command::Top {
+            inputs,
+            n,
+            by,
+            filter,
+        } => {
+            let by = aggregate::GroupBy::from(by);
+            let mut top = TopN::new(|row: &Pageviews| by.key(row), n);
+            for_each_row(&inputs, &filter.into_filter()?, &read, |row| {
+                top.push(row);
+                Ok(())
+            })?;
+
+            let mut out = BufWriter::new(stdout().lock());
+            for (group, rows) in top.into_sorted() {
+                for row in rows {
+                    writeln!(out, "{group}\t{}\t{}", row.views, row.page_title)?;
+                }
+            }
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("pvstream: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_filter_args() {
+        let cli = Cli::parse_from([
+            "pvstream",
+            "filter",
+            "pageviews.gz",
+            "--language",
+            "en",
+            "--language",
+            "de",
+            "--weekday",
+            "sat",
+            "--mobile",
+            "true",
+            "--project",
+            "wiktionary",
+        ]);
+        let Command::Filter { inputs, filter, .. } = cli.command else {
+            panic!("expected the filter command");
+        };
+        let filter = filter.into_filter().unwrap();
+
+        assert_eq!(inputs, ["pageviews.gz"]);
+        assert_eq!(filter.languages, Some(vec!["en".into(), "de".into()]));
+        assert_eq!(filter.weekdays, Some(vec![Weekday::Sat]));
+        assert_eq!(filter.mobile, Some(true));
+        assert_eq!(filter.domains, None);
+        assert_eq!(filter.projects, Some(vec![Project::Wiktionary]));
+    }
+}
@@ -0,0 +1,371 @@
+//! Local cache of downloaded pageviews files.
+//!
+//! Streaming the same hour over and over, e.g. while tuning filters, would
+//! download the same file every time. The cache stores each file on disk,
+//! keyed by its URL, along with the `ETag` and `Last-Modified` headers sent
+//! by the server. Later runs check the file with a conditional `HEAD` request,
+//! and only download it again if it has changed, or keep using it if the
+//! server can't be reached.
+
+use crate::filter::Filter;
+use crate::stream::{
+    Source, StreamConfig, StreamError, http_head, http_to_file_with_headers, is_retryable,
+};
+use crate::{RowIterator, stream_from_source};
+use reqwest::StatusCode;
+use reqwest::blocking::Response;
+use reqwest::header::{
+    ETAG, HeaderMap, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use serde_json::{Value, json};
+use std::fs::{self, create_dir_all, rename};
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Path of the cached copy of `url` in `cache_dir`.
+///
+/// Files are stored under the host and path of the URL, keeping the file
+/// name, so timestamps can still be parsed from it. Query strings are
+/// appended to the file name with unsafe characters replaced.
+pub fn cache_path(url: &Url, cache_dir: &Path) -> PathBuf {
+    let mut path = cache_dir.join(url.host_str().unwrap_or("_"));
+    for segment in url.path_segments().into_iter().flatten() {
+        if !segment.is_empty() && segment != "." && segment != ".." {
+            path.push(segment);
+        }
+    }
+    if let Some(query) = url.query() {
+        let query: String = query
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        path.as_mut_os_string().push(format!("_{query}"));
+    }
+    path
+}
+
+/// Path of the file storing the validators of a cached file.
+fn meta_path(path: &Path) -> PathBuf {
+    let mut meta = path.as_os_str().to_owned();
+    meta.push(".meta.json");
+    PathBuf::from(meta)
+}
+
+/// Returns a local copy of the file at `url`, downloading it if needed.
+///
+/// A cached copy is validated with a conditional `HEAD` request, using the
+/// `ETag` and `Last-Modified` headers from when it was downloaded, with the
+/// retries and mirrors of `config`. If the server responds with
+/// `304 Not Modified`, the copy is used as is. If not, or if there is no copy
+/// yet, the file is downloaded following `config`, storing the validators of
+/// the download. A copy is also used as is when the check fails with an
+/// error that might be temporary, e.g. when offline or on a server error.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::cache::cached_file;
+/// use pvstream::stream::StreamConfig;
+/// use std::path::Path;
+/// use url::Url;
+///
+/// let url = Url::parse("https://dumps.wikimedia.org/other/pageviews/2024/2024-08/pageviews-20240818-080000.gz")?;
+/// let path = cached_file(&url, Path::new("cache"), &StreamConfig::default())?;
+/// println!("{}", path.display());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn cached_file(
+    url: &Url,
+    cache_dir: &Path,
+    config: &StreamConfig,
+) -> Result<PathBuf, StreamError> {
+    let path = cache_path(url, cache_dir);
+    let meta = meta_path(&path);
+    let validators = read_validators(&path, &meta);
+
+    if let Some(validators) = &validators {
+        match head(url, validators, config) {
+            Ok(response) if response.status() == StatusCode::NOT_MODIFIED => return Ok(path),
+            Err(err) if is_retryable(&err) => return Ok(path),
+            // Changed, or the server doesn't answer `HEAD`, so download it
+            Ok(_) | Err(_) => {}
+        }
+    }
+
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    // Download next to the cached copy, so a failed download never replaces it
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    let headers = http_to_file_with_headers(url, &partial, config)?;
+    rename(&partial, &path)?;
+    fs::write(&meta, validators_from(&headers).to_string())?;
+
+    Ok(path)
+}
+
+/// Reads the validators of a cached file, if both the file and its
+/// validators exist.
+fn read_validators(path: &Path, meta: &Path) -> Option<Value> {
+    if !path.is_file() {
+        return None;
+    }
+    serde_json::from_slice(&fs::read(meta).ok()?).ok()
+}
+
+/// Picks the validators to store from the response headers.
+fn validators_from(headers: &HeaderMap) -> Value {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    json!({
+        "etag": header(ETAG),
+        "last_modified": header(LAST_MODIFIED),
+    })
+}
+
+/// Sends a `HEAD` request conditional on `validators`, with the retries and
+/// mirrors of `config`.
+fn head(url: &Url, validators: &Value, config: &StreamConfig) -> Result<Response, StreamError> {
+    let mut headers = HeaderMap::new();
+    for (name, key) in [
+        (IF_NONE_MATCH, "etag"),
+        (IF_MODIFIED_SINCE, "last_modified"),
+    ] {
+        if let Some(value) = validators[key].as_str()
+            && let Ok(value) = HeaderValue::from_str(value)
+        {
+            headers.insert(name, value);
+        }
+    }
+    http_head(url, headers, config)
+}
+
+/// Stream, parse, and filter a remote pageviews file through a local cache.
+///
+/// Same as `stream_from_url`, but the file is stored in `cache_dir` and
+/// reused on later runs as long as it's unchanged on the server. See
+/// `cached_file` for details.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::cache::stream_from_url_cached;
+/// use pvstream::filter::FilterBuilder;
+/// use std::path::Path;
+/// use url::Url;
+///
+/// let url = Url::parse("https://dumps.wikimedia.org/other/pageviews/2024/2024-08/pageviews-20240818-080000.gz")?;
+/// let filter = FilterBuilder::new().languages(["nn"]).build();
+///
+/// for row in stream_from_url_cached(&url, Path::new("cache"), &filter)? {
+///     println!("{:?}", row?);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn stream_from_url_cached(
+    url: &Url,
+    cache_dir: &Path,
+    filter: &Filter,
+) -> Result<RowIterator, StreamError> {
+    let config = StreamConfig::default();
+    let path = cached_file(url, cache_dir, &config)?;
+    stream_from_source(&Source::File(path), filter, &config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+    use crate::urls::Mirror;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread::{JoinHandle, spawn};
+    use std::time::Duration;
+
+    /// Serves `data` with an `ETag`, answering conditional requests with
+    /// `304 Not Modified`. Like some servers, the `ETag` is left out of
+    /// `HEAD` responses for the full file.
+    ///
+    /// Returns the URL of the file and the methods of the requests received.
+    fn caching_server(data: Vec<u8>, connections: usize) -> (Url, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/other/pageviews-20240803-060000.gz",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+
+        let handle = spawn(move || {
+            let mut requests = Vec::new();
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                for line in BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    request.push(line);
+                }
+                let method = request[0].split(' ').next().unwrap().to_string();
+                let fresh = request.iter().any(|line| line == "if-none-match: \"v1\"");
+
+                let head = if fresh {
+                    "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n".to_string()
+                } else if method == "GET" {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nETag: \"v1\"\r\n",
+                        data.len()
+                    )
+                } else {
+                    format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n", data.len())
+                };
+                write!(stream, "{head}Connection: close\r\n\r\n").unwrap();
+                if method == "GET" {
+                    stream.write_all(&data).unwrap();
+                }
+                requests.push(if fresh {
+                    format!("{method} 304")
+                } else {
+                    method
+                });
+            }
+            requests
+        });
+
+        (url, handle)
+    }
+
+    /// Answers every request with an empty response with `status`.
+    fn status_server(status: &'static str, connections: usize) -> (Url, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/other/pageviews-20240803-060000.gz",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        let handle = spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                for line in BufReader::new(&stream).lines() {
+                    if line.unwrap().is_empty() {
+                        break;
+                    }
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                )
+                .unwrap();
+            }
+        });
+        (url, handle)
+    }
+
+    /// Stores `data` as the cached copy of `url`, with the `ETag` of
+    /// `caching_server`.
+    fn write_cached(url: &Url, cache_dir: &Path, data: &[u8]) -> PathBuf {
+        let path = cache_path(url, cache_dir);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, data).unwrap();
+        fs::write(
+            meta_path(&path),
+            r#"{"etag":"\"v1\"","last_modified":null}"#,
+        )
+        .unwrap();
+        path
+    }
+
+    /// A config without waiting between retries.
+    fn no_backoff() -> StreamConfig {
+        StreamConfig {
+            retry_backoff: Duration::ZERO,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cache_path() {
+        let url = Url::parse("https://example.org/a/../b/pageviews.gz?x=1&y=2").unwrap();
+        assert_eq!(
+            cache_path(&url, Path::new("cache")),
+            Path::new("cache/example.org/b/pageviews.gz_x_1_y_2")
+        );
+    }
+
+    #[test]
+    fn test_cached_file() {
+        let data = fs::read("tests/files/pageviews-20240803-060000.gz").unwrap();
+        let dir = TestDir::new("cached-file");
+        let cache_dir = dir.path();
+
+        // First run: download only. Second run: conditional HEAD only.
+        let (url, server) = caching_server(data.clone(), 2);
+        let filter = Filter::default();
+        let first = stream_from_url_cached(&url, cache_dir, &filter)
+            .unwrap()
+            .count();
+        let path = cached_file(&url, cache_dir, &StreamConfig::default()).unwrap();
+
+        assert_eq!(first, 1000);
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert_eq!(path.file_name().unwrap(), "pageviews-20240803-060000.gz");
+        assert_eq!(server.join().unwrap(), ["GET", "HEAD 304"]);
+
+        // The server is gone, the cached copy is used as is
+        let offline = cached_file(&url, cache_dir, &no_backoff()).unwrap();
+        assert_eq!(offline, path);
+    }
+
+    #[test]
+    fn test_cached_file_server_error() {
+        let data = fs::read("tests/files/pageviews-20240803-060000.gz").unwrap();
+        let dir = TestDir::new("cached-file-server-error");
+        let cache_dir = dir.path();
+        let config = StreamConfig {
+            retries: 0,
+            ..Default::default()
+        };
+
+        // Without a cached copy, the error of the download is returned
+        let (url, server) = status_server("503 Service Unavailable", 2);
+        assert!(cached_file(&url, cache_dir, &config).is_err());
+
+        // With one, it's used as is
+        let path = write_cached(&url, cache_dir, &data);
+        assert_eq!(cached_file(&url, cache_dir, &config).unwrap(), path);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_cached_file_mirrors() {
+        let data = fs::read("tests/files/pageviews-20240803-060000.gz").unwrap();
+        let dir = TestDir::new("cached-file-mirrors");
+        let cache_dir = dir.path();
+
+        // The check fails over to the mirror like a download
+        let (primary, primary_server) = status_server("503 Service Unavailable", 2);
+        let (secondary, secondary_server) = caching_server(data.clone(), 1);
+        let base = |url: &Url| Mirror::new(url.join("/").unwrap().as_str()).unwrap();
+        let config = StreamConfig {
+            retries: 1,
+            mirrors: vec![base(&primary), base(&secondary)],
+            ..no_backoff()
+        };
+        let path = write_cached(&primary, cache_dir, &data);
+        assert_eq!(cached_file(&primary, cache_dir, &config).unwrap(), path);
+        primary_server.join().unwrap();
+        assert_eq!(secondary_server.join().unwrap(), ["HEAD 304"]);
+
+        // A server refusing `HEAD` gets a download instead, refused here too
+        let (url, server) = status_server("405 Method Not Allowed", 2);
+        write_cached(&url, cache_dir, &data);
+        let err = cached_file(&url, cache_dir, &config).unwrap_err();
+        let StreamError::Http(err) = err else {
+            panic!("expected an HTTP error, got {err:?}");
+        };
+        assert_eq!(err.status(), Some(StatusCode::METHOD_NOT_ALLOWED));
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+//! Cancelling long running streams and exports.
+//!
+//! Set `StreamConfig::cancel` to a `CancellationToken`, and call
+//! `CancellationToken::cancel` from any thread to stop reading, e.g. when a
+//! user presses a stop button. Streams end with a read error wrapping
+//! `Cancelled`, and functions writing files return `StreamError::Cancelled`,
+//! closing the file or HTTP connection right away.
+
+use crate::stream::LineReader;
+use std::io::{Error as IoError, Read};
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+
+/// A stream was stopped by its `CancellationToken`.
+///
+/// While streaming, this error is returned wrapped in an `std::io::Error`,
+/// which can be downcast with `get_ref()` if you need to tell it apart.
+#[derive(Debug, Error)]
+#[error("cancelled")]
+pub struct Cancelled;
+
+#[derive(Default)]
+struct Flag {
+    cancelled: AtomicBool,
+    lock: Mutex<()>,
+    wake: Condvar,
+}
+
+/// Handle to stop the streams it's passed to, through `StreamConfig::cancel`.
+///
+/// Cheap to clone, all clones cancel the same streams. The token is checked
+/// for every line read, so streams stop within a line of being cancelled,
+/// unless they're waiting for the network, which is bounded by
+/// `ClientConfig::read_timeout`. Once cancelled, a token stays cancelled.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::cancel::CancellationToken;
+/// use pvstream::stream::{Source, StreamConfig, StreamError};
+/// use pvstream::{filter::FilterBuilder, parquet_from_source};
+/// use std::path::PathBuf;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// let cancel = CancellationToken::new();
+/// let config = StreamConfig {
+///     cancel: Some(cancel.clone()),
+///     ..Default::default()
+/// };
+/// thread::spawn(move || {
+///     thread::sleep(Duration::from_secs(60));
+///     cancel.cancel();
+/// });
+///
+/// let result = parquet_from_source(
+///     &Source::File(PathBuf::from("pageviews-20240818-080000.gz")),
+///     PathBuf::from("output.parquet"),
+///     &FilterBuilder::new().build(),
+///     &config,
+///     &Default::default(),
+/// );
+/// if let Err(StreamError::Cancelled(_)) = result {
+///     eprintln!("export took more than a minute");
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<Flag>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops every stream using this token, and wakes any waiting to poll.
+    pub fn cancel(&self) {
+        let _guard = self.0.lock.lock().unwrap();
+        self.0.cancelled.store(true, Relaxed);
+        self.0.wake.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Relaxed)
+    }
+
+    /// Sleeps for `duration`, or until cancelled. Returns whether the token
+    /// was cancelled.
+    pub(crate) fn sleep(&self, duration: Duration) -> bool {
+        let guard = self.0.lock.lock().unwrap();
+        let _ = self
+            .0
+            .wake
+            .wait_timeout_while(guard, duration, |_| !self.is_cancelled())
+            .unwrap();
+        self.is_cancelled()
+    }
+}
+
+impl std::fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CancellationToken")
+            .field(&self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Sleeps for `duration`, or until `cancel` is cancelled. Returns whether it
+/// was cancelled.
+pub(crate) fn sleep(cancel: Option<&CancellationToken>, duration: Duration) -> bool {
+    match cancel {
+        Some(cancel) => cancel.sleep(duration),
+        None => {
+            std::thread::sleep(duration);
+            false
+        }
+    }
+}
+
+/// Ends `lines` with a `Cancelled` error once `cancel` is cancelled.
+///
+/// The lines are dropped along with the error, closing the file or HTTP
+/// connection.
+pub(crate) fn cancellable_lines(
+    lines: LineReader,
+    cancel: Option<&CancellationToken>,
+) -> LineReader {
+    let Some(cancel) = cancel.cloned() else {
+        return lines;
+    };
+    let mut lines = Some(lines);
+    Box::new(std::iter::from_fn(move || {
+        if cancel.is_cancelled() {
+            drop(lines.take()?);
+            return Some(Err(IoError::other(Cancelled)));
+        }
+        lines.as_mut()?.next()
+    }))
+}
+
+/// Reader returning a `Cancelled` error once the token is cancelled.
+pub(crate) struct CancellableReader<R> {
+    inner: R,
+    cancel: Option<CancellationToken>,
+}
+
+impl<R> CancellableReader<R> {
+    pub(crate) fn new(inner: R, cancel: Option<&CancellationToken>) -> Self {
+        Self {
+            inner,
+            cancel: cancel.cloned(),
+        }
+    }
+}
+
+impl<R: Read> Read for CancellableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        if self
+            .cancel
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Err(IoError::other(Cancelled));
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+    use crate::stream::{Source, StreamConfig, StreamError};
+    use crate::testing::TestDir;
+    use crate::{ParquetOptions, parquet_from_source, stream_from_source};
+    use std::path::PathBuf;
+    use std::time::Instant;
+
+    #[test]
+    fn test_cancel_stream() {
+        let source = Source::File(PathBuf::from("tests/files/pageviews-20240803-060000.gz"));
+        let cancel = CancellationToken::new();
+        let config = StreamConfig {
+            cancel: Some(cancel.clone()),
+            ..Default::default()
+        };
+        let mut rows = stream_from_source(&source, &FilterBuilder::new().build(), &config).unwrap();
+
+        assert!(rows.nth(9).unwrap().is_ok());
+        cancel.cancel();
+        let err = rows.next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("cancelled"));
+        assert!(rows.next().is_none());
+
+        let dir = TestDir::new("cancel");
+        let output = dir.join("pageviews.parquet");
+        let result = parquet_from_source(
+            &source,
+            output.clone(),
+            &FilterBuilder::new().build(),
+            &config,
+            &ParquetOptions::default(),
+        );
+        assert!(matches!(result, Err(StreamError::Cancelled(_))));
+    }
+
+    #[test]
+    fn test_cancel_sleep() {
+        let cancel = CancellationToken::new();
+        assert!(!cancel.sleep(Duration::from_millis(1)));
+
+        let started = Instant::now();
+        let waker = cancel.clone();
+        let handle = std::thread::spawn(move || waker.cancel());
+        assert!(cancel.sleep(Duration::from_secs(60)));
+        assert!(started.elapsed() < Duration::from_secs(60));
+        handle.join().unwrap();
+
+        let mut reader = CancellableReader::new(&b"abc"[..], Some(&cancel));
+        assert!(reader.read(&mut [0; 3]).is_err());
+    }
+}
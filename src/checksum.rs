@@ -0,0 +1,359 @@
+//! Verification of downloaded files against checksums.
+//!
+//! Wikimedia publishes `md5sums.txt` and `sha1sums.txt` listings next to the
+//! pageviews files of each month. `http_to_file` and `cache::cached_file`
+//! hash files as they're downloaded, and compare the digest to the listing,
+//! or to a digest of your own, following `StreamConfig::checksum`. A file
+//! which doesn't match is removed, and `StreamError::ChecksumMismatch` is
+//! returned, so a corrupted download never ends up in a Parquet file.
+
+use crate::stream::{StreamConfig, StreamError};
+use crate::urls::Mirror;
+use md5::{Digest, Md5};
+use reqwest::StatusCode;
+use sha1::Sha1;
+use std::fmt;
+use std::io::{self, Write};
+use thiserror::Error;
+use url::Url;
+
+/// Hash function of a checksum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+}
+
+impl Algorithm {
+    /// Name of the listing published next to the files.
+    fn listing(self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "md5sums.txt",
+            Algorithm::Sha1 => "sha1sums.txt",
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Algorithm::Md5 => write!(f, "md5"),
+            Algorithm::Sha1 => write!(f, "sha1"),
+        }
+    }
+}
+
+/// Expected digest of a file, in hexadecimal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: Algorithm,
+    /// Lowercase hexadecimal digest.
+    pub digest: String,
+}
+
+impl Checksum {
+    /// An MD5 digest, e.g. from `md5sum`.
+    pub fn md5(digest: &str) -> Self {
+        Self {
+            algorithm: Algorithm::Md5,
+            digest: digest.trim().to_ascii_lowercase(),
+        }
+    }
+
+    /// A SHA-1 digest, e.g. from `sha1sum`.
+    pub fn sha1(digest: &str) -> Self {
+        Self {
+            algorithm: Algorithm::Sha1,
+            digest: digest.trim().to_ascii_lowercase(),
+        }
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.digest)
+    }
+}
+
+/// How downloaded files are verified, see `StreamConfig::checksum`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumPolicy {
+    /// Verify files on Wikimedia's servers, or on one of the mirrors in
+    /// `StreamConfig::mirrors`, with the checksums published next to them.
+    /// Files from anywhere else, or missing from the listings, are not
+    /// verified.
+    #[default]
+    Published,
+    /// Verify the file against this checksum. As it's the checksum of one
+    /// file, the config should only be used to download that file.
+    Expected(Checksum),
+    /// Don't verify files.
+    Skip,
+}
+
+/// A downloaded file didn't match its checksum.
+#[derive(Debug, Error)]
+#[error("{} checksum mismatch for {url}: expected {}, got {actual}", expected.algorithm, expected.digest)]
+pub struct ChecksumMismatch {
+    pub url: Url,
+    pub expected: Checksum,
+    /// Digest of the downloaded file, in hexadecimal.
+    pub actual: String,
+}
+
+/// Looks up the checksum of a file in the listings published next to it.
+///
+/// Only files on Wikimedia's servers and the mirrors in `config` are looked
+/// up, trying `md5sums.txt` first and then `sha1sums.txt`. Returns `None`
+/// if there are no listings, or the file isn't in them.
+pub fn published_checksum(
+    url: &Url,
+    config: &StreamConfig,
+) -> Result<Option<Checksum>, StreamError> {
+    let on_mirror = std::iter::once(&Mirror::default())
+        .chain(&config.mirrors)
+        .any(|mirror| mirror.relative_path(url).is_some());
+    let Some(file_name) = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+    else {
+        return Ok(None);
+    };
+    if !on_mirror || file_name.is_empty() {
+        return Ok(None);
+    }
+
+    let client = config.client.build()?;
+    for algorithm in [Algorithm::Md5, Algorithm::Sha1] {
+        let response = client.get(url.join(algorithm.listing())?).send()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            continue;
+        }
+        let listing = response.error_for_status()?.text()?;
+        if let Some(digest) = find_digest(&listing, file_name) {
+            return Ok(Some(Checksum {
+                algorithm,
+                digest: digest.to_ascii_lowercase(),
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// Finds the digest of a file in a listing in the `md5sum` format, with a
+/// digest and a file name on each line.
+fn find_digest<'a>(listing: &'a str, file_name: &str) -> Option<&'a str> {
+    listing.lines().find_map(|line| {
+        let (digest, name) = line.trim().split_once(char::is_whitespace)?;
+        // `*` marks files hashed in binary mode
+        let name = name.trim_start();
+        (name.strip_prefix('*').unwrap_or(name) == file_name).then_some(digest)
+    })
+}
+
+/// The checksum to verify a download of `url` against, if any.
+pub(crate) fn expected_checksum(
+    url: &Url,
+    config: &StreamConfig,
+) -> Result<Option<Checksum>, StreamError> {
+    match &config.checksum {
+        ChecksumPolicy::Published => published_checksum(url, config),
+        ChecksumPolicy::Expected(checksum) => Ok(Some(checksum.clone())),
+        ChecksumPolicy::Skip => Ok(None),
+    }
+}
+
+/// State of a hash function.
+enum Hasher {
+    Md5(Md5),
+    Sha1(Sha1),
+}
+
+/// Writer hashing the bytes written through it.
+pub(crate) struct HashingWriter<W> {
+    inner: W,
+    hasher: Option<Hasher>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    /// Hashes with `algorithm`, or passes the bytes through if `None`.
+    pub(crate) fn new(inner: W, algorithm: Option<Algorithm>) -> Self {
+        let hasher = algorithm.map(|algorithm| match algorithm {
+            Algorithm::Md5 => Hasher::Md5(Md5::new()),
+            Algorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+        });
+        Self { inner, hasher }
+    }
+
+    /// Checks the digest of the bytes written against `expected`.
+    pub(crate) fn verify(self, url: &Url, expected: &Checksum) -> Result<W, StreamError> {
+        let actual = match self.hasher {
+            Some(Hasher::Md5(hasher)) => format!("{:x}", hasher.finalize()),
+            Some(Hasher::Sha1(hasher)) => format!("{:x}", hasher.finalize()),
+            None => String::new(),
+        };
+        if actual != expected.digest {
+            return Err(ChecksumMismatch {
+                url: url.clone(),
+                expected: expected.clone(),
+                actual,
+            }
+            .into());
+        }
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        match &mut self.hasher {
+            Some(Hasher::Md5(hasher)) => hasher.update(&buf[..n]),
+            Some(Hasher::Sha1(hasher)) => hasher.update(&buf[..n]),
+            None => {}
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_find_digest() {
+        let listing = "\
+            9e107d9d372bb6826bd81d3542a419d6  pageviews-20240818-070000.gz\n\
+            E4D909C290D0FB1CA068FFADDF22CBD0 *pageviews-20240818-080000.gz\n";
+        assert_eq!(
+            find_digest(listing, "pageviews-20240818-070000.gz"),
+            Some("9e107d9d372bb6826bd81d3542a419d6")
+        );
+        assert_eq!(
+            find_digest(listing, "pageviews-20240818-080000.gz"),
+            Some("E4D909C290D0FB1CA068FFADDF22CBD0")
+        );
+        assert_eq!(find_digest(listing, "pageviews-20240818-090000.gz"), None);
+    }
+
+    #[test]
+    fn test_hashing_writer() {
+        let url = Url::parse("https://example.org/file").unwrap();
+        let md5 = Checksum::md5("9E107D9D372BB6826BD81D3542A419D6");
+        let sha1 = Checksum::sha1("2fd4e1c67a2d28fced849ee1bb76e7391b93eb12");
+        let text = b"The quick brown fox jumps over the lazy dog";
+
+        for checksum in [&md5, &sha1] {
+            let mut writer = HashingWriter::new(Vec::new(), Some(checksum.algorithm));
+            writer.write_all(text).unwrap();
+            assert_eq!(writer.verify(&url, checksum).unwrap(), text);
+        }
+
+        let mut writer = HashingWriter::new(Vec::new(), Some(Algorithm::Md5));
+        writer.write_all(&text[1..]).unwrap();
+        let Err(StreamError::ChecksumMismatch(err)) = writer.verify(&url, &md5) else {
+            panic!("expected a checksum mismatch");
+        };
+        assert_eq!(err.expected, md5);
+        assert_ne!(err.actual, md5.digest);
+    }
+
+    /// Serves a file and an `md5sums.txt` listing on a mirror, with
+    /// `404 Not Found` for anything else.
+    fn mirror_server(data: Vec<u8>, listing: String, connections: usize) -> Mirror {
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}/pageviews/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                BufReader::new(&stream).read_line(&mut request).unwrap();
+                let path = request.split(' ').nth(1).unwrap().to_string();
+                let body = match path.rsplit('/').next().unwrap() {
+                    "md5sums.txt" => Some(listing.as_bytes()),
+                    "pageviews-20240803-060000.gz" => Some(&data[..]),
+                    _ => None,
+                };
+                let status = if body.is_some() {
+                    "200 OK"
+                } else {
+                    "404 Not Found"
+                };
+                let body = body.unwrap_or_default();
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+        Mirror::new(&base).unwrap()
+    }
+
+    #[test]
+    fn test_verified_download() {
+        use crate::stream::http_to_file_with_config;
+
+        let data = std::fs::read("tests/files/pageviews-20240803-060000.gz").unwrap();
+        let digest = format!("{:x}", Md5::digest(&data));
+        let dir = TestDir::new("verified-download");
+        let path = dir.join("pageviews-20240803-060000.gz");
+
+        for (listed, valid) in [(&digest[..], true), ("0123456789abcdef", false)] {
+            let listing = format!("{listed}  pageviews-20240803-060000.gz\n");
+            let mirror = mirror_server(data.clone(), listing, 3);
+            let url = mirror
+                .base()
+                .join("2024/2024-08/pageviews-20240803-060000.gz")
+                .unwrap();
+            let config = StreamConfig {
+                mirrors: vec![mirror],
+                ..Default::default()
+            };
+            assert_eq!(
+                published_checksum(&url, &config).unwrap(),
+                Some(Checksum::md5(listed))
+            );
+            let result = http_to_file_with_config(&url, &path, &config);
+            if valid {
+                result.unwrap();
+                assert_eq!(std::fs::read(&path).unwrap(), data);
+            } else {
+                let Err(StreamError::ChecksumMismatch(err)) = result else {
+                    panic!("expected a checksum mismatch");
+                };
+                assert_eq!(err.actual, digest);
+                assert!(!path.exists());
+            }
+        }
+
+        // A digest of your own, with the listing missing
+        let mirror = mirror_server(data, String::new(), 1);
+        let url = mirror.base().join("pageviews-20240803-060000.gz").unwrap();
+        let config = StreamConfig {
+            checksum: ChecksumPolicy::Expected(Checksum::md5(&digest)),
+            ..Default::default()
+        };
+        http_to_file_with_config(&url, &path, &config).unwrap();
+    }
+
+    #[test]
+    fn test_published_checksum_elsewhere() {
+        // Files which aren't on Wikimedia's servers or a mirror aren't looked up
+        let url = Url::parse("http://127.0.0.1:1/pageviews-20240818-080000.gz").unwrap();
+        assert_eq!(
+            published_checksum(&url, &StreamConfig::default()).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! Reading and writing objects in S3 and Google Cloud Storage.
+//!
+//! Requires the `cloud` feature. `s3://bucket/key` and `gs://bucket/key`
+//! URLs can then be used as a `Source::Url`, and as the path of Parquet
+//! files, which are uploaded as they're written without a local copy.
+//!
+//! Credentials and the region are read from the usual environment variables,
+//! like `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, and `AWS_REGION` for S3,
+//! and `GOOGLE_APPLICATION_CREDENTIALS` or `GOOGLE_SERVICE_ACCOUNT` for GCS.
+
+use bytes::Bytes;
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use object_store::ObjectStore;
+use object_store::aws::AmazonS3Builder;
+use object_store::buffered::BufWriter;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path as ObjectPath;
+use object_store::path::PathPart;
+use std::io::{Error as IoError, ErrorKind, Read, Write};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::{Builder, Runtime};
+use url::Url;
+
+/// Opens the store of a bucket and the path of an object in it.
+fn open_store(url: &Url) -> Result<(Arc<dyn ObjectStore>, ObjectPath), IoError> {
+    let store: Arc<dyn ObjectStore> = match url.scheme() {
+        "s3" => Arc::new(
+            AmazonS3Builder::from_env()
+                .with_url(url.as_str())
+                .build()
+                .map_err(IoError::other)?,
+        ),
+        "gs" => Arc::new(
+            GoogleCloudStorageBuilder::from_env()
+                .with_url(url.as_str())
+                .build()
+                .map_err(IoError::other)?,
+        ),
+        scheme => {
+            return Err(IoError::new(
+                ErrorKind::Unsupported,
+                format!("unsupported object store scheme `{scheme}`"),
+            ));
+        }
+    };
+    Ok((store, object_path(url)?))
+}
+
+/// Path of an object within its bucket, with percent-encoded characters of
+/// the URL decoded.
+fn object_path(url: &Url) -> Result<ObjectPath, IoError> {
+    let parts = url
+        .path_segments()
+        .into_iter()
+        .flatten()
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            percent_encoding::percent_decode_str(segment)
+                .decode_utf8()
+                .map(|segment| PathPart::from(segment.into_owned()))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| IoError::new(ErrorKind::InvalidInput, err))?;
+    if parts.is_empty() {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!("no object key in `{url}`"),
+        ));
+    }
+    Ok(ObjectPath::from_iter(parts))
+}
+
+/// A single threaded runtime, driving the requests of one reader or writer.
+fn runtime() -> Result<Runtime, IoError> {
+    Builder::new_current_thread().enable_all().build()
+}
+
+/// Reads an object as a stream of chunks, blocking on each chunk.
+pub(crate) struct ObjectReader {
+    runtime: Runtime,
+    stream: BoxStream<'static, object_store::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl ObjectReader {
+    /// Starts reading the object at `url`, returning its size as well.
+    pub(crate) fn open(url: &Url) -> Result<(Self, u64), IoError> {
+        let (store, path) = open_store(url)?;
+        Self::open_in(store, &path)
+    }
+
+    fn open_in(store: Arc<dyn ObjectStore>, path: &ObjectPath) -> Result<(Self, u64), IoError> {
+        let runtime = runtime()?;
+        let result = runtime
+            .block_on(store.get(path))
+            .map_err(object_store_error)?;
+        let size = result.meta.size;
+        let reader = Self {
+            runtime,
+            stream: result.into_stream(),
+            chunk: Bytes::new(),
+        };
+        Ok((reader, size))
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.runtime.block_on(self.stream.next()) {
+                Some(chunk) => self.chunk = chunk.map_err(object_store_error)?,
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+        Ok(len)
+    }
+}
+
+/// Uploads an object as it's written, in parts once it grows large.
+///
+/// The object is only created once the writer is closed, so an object left
+/// unfinished by an error is never seen in its bucket.
+pub(crate) struct ObjectWriter {
+    runtime: Runtime,
+    writer: BufWriter,
+}
+
+impl ObjectWriter {
+    /// Starts writing the object at `url`, replacing it if it exists.
+    pub(crate) fn create(url: &Url) -> Result<Self, IoError> {
+        let (store, path) = open_store(url)?;
+        Self::create_in(store, path)
+    }
+
+    fn create_in(store: Arc<dyn ObjectStore>, path: ObjectPath) -> Result<Self, IoError> {
+        Ok(Self {
+            runtime: runtime()?,
+            writer: BufWriter::new(store, path),
+        })
+    }
+
+    /// Completes the upload, which makes the object visible.
+    pub(crate) fn close(mut self) -> Result<(), IoError> {
+        self.runtime.block_on(self.writer.shutdown())
+    }
+}
+
+impl Write for ObjectWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.runtime.block_on(self.writer.write(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        // Parts are uploaded once they're large enough, and the rest on close
+        Ok(())
+    }
+}
+
+/// Maps a missing object to `ErrorKind::NotFound`, like a missing file.
+fn object_store_error(err: object_store::Error) -> IoError {
+    match err {
+        object_store::Error::NotFound { .. } => IoError::new(ErrorKind::NotFound, err),
+        err => IoError::other(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn test_object_path() {
+        let url = Url::parse("s3://bucket/dumps/2024/pageviews%20x.gz").unwrap();
+        assert_eq!(
+            object_path(&url).unwrap().as_ref(),
+            "dumps/2024/pageviews x.gz"
+        );
+        assert!(object_path(&Url::parse("gs://bucket/").unwrap()).is_err());
+        assert_eq!(
+            open_store(&Url::parse("ftp://bucket/key").unwrap())
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let path = ObjectPath::from("dir/object");
+        let data: Vec<u8> = (0..100_000u32).flat_map(u32::to_le_bytes).collect();
+
+        let mut writer = ObjectWriter::create_in(store.clone(), path.clone()).unwrap();
+        for chunk in data.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        let missing = ObjectReader::open_in(store.clone(), &path);
+        assert_eq!(missing.err().unwrap().kind(), ErrorKind::NotFound);
+        writer.close().unwrap();
+
+        let (mut reader, size) = ObjectReader::open_in(store, &path).unwrap();
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(read, data);
+    }
+}
@@ -0,0 +1,355 @@
+//! Daily and monthly `pageview_complete` dumps.
+//!
+//! The [pageview_complete](https://dumps.wikimedia.org/other/pageview_complete/readme.html)
+//! dataset sums up the hourly pageviews per day (or month), split in one file
+//! per agent type, e.g. `pageviews-20240818-user.bz2`. Each line has six space
+//! separated columns:
+//!
+//! ```text
+//! en.wikipedia Main_Page 15580374 desktop 3085231 A120934B118723C113456...
+//! ```
+//!
+//! These are the wiki code, page title, page id (`null` if unknown), access
+//! method (`desktop`, `mobile-web`, or `mobile-app`), total views, and the
+//! views per hour in the format described in `parse::parse_hourly_views`.
+//!
+//! Rows are parsed into the same `Pageviews` struct as the hourly files, with
+//! the domain code translated from the wiki code, so the usual `Filter` works
+//! on them. `Filter::agents` picks the agent types to read, based on the file
+//! name, e.g. to only read the `user` files for numbers without bots.
+
+use crate::filter::Filter;
+use crate::parse::{
+    DomainCode, Pageviews, ParseError, decode_page_title, domain_code_from_wiki_code, invalid,
+    missing, normalize_string, parse_domain_code, parse_hourly_views,
+};
+use crate::progress::Tracker;
+use crate::stream::{Source, StreamConfig, StreamError};
+use crate::track_emitted;
+use chrono::{DateTime, NaiveDate, Utc};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use url::Url;
+
+/// Iterator type returned by the `complete_from_` functions.
+pub type CompleteIterator =
+    Box<dyn Iterator<Item = Result<CompleteRow, ParseError>> + Send + 'static>;
+
+/// Kind of client behind the views, as classified by Wikimedia.
+///
+/// The dumps have one file for each agent type. `User` is what most analyses
+/// need, while `Spider` is self-identified crawlers and `Automated` is traffic
+/// classified as bots by Wikimedia's heuristics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AgentType {
+    User,
+    Spider,
+    Automated,
+}
+
+impl AgentType {
+    /// Name of the agent type, as used in the file names.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AgentType::User => "user",
+            AgentType::Spider => "spider",
+            AgentType::Automated => "automated",
+        }
+    }
+}
+
+impl fmt::Display for AgentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AgentType {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "user" => Ok(AgentType::User),
+            "spider" => Ok(AgentType::Spider),
+            "automated" => Ok(AgentType::Automated),
+            _ => Err(format!(
+                "unknown agent type '{value}', expected user, spider, or automated"
+            )),
+        }
+    }
+}
+
+/// A single row from a `pageview_complete` file.
+#[derive(Clone, Debug)]
+pub struct CompleteRow {
+    /// Wiki code from the file (e.g., "en.wikipedia", "commons.wikimedia")
+    pub wiki_code: String,
+    /// Page id, `None` if unknown
+    pub page_id: Option<u64>,
+    /// Access method (e.g., "desktop", "mobile-web", "mobile-app")
+    pub access: String,
+    /// Agent type of the file, `None` if not found in the file name
+    pub agent: Option<AgentType>,
+    /// The row in the same form as in the hourly files
+    ///
+    /// `views` is the total of the day (or month), with the breakdown in
+    /// `hourly_views`. The domain code is translated from the wiki code and
+    /// access method, and `timestamp` is the start of the day (or month).
+    pub pageviews: Pageviews,
+}
+
+/// Parses a single line from a `pageview_complete` file.
+///
+/// The agent type and timestamp are not part of the line, and left empty.
+pub fn parse_complete_line(line: String) -> Result<CompleteRow, ParseError> {
+    let mut parts = line.split(' ');
+
+    let wiki_code = parts.next().ok_or_else(|| missing("wiki code", &line))?;
+    let page_title = parts.next().ok_or_else(|| missing("page title", &line))?;
+    let page_id = match parts.next().ok_or_else(|| missing("page id", &line))? {
+        "null" => None,
+        id => Some(id.parse().map_err(|_| invalid("page id", &line))?),
+    };
+    let access = parts.next().ok_or_else(|| missing("access", &line))?;
+    let views = parts
+        .next()
+        .ok_or_else(|| missing("views", &line))?
+        .parse()
+        .map_err(|_| invalid("views", &line))?;
+    let hourly_views = parts
+        .next()
+        .ok_or_else(|| missing("hourly views", &line))
+        .and_then(|value| {
+            parse_hourly_views(value).ok_or_else(|| invalid("hourly views", &line))
+        })?;
+
+    let domain_code = domain_code_from_wiki_code(wiki_code, access != "desktop");
+    let parsed_domain_code = match parse_domain_code(&domain_code) {
+        Ok(parsed) => parsed,
+        Err(_) => DomainCode {
+            language: wiki_code.to_string(),
+            domain: None,
+            mobile: access != "desktop",
+            project: None,
+        },
+    };
+
+    Ok(CompleteRow {
+        wiki_code: wiki_code.to_string(),
+        page_id,
+        access: access.to_string(),
+        agent: None,
+        pageviews: Pageviews {
+            domain_code,
+            page_title: normalize_string(page_title),
+            views,
+            parsed_domain_code,
+            timestamp: None,
+            response_size: None,
+            hourly_views: Some(hourly_views),
+            page_id: None,
+            line_number: None,
+        },
+    })
+}
+
+/// Parses the start date and agent type from a file name like
+/// `pageviews-20240818-user.bz2` or `pageviews-202408-user.bz2`.
+fn file_name_info(name: &str) -> (Option<DateTime<Utc>>, Option<AgentType>) {
+    let stem = name.split('.').next().unwrap_or(name);
+    let mut parts = stem.rsplitn(3, '-');
+    let agent = parts.next().and_then(|agent| agent.parse().ok());
+    let date = parts.next().and_then(|date| {
+        NaiveDate::parse_from_str(date, "%Y%m%d")
+            .or_else(|_| NaiveDate::parse_from_str(&format!("{date}01"), "%Y%m%d"))
+            .ok()
+    });
+    let timestamp = date
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|timestamp| timestamp.and_utc());
+    (timestamp, agent)
+}
+
+/// Stream, parse, and filter a local `pageview_complete` file.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::complete::{AgentType, complete_from_file};
+/// use pvstream::filter::FilterBuilder;
+/// use std::path::PathBuf;
+///
+/// let filter = FilterBuilder::new()
+///     .languages(["nn"])
+///     .agents([AgentType::User])
+///     .build();
+///
+/// for row in complete_from_file(PathBuf::from("pageviews-20240818-user.bz2"), &filter)? {
+///     let row = row?;
+///     println!("{} {} {}", row.wiki_code, row.pageviews.page_title, row.pageviews.views);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn complete_from_file(path: PathBuf, filter: &Filter) -> Result<CompleteIterator, StreamError> {
+    complete_from_source(&Source::File(path), filter, &StreamConfig::default())
+}
+
+/// Stream, parse, and filter a remote `pageview_complete` file.
+///
+/// See `complete_from_file` for details.
+pub fn complete_from_url(url: Url, filter: &Filter) -> Result<CompleteIterator, StreamError> {
+    complete_from_source(&Source::Url(url), filter, &StreamConfig::default())
+}
+
+/// Stream, parse, and filter a `pageview_complete` file from any source.
+///
+/// Files of agent types not in `Filter::agents` are skipped without being
+/// opened, as are files without an agent type in their name when it's set.
+/// `line_regex` is matched against the raw lines, and the post-parsing
+/// filters against `CompleteRow::pageviews`. The time based filters and
+/// the transforms (`rollup_namespaces` and `residual_rows`) only apply to the
+/// hourly files, and are ignored.
+pub fn complete_from_source(
+    source: &Source,
+    filter: &Filter,
+    config: &StreamConfig,
+) -> Result<CompleteIterator, StreamError> {
+    let (timestamp, agent) = source.file_name().map(file_name_info).unwrap_or_default();
+    if filter
+        .agents
+        .as_ref()
+        .is_some_and(|agents| agent.is_none_or(|agent| !agents.contains(&agent)))
+    {
+        return Ok(Box::new(std::iter::empty()));
+    }
+
+    let tracker = Tracker::for_config(config);
+    let lines = source.tracked_lines(config, tracker.as_ref())?;
+
+    let line_regex = filter.line_regex.clone();
+    let filter = filter.clone();
+    let decode_titles = config.decode_titles;
+    let rows = lines
+        .filter(move |line| match (line, &line_regex) {
+            (Ok(line), Some(regex)) => regex.is_match(line),
+            _ => true,
+        })
+        .map(move |line| {
+            let mut row = parse_complete_line(line.map_err(ParseError::ReadError)?)?;
+            row.agent = agent;
+            row.pageviews.timestamp = timestamp;
+            Ok(row)
+        })
+        .filter(move |row| match row {
+            Ok(row) => filter.post_filter(&row.pageviews),
+            Err(_) => true, // Pass through to handle later
+        })
+        .map(move |row| {
+            row.map(|mut row| {
+                if decode_titles {
+                    decode_page_title(&mut row.pageviews);
+                }
+                row
+            })
+        });
+
+    let rows = config.limit_rows(config.error_policy.apply(Box::new(rows)));
+    Ok(track_emitted(rows, tracker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+
+    fn test_file() -> PathBuf {
+        PathBuf::from("tests/files/pageviews-20240803-user.bz2")
+    }
+
+    #[test]
+    fn test_parse_complete_line() {
+        let row = parse_complete_line(
+            "de.wikibooks Mathe_für_Nicht-Freaks 123456 mobile-web 3 J1S2".into(),
+        )
+        .unwrap();
+        assert_eq!(row.wiki_code, "de.wikibooks");
+        assert_eq!(row.page_id, Some(123456));
+        assert_eq!(row.access, "mobile-web");
+        assert_eq!(row.pageviews.domain_code, "de.m.b");
+        assert_eq!(row.pageviews.page_title, "Mathe_für_Nicht-Freaks");
+        assert_eq!(row.pageviews.views, 3);
+        assert_eq!(row.pageviews.parsed_domain_code.language, "de");
+        assert_eq!(
+            row.pageviews.parsed_domain_code.domain,
+            Some("wikibooks.org")
+        );
+        assert!(row.pageviews.parsed_domain_code.mobile);
+        let hours = row.pageviews.hourly_views.unwrap();
+        assert_eq!((hours[9], hours[18]), (1, 2));
+
+        let row =
+            parse_complete_line("en.wikipedia Special:Search null desktop 1 A1".into()).unwrap();
+        assert_eq!(row.page_id, None);
+        assert_eq!(row.pageviews.domain_code, "en");
+
+        assert!(parse_complete_line("en.wikipedia Main_Page 1 desktop 1".into()).is_err());
+        assert!(parse_complete_line("en.wikipedia Main_Page x desktop 1 A1".into()).is_err());
+        assert!(parse_complete_line("en.wikipedia Main_Page 1 desktop 1 0".into()).is_err());
+    }
+
+    #[test]
+    fn test_file_name_info() {
+        let midnight = NaiveDate::from_ymd_opt(2024, 8, 18)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc();
+        assert_eq!(
+            file_name_info("pageviews-20240818-spider.bz2"),
+            (Some(midnight), Some(AgentType::Spider))
+        );
+        assert_eq!(
+            file_name_info("pageviews-202408-automated.bz2").1,
+            Some(AgentType::Automated)
+        );
+        assert_eq!(file_name_info("pageviews.bz2"), (None, None));
+    }
+
+    #[test]
+    fn test_complete_from_file() {
+        let rows: Vec<_> = complete_from_file(test_file(), &Filter::default())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), 12);
+        assert!(rows.iter().all(|row| row.agent == Some(AgentType::User)));
+        assert!(rows.iter().all(|row| {
+            let hours = row.pageviews.hourly_views.as_ref().unwrap();
+            hours.iter().sum::<u32>() == row.pageviews.views
+        }));
+        assert_eq!(rows[6].pageviews.page_title, r#""Hello,_World!""#);
+        assert_eq!(rows[7].pageviews.domain_code, "commons.m");
+        assert_eq!(
+            rows[7].pageviews.parsed_domain_code.domain,
+            Some("commons.wikimedia.org")
+        );
+
+        let filter = FilterBuilder::new()
+            .languages(["de"])
+            .mobile(false)
+            .agents([AgentType::User])
+            .build();
+        let rows: Vec<_> = complete_from_file(test_file(), &filter)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].pageviews.page_title, "Mathe_für_Nicht-Freaks");
+
+        let bots = FilterBuilder::new()
+            .agents([AgentType::Spider, AgentType::Automated])
+            .build();
+        assert_eq!(complete_from_file(test_file(), &bots).unwrap().count(), 0);
+    }
+}
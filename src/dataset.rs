@@ -0,0 +1,337 @@
+//! Partitioned Parquet datasets.
+//!
+//! Writes rows to a directory of Parquet files in the Hive layout, with one
+//! directory level per partition column:
+//!
+//! ```text
+//! output/language=de/date=2024-08-18/part-000.parquet
+//! output/language=de/date=2024-08-18/part-001.parquet
+//! output/language=en/date=2024-08-18/part-000.parquet
+//! ```
+//!
+//! Spark, DuckDB (`hive_partitioning`), Polars, and pyarrow datasets read
+//! the directory as one table, with the partition columns taken from the
+//! paths, and skip the directories a query doesn't need.
+//!
+//! Each input file gets its own part file in every partition it has rows in,
+//! numbered by its position in the input, so running the same inputs again
+//! overwrites the same files. The partition columns are left out of the
+//! files, as they would be the same in every row.
+
+use crate::filter::Filter;
+use crate::parse::Pageviews;
+use crate::store::{ParquetFile, ParquetOptions, SkippedErrors, arrow_chunks_from_structs};
+use crate::stream::{ErrorPolicy, Source, StreamConfig, StreamError, object_store_url};
+use crate::stream_from_source;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use url::Url;
+
+/// Directory name used by Hive for rows without a value in a partition column.
+pub const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// Column to partition a dataset by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PartitionBy {
+    /// The language code, e.g. `language=en`.
+    Language,
+    /// The UTC date of the file, e.g. `date=2024-08-18`.
+    Date,
+    /// The Wikimedia domain, e.g. `domain=wikibooks.org`.
+    Domain,
+}
+
+impl PartitionBy {
+    /// Name of the partition column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PartitionBy::Language => "language",
+            PartitionBy::Date => "date",
+            PartitionBy::Domain => "domain",
+        }
+    }
+
+    /// Value of the partition column for a row, if any.
+    fn value(&self, row: &Pageviews) -> Option<String> {
+        match self {
+            PartitionBy::Language => Some(row.parsed_domain_code.language.clone()),
+            PartitionBy::Date => row.timestamp.map(|ts| ts.format("%Y-%m-%d").to_string()),
+            PartitionBy::Domain => row.parsed_domain_code.domain.map(str::to_string),
+        }
+    }
+}
+
+impl fmt::Display for PartitionBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PartitionBy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "language" => Ok(PartitionBy::Language),
+            "date" => Ok(PartitionBy::Date),
+            "domain" => Ok(PartitionBy::Domain),
+            _ => Err(format!(
+                "unknown partition column '{value}', expected language, date, or domain"
+            )),
+        }
+    }
+}
+
+/// Download remote pageviews files and write the filtered rows to a
+/// partitioned dataset.
+///
+/// Returns the paths of the part files written, relative to `output_dir`.
+///
+/// # Example
+///
+/// ```no_run
+/// use chrono::NaiveDate;
+/// use pvstream::dataset::{PartitionBy, parquet_dataset_from_urls};
+/// use pvstream::filter::FilterBuilder;
+/// use pvstream::urls::pageviews_urls_for_day;
+/// use std::path::Path;
+///
+/// let date = NaiveDate::from_ymd_opt(2024, 8, 18).unwrap();
+/// parquet_dataset_from_urls(
+///     &pageviews_urls_for_day(date),
+///     Path::new("pageviews"),
+///     &[PartitionBy::Language, PartitionBy::Date],
+///     &FilterBuilder::new().min_views(10).build(),
+/// )?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn parquet_dataset_from_urls(
+    urls: &[Url],
+    output_dir: &Path,
+    partition_by: &[PartitionBy],
+    filter: &Filter,
+) -> Result<Vec<PathBuf>, StreamError> {
+    let sources: Vec<_> = urls.iter().cloned().map(Source::Url).collect();
+    parquet_dataset_from_sources(
+        &sources,
+        output_dir,
+        partition_by,
+        filter,
+        &StreamConfig::default(),
+        &ParquetOptions::default(),
+    )
+}
+
+/// Write the filtered rows of pageviews files from any source to a
+/// partitioned dataset.
+///
+/// Files are read one at a time. Rows are buffered per partition until a
+/// full row group can be written, and all part files of an input are open
+/// until it has been read, which is one file per language when partitioning
+/// by language. Rows failing to parse are skipped, and listed in
+/// `ParquetOptions::error_file` if set, while a read error, or a parse error
+/// with `ErrorPolicy::FailFast`, stops the write and is returned, leaving the
+/// part files of that input unfinished. `ParquetOptions::top_k` picks the top
+/// rows of each input on its own, and `ParquetOptions::sort` sorts the rows
+/// of each part file.
+pub fn parquet_dataset_from_sources(
+    sources: &[Source],
+    output_dir: &Path,
+    partition_by: &[PartitionBy],
+    filter: &Filter,
+    config: &StreamConfig,
+    options: &ParquetOptions,
+) -> Result<Vec<PathBuf>, StreamError> {
+    let batch_size = options.batch_size.unwrap_or(122_880);
+    let excluded: Vec<_> = partition_by.iter().map(PartitionBy::as_str).collect();
+    let mut skipped = SkippedErrors::new(options.error_file.as_deref())?;
+    let mut written = Vec::new();
+
+    for (index, source) in sources.iter().enumerate() {
+        let file_name = format!("part-{index:03}.parquet");
+        let mut parts: HashMap<PathBuf, Part> = HashMap::new();
+
+        let mut rows = stream_from_source(source, filter, config)?;
+        if let Some(top_k) = options.top_k {
+            rows = top_k.apply(rows);
+        }
+        if let Some(sort) = &options.sort {
+            rows = sort.apply(rows);
+        }
+        for row in rows {
+            let row = match row {
+                Ok(row) => row,
+                Err(err) if config.error_policy.is_fatal(&err) => return Err(err.into()),
+                Err(err) => {
+                    skipped.record(err);
+                    continue;
+                }
+            };
+            let path = partition_dir(partition_by, &row).join(&file_name);
+            let part = match parts.entry(path) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let full_path = output_dir.join(entry.key());
+                    // Buckets have no directories to create
+                    if let Some(dir) = full_path.parent()
+                        && object_store_url(&full_path).is_none()
+                    {
+                        create_dir_all(dir)?;
+                    }
+                    let file = ParquetFile::create(&full_path, options, &excluded)?
+                        .with_metrics(config.metrics.as_ref());
+                    entry.insert(Part {
+                        file,
+                        rows: Vec::new(),
+                    })
+                }
+            };
+            part.rows.push(row);
+            if part.rows.len() >= batch_size {
+                part.flush()?;
+            }
+        }
+
+        let mut paths: Vec<_> = parts.keys().cloned().collect();
+        paths.sort();
+        for (_, mut part) in parts {
+            part.flush()?;
+            part.file.finish()?;
+        }
+        written.extend(paths);
+    }
+    skipped.finish()?;
+
+    Ok(written)
+}
+
+/// A part file and the rows waiting to be written to it.
+struct Part {
+    file: ParquetFile,
+    rows: Vec<Pageviews>,
+}
+
+impl Part {
+    /// Writes the buffered rows as a row group.
+    fn flush(&mut self) -> Result<(), StreamError> {
+        let rows = self.rows.drain(..).map(Ok);
+        for chunk in arrow_chunks_from_structs(rows, Some(usize::MAX), &ErrorPolicy::Skip) {
+            self.file.write(chunk?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Relative directory of the partition a row belongs to.
+fn partition_dir(partition_by: &[PartitionBy], row: &Pageviews) -> PathBuf {
+    partition_by
+        .iter()
+        .map(|column| {
+            let value = column
+                .value(row)
+                .map_or_else(|| DEFAULT_PARTITION.to_string(), |value| escape(&value));
+            format!("{column}={value}")
+        })
+        .collect()
+}
+
+/// Escapes characters with a special meaning in paths, as Hive does.
+///
+/// Values with such characters are very rare, as languages and domains are
+/// plain ASCII, but nothing stops a misconfigured row from containing them.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_control() || "\"#%'*/:=?\\{[]^".contains(c) {
+            for byte in c.to_string().bytes() {
+                escaped.push_str(&format!("%{byte:02X}"));
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+    use crate::stream_from_parquet;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_parquet_dataset_from_sources() {
+        let source = Source::File(PathBuf::from("tests/files/pageviews-20240803-060000.gz"));
+        let dir = TestDir::new("dataset");
+        let output_dir = dir.path();
+        let filter = FilterBuilder::new().languages(["de", "ja"]).build();
+        let config = StreamConfig::default();
+        let options = ParquetOptions {
+            batch_size: Some(2),
+            ..Default::default()
+        };
+
+        let written = parquet_dataset_from_sources(
+            &[source.clone(), source.clone()],
+            output_dir,
+            &[PartitionBy::Language, PartitionBy::Date],
+            &filter,
+            &config,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            written,
+            [
+                "language=de/date=2024-08-03/part-000.parquet",
+                "language=ja/date=2024-08-03/part-000.parquet",
+                "language=de/date=2024-08-03/part-001.parquet",
+                "language=ja/date=2024-08-03/part-001.parquet",
+            ]
+            .map(PathBuf::from)
+        );
+
+        let expected: Vec<_> = stream_from_source(&source, &filter, &config)
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|row| row.parsed_domain_code.language == "de")
+            .collect();
+        let rows: Vec<_> = stream_from_parquet(output_dir.join(&written[0]))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), expected.len());
+        assert_eq!(rows[0].page_title, expected[0].page_title);
+    }
+
+    #[test]
+    fn test_partition_dir() {
+        let mut row = crate::parse::parse_line("en.b Main_Page 10 0".to_string()).unwrap();
+        let partition_by = [
+            PartitionBy::Domain,
+            PartitionBy::Date,
+            PartitionBy::Language,
+        ];
+        assert_eq!(
+            partition_dir(&partition_by, &row),
+            PathBuf::from("domain=wikibooks.org/date=__HIVE_DEFAULT_PARTITION__/language=en")
+        );
+
+        row.parsed_domain_code.language = "a/b=c".to_string();
+        assert_eq!(
+            partition_dir(&[PartitionBy::Language], &row),
+            PathBuf::from("language=a%2Fb%3Dc")
+        );
+    }
+
+    #[test]
+    fn test_partition_by_from_str() {
+        assert_eq!("date".parse(), Ok(PartitionBy::Date));
+        assert!("hour".parse::<PartitionBy>().is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//! Streaming into DuckDB tables.
+//!
+//! Requires the `duckdb` feature. Rows are appended to a table through the
+//! Arrow appender of DuckDB, one batch at a time, so the results can be
+//! queried with SQL right away, without an intermediate Parquet file.
+//!
+//! The table is created if it doesn't exist, with the columns of the flat
+//! Parquet layout:
+//!
+//! ```sql
+//! domain_code VARCHAR NOT NULL,
+//! page_title VARCHAR NOT NULL,
+//! views UINTEGER NOT NULL,
+//! language VARCHAR NOT NULL,
+//! domain VARCHAR,
+//! mobile BOOLEAN NOT NULL,
+//! project VARCHAR,
+//! timestamp TIMESTAMP,
+//! response_size UBIGINT,
+//! hourly_views UINTEGER[],
+//! page_id VARCHAR
+//! ```
+//!
+//! Existing tables must have the same columns, in the same order. The
+//! timestamp is stored in UTC as a plain `TIMESTAMP`, as `TIMESTAMPTZ` needs
+//! the ICU extension for most operations, which isn't bundled.
+
+use crate::filter::Filter;
+use crate::parse::{Pageviews, ParseError};
+use crate::stream::{ErrorPolicy, Source, StreamConfig, StreamError};
+use crate::stream_from_source;
+use ::duckdb::Connection;
+use ::duckdb::arrow::array::{
+    ArrayRef, BooleanBuilder, ListBuilder, StringBuilder, TimestampMicrosecondBuilder,
+    UInt32Builder, UInt64Builder,
+};
+use ::duckdb::arrow::record_batch::RecordBatch;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use url::Url;
+
+/// Rows appended to the table in each batch.
+const BATCH_SIZE: usize = 122_880;
+
+/// Column definitions of the tables created by this module.
+const COLUMNS: &str = "\
+    domain_code VARCHAR NOT NULL, \
+    page_title VARCHAR NOT NULL, \
+    views UINTEGER NOT NULL, \
+    language VARCHAR NOT NULL, \
+    domain VARCHAR, \
+    mobile BOOLEAN NOT NULL, \
+    project VARCHAR, \
+    timestamp TIMESTAMP, \
+    response_size UBIGINT, \
+    hourly_views UINTEGER[], \
+    page_id VARCHAR";
+
+/// Parse a local pageviews file and append the filtered rows to a DuckDB table.
+///
+/// The database file at `db_path` is created if it doesn't exist, as is the
+/// table. Returns the number of rows appended.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::duckdb::duckdb_from_file;
+/// use pvstream::filter::FilterBuilder;
+/// use std::path::{Path, PathBuf};
+///
+/// let filter = FilterBuilder::new().languages(["nn", "nb"]).build();
+/// let rows = duckdb_from_file(
+///     PathBuf::from("pageviews-20240818-080000.gz"),
+///     Path::new("pageviews.duckdb"),
+///     "pageviews",
+///     &filter,
+/// )?;
+/// println!("{rows} rows appended");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn duckdb_from_file(
+    input_path: PathBuf,
+    db_path: &Path,
+    table: &str,
+    filter: &Filter,
+) -> Result<usize, StreamError> {
+    let connection = Connection::open(db_path)?;
+    duckdb_from_source(
+        &Source::File(input_path),
+        &connection,
+        table,
+        filter,
+        &StreamConfig::default(),
+    )
+}
+
+/// Download a remote pageviews file and append the filtered rows to a
+/// DuckDB table.
+///
+/// See `duckdb_from_file` for details.
+pub fn duckdb_from_url(
+    url: Url,
+    db_path: &Path,
+    table: &str,
+    filter: &Filter,
+) -> Result<usize, StreamError> {
+    let connection = Connection::open(db_path)?;
+    duckdb_from_source(
+        &Source::Url(url),
+        &connection,
+        table,
+        filter,
+        &StreamConfig::default(),
+    )
+}
+
+/// Append the filtered rows of a pageviews file from any source to a table.
+///
+/// Takes an open connection, which makes it possible to use an in-memory
+/// database, or to append several files in one transaction. Rows failing to
+/// parse are skipped, while a read error, or a parse error with
+/// `ErrorPolicy::FailFast`, stops the append and is returned. Rows appended
+/// before the error are kept, unless the caller rolls back.
+pub fn duckdb_from_source(
+    source: &Source,
+    connection: &Connection,
+    table: &str,
+    filter: &Filter,
+    config: &StreamConfig,
+) -> Result<usize, StreamError> {
+    connection.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {} ({COLUMNS})",
+        quote_identifier(table)
+    ))?;
+
+    let mut appender = connection.appender(table)?;
+    let mut rows = stream_from_source(source, filter, config)?;
+    let mut appended = 0;
+    loop {
+        let batch = next_batch(&mut rows, &config.error_policy)?;
+        if batch.is_empty() {
+            break;
+        }
+        appended += batch.len();
+        appender.append_record_batch(record_batch(&batch))?;
+        if let Some(metrics) = &config.metrics {
+            metrics.batches_written(1);
+        }
+    }
+    appender.flush()?;
+
+    Ok(appended)
+}
+
+/// Quotes a table name, so any name can be used as is.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Collects the next batch of rows, skipping errors which aren't fatal.
+fn next_batch(
+    rows: &mut impl Iterator<Item = Result<Pageviews, ParseError>>,
+    policy: &ErrorPolicy,
+) -> Result<Vec<Pageviews>, StreamError> {
+    let mut batch = Vec::new();
+    for row in rows.by_ref() {
+        match row {
+            Ok(row) => batch.push(row),
+            Err(err) if policy.is_fatal(&err) => return Err(err.into()),
+            Err(_) => continue,
+        }
+        if batch.len() >= BATCH_SIZE {
+            break;
+        }
+    }
+    Ok(batch)
+}
+
+/// Converts rows to a record batch with the columns of the table.
+fn record_batch(rows: &[Pageviews]) -> RecordBatch {
+    let mut domain_code = StringBuilder::new();
+    let mut page_title = StringBuilder::new();
+    let mut views = UInt32Builder::new();
+    let mut language = StringBuilder::new();
+    let mut domain = StringBuilder::new();
+    let mut mobile = BooleanBuilder::new();
+    let mut project = StringBuilder::new();
+    let mut timestamp = TimestampMicrosecondBuilder::new();
+    let mut response_size = UInt64Builder::new();
+    let mut hourly_views = ListBuilder::new(UInt32Builder::new());
+    let mut page_id = StringBuilder::new();
+
+    for row in rows {
+        domain_code.append_value(&row.domain_code);
+        page_title.append_value(&row.page_title);
+        views.append_value(row.views);
+        language.append_value(&row.parsed_domain_code.language);
+        domain.append_option(row.parsed_domain_code.domain);
+        mobile.append_value(row.parsed_domain_code.mobile);
+        project.append_option(row.parsed_domain_code.project.map(|p| p.as_str()));
+        timestamp.append_option(row.timestamp.map(|ts| ts.timestamp_micros()));
+        response_size.append_option(row.response_size);
+        hourly_views.append_option(
+            row.hourly_views
+                .as_ref()
+                .map(|hours| hours.iter().copied().map(Some)),
+        );
+        page_id.append_option(row.page_id.as_deref());
+    }
+
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        ("domain_code", Arc::new(domain_code.finish())),
+        ("page_title", Arc::new(page_title.finish())),
+        ("views", Arc::new(views.finish())),
+        ("language", Arc::new(language.finish())),
+        ("domain", Arc::new(domain.finish())),
+        ("mobile", Arc::new(mobile.finish())),
+        ("project", Arc::new(project.finish())),
+        ("timestamp", Arc::new(timestamp.finish())),
+        ("response_size", Arc::new(response_size.finish())),
+        ("hourly_views", Arc::new(hourly_views.finish())),
+        ("page_id", Arc::new(page_id.finish())),
+    ];
+    RecordBatch::try_from_iter(columns).expect("all columns have one value per row")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+
+    #[test]
+    fn test_duckdb_from_source() {
+        let source = Source::File(PathBuf::from("tests/files/pageviews-20240803-060000.gz"));
+        let connection = Connection::open_in_memory().unwrap();
+        let filter = FilterBuilder::new().languages(["de", "ja"]).build();
+        let config = StreamConfig::default();
+
+        let appended =
+            duckdb_from_source(&source, &connection, "page views", &filter, &config).unwrap();
+        let (rows, views, epoch): (usize, u64, i64) = connection
+            .query_row(
+                "SELECT count(*), sum(views), max(epoch(timestamp))::BIGINT \
+                 FROM \"page views\" WHERE language IN ('de', 'ja')",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+
+        let expected: Vec<_> = stream_from_source(&source, &filter, &config)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(appended, expected.len());
+        assert_eq!(rows, expected.len());
+        assert_eq!(
+            views,
+            expected.iter().map(|row| u64::from(row.views)).sum::<u64>()
+        );
+        assert_eq!(Some(epoch), source.timestamp().map(|ts| ts.timestamp()));
+
+        // A second file is appended to the same table
+        duckdb_from_source(&source, &connection, "page views", &filter, &config).unwrap();
+        let rows: usize = connection
+            .query_row("SELECT count(*) FROM \"page views\"", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 2 * expected.len());
+    }
+}
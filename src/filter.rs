@@ -1,6 +1,23 @@
-use regex::Regex;
+use chrono::{DateTime, Datelike, Timelike, Utc, Weekday};
+use memchr::memchr;
+use memchr::memmem::Finder;
+use regex::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+use unicode_normalization::{UnicodeNormalization, is_nfc};
 
-use crate::parse::Pageviews;
+use crate::complete::AgentType;
+use crate::join::PageIds;
+use crate::parse::{DomainCodeRef, Pageviews, PageviewsRef, RawPageviews};
+use crate::sites::{Project, resolve_domain_code};
+
+/// Language of domain codes which don't start with one, like `commons.m`.
+const DEFAULT_LANGUAGE: &str = "en";
 
 /// Filter for rows/objects. Apply to restrict returned data.
 ///
@@ -10,62 +27,605 @@ use crate::parse::Pageviews;
 /// `line_regex` is applied before parsing each row, the rest are applied
 /// after parsing. Use `line_regex` when possible, as it's far more efficient.
 ///
+/// `page_titles_any` matches page titles against a set of patterns in one
+/// pass, accepting titles matching any of them, which is faster and easier to
+/// maintain than one large alternation in `page_title`.
+///
+/// `page_title_exact` and `page_title_prefix` match page titles with plain
+/// string comparisons instead of a regex.
+///
+/// Where it's safe, the exact match filters (`domain_codes`, `languages`, the
+/// languages of `page_title_by_language`, `page_title_exact`, and
+/// `page_title_prefix`) are also checked on the raw line before parsing, so
+/// lines which can't match are never parsed. This is skipped for
+/// `residual_rows`, which needs every rejected row.
+///
+/// As the dumps are sorted by domain code, `domain_codes` and `languages`
+/// also tell where in a file the matching rows end, see `end_of_range`.
+/// Reading stops there when `StreamConfig::assume_sorted` is set, and
+/// uncompressed files are read from where they begin, see `start_of_range`.
+///
+/// `title_matching` controls how the title filters (`page_title`,
+/// `page_titles_any`, `page_title_by_language`, `page_title_exact`,
+/// `page_title_prefix`, and `exclude_page_title`) compare titles, e.g. to
+/// ignore case. Spaces in `page_title_exact` and `page_title_prefix` always
+/// match the underscores of the dumps, so `Main Page` matches `Main_Page`.
+///
+/// `exclude_languages`, `exclude_domains`, and `exclude_page_title` reject
+/// rows matching them, e.g. to read all wikis except English ones, or to
+/// drop the `Special:` and `Talk:` namespaces.
+///
+/// With `negate` set, the result of the post-parsing filters is inverted,
+/// returning exactly the rows they would otherwise reject. `line_regex` is
+/// still applied as is, before parsing.
+///
+/// `rollup_namespaces` is not a filter, but a transformation applied to the
+/// rows which pass the filters. It collapses all rows from the listed
+/// namespaces (e.g. `Special`) into one row per domain code and namespace,
+/// with the title `Special:*` and the summed views.
+///
+/// `hours` and `weekdays` restrict which files are read at all, based on the
+/// timestamp in the file name (UTC). Files outside of these are skipped
+/// without being opened, which makes it possible to e.g. only read evening
+/// hours or weekends when streaming many files. Files without a recognizable
+/// timestamp are always read.
+///
+/// `sample` keeps a deterministic fraction of the rows passing the other
+/// filters, see `Sample`. It's applied after `negate`, so the sample is
+/// drawn from the rows which are otherwise returned.
+///
+/// `agents` restricts which `pageview_complete` files are read, based on the
+/// agent type in the file name. It has no effect on the hourly files.
+///
+/// With `residual_rows` set, rows rejected by the post-parsing filters or by
+/// `custom` are not dropped entirely, but summed into one residual row per
+/// language and mobile flag, with `*` as domain code and page title. These are
+/// returned after all other rows, making it possible to reconcile filtered
+/// output against the totals of the full file. Lines rejected by `line_regex`
+/// are never parsed, and are not counted.
+///
+/// `custom` is a predicate of your own, for conditions the other filters
+/// don't cover, see `CustomFilter`. It's called with batches of the rows
+/// passing the other filters, before the transforms. Like `line_regex`, it's
+/// not inverted by `negate`.
+///
+/// With `merge_mobile` set, rows of mobile sites are merged into the rows of
+/// the desktop sites with the same title, summing their views, for analyses
+/// which don't care about the platform. Only rows passing the filters are
+/// merged, and the rows of one language are kept in memory at a time, as
+/// they are only complete once the domain codes of the sorted file are past
+/// the language.
+///
+/// `min_rank` and `max_rank` keep the rows ranked within that range by views
+/// among the rows of their domain code passing the other filters, counting
+/// from 1 for the most viewed, e.g. `max_rank = 100` for the 100 most viewed
+/// pages of each wiki. Ties are broken by title. Ranks are computed after
+/// `merge_mobile`, one domain code at a time as the files are sorted by
+/// domain code, and are not inverted by `negate`. With `residual_rows`, the
+/// rows ranked out of the range are summed in the residual rows.
+///
+/// `page_ids` is not a filter either, but sets `Pageviews::page_id` of the
+/// rows from a sorted mapping file, read side by side with each pageviews
+/// file, see `join::PageIds`. Rows are joined after `merge_mobile`, so merged
+/// rows are looked up by their desktop domain code.
+///
+/// `title_transform` rewrites the page titles of the rows returned, e.g. to
+/// strip a suffix before aggregating, see `TitleTransform`. It's applied
+/// last, so the filters and the other transforms see the original titles,
+/// and rows whose titles become equal are not merged.
+///
 /// Use `FilterBuilder` for a more convenient setup.
 #[derive(Clone, Default, Debug)]
 pub struct Filter {
     pub line_regex: Option<Regex>,
     pub domain_codes: Option<Vec<String>>,
     pub page_title: Option<Regex>,
+    pub page_titles_any: Option<RegexSet>,
+    pub page_title_by_language: Option<HashMap<String, Regex>>,
+    pub page_title_exact: Option<HashSet<String>>,
+    pub page_title_prefix: Option<String>,
+    pub title_matching: TitleMatching,
     pub min_views: Option<u32>,
     pub max_views: Option<u32>,
     pub languages: Option<Vec<String>>,
     pub domains: Option<Vec<String>>,
+    pub projects: Option<Vec<Project>>,
+    pub hostnames: Option<Vec<String>>,
     pub mobile: Option<bool>,
+    pub blocklist: Option<Blocklist>,
+    pub exclude_languages: Option<Vec<String>>,
+    pub exclude_domains: Option<Vec<String>>,
+    pub exclude_page_title: Option<Regex>,
+    pub negate: bool,
+    pub rollup_namespaces: Option<Vec<String>>,
+    pub residual_rows: bool,
+    pub merge_mobile: bool,
+    pub min_rank: Option<usize>,
+    pub max_rank: Option<usize>,
+    pub page_ids: Option<PageIds>,
+    pub title_transform: Option<TitleTransform>,
+    pub hours: Option<Vec<u32>>,
+    pub weekdays: Option<Vec<Weekday>>,
+    pub agents: Option<Vec<AgentType>>,
+    pub sample: Option<Sample>,
+    pub custom: Option<CustomFilter>,
+}
+
+/// Deterministic sample of rows, see `FilterBuilder::sample`.
+///
+/// Rows are picked by a hash of the seed, domain code, and page title, so
+/// the same pages are sampled from every file, and on every run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    /// Fraction of rows to keep, between 0 and 1.
+    pub fraction: f64,
+    /// Seed of the hash, picking a different sample for each value.
+    pub seed: u64,
+}
+
+impl Sample {
+    /// Checks if the page with this domain code and title is in the sample.
+    pub fn contains(&self, domain_code: &str, page_title: &str) -> bool {
+        // FNV-1a, as it's stable across platforms and Rust versions
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let bytes = self
+            .seed
+            .to_le_bytes()
+            .into_iter()
+            .chain(domain_code.bytes().chain([b' ']).chain(page_title.bytes()));
+        for byte in bytes {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+
+        // Mixes the bits (splitmix64) before mapping the top 53 bits to [0, 1)
+        hash ^= hash >> 30;
+        hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash ^= hash >> 27;
+        hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash ^= hash >> 31;
+        ((hash >> 11) as f64 / (1u64 << 53) as f64) < self.fraction
+    }
+}
+
+/// Rewrites page titles with a regex and a replacement, see
+/// `FilterBuilder::title_transform`.
+///
+/// Every match in a title is replaced, and the replacement can refer to
+/// capture groups as `$1` or `${name}`, like `Regex::replace_all`.
+#[derive(Clone, Debug)]
+pub struct TitleTransform {
+    regex: Regex,
+    replacement: String,
+}
+
+impl TitleTransform {
+    /// Creates a transform replacing the matches of `pattern` with
+    /// `replacement`, failing if `pattern` is not a valid regex.
+    pub fn new(pattern: &str, replacement: impl Into<String>) -> Result<Self, regex::Error> {
+        Ok(Self {
+            regex: Regex::new(pattern)?,
+            replacement: replacement.into(),
+        })
+    }
+
+    /// Returns the title with the matches replaced, borrowed if there were
+    /// none.
+    pub fn apply<'a>(&self, title: &'a str) -> Cow<'a, str> {
+        self.regex.replace_all(title, self.replacement.as_str())
+    }
+}
+
+/// Predicate of a batch of rows, returning whether to keep each of them.
+type BatchPredicate = dyn Fn(&[Pageviews]) -> Result<Vec<bool>, IoError> + Send + Sync;
+
+/// Custom predicate deciding which rows to keep, see `FilterBuilder::custom`.
+///
+/// The predicate is called with batches of rows, so predicates with a fixed
+/// cost per call, like python callables, are cheaper to run. Rows without a
+/// result, if fewer results than rows are returned, are dropped. An error
+/// drops the rows of the batch, and is passed on as a read error, ending the
+/// stream.
+#[derive(Clone)]
+pub struct CustomFilter {
+    predicate: Arc<BatchPredicate>,
+    batch_size: usize,
+}
+
+impl CustomFilter {
+    /// Number of rows per batch of `CustomFilter::new`.
+    pub const DEFAULT_BATCH_SIZE: usize = 1024;
+
+    /// Keeps the rows for which `predicate` returns true.
+    pub fn new(predicate: impl Fn(&Pageviews) -> bool + Send + Sync + 'static) -> Self {
+        Self::batched(Self::DEFAULT_BATCH_SIZE, move |rows| {
+            Ok(rows.iter().map(&predicate).collect())
+        })
+    }
+
+    /// Keeps the rows `predicate` returns true for, called with up to
+    /// `batch_size` rows at a time.
+    ///
+    /// Panics if `batch_size` is 0.
+    pub fn batched(
+        batch_size: usize,
+        predicate: impl Fn(&[Pageviews]) -> Result<Vec<bool>, IoError> + Send + Sync + 'static,
+    ) -> Self {
+        assert!(batch_size > 0, "Batch size must be at least 1");
+        Self {
+            predicate: Arc::new(predicate),
+            batch_size,
+        }
+    }
+
+    /// Number of rows passed to the predicate at a time.
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Checks which rows of a batch to keep.
+    pub fn check(&self, rows: &[Pageviews]) -> Result<Vec<bool>, IoError> {
+        (self.predicate)(rows)
+    }
+}
+
+impl fmt::Debug for CustomFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomFilter")
+            .field("batch_size", &self.batch_size)
+            .finish_non_exhaustive()
+    }
+}
+
+/// How the title filters compare page titles, see `Filter::with_title_matching`.
+///
+/// Titles are compared in the form returned by `normalize`, with spaces
+/// replaced by underscores as in the dumps.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TitleMatching {
+    /// Compare titles ignoring case, so `main_page` matches `Main_Page`.
+    pub case_insensitive: bool,
+    /// Compare titles in Unicode NFC, so titles with composed and decomposed
+    /// characters, like `é` and `e` followed by a combining accent, match.
+    pub normalize_unicode: bool,
+}
+
+impl TitleMatching {
+    /// The form titles are compared in.
+    ///
+    /// Borrows the title when it's already in that form, which it is for most
+    /// titles when only replacing spaces.
+    pub fn normalize<'a>(&self, title: &'a str) -> Cow<'a, str> {
+        let mut title = Cow::Borrowed(title);
+        if title.contains(' ') {
+            title = Cow::Owned(title.replace(' ', "_"));
+        }
+        if self.normalize_unicode && !is_nfc(&title) {
+            title = Cow::Owned(title.nfc().collect());
+        }
+        if self.case_insensitive {
+            title = Cow::Owned(title.to_lowercase());
+        }
+        title
+    }
+}
+
+/// Page titles which should never be returned, e.g. known bot targets.
+///
+/// The blocklist file has one entry per line. Lines starting with `re:` are
+/// regular expressions matched against the page title, all other lines are
+/// exact page titles. Blank lines and lines starting with `#` are ignored,
+/// which makes it possible to document the file inline:
+///
+/// ```text
+/// # Frequent bot targets
+/// Special:Search
+/// re:^Special:(BlankPage|CentralAutoLogin)
+/// ```
+#[derive(Clone, Default, Debug)]
+pub struct Blocklist {
+    titles: HashSet<String>,
+    patterns: Option<RegexSet>,
+}
+
+impl Blocklist {
+    /// Loads a blocklist file from the local file system.
+    pub fn from_file(path: &Path) -> Result<Self, IoError> {
+        Self::from_lines(fs::read_to_string(path)?.lines())
+    }
+
+    /// Creates a blocklist from lines in the blocklist file format.
+    pub fn from_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> Result<Self, IoError> {
+        let mut titles = HashSet::new();
+        let mut patterns = Vec::new();
+
+        for line in lines.into_iter().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_prefix("re:") {
+                Some(pattern) => patterns.push(pattern),
+                None => {
+                    titles.insert(line.to_string());
+                }
+            }
+        }
+
+        let patterns = if patterns.is_empty() {
+            None
+        } else {
+            Some(RegexSet::new(patterns).map_err(|e| IoError::new(ErrorKind::InvalidData, e))?)
+        };
+
+        Ok(Self { titles, patterns })
+    }
+
+    /// Checks if a page title is blocked.
+    pub fn is_blocked(&self, page_title: &str) -> bool {
+        self.titles.contains(page_title)
+            || self
+                .patterns
+                .as_ref()
+                .is_some_and(|set| set.is_match(page_title))
+    }
+}
+
+/// Reads a list of page titles to match exactly, one per line.
+///
+/// Blank lines are ignored. Spaces are replaced with underscores, as titles in
+/// the dumps use underscores, so titles can be copied as shown on the wiki.
+/// Use the result as `Filter::page_title_exact`, which is checked before
+/// parsing, so even tens of thousands of titles are cheap to look up.
+pub fn titles_from_file(path: &Path) -> Result<HashSet<String>, IoError> {
+    Ok(titles_from_lines(fs::read_to_string(path)?.lines()))
+}
+
+/// Creates a set of page titles from lines in the format of `titles_from_file`.
+pub fn titles_from_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> HashSet<String> {
+    lines
+        .into_iter()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.replace(' ', "_"))
+        .collect()
 }
 
 impl Filter {
+    /// Sets how titles are compared, and brings the title filters set so far
+    /// into the same form.
+    ///
+    /// Only needed when setting up a `Filter` by hand, as `FilterBuilder`
+    /// calls it when building. Call it after setting the title filters.
+    pub fn with_title_matching(mut self, matching: TitleMatching) -> Self {
+        self.title_matching = matching;
+        self.page_title_exact = self.page_title_exact.map(|titles| {
+            titles
+                .iter()
+                .map(|title| matching.normalize(title).into_owned())
+                .collect()
+        });
+        self.page_title_prefix = self
+            .page_title_prefix
+            .map(|prefix| matching.normalize(&prefix).into_owned());
+
+        if matching.case_insensitive {
+            let ignore_case = |regex: Regex| {
+                RegexBuilder::new(regex.as_str())
+                    .case_insensitive(true)
+                    .build()
+                    .expect("Regex was already valid")
+            };
+            self.page_title = self.page_title.map(ignore_case);
+            self.exclude_page_title = self.exclude_page_title.map(ignore_case);
+            self.page_title_by_language = self.page_title_by_language.map(|patterns| {
+                patterns
+                    .into_iter()
+                    .map(|(language, regex)| (language, ignore_case(regex)))
+                    .collect()
+            });
+            self.page_titles_any = self.page_titles_any.map(|set| {
+                RegexSetBuilder::new(set.patterns())
+                    .case_insensitive(true)
+                    .build()
+                    .expect("Regex was already valid")
+            });
+        }
+        self
+    }
+
+    /// Checks if a file with the given timestamp should be read.
+    pub fn matches_time(&self, timestamp: &DateTime<Utc>) -> bool {
+        self.hours
+            .as_ref()
+            .is_none_or(|hours| hours.contains(&timestamp.hour()))
+            && self
+                .weekdays
+                .as_ref()
+                .is_none_or(|days| days.contains(&timestamp.weekday()))
+    }
+
     /// Checks if any filters should be applied before parsing.
     fn has_pre_filters(&self) -> bool {
-        self.line_regex.is_some()
+        self.line_regex.is_some() || self.has_derived_pre_filters()
+    }
+
+    /// Checks if any post-parsing filters can also be checked on raw lines.
+    ///
+    /// Rows rejected by the post-parsing filters are counted when
+    /// `residual_rows` is set, so they must be parsed in that case.
+    fn has_derived_pre_filters(&self) -> bool {
+        !self.residual_rows
+            && !self.negate
+            && (self.domain_codes.is_some()
+                || self.page_title_exact.is_some()
+                || self.page_title_prefix.is_some()
+                || !self.line_languages().is_empty())
+    }
+
+    /// Sets of languages a row must belong to, which can be checked on the
+    /// first part of the raw domain code.
+    ///
+    /// Domain codes of Wikimedia projects like `commons.m` don't start with
+    /// their language, which is always `en`. Sets including `en` can't be
+    /// checked before parsing, and are left out.
+    fn line_languages(&self) -> Vec<HashSet<&str>> {
+        let languages = self
+            .languages
+            .as_ref()
+            .map(|langs| langs.iter().map(String::as_str).collect::<HashSet<_>>());
+        let by_language = self
+            .page_title_by_language
+            .as_ref()
+            .map(|patterns| patterns.keys().map(String::as_str).collect());
+
+        [languages, by_language]
+            .into_iter()
+            .flatten()
+            .filter(|langs| !langs.contains(DEFAULT_LANGUAGE))
+            .collect()
+    }
+
+    /// Checks a page title, in the form of `TitleMatching::normalize`, against
+    /// the string title filters.
+    fn matches_title(&self, title: &str) -> bool {
+        self.page_title_exact
+            .as_ref()
+            .is_none_or(|titles| titles.contains(title))
+            && self
+                .page_title_prefix
+                .as_ref()
+                .is_none_or(|prefix| title.starts_with(prefix.as_str()))
     }
 
     /// Checks if any filters should be applied after parsing.
     fn has_post_filters(&self) -> bool {
         self.domain_codes.is_some()
             || self.page_title.is_some()
+            || self.page_titles_any.is_some()
+            || self.page_title_by_language.is_some()
+            || self.page_title_exact.is_some()
+            || self.page_title_prefix.is_some()
             || self.min_views.is_some()
             || self.max_views.is_some()
             || self.languages.is_some()
             || self.domains.is_some()
+            || self.projects.is_some()
+            || self.hostnames.is_some()
             || self.mobile.is_some()
+            || self.blocklist.is_some()
+            || self.exclude_languages.is_some()
+            || self.exclude_domains.is_some()
+            || self.exclude_page_title.is_some()
+            || self.negate
+            || self.sample.is_some()
     }
 
     /// Filters parsed row objects.
-    fn post_filter(&self, obj: &Pageviews) -> bool {
+    pub(crate) fn post_filter(&self, obj: &Pageviews) -> bool {
+        self.matches_row(&PageviewsRef::from(obj))
+    }
+
+    /// Filters parsed rows borrowed from their lines, see `post_filter`.
+    pub(crate) fn matches_row(&self, obj: &PageviewsRef<'_>) -> bool {
+        let language = obj.parsed_domain_code.language.as_ref();
+        let title = self.title_matching.normalize(&obj.page_title);
+        let title_checks = [
+            self.page_title.as_ref().map(|regex| regex.is_match(&title)),
+            self.page_titles_any
+                .as_ref()
+                .map(|set| set.is_match(&title)),
+            self.page_title_by_language.as_ref().map(|patterns| {
+                patterns
+                    .get(language)
+                    .map(|regex| regex.is_match(&title))
+                    .unwrap_or(false)
+            }),
+            Some(self.matches_title(&title)),
+            self.blocklist
+                .as_ref()
+                .map(|blocklist| !blocklist.is_blocked(&obj.page_title)),
+            self.exclude_page_title
+                .as_ref()
+                .map(|regex| !regex.is_match(&title)),
+        ];
+        self.domain_checks(&obj.domain_code, &obj.parsed_domain_code, obj.views)
+            .into_iter()
+            .chain(title_checks)
+            .all(|check| check.unwrap_or(true))
+            != self.negate
+            && self
+                .sample
+                .is_none_or(|sample| sample.contains(&obj.domain_code, &obj.page_title))
+    }
+
+    /// Checks the filters which don't need the page title, to reject rows
+    /// before their title is read, see `parse::parse_line_bytes`.
+    ///
+    /// Rows passing this must still be checked with `matches_row`. Always
+    /// true with `negate`, where failing a check lets the row through.
+    pub(crate) fn may_match(&self, obj: &RawPageviews<'_>) -> bool {
+        self.negate
+            || self
+                .domain_checks(&obj.domain_code, &obj.parsed_domain_code, obj.views)
+                .into_iter()
+                .all(|check| check.unwrap_or(true))
+    }
+
+    /// Checks the filters on the domain code and views of a row, `None` for
+    /// filters which aren't set.
+    fn domain_checks(
+        &self,
+        domain_code: &str,
+        parsed: &DomainCodeRef<'_>,
+        views: u32,
+    ) -> [Option<bool>; 10] {
+        let language = parsed.language.as_ref();
         [
             self.domain_codes
                 .as_ref()
-                .map(|allowed| allowed.contains(&obj.domain_code)),
-            self.page_title
-                .as_ref()
-                .map(|regex| regex.is_match(&obj.page_title)),
-            self.min_views.map(|min| obj.views >= min),
-            self.max_views.map(|max| obj.views <= max),
+                .map(|allowed| allowed.iter().any(|code| code == domain_code)),
+            self.min_views.map(|min| views >= min),
+            self.max_views.map(|max| views <= max),
             self.languages
                 .as_ref()
-                .map(|langs| langs.contains(&obj.parsed_domain_code.language)),
+                .map(|langs| langs.iter().any(|lang| lang == language)),
             self.domains.as_ref().map(|domains| {
-                obj.parsed_domain_code
+                parsed
                     .domain
-                    .as_ref()
-                    .map(|d| domains.contains(&d.to_string()))
-                    .unwrap_or(false)
+                    .is_some_and(|d| domains.iter().any(|domain| domain == d))
+            }),
+            self.projects.as_ref().map(|projects| {
+                parsed
+                    .project
+                    .is_some_and(|project| projects.contains(&project))
+            }),
+            self.hostnames.as_ref().map(|hostnames| {
+                resolve_domain_code(domain_code)
+                    .is_some_and(|site| hostnames.contains(&site.hostname))
+            }),
+            self.mobile.map(|expected| parsed.mobile == expected),
+            self.exclude_languages
+                .as_ref()
+                .map(|langs| !langs.iter().any(|lang| lang == language)),
+            self.exclude_domains.as_ref().map(|domains| {
+                parsed
+                    .domain
+                    .is_none_or(|d| !domains.iter().any(|excluded| excluded == d))
             }),
-            self.mobile
-                .map(|expected| obj.parsed_domain_code.mobile == expected),
         ]
-        .into_iter()
-        .all(|check| check.unwrap_or(true))
+    }
+
+    /// Checks if the filter has transforms which need owned rows, as they
+    /// combine, reorder, or join them.
+    pub(crate) fn has_transforms(&self) -> bool {
+        self.custom.is_some()
+            || self.merge_mobile
+            || self.min_rank.is_some()
+            || self.max_rank.is_some()
+            || self.page_ids.is_some()
+            || self.title_transform.is_some()
+            || self.residual_rows
+            || self.rollup_namespaces.is_some()
     }
 }
 
@@ -102,6 +662,97 @@ impl FilterBuilder {
         self
     }
 
+    /// Matches page titles against any of several patterns.
+    ///
+    /// The patterns are compiled into one `RegexSet`, and checked in a single
+    /// pass over each title, e.g. to track a list of topics:
+    ///
+    /// ```
+    /// use pvstream::filter::FilterBuilder;
+    ///
+    /// let filter = FilterBuilder::new()
+    ///     .page_titles_any(["^Olympi", "_Olympics$", "^Paralympi"])
+    ///     .build();
+    /// ```
+    pub fn page_titles_any<P: AsRef<str>>(mut self, patterns: impl IntoIterator<Item = P>) -> Self {
+        self.filter.page_titles_any = Some(RegexSet::new(patterns).expect("Invalid regex"));
+        self
+    }
+
+    /// Matches page titles against a separate pattern for each language.
+    ///
+    /// Rows from languages without a pattern are rejected. This makes it
+    /// possible to track the same topic across languages in a single pass,
+    /// e.g. `Coupe_du_monde` on fr and `World_Cup` on en.
+    pub fn page_title_by_language<K: Into<String>, P: AsRef<str>>(
+        mut self,
+        patterns: impl IntoIterator<Item = (K, P)>,
+    ) -> Self {
+        self.filter.page_title_by_language = Some(
+            patterns
+                .into_iter()
+                .map(|(lang, pattern)| {
+                    (
+                        lang.into(),
+                        Regex::new(pattern.as_ref()).expect("Invalid regex"),
+                    )
+                })
+                .collect(),
+        );
+        self
+    }
+
+    /// Matches page titles exactly against a set of titles.
+    pub fn page_title_exact<T: Into<String>>(
+        mut self,
+        titles: impl IntoIterator<Item = T>,
+    ) -> Self {
+        self.filter.page_title_exact = Some(titles.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Matches page titles exactly against a list of titles in a file, in
+    /// addition to any set with `page_title_exact`.
+    ///
+    /// The file has one title per line, see `titles_from_file`. This is meant
+    /// for watchlists, e.g. to get the views of a few thousand articles:
+    ///
+    /// ```no_run
+    /// use pvstream::filter::FilterBuilder;
+    ///
+    /// let filter = FilterBuilder::new()
+    ///     .languages(["en"])
+    ///     .page_titles_from_file("watchlist.txt")?
+    ///     .build();
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn page_titles_from_file(mut self, path: impl AsRef<Path>) -> Result<Self, IoError> {
+        let titles = titles_from_file(path.as_ref())?;
+        self.filter
+            .page_title_exact
+            .get_or_insert_default()
+            .extend(titles);
+        Ok(self)
+    }
+
+    /// Matches page titles starting with `prefix`.
+    pub fn page_title_prefix(mut self, prefix: &str) -> Self {
+        self.filter.page_title_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Compares titles ignoring case in all title filters.
+    pub fn ignore_title_case(mut self, value: bool) -> Self {
+        self.filter.title_matching.case_insensitive = value;
+        self
+    }
+
+    /// Compares titles in Unicode NFC in all title filters.
+    pub fn normalize_titles(mut self, value: bool) -> Self {
+        self.filter.title_matching.normalize_unicode = value;
+        self
+    }
+
     pub fn min_views(mut self, min: u32) -> Self {
         self.filter.min_views = Some(min);
         self
@@ -122,33 +773,373 @@ impl FilterBuilder {
         self
     }
 
+    /// Only keeps rows from these project families, e.g. all Wiktionaries
+    /// with `Project::Wiktionary`, without knowing their domain codes.
+    pub fn projects(mut self, projects: impl IntoIterator<Item = Project>) -> Self {
+        self.filter.projects = Some(projects.into_iter().collect());
+        self
+    }
+
+    /// Only keeps rows from these sites, e.g. `en.m.wikipedia.org` for the
+    /// mobile English Wikipedia, see `Pageviews::hostname`.
+    pub fn hostnames<T: Into<String>>(mut self, hosts: impl IntoIterator<Item = T>) -> Self {
+        self.filter.hostnames = Some(hosts.into_iter().map(Into::into).collect());
+        self
+    }
+
     pub fn mobile(mut self, value: bool) -> Self {
         self.filter.mobile = Some(value);
         self
     }
 
+    pub fn blocklist(mut self, blocklist: Blocklist) -> Self {
+        self.filter.blocklist = Some(blocklist);
+        self
+    }
+
+    /// Rejects rows from these languages.
+    pub fn exclude_languages<T: Into<String>>(
+        mut self,
+        langs: impl IntoIterator<Item = T>,
+    ) -> Self {
+        self.filter.exclude_languages = Some(langs.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Rejects rows from these domains.
+    pub fn exclude_domains<T: Into<String>>(mut self, doms: impl IntoIterator<Item = T>) -> Self {
+        self.filter.exclude_domains = Some(doms.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Rejects rows with page titles matching the pattern.
+    pub fn exclude_page_title(mut self, pattern: &str) -> Self {
+        self.filter.exclude_page_title = Some(Regex::new(pattern).expect("Invalid regex"));
+        self
+    }
+
+    /// Inverts the post-parsing filters, returning the rows they reject.
+    pub fn negate(mut self) -> Self {
+        self.filter.negate = !self.filter.negate;
+        self
+    }
+
+    pub fn rollup_namespaces<T: Into<String>>(
+        mut self,
+        namespaces: impl IntoIterator<Item = T>,
+    ) -> Self {
+        self.filter.rollup_namespaces = Some(namespaces.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn residual_rows(mut self, value: bool) -> Self {
+        self.filter.residual_rows = value;
+        self
+    }
+
+    /// Merges mobile rows into desktop rows, e.g. `en.m` into `en`.
+    pub fn merge_mobile(mut self, value: bool) -> Self {
+        self.filter.merge_mobile = value;
+        self
+    }
+
+    /// Drops the rows ranked above `min` by views within their domain code,
+    /// with 1 for the most viewed.
+    pub fn min_rank(mut self, min: usize) -> Self {
+        self.filter.min_rank = Some(min);
+        self
+    }
+
+    /// Keeps the `max` rows with most views of each domain code.
+    pub fn max_rank(mut self, max: usize) -> Self {
+        self.filter.max_rank = Some(max);
+        self
+    }
+
+    /// Sets the page IDs of the rows from a mapping file, see `join::PageIds`.
+    pub fn page_ids(mut self, page_ids: PageIds) -> Self {
+        self.filter.page_ids = Some(page_ids);
+        self
+    }
+
+    /// Replaces the matches of `pattern` in the page titles of the rows
+    /// returned with `replacement`, which can refer to capture groups.
+    ///
+    /// ```
+    /// use pvstream::filter::FilterBuilder;
+    ///
+    /// // `Paris_(disambiguation)` becomes `Paris`
+    /// let filter = FilterBuilder::new()
+    ///     .title_transform(r"_\(disambiguation\)$", "")
+    ///     .build();
+    /// ```
+    ///
+    /// Panics if `pattern` is not a valid regex.
+    pub fn title_transform(mut self, pattern: &str, replacement: &str) -> Self {
+        self.filter.title_transform =
+            Some(TitleTransform::new(pattern, replacement).expect("Invalid regex"));
+        self
+    }
+
+    pub fn hours(mut self, hours: impl IntoIterator<Item = u32>) -> Self {
+        self.filter.hours = Some(hours.into_iter().collect());
+        self
+    }
+
+    pub fn weekdays(mut self, days: impl IntoIterator<Item = Weekday>) -> Self {
+        self.filter.weekdays = Some(days.into_iter().collect());
+        self
+    }
+
+    pub fn agents(mut self, agents: impl IntoIterator<Item = AgentType>) -> Self {
+        self.filter.agents = Some(agents.into_iter().collect());
+        self
+    }
+
+    /// Keeps a deterministic `fraction` of the rows, picked by `seed`.
+    ///
+    /// The same pages are picked from every file, which makes this useful
+    /// for building small test datasets from full dumps:
+    ///
+    /// ```
+    /// use pvstream::filter::FilterBuilder;
+    ///
+    /// let filter = FilterBuilder::new().languages(["de"]).sample(0.01, 42).build();
+    /// ```
+    ///
+    /// Panics if `fraction` is not between 0 and 1.
+    pub fn sample(mut self, fraction: f64, seed: u64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "Sample fraction must be between 0 and 1"
+        );
+        self.filter.sample = Some(Sample { fraction, seed });
+        self
+    }
+
+    /// Keeps only the rows for which `predicate` returns true, for conditions
+    /// the other filters don't cover.
+    ///
+    /// ```
+    /// use pvstream::filter::FilterBuilder;
+    ///
+    /// // Titles with a year in them, like `2024_Summer_Olympics`
+    /// let filter = FilterBuilder::new()
+    ///     .custom(|row| {
+    ///         let is_year = |word: &str| word.len() == 4 && word.parse::<u16>().is_ok();
+    ///         row.page_title.split('_').any(is_year)
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn custom(
+        mut self,
+        predicate: impl Fn(&Pageviews) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.filter.custom = Some(CustomFilter::new(predicate));
+        self
+    }
+
     pub fn build(self) -> Filter {
-        self.filter
+        let matching = self.filter.title_matching;
+        self.filter.with_title_matching(matching)
     }
 }
 
 type PreFilterFn<E> = Box<dyn Fn(&Result<String, E>) -> bool + Send + Sync>;
 type PostFilterFn<E> = Box<dyn Fn(&Result<Pageviews, E>) -> bool + Send + Sync>;
 
-/// Filters raw lines by a regular expression.
+/// Filters raw lines by a regular expression and the string title filters.
 ///
 /// Optional filter for lines from the pageviews file. Applied before parsing,
 /// which makes it possible to significantly reduce the amount of parsing in
 /// cases where we're only looking for a subset of the file.
 pub fn pre_filter<E>(filter: &Filter) -> PreFilterFn<E> {
-    if filter.has_pre_filters() {
-        let regex = filter.line_regex.clone().unwrap();
-        return Box::new(move |line| match line {
-            Ok(line) => regex.is_match(line),
+    match LineFilter::new(filter) {
+        Some(keep) => Box::new(move |line| match line {
+            Ok(line) => keep.matches(line.as_bytes()),
             Err(_) => true, // Pass through to handle later
-        });
+        }),
+        None => Box::new(|_| true),
     }
-    Box::new(|_| true)
+}
+
+/// Line filters compiled to check the bytes of raw lines, see `pre_filter`.
+///
+/// Plain `line_regex` patterns, and the filters on domain codes and languages,
+/// are checked with `memchr` and `memmem` substring search instead of a regex
+/// or splitting the line into strings. A regex is only used for patterns
+/// which aren't literals.
+pub(crate) struct LineFilter {
+    filter: Filter,
+    line_regex: Option<LineMatcher>,
+    derived: bool,
+    languages: Vec<HashSet<Vec<u8>>>,
+}
+
+impl LineFilter {
+    /// Compiles the line filters, `None` if there are none.
+    pub(crate) fn new(filter: &Filter) -> Option<Self> {
+        if !filter.has_pre_filters() {
+            return None;
+        }
+        let languages = filter
+            .line_languages()
+            .into_iter()
+            .map(|langs| {
+                langs
+                    .into_iter()
+                    .map(|lang| lang.as_bytes().to_vec())
+                    .collect()
+            })
+            .collect();
+        Some(Self {
+            filter: filter.clone(),
+            line_regex: filter.line_regex.as_ref().map(LineMatcher::new),
+            derived: filter.has_derived_pre_filters(),
+            languages,
+        })
+    }
+
+    /// Checks a raw line against the line filters.
+    pub(crate) fn matches(&self, line: &[u8]) -> bool {
+        self.line_regex
+            .as_ref()
+            .is_none_or(|matcher| matcher.is_match(line))
+            && (!self.derived || self.matches_fields(line))
+    }
+
+    /// Checks a raw line against the filters which can be derived from the
+    /// post-parsing filters.
+    ///
+    /// Only fields in plain form are checked. Lines with missing, quoted, or
+    /// invalid UTF-8 fields are passed through, to be handled by the parser.
+    fn matches_fields(&self, line: &[u8]) -> bool {
+        let domain_end = memchr(b' ', line).unwrap_or(line.len());
+        let domain_code = &line[..domain_end];
+        let title = line
+            .get(domain_end + 1..)
+            .map(|rest| match memchr(b' ', rest) {
+                Some(end) => &rest[..end],
+                None => rest,
+            });
+
+        let domain_code_matches = domain_code.starts_with(b"\"") || {
+            let language = match memchr(b'.', domain_code) {
+                Some(end) => &domain_code[..end],
+                None => domain_code,
+            };
+            self.filter
+                .domain_codes
+                .as_ref()
+                .is_none_or(|codes| codes.iter().any(|code| code.as_bytes() == domain_code))
+                && self.languages.iter().all(|langs| langs.contains(language))
+        };
+
+        let filter = &self.filter;
+        domain_code_matches
+            && title
+                .filter(|title| !title.starts_with(b"\""))
+                .and_then(|title| std::str::from_utf8(title).ok())
+                .is_none_or(|title| filter.matches_title(&filter.title_matching.normalize(title)))
+    }
+}
+
+/// A `line_regex` pattern, searched for without a regex if it's a literal.
+enum LineMatcher {
+    /// `^` followed by a literal, compared with the start of the line
+    Prefix(Vec<u8>),
+    /// A literal anywhere in the line, found with SIMD accelerated `memmem`
+    Contains(Box<Finder<'static>>),
+    /// Any other pattern
+    Regex(Regex),
+}
+
+impl LineMatcher {
+    fn new(regex: &Regex) -> Self {
+        let pattern = regex.as_str();
+        let is_literal = |pattern: &str| regex::escape(pattern) == pattern;
+        match pattern.strip_prefix('^') {
+            Some(prefix) if is_literal(prefix) => LineMatcher::Prefix(prefix.as_bytes().to_vec()),
+            None if is_literal(pattern) => {
+                LineMatcher::Contains(Box::new(Finder::new(pattern).into_owned()))
+            }
+            _ => LineMatcher::Regex(regex.clone()),
+        }
+    }
+
+    fn is_match(&self, line: &[u8]) -> bool {
+        match self {
+            LineMatcher::Prefix(prefix) => line.starts_with(prefix),
+            LineMatcher::Contains(finder) => finder.find(line).is_some(),
+            LineMatcher::Regex(regex) => match std::str::from_utf8(line) {
+                Ok(line) => regex.is_match(line),
+                // Passed through, for the parser to report
+                Err(_) => true,
+            },
+        }
+    }
+}
+
+/// The smallest domain code the filters can match.
+///
+/// In a file sorted by domain code, no line before the first one with this
+/// domain code or a larger one can match, see `seek::seek_domain_code`.
+/// Returns `None` under the same conditions as `end_of_range`.
+pub fn start_of_range(filter: &Filter) -> Option<String> {
+    if !filter.has_derived_pre_filters() {
+        return None;
+    }
+    let first_code = filter
+        .domain_codes
+        .as_ref()
+        .and_then(|codes| codes.iter().min().cloned());
+    // Domain codes of `de` are `de` and `de.*`, which all sort after `de`
+    let first_language = filter
+        .line_languages()
+        .iter()
+        .filter_map(|langs| langs.iter().min().map(|lang| lang.to_string()))
+        .max();
+    first_code.into_iter().chain(first_language).max()
+}
+
+/// Checks if a raw line is past the domain codes the filters can match.
+///
+/// The dumps are sorted by domain code, so once a line is past the last
+/// domain code in `domain_codes`, or the last domain code of the languages in
+/// `languages` and `page_title_by_language`, no later line can match, and the
+/// rest of the file can be skipped. Returns `None` if the filters don't
+/// restrict the range, or if the rejected rows are needed.
+pub fn end_of_range<E>(filter: &Filter) -> Option<PreFilterFn<E>> {
+    if !filter.has_derived_pre_filters() {
+        return None;
+    }
+    let last_code = filter
+        .domain_codes
+        .as_ref()
+        .and_then(|codes| codes.iter().max().cloned());
+    // Domain codes of `de` are `de` and `de.*`, which all sort before `de/`
+    let language_end = filter
+        .line_languages()
+        .iter()
+        .filter_map(|langs| langs.iter().max().map(|lang| format!("{lang}/")))
+        .min();
+    if last_code.is_none() && language_end.is_none() {
+        return None;
+    }
+
+    Some(Box::new(move |line| match line {
+        Ok(line) => {
+            let domain_code = line.split(' ').next().unwrap_or_default();
+            !domain_code.starts_with('"')
+                && (last_code
+                    .as_ref()
+                    .is_some_and(|last| domain_code > last.as_str())
+                    || language_end
+                        .as_ref()
+                        .is_some_and(|end| domain_code >= end.as_str()))
+        }
+        Err(_) => false, // Pass through to handle later
+    }))
 }
 
 pub fn post_filter<E>(filter: &Filter) -> PostFilterFn<E> {
@@ -166,6 +1157,7 @@ pub fn post_filter<E>(filter: &Filter) -> PostFilterFn<E> {
 mod tests {
     use super::*;
     use crate::parse::DomainCode;
+    use crate::testing::TestDir;
 
     fn make_lines() -> (String, String) {
         let str1 = "en Main_Page 1000 0".to_string();
@@ -183,7 +1175,13 @@ mod tests {
                 language: "en".to_string(),
                 domain: Some("wikipedia.org"),
                 mobile: false,
+                project: Some(Project::Wikipedia),
             },
+            timestamp: None,
+            response_size: Some(0),
+            hourly_views: None,
+            page_id: None,
+            line_number: None,
         };
 
         let pv2 = Pageviews {
@@ -194,7 +1192,13 @@ mod tests {
                 language: "de".to_string(),
                 domain: Some("wikipedia.de"),
                 mobile: true,
+                project: Some(Project::Wikipedia),
             },
+            timestamp: None,
+            response_size: Some(0),
+            hourly_views: None,
+            page_id: None,
+            line_number: None,
         };
 
         (pv1, pv2)
@@ -215,6 +1219,49 @@ mod tests {
         assert!(pre(&Err(())));
     }
 
+    #[test]
+    fn test_line_matcher() {
+        let matcher = |pattern: &str| LineMatcher::new(&Regex::new(pattern).unwrap());
+        let (en, de) = make_lines();
+        let (en, de) = (en.as_bytes(), de.as_bytes());
+
+        let prefix = matcher("^de.m ");
+        assert!(matches!(prefix, LineMatcher::Regex(_)));
+        let prefix = matcher("^de ");
+        assert!(matches!(prefix, LineMatcher::Prefix(_)));
+        assert!(!prefix.is_match(de) && !prefix.is_match(en));
+
+        let contains = matcher("Start");
+        assert!(matches!(contains, LineMatcher::Contains(_)));
+        assert!(contains.is_match(de) && !contains.is_match(en));
+
+        let regex = matcher("(?i)main_page");
+        assert!(matches!(regex, LineMatcher::Regex(_)));
+        assert!(regex.is_match(en) && !regex.is_match(de));
+        assert!(regex.is_match(b"en \xff 1 0"));
+    }
+
+    #[test]
+    fn test_line_filter_bytes() {
+        let filter = FilterBuilder::new()
+            .domain_codes(["de.m"])
+            .page_title_prefix("Start")
+            .build();
+        let keep = LineFilter::new(&filter).unwrap();
+        assert!(keep.matches(b"de.m Startseite 500 0"));
+        assert!(!keep.matches(b"de Startseite 500 0"));
+        assert!(!keep.matches(b"de.m Hauptseite 500 0"));
+        // Quoted and invalid fields are left to the parser
+        assert!(keep.matches(br#""de" Startseite 500 0"#));
+        assert!(keep.matches(b"de.m \xff 500 0"));
+
+        let filter = FilterBuilder::new().languages(["de"]).build();
+        let keep = LineFilter::new(&filter).unwrap();
+        assert!(keep.matches(b"de.m.b Startseite 500 0"));
+        assert!(!keep.matches(b"dsb Startseite 500 0"));
+        assert!(LineFilter::new(&FilterBuilder::new().build()).is_none());
+    }
+
     #[test]
     fn test_default_filter() {
         let (en, de) = make_pageviews();
@@ -263,4 +1310,406 @@ mod tests {
         assert!(!post(&Ok(en)));
         assert!(post(&Ok(de)));
     }
+
+    #[test]
+    fn test_page_title_by_language() {
+        let (en, de) = make_pageviews();
+        let filters = FilterBuilder::new()
+            .page_title_by_language([("en", "^Main"), ("fr", "^Accueil")])
+            .build();
+
+        assert!(filters.has_post_filters());
+
+        let post = post_filter::<()>(&filters);
+
+        assert!(post(&Ok(en)));
+        assert!(!post(&Ok(de))); // No pattern for German
+    }
+
+    #[test]
+    fn test_page_titles_any() {
+        let (en, de) = make_pageviews();
+        let filters = FilterBuilder::new()
+            .page_titles_any(["^Main_", "^Haupt", "seite$"])
+            .build();
+
+        assert!(!filters.has_pre_filters());
+        assert!(filters.has_post_filters());
+
+        let post = post_filter::<()>(&filters);
+
+        assert!(post(&Ok(en.clone())));
+        assert!(post(&Ok(de)));
+
+        let filters = FilterBuilder::new().page_titles_any(["^Haupt"]).build();
+        assert!(!post_filter::<()>(&filters)(&Ok(en)));
+    }
+
+    #[test]
+    fn test_page_title_exact_and_prefix() {
+        let (en, de) = make_pageviews();
+        let (en_line, de_line) = make_lines();
+
+        let filters = FilterBuilder::new()
+            .page_title_exact(["Main_Page", "Hauptseite"])
+            .build();
+        assert!(filters.has_pre_filters());
+
+        let pre = pre_filter::<()>(&filters);
+        assert!(pre(&Ok(en_line.clone())));
+        assert!(!pre(&Ok(de_line.clone())));
+        assert!(!pre(&Ok("en Main_Page_2 1 0".to_string())));
+        assert!(pre(&Ok("en".to_string()))); // Left for the parser to report
+
+        let post = post_filter::<()>(&filters);
+        assert!(post(&Ok(en.clone())));
+        assert!(!post(&Ok(de.clone())));
+
+        let filters = FilterBuilder::new().page_title_prefix("Start").build();
+        let pre = pre_filter::<()>(&filters);
+        assert!(!pre(&Ok(en_line)));
+        assert!(pre(&Ok(de_line)));
+        assert!(!pre(&Ok("de Der_Start 1 0".to_string())));
+
+        let post = post_filter::<()>(&filters);
+        assert!(!post(&Ok(en)));
+        assert!(post(&Ok(de)));
+    }
+
+    #[test]
+    fn test_title_matching() {
+        let (en, de) = make_pageviews();
+        let (en_line, _) = make_lines();
+
+        // Spaces always match underscores
+        let filters = FilterBuilder::new().page_title_exact(["Main Page"]).build();
+        assert!(pre_filter::<()>(&filters)(&Ok(en_line.clone())));
+        assert!(post_filter::<()>(&filters)(&Ok(en.clone())));
+
+        let filters = FilterBuilder::new()
+            .page_title_exact(["main page"])
+            .page_title_prefix("START")
+            .build();
+        assert!(!pre_filter::<()>(&filters)(&Ok(en_line.clone())));
+
+        let filters = FilterBuilder::new()
+            .page_title_exact(["main page", "startseite"])
+            .page_title("^(MAIN|START)")
+            .ignore_title_case(true)
+            .build();
+        assert!(pre_filter::<()>(&filters)(&Ok(en_line)));
+        assert!(post_filter::<()>(&filters)(&Ok(en)));
+        assert!(post_filter::<()>(&filters)(&Ok(de)));
+
+        let mut decomposed = make_pageviews().0;
+        decomposed.page_title = "Cafe\u{301}".to_string();
+        let filters = FilterBuilder::new().page_title_exact(["Caf\u{e9}"]);
+        assert!(!post_filter::<()>(&filters.build())(
+            &Ok(decomposed.clone())
+        ));
+        let filters = FilterBuilder::new()
+            .page_title_exact(["Caf\u{e9}"])
+            .normalize_titles(true)
+            .build();
+        assert!(post_filter::<()>(&filters)(&Ok(decomposed.clone())));
+
+        let filters = FilterBuilder::new()
+            .page_titles_any(["^CAFÉ$"])
+            .exclude_page_title("^café$")
+            .normalize_titles(true)
+            .ignore_title_case(true)
+            .build();
+        assert!(!post_filter::<()>(&filters)(&Ok(decomposed)));
+    }
+
+    #[test]
+    fn test_derived_pre_filter() {
+        let (en, de) = make_lines();
+
+        let filters = FilterBuilder::new().domain_codes(["de.m"]).build();
+        assert!(filters.has_pre_filters());
+        let pre = pre_filter::<()>(&filters);
+        assert!(!pre(&Ok(en.clone())));
+        assert!(pre(&Ok(de.clone())));
+        assert!(pre(&Ok(r#""de.m" Startseite 1 0"#.to_string()))); // Quoted
+
+        let filters = FilterBuilder::new().languages(["de", "fr"]).build();
+        let pre = pre_filter::<()>(&filters);
+        assert!(!pre(&Ok(en.clone())));
+        assert!(pre(&Ok(de.clone())));
+        assert!(pre(&Ok("fr.m.b Livre 1 0".to_string())));
+        assert!(!pre(&Ok("commons.m Category:Paris 1 0".to_string())));
+
+        // `commons.m` is an English domain code, so `en` can't be derived
+        let filters = FilterBuilder::new().languages(["en", "de"]).build();
+        assert!(!filters.has_pre_filters());
+
+        let filters = FilterBuilder::new()
+            .page_title_by_language([("fr", "^Accueil")])
+            .build();
+        let pre = pre_filter::<()>(&filters);
+        assert!(!pre(&Ok(de.clone())));
+
+        // Rejected rows are counted as residual rows, and must be parsed
+        let filters = FilterBuilder::new()
+            .domain_codes(["de.m"])
+            .residual_rows(true)
+            .build();
+        assert!(!filters.has_pre_filters());
+    }
+
+    #[test]
+    fn test_end_of_range() {
+        let line = |code: &str| Ok(format!("{code} Title 1 0"));
+
+        let filters = FilterBuilder::new().domain_codes(["de", "da.m"]).build();
+        let past = end_of_range::<()>(&filters).unwrap();
+        assert!(!past(&line("da")));
+        assert!(!past(&line("de")));
+        assert!(past(&line("de.m")));
+        assert!(!past(&line(r#""en""#))); // Quoted
+        assert!(!past(&Err(())));
+
+        // `de-x` sorts between `de` and `de.m`, so the range ends at `de/`
+        let filters = FilterBuilder::new().languages(["da", "de"]).build();
+        let past = end_of_range::<()>(&filters).unwrap();
+        assert!(!past(&line("de-x")));
+        assert!(!past(&line("de.m.b")));
+        assert!(past(&line("dea")));
+        assert!(past(&line("en")));
+
+        // No range to end, or the rejected rows are needed
+        let filters = FilterBuilder::new().languages(["en"]).build();
+        assert!(end_of_range::<()>(&filters).is_none());
+        let filters = FilterBuilder::new().page_title_prefix("Main").build();
+        assert!(end_of_range::<()>(&filters).is_none());
+        let filters = FilterBuilder::new().domain_codes(["de"]).negate().build();
+        assert!(end_of_range::<()>(&filters).is_none());
+    }
+
+    #[test]
+    fn test_start_of_range() {
+        let start = |builder: FilterBuilder| start_of_range(&builder.build());
+
+        assert_eq!(
+            start(FilterBuilder::new().domain_codes(["de.m", "da"])),
+            Some("da".into())
+        );
+        assert_eq!(
+            start(FilterBuilder::new().languages(["fr", "de"])),
+            Some("de".into())
+        );
+        assert_eq!(
+            start(
+                FilterBuilder::new()
+                    .domain_codes(["de.m", "fr"])
+                    .languages(["fr"])
+            ),
+            Some("fr".into())
+        );
+        assert_eq!(start(FilterBuilder::new().languages(["en", "de"])), None);
+        assert_eq!(start(FilterBuilder::new().min_views(10)), None);
+    }
+
+    #[test]
+    fn test_exclusions() {
+        let (en, de) = make_pageviews();
+
+        let filters = FilterBuilder::new().exclude_languages(["en"]).build();
+        let post = post_filter::<()>(&filters);
+        assert!(!post(&Ok(en.clone())));
+        assert!(post(&Ok(de.clone())));
+
+        let filters = FilterBuilder::new()
+            .exclude_domains(["wikipedia.de"])
+            .build();
+        let post = post_filter::<()>(&filters);
+        assert!(post(&Ok(en.clone())));
+        assert!(!post(&Ok(de.clone())));
+
+        let filters = FilterBuilder::new()
+            .exclude_page_title("^(Special|Talk):|^Main_")
+            .build();
+        let post = post_filter::<()>(&filters);
+        assert!(!post(&Ok(en)));
+        assert!(post(&Ok(de)));
+    }
+
+    #[test]
+    fn test_projects() {
+        let (en, mut de) = make_pageviews();
+        de.parsed_domain_code.project = Some(Project::Wiktionary);
+
+        let filters = FilterBuilder::new()
+            .projects([Project::Wiktionary, Project::Wikibooks])
+            .build();
+        assert!(filters.has_post_filters());
+        let post = post_filter::<()>(&filters);
+        assert!(!post(&Ok(en)));
+        assert!(post(&Ok(de.clone())));
+
+        de.parsed_domain_code.project = None;
+        assert!(!post(&Ok(de)));
+    }
+
+    #[test]
+    fn test_hostnames() {
+        let (en, de) = make_pageviews();
+        assert_eq!(de.hostname().as_deref(), Some("de.m.wikipedia.org"));
+
+        let filters = FilterBuilder::new()
+            .hostnames(["de.m.wikipedia.org", "de.wikibooks.org"])
+            .build();
+        assert!(filters.has_post_filters());
+        let post = post_filter::<()>(&filters);
+        assert!(!post(&Ok(en)));
+        assert!(post(&Ok(de)));
+    }
+
+    #[test]
+    fn test_may_match() {
+        use crate::parse::{ParseMode, parse_line_bytes};
+
+        let en = parse_line_bytes(b"en Main_Page 1000 0", ParseMode::Lenient).unwrap();
+        let de = parse_line_bytes(b"de.m \xff 500 0", ParseMode::Lenient).unwrap();
+
+        let filters = FilterBuilder::new()
+            .languages(["en"])
+            .min_views(100)
+            .build();
+        assert!(filters.may_match(&en));
+        assert!(!filters.may_match(&de));
+
+        // Title filters are left to `matches_row`
+        let filters = FilterBuilder::new().page_title("^Main").build();
+        assert!(filters.may_match(&de));
+
+        let filters = FilterBuilder::new().languages(["en"]).negate().build();
+        assert!(filters.may_match(&en) && filters.may_match(&de));
+    }
+
+    #[test]
+    fn test_negate() {
+        let (en, de) = make_pageviews();
+        let (en_line, _) = make_lines();
+        let filters = FilterBuilder::new().domain_codes(["en"]).negate().build();
+
+        // Lines can't be rejected before parsing, as the result is inverted
+        assert!(!filters.has_pre_filters());
+        assert!(pre_filter::<()>(&filters)(&Ok(en_line)));
+
+        let post = post_filter::<()>(&filters);
+        assert!(!post(&Ok(en)));
+        assert!(post(&Ok(de)));
+        assert!(post(&Err(())));
+    }
+
+    #[test]
+    fn test_sample() {
+        let (en, de) = make_pageviews();
+        let all = FilterBuilder::new().sample(1.0, 0).build();
+        let none = FilterBuilder::new().sample(0.0, 0).build();
+
+        assert!(all.has_post_filters());
+        assert!(all.post_filter(&en) && all.post_filter(&de));
+        assert!(!none.post_filter(&en) && !none.post_filter(&de));
+
+        // The same pages are picked on every call, roughly at the fraction
+        let sample = Sample {
+            fraction: 0.25,
+            seed: 7,
+        };
+        let picked: Vec<bool> = (0..10_000)
+            .map(|i| sample.contains("en", &format!("Page_{i}")))
+            .collect();
+        let again: Vec<bool> = (0..10_000)
+            .map(|i| sample.contains("en", &format!("Page_{i}")))
+            .collect();
+        let count = picked.iter().filter(|&&p| p).count();
+        assert_eq!(picked, again);
+        assert!((2_300..2_700).contains(&count));
+
+        // Another seed picks other pages
+        let reseeded = Sample { seed: 8, ..sample };
+        assert!((0..10_000).any(|i| {
+            let title = format!("Page_{i}");
+            sample.contains("en", &title) != reseeded.contains("en", &title)
+        }));
+    }
+
+    #[test]
+    #[should_panic(expected = "between 0 and 1")]
+    fn test_sample_invalid_fraction() {
+        FilterBuilder::new().sample(1.5, 0);
+    }
+
+    #[test]
+    fn test_blocklist() {
+        let (en, de) = make_pageviews();
+        let blocklist =
+            Blocklist::from_lines(["# Comment", "", "Main_Page", "re:^Special:"]).unwrap();
+
+        assert!(blocklist.is_blocked("Main_Page"));
+        assert!(blocklist.is_blocked("Special:Search"));
+        assert!(!blocklist.is_blocked("Main_Page_2"));
+        assert!(!blocklist.is_blocked("# Comment"));
+
+        let filters = FilterBuilder::new().blocklist(blocklist).build();
+        let post = post_filter::<()>(&filters);
+
+        assert!(!post(&Ok(en)));
+        assert!(post(&Ok(de)));
+    }
+
+    #[test]
+    fn test_page_titles_from_file() {
+        let (en, de) = make_pageviews();
+        let dir = TestDir::new("page-titles-from-file");
+        let path = dir.join("titles.txt");
+        fs::write(&path, "Main Page\r\n\nHauptseite\n").unwrap();
+
+        let filters = FilterBuilder::new()
+            .page_title_exact(["Startseite"])
+            .page_titles_from_file(&path)
+            .unwrap()
+            .build();
+
+        assert_eq!(
+            filters.page_title_exact,
+            Some(titles_from_lines(["Main_Page", "Hauptseite", "Startseite"]))
+        );
+        let post = post_filter::<()>(&filters);
+        assert!(post(&Ok(en)));
+        assert!(post(&Ok(de)));
+
+        let err = FilterBuilder::new()
+            .page_titles_from_file(Path::new("no/such/file.txt"))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_blocklist_invalid_regex() {
+        let err = Blocklist::from_lines(["re:(unclosed"]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_matches_time() {
+        let saturday_evening = "2024-08-03T19:00:00Z".parse().unwrap();
+        let monday_evening = "2024-08-05T19:00:00Z".parse().unwrap();
+        let saturday_morning = "2024-08-03T08:00:00Z".parse().unwrap();
+
+        let filters = FilterBuilder::new().build();
+        assert!(filters.matches_time(&saturday_morning));
+
+        let filters = FilterBuilder::new()
+            .hours(18..=23)
+            .weekdays([Weekday::Sat, Weekday::Sun])
+            .build();
+
+        assert!(filters.matches_time(&saturday_evening));
+        assert!(!filters.matches_time(&monday_evening));
+        assert!(!filters.matches_time(&saturday_morning));
+    }
 }
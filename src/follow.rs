@@ -0,0 +1,342 @@
+//! Following the dumps as new hours are published.
+//!
+//! Wikimedia publishes the pageviews of each hour some time after the hour
+//! ends. `follow` streams every hour from a starting point, catching up on the
+//! hours already published and then waiting for each new one to appear, which
+//! makes pvstream usable as a near-real-time ingestion component.
+//!
+//! The server is polled with the directory listing of the month, see
+//! `urls::list_available_hours`, so waiting costs a single small request per
+//! poll. Hours which are never published are skipped once a later hour shows
+//! up, instead of waiting for them forever.
+
+use crate::cancel::{self, CancellationToken};
+use crate::filter::Filter;
+use crate::parse::ParseError;
+use crate::stream::{Source, StreamConfig, StreamError};
+use crate::urls::Mirror;
+use crate::{RowIterator, stream_from_source};
+use chrono::{DateTime, Datelike, DurationRound, Months, TimeDelta, Utc};
+use std::io::Error as IoError;
+use std::iter::once;
+use std::time::Duration;
+
+/// Settings for following the dumps, see `follow`.
+#[derive(Clone, Debug)]
+pub struct FollowOptions {
+    /// Server to poll and download the files from.
+    pub mirror: Mirror,
+    /// Wait between polls while the next hour isn't published yet, and after
+    /// a failed poll.
+    pub poll_interval: Duration,
+    /// Stop before this hour, `None` to follow the dumps forever.
+    pub until: Option<DateTime<Utc>>,
+    /// Settings for polling and reading the files.
+    pub config: StreamConfig,
+}
+
+impl Default for FollowOptions {
+    fn default() -> Self {
+        Self {
+            mirror: Mirror::default(),
+            poll_interval: Duration::from_secs(300),
+            until: None,
+            config: StreamConfig::default(),
+        }
+    }
+}
+
+/// Whether an hour has been published.
+#[derive(Debug, PartialEq)]
+enum Published {
+    Yes,
+    NotYet,
+    /// A later hour has been published instead
+    Never,
+}
+
+/// Iterator over the published hours, from a starting point and on.
+///
+/// Blocks until the next hour is published. A failed poll is yielded as an
+/// error, and polled again after `FollowOptions::poll_interval`. Ends once
+/// `StreamConfig::cancel` of the options is cancelled, also while waiting.
+pub struct Hours {
+    next: DateTime<Utc>,
+    options: FollowOptions,
+    /// Hours in the latest listing, with the year and month it lists
+    listing: Option<(i32, u32, Vec<DateTime<Utc>>)>,
+    failed: bool,
+}
+
+impl Hours {
+    /// Waits for the poll interval. Returns whether the wait was cancelled.
+    fn wait(&self) -> bool {
+        cancel::sleep(
+            self.options.config.cancel.as_ref(),
+            self.options.poll_interval,
+        )
+    }
+
+    /// Lists the hours of the month of `hour`, unless the latest listing is
+    /// of the same month and already has it.
+    fn listing(&mut self, hour: DateTime<Utc>) -> Result<&[DateTime<Utc>], StreamError> {
+        let (year, month) = (hour.year(), hour.month());
+        let fresh = matches!(
+            &self.listing,
+            Some((y, m, hours)) if (*y, *m) == (year, month) && hours.contains(&hour)
+        );
+        if !fresh {
+            let hours =
+                self.options
+                    .mirror
+                    .list_available_hours(year, month, &self.options.config)?;
+            self.listing = Some((year, month, hours));
+        }
+        Ok(self.listing.as_ref().map_or(&[], |(_, _, hours)| hours))
+    }
+
+    fn published(&mut self, hour: DateTime<Utc>) -> Result<Published, StreamError> {
+        let hours = self.listing(hour)?;
+        if hours.contains(&hour) {
+            return Ok(Published::Yes);
+        }
+        if hours.last().is_some_and(|last| *last > hour) {
+            return Ok(Published::Never);
+        }
+        // The last hours of a month are only known to be missing once the
+        // next month has files
+        let next_month = hour
+            .date_naive()
+            .with_day(1)
+            .and_then(|first| first.checked_add_months(Months::new(1)))
+            .filter(|next_month| *next_month <= Utc::now().date_naive());
+        if let Some(next_month) = next_month {
+            let later = self.options.mirror.list_available_hours(
+                next_month.year(),
+                next_month.month(),
+                &self.options.config,
+            )?;
+            if !later.is_empty() {
+                return Ok(Published::Never);
+            }
+        }
+        Ok(Published::NotYet)
+    }
+}
+
+impl Iterator for Hours {
+    type Item = Result<DateTime<Utc>, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let cancel = self.options.config.cancel.as_ref();
+            if self.options.until.is_some_and(|until| self.next >= until)
+                || cancel.is_some_and(CancellationToken::is_cancelled)
+            {
+                return None;
+            }
+            if std::mem::take(&mut self.failed) && self.wait() {
+                return None;
+            }
+            match self.published(self.next) {
+                Ok(Published::Yes) => {
+                    let hour = self.next;
+                    self.next += TimeDelta::hours(1);
+                    return Some(Ok(hour));
+                }
+                Ok(Published::Never) => self.next += TimeDelta::hours(1),
+                Ok(Published::NotYet) => {
+                    if self.wait() {
+                        return None;
+                    }
+                }
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Yields each hour from `start` as soon as its file has been published.
+///
+/// `start` is rounded down to the start of its hour. Hours already published
+/// are yielded right away.
+pub fn follow_hours(start: DateTime<Utc>, options: FollowOptions) -> Hours {
+    let hour = TimeDelta::hours(1);
+    Hours {
+        next: start.duration_trunc(hour).unwrap_or(start),
+        options,
+        listing: None,
+        failed: false,
+    }
+}
+
+/// Streams the rows of every hour from `start`, waiting for each new hour to
+/// be published.
+///
+/// This is `stream_from_range` without an end: the iterator catches up on the
+/// hours already published, then blocks until the next one appears, unless
+/// `FollowOptions::until` is reached. Each row has the hour it was recorded
+/// in its `timestamp` field. Failed polls and downloads are yielded as a
+/// `ParseError::ReadError`, and the iterator carries on.
+///
+/// # Example
+///
+/// ```no_run
+/// use chrono::{TimeDelta, Utc};
+/// use pvstream::filter::FilterBuilder;
+/// use pvstream::follow::{FollowOptions, follow};
+///
+/// let filter = FilterBuilder::new().page_title("^Rust$").build();
+/// let start = Utc::now() - TimeDelta::hours(6);
+///
+/// for result in follow(start, &filter, FollowOptions::default()) {
+///     let row = result?;
+///     println!("{}: {}", row.timestamp.unwrap(), row.views);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn follow(start: DateTime<Utc>, filter: &Filter, options: FollowOptions) -> RowIterator {
+    let filter = filter.clone();
+    let mirror = options.mirror.clone();
+    let config = options.config.clone();
+    Box::new(follow_hours(start, options).flat_map(move |hour| {
+        let rows = hour.and_then(|hour| {
+            let source = Source::Url(mirror.dump_url(&hour));
+            stream_from_source(&source, &filter, &config)
+        });
+        match rows {
+            Ok(rows) => rows,
+            Err(err) => Box::new(once(Err(ParseError::ReadError(IoError::other(err))))),
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+    use chrono::TimeZone;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// Serves the listings of August 2024 in turn, one per request, and the
+    /// test file. Returns the mirror and the paths requested.
+    fn dump_server(listings: Vec<&'static str>) -> (Mirror, Arc<Mutex<Vec<String>>>) {
+        let data = std::fs::read("tests/files/pageviews-20240803-060000.gz").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}/pageviews/", listener.local_addr().unwrap());
+        let requested = Arc::new(Mutex::new(Vec::new()));
+
+        let paths = requested.clone();
+        std::thread::spawn(move || {
+            let mut listings = listings.into_iter();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                BufReader::new(&stream).read_line(&mut request).unwrap();
+                let path = request.split(' ').nth(1).unwrap().to_string();
+                paths.lock().unwrap().push(path.clone());
+
+                let (status, body) = match path.as_str() {
+                    "/pageviews/2024/2024-08/" => match listings.next() {
+                        Some(listing) => ("200 OK", listing.as_bytes()),
+                        None => ("500 Internal Server Error", &b""[..]),
+                    },
+                    "/pageviews/2024/2024-08/pageviews-20240803-060000.gz" => ("200 OK", &data[..]),
+                    _ => ("404 Not Found", &b""[..]),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+        (Mirror::new(&base).unwrap(), requested)
+    }
+
+    #[test]
+    fn test_follow() {
+        let (mirror, requested) = dump_server(vec![
+            r#"<a href="pageviews-20240803-040000.gz">"#,
+            r#"<a href="pageviews-20240803-040000.gz"><a href="pageviews-20240803-060000.gz">"#,
+        ]);
+        let options = FollowOptions {
+            mirror,
+            poll_interval: Duration::from_millis(10),
+            until: Some(Utc.with_ymd_and_hms(2024, 8, 3, 7, 0, 0).unwrap()),
+            ..Default::default()
+        };
+        let start = Utc.with_ymd_and_hms(2024, 8, 3, 5, 30, 0).unwrap();
+        let filter = FilterBuilder::new().languages(["ja"]).build();
+
+        let rows = follow(start, &filter, options)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(!rows.is_empty());
+        assert!(
+            rows.iter()
+                .all(|row| row.parsed_domain_code.language == "ja")
+        );
+        let hour = Utc.with_ymd_and_hms(2024, 8, 3, 6, 0, 0).unwrap();
+        assert!(rows.iter().all(|row| row.timestamp == Some(hour)));
+
+        // 05:00 wasn't published at first, and skipped once 06:00 was
+        assert_eq!(
+            *requested.lock().unwrap(),
+            [
+                "/pageviews/2024/2024-08/",
+                "/pageviews/2024/2024-09/",
+                "/pageviews/2024/2024-08/",
+                "/pageviews/2024/2024-08/pageviews-20240803-060000.gz",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_follow_hours_failed_poll() {
+        // Listings after the first fail with a server error
+        let (mirror, _) = dump_server(vec![r#"<a href="pageviews-20240803-060000.gz">"#]);
+        let options = FollowOptions {
+            mirror,
+            poll_interval: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let start = Utc.with_ymd_and_hms(2024, 8, 3, 6, 0, 0).unwrap();
+
+        let mut hours = follow_hours(start, options);
+        assert_eq!(hours.next().unwrap().unwrap(), start);
+        assert!(hours.next().unwrap().is_err());
+        assert!(hours.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_follow_hours_cancelled() {
+        // The listing never has the hour, so only cancelling ends the wait
+        let (mirror, _) = dump_server(vec![r#"<a href="pageviews-20240803-050000.gz">"#; 1000]);
+        let cancel = CancellationToken::new();
+        let options = FollowOptions {
+            mirror,
+            poll_interval: Duration::from_secs(60),
+            config: StreamConfig {
+                cancel: Some(cancel.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let start = Utc.with_ymd_and_hms(2024, 8, 3, 6, 0, 0).unwrap();
+
+        let waker = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            cancel.cancel();
+        });
+        assert!(follow_hours(start, options).next().is_none());
+        waker.join().unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+//! Block gzipped copies of pageviews files, with an index for random access.
+//!
+//! A regular gzip file can only be read from the start. `index_from_source`
+//! compresses a file again as a series of small gzip members (blocks), like
+//! BGZF, and writes the domain code each block starts with to an index next
+//! to it. The result is still a valid gzip file, readable by any tool, while
+//! `StreamConfig::assume_sorted` can use the index to start reading at the
+//! block where the matching domain codes begin.
+
+use crate::stream::{Source, StreamConfig, StreamError};
+use flate2::Compression as GzLevel;
+use flate2::write::GzEncoder;
+use std::fs::{File, read_to_string};
+use std::io::{BufWriter, Error as IoError, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Uncompressed size of a block, the same as in BGZF.
+///
+/// Blocks are closed at the first line ending after this size, so they hold
+/// whole lines.
+pub const BLOCK_BYTES: usize = 64 * 1024;
+
+/// A block in a block gzipped file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    /// Offset of the gzip member in the compressed file.
+    pub offset: u64,
+    /// Domain code of the first line in the block.
+    pub first_domain_code: String,
+}
+
+/// Index of the blocks in a block gzipped file, sorted by offset.
+///
+/// Stored next to the file, with `.idx` appended to the file name, see
+/// `BlockIndex::path_for`. The first line holds the size of the file, so an
+/// index is ignored once the file is replaced. Each following line holds the
+/// offset and first domain code of a block, separated by a tab.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockIndex {
+    /// Size of the block gzipped file in bytes.
+    pub file_bytes: u64,
+    pub blocks: Vec<Block>,
+}
+
+impl BlockIndex {
+    /// Path of the index of the block gzipped file at `path`.
+    pub fn path_for(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".idx");
+        PathBuf::from(name)
+    }
+
+    /// Reads the index of the block gzipped file at `path`.
+    ///
+    /// Returns `None` if there is no index, or if it doesn't match the size
+    /// of the file.
+    pub fn for_file(path: &Path) -> Result<Option<Self>, IoError> {
+        let index = match read_to_string(Self::path_for(path)) {
+            Ok(index) => Self::parse(&index)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let file_bytes = path.metadata()?.len();
+        Ok((index.file_bytes == file_bytes).then_some(index))
+    }
+
+    /// Parses an index in the format written by `write`.
+    pub fn parse(index: &str) -> Result<Self, IoError> {
+        let invalid = |line: &str| {
+            IoError::new(
+                ErrorKind::InvalidData,
+                format!("invalid block index line: {line:?}"),
+            )
+        };
+        let mut lines = index.lines();
+        let header = lines.next().unwrap_or_default();
+        let file_bytes = header.parse().map_err(|_| invalid(header))?;
+        let blocks = lines
+            .map(|line| {
+                let (offset, domain_code) = line.split_once('\t').ok_or_else(|| invalid(line))?;
+                Ok(Block {
+                    offset: offset.parse().map_err(|_| invalid(line))?,
+                    first_domain_code: domain_code.to_string(),
+                })
+            })
+            .collect::<Result<_, IoError>>()?;
+        Ok(Self { file_bytes, blocks })
+    }
+
+    /// Writes the index to `path`, overwriting any existing file.
+    pub fn write(&self, path: &Path) -> Result<(), IoError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", self.file_bytes)?;
+        for block in &self.blocks {
+            writeln!(writer, "{}\t{}", block.offset, block.first_domain_code)?;
+        }
+        writer.flush()
+    }
+
+    /// Offset of the block to start reading at, to find the first line with
+    /// a domain code of at least `domain_code`.
+    ///
+    /// The line may be in the last block starting with a smaller domain code,
+    /// so reading starts there.
+    pub fn offset_of(&self, domain_code: &str) -> u64 {
+        let after = self
+            .blocks
+            .partition_point(|block| block.first_domain_code.as_str() < domain_code);
+        after
+            .checked_sub(1)
+            .and_then(|block| self.blocks.get(block))
+            .map_or(0, |block| block.offset)
+    }
+}
+
+/// Compresses a pageviews file from any source as a block gzipped file, and
+/// writes its index next to it.
+///
+/// The lines are copied as they are, so the file must be sorted by domain
+/// code for the index to be useful, like the Wikimedia dumps.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::index::index_from_source;
+/// use pvstream::stream::{Source, StreamConfig};
+/// use std::path::{Path, PathBuf};
+///
+/// let source = Source::File(PathBuf::from("pageviews-20240818-080000.gz"));
+/// let index = index_from_source(
+///     &source,
+///     Path::new("indexed/pageviews-20240818-080000.gz"),
+///     &StreamConfig::default(),
+/// )?;
+/// println!("{} blocks", index.blocks.len());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn index_from_source(
+    source: &Source,
+    output_path: &Path,
+    config: &StreamConfig,
+) -> Result<BlockIndex, StreamError> {
+    let mut writer = BlockWriter::new(File::create(output_path)?);
+    for line in source.lines(config)? {
+        writer.write_line(&line?)?;
+    }
+    let index = writer.finish()?;
+    index.write(&BlockIndex::path_for(output_path))?;
+    Ok(index)
+}
+
+/// Writes lines as gzip members of about `BLOCK_BYTES` each.
+struct BlockWriter<W: Write> {
+    writer: W,
+    offset: u64,
+    block: Vec<u8>,
+    index: BlockIndex,
+}
+
+impl<W: Write> BlockWriter<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer,
+            offset: 0,
+            block: Vec::with_capacity(BLOCK_BYTES + 1024),
+            index: BlockIndex::default(),
+        }
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), IoError> {
+        if self.block.is_empty() {
+            let domain_code = line.split(' ').next().unwrap_or_default();
+            self.index.blocks.push(Block {
+                offset: self.offset,
+                first_domain_code: domain_code.to_string(),
+            });
+        }
+        self.block.extend_from_slice(line.as_bytes());
+        self.block.push(b'\n');
+        if self.block.len() >= BLOCK_BYTES {
+            self.flush_block()?;
+        }
+        Ok(())
+    }
+
+    fn flush_block(&mut self) -> Result<(), IoError> {
+        let mut encoder = GzEncoder::new(Vec::new(), GzLevel::default());
+        encoder.write_all(&self.block)?;
+        let member = encoder.finish()?;
+        self.writer.write_all(&member)?;
+        self.offset += member.len() as u64;
+        self.block.clear();
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<BlockIndex, IoError> {
+        if !self.block.is_empty() {
+            self.flush_block()?;
+        }
+        self.writer.flush()?;
+        self.index.file_bytes = self.offset;
+        Ok(self.index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_block_writer() {
+        let lines: Vec<String> = (0..20_000)
+            .map(|i| format!("{} Page_{i} 1 0", ["da", "de", "en"][i * 3 / 20_000]))
+            .collect();
+        let mut output = Vec::new();
+        let mut writer = BlockWriter::new(&mut output);
+        for line in &lines {
+            writer.write_line(line).unwrap();
+        }
+        let index = writer.finish().unwrap();
+
+        // Blocks are whole gzip members, which read back as one file
+        let mut text = String::new();
+        MultiGzDecoder::new(&output[..])
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, lines.join("\n") + "\n");
+        assert!(index.blocks.len() > 1);
+        assert_eq!(index.blocks[0].offset, 0);
+
+        // Reading from the block of a domain code skips the blocks before
+        let offset = index.offset_of("en") as usize;
+        assert!(offset > 0);
+        let mut text = String::new();
+        MultiGzDecoder::new(&output[offset..])
+            .read_to_string(&mut text)
+            .unwrap();
+        assert!(text.lines().next().unwrap().starts_with("de "));
+        assert!(text.contains("\nen Page_13334 1 0\n"));
+        assert_eq!(index.offset_of("aa"), 0);
+    }
+
+    #[test]
+    fn test_index_from_source() {
+        let dir = TestDir::new("index-from-source");
+        let input = dir.join("unindexed.txt");
+        let output = dir.join("indexed.gz");
+        let lines: String = (0..20_000)
+            .map(|i| format!("{} Page_{i} 1 0\n", ["da", "de", "en"][i * 3 / 20_000]))
+            .collect();
+        std::fs::write(&input, &lines).unwrap();
+
+        let config = StreamConfig::default();
+        let index = index_from_source(&Source::File(input.clone()), &output, &config).unwrap();
+        assert_eq!(BlockIndex::for_file(&output).unwrap(), Some(index));
+
+        // The whole file reads as gzip, or from the block of a domain code
+        let source = Source::File(output.clone());
+        assert_eq!(source.lines(&config).unwrap().count(), 20_000);
+        let mut from_en = source.tracked_lines_from("en", &config, None).unwrap();
+        assert!(from_en.next().unwrap().unwrap().starts_with("de "));
+        assert!(from_en.count() < 12_000);
+
+        // A stale index is ignored
+        std::fs::write(&output, b"\x1f\x8b").unwrap();
+        assert_eq!(BlockIndex::for_file(&output).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_index() {
+        let index = BlockIndex {
+            file_bytes: 300,
+            blocks: vec![
+                Block {
+                    offset: 0,
+                    first_domain_code: "aa".into(),
+                },
+                Block {
+                    offset: 120,
+                    first_domain_code: "de.m".into(),
+                },
+            ],
+        };
+        let dir = TestDir::new("parse-index");
+        let path = dir.join("pageviews.idx");
+        index.write(&path).unwrap();
+
+        let text = read_to_string(&path).unwrap();
+        assert_eq!(text, "300\n0\taa\n120\tde.m\n");
+        assert_eq!(BlockIndex::parse(&text).unwrap(), index);
+        assert!(BlockIndex::parse("300\n0 aa\n").is_err());
+    }
+}
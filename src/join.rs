@@ -0,0 +1,216 @@
+//! Enrichment of rows with page IDs from a user provided mapping file.
+//!
+//! The pageviews files only hold titles, which change when pages are moved.
+//! A mapping of titles to stable IDs, e.g. page IDs from the `page` table
+//! dumps or Wikidata QIDs, links the views to other datasets. The mapping is
+//! a tab separated file with the domain code, page title, and ID per line,
+//! shown here with the tabs as spaces:
+//!
+//! ```text
+//! de    Berlin    Q64
+//! en    Berlin    Q64
+//! en    Oslo    Q585
+//! ```
+//!
+//! Like the pageviews files, it must be sorted by domain code and then page
+//! title, in byte order (`LC_ALL=C sort`). Both files are then read side by
+//! side in one pass, so the mapping is never loaded into memory, no matter
+//! how many pages it covers. Titles use underscores, as in the dumps. Blank
+//! lines and lines starting with `#` are ignored.
+
+use crate::parse::{Pageviews, ParseError};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error as IoError, Lines};
+use std::path::{Path, PathBuf};
+
+type RowResult = Result<Pageviews, ParseError>;
+
+/// Mapping file of page titles to IDs, see the module documentation.
+///
+/// Set as `Filter::page_ids` to fill in `Pageviews::page_id`. The file is
+/// read again for each pageviews file, as each is sorted on its own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageIds {
+    path: PathBuf,
+}
+
+impl PageIds {
+    /// Uses the mapping file at `path`, failing early if it can't be opened.
+    pub fn from_file(path: &Path) -> Result<Self, IoError> {
+        File::open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Path of the mapping file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Opens the mapping file for a join.
+    pub fn open(&self) -> Result<BufReader<File>, IoError> {
+        Ok(BufReader::new(File::open(&self.path)?))
+    }
+}
+
+/// A line of the mapping file.
+struct Entry {
+    domain_code: String,
+    page_title: String,
+    id: String,
+}
+
+impl Entry {
+    fn key(&self) -> (&str, &str) {
+        (&self.domain_code, &self.page_title)
+    }
+}
+
+/// Parses a line of the mapping file, `None` for blank lines and comments.
+fn parse_entry(line: String) -> Option<Result<Entry, ParseError>> {
+    if line.trim().is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let mut parts = line.splitn(3, '\t');
+    let entry = match (parts.next(), parts.next(), parts.next().map(str::trim_end)) {
+        (Some(domain_code), Some(page_title), Some(id)) if !id.is_empty() => Entry {
+            domain_code: domain_code.to_string(),
+            page_title: page_title.to_string(),
+            id: id.to_string(),
+        },
+        _ => {
+            return Some(Err(ParseError::InvalidField(
+                "page id mapping",
+                line.into(),
+            )));
+        }
+    };
+    Some(Ok(entry))
+}
+
+/// Sets the page ID of the rows found in a sorted mapping file.
+///
+/// The rows and the mapping are merged like in a merge join: the mapping is
+/// read up to the key of each row, and the row gets the ID of the entry with
+/// the same domain code and title, if any. Rows out of order only miss their
+/// IDs. Invalid mapping lines are returned as errors before the next row, and
+/// a read error ends the join, leaving the remaining rows without IDs.
+pub struct PageIdJoin<I: Iterator<Item = RowResult>, R: BufRead> {
+    iter: I,
+    lines: Lines<R>,
+    /// Entry of the mapping the rows are compared to, `None` once exhausted
+    entry: Option<Entry>,
+    /// Errors from the mapping, and the row read after them
+    pending: VecDeque<RowResult>,
+}
+
+impl<I: Iterator<Item = RowResult>, R: BufRead> PageIdJoin<I, R> {
+    /// Reads the next entry of the mapping.
+    fn advance(&mut self) {
+        self.entry = loop {
+            match self.lines.next() {
+                Some(Ok(line)) => match parse_entry(line) {
+                    Some(Ok(entry)) => break Some(entry),
+                    Some(Err(err)) => self.pending.push_back(Err(err)),
+                    None => {}
+                },
+                Some(Err(err)) => {
+                    self.pending.push_back(Err(ParseError::ReadError(err)));
+                    break None;
+                }
+                None => break None,
+            }
+        };
+    }
+}
+
+impl<I: Iterator<Item = RowResult>, R: BufRead> Iterator for PageIdJoin<I, R> {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(row) = self.pending.pop_front() {
+            return Some(row);
+        }
+
+        let mut row = match self.iter.next()? {
+            Ok(row) => row,
+            Err(err) => return Some(Err(err)),
+        };
+        let key = (row.domain_code.as_str(), row.page_title.as_str());
+        while self.entry.as_ref().is_some_and(|entry| entry.key() < key) {
+            self.advance();
+        }
+        if let Some(entry) = &self.entry
+            && entry.key() == key
+        {
+            row.page_id = Some(entry.id.clone());
+        }
+
+        self.pending.push_back(Ok(row));
+        self.pending.pop_front()
+    }
+}
+
+/// Sets the page ID of the rows found in a sorted mapping.
+pub fn join_page_ids<I, R>(iter: I, mapping: R) -> PageIdJoin<I, R>
+where
+    I: Iterator<Item = RowResult>,
+    R: BufRead,
+{
+    let mut join = PageIdJoin {
+        iter,
+        lines: mapping.lines(),
+        entry: None,
+        pending: VecDeque::new(),
+    };
+    join.advance();
+    join
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_line;
+
+    #[test]
+    fn test_join_page_ids() {
+        let rows = [
+            "de Berlin 10 0",
+            "en Berlin 3 0",
+            "en Missing 1 0",
+            "invalid",
+            "en Oslo 2 0",
+            "en.m Oslo 4 0",
+        ]
+        .map(|line| parse_line(line.to_string()));
+        let mapping = "# domain code, title, id\n\
+                       de\tBerlin\tQ64\n\
+                       en\tAmsterdam\tQ727\n\
+                       en\tBerlin\tQ64\n\
+                       en Oslo Q585\n\
+                       en\tOslo\tQ585\n";
+
+        let result: Vec<_> = join_page_ids(rows.into_iter(), mapping.as_bytes())
+            .map(|row| row.map(|r| (r.domain_code, r.page_title, r.page_id)))
+            .collect();
+
+        assert_eq!(result.len(), 7);
+        let row = |index: usize| result[index].as_ref().ok().cloned();
+        let id = |code: &str, title: &str, id: Option<&str>| {
+            Some((code.to_string(), title.to_string(), id.map(String::from)))
+        };
+        assert_eq!(row(0), id("de", "Berlin", Some("Q64")));
+        assert_eq!(row(1), id("en", "Berlin", Some("Q64")));
+        // The invalid mapping line is read while looking up the next row
+        assert!(matches!(
+            result[2],
+            Err(ParseError::InvalidField("page id mapping", _))
+        ));
+        assert_eq!(row(3), id("en", "Missing", None));
+        assert!(matches!(result[4], Err(ParseError::MissingField(..))));
+        assert_eq!(row(5), id("en", "Oslo", Some("Q585")));
+        assert_eq!(row(6), id("en.m", "Oslo", None));
+    }
+}
@@ -0,0 +1,157 @@
+//! Streaming into Lance datasets.
+//!
+//! Requires the `lance` feature. [Lance](https://lancedb.github.io/lance/) is
+//! a columnar format with fast random access and indexing, convenient for
+//! joining pageviews with embeddings. Record batches are handed to Lance as
+//! they're parsed, so the whole file is never held in memory.
+//!
+//! The dataset has the columns described by `arrow_schema`, with the string
+//! columns with few distinct values dictionary encoded. Existing datasets are
+//! appended to as a new version, and must have the same columns.
+//!
+//! Building the feature requires `protoc`, the Protocol Buffers compiler, on
+//! the `PATH` or in the `PROTOC` environment variable.
+
+use crate::filter::Filter;
+use crate::stream::{Source, StreamConfig, StreamError};
+use crate::{arrow_from_source, arrow_schema};
+use ::lance::Dataset;
+use ::lance::dataset::{WriteMode, WriteParams};
+use arrow::record_batch::RecordBatchIterator;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::runtime::Builder;
+use url::Url;
+
+/// Parse a local pageviews file and append the filtered rows to a Lance
+/// dataset.
+///
+/// The dataset at `uri`, a local path or an object store URL, is created if
+/// it doesn't exist. Returns the number of rows appended.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::filter::FilterBuilder;
+/// use pvstream::lance::lance_from_file;
+/// use std::path::PathBuf;
+///
+/// let filter = FilterBuilder::new().languages(["nn", "nb"]).build();
+/// let rows = lance_from_file(
+///     PathBuf::from("pageviews-20240818-080000.gz"),
+///     "pageviews.lance",
+///     &filter,
+/// )?;
+/// println!("{rows} rows appended");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn lance_from_file(
+    input_path: PathBuf,
+    uri: &str,
+    filter: &Filter,
+) -> Result<usize, StreamError> {
+    lance_from_source(
+        &Source::File(input_path),
+        uri,
+        filter,
+        &StreamConfig::default(),
+    )
+}
+
+/// Download a remote pageviews file and append the filtered rows to a Lance
+/// dataset.
+///
+/// See `lance_from_file` for details.
+pub fn lance_from_url(url: Url, uri: &str, filter: &Filter) -> Result<usize, StreamError> {
+    lance_from_source(&Source::Url(url), uri, filter, &StreamConfig::default())
+}
+
+/// Append the filtered rows of a pageviews file from any source to a Lance
+/// dataset.
+///
+/// All rows of a file are committed as one version of the dataset, so a
+/// failed append leaves the dataset as it was. Rows failing to parse are
+/// skipped, while a read error, or a parse error with
+/// `ErrorPolicy::FailFast`, stops the append and is returned.
+pub fn lance_from_source(
+    source: &Source,
+    uri: &str,
+    filter: &Filter,
+    config: &StreamConfig,
+) -> Result<usize, StreamError> {
+    let appended = Arc::new(AtomicUsize::new(0));
+    let chunks = arrow_from_source(source, filter, config, None)?.inspect({
+        let appended = appended.clone();
+        let metrics = config.metrics.clone();
+        move |chunk| {
+            if let Ok(chunk) = chunk {
+                appended.fetch_add(chunk.num_rows(), Ordering::Relaxed);
+                if let Some(metrics) = &metrics {
+                    metrics.batches_written(1);
+                }
+            }
+        }
+    });
+    let reader = RecordBatchIterator::new(chunks, Arc::new(arrow_schema()));
+    let params = WriteParams {
+        mode: WriteMode::Append,
+        ..Default::default()
+    };
+
+    let runtime = Builder::new_current_thread().enable_all().build()?;
+    runtime.block_on(Dataset::write(reader, uri, Some(params)))?;
+    Ok(appended.load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+    use crate::stream_from_source;
+    use crate::testing::TestDir;
+    use arrow::array::{AsArray, UInt32Array};
+
+    #[test]
+    fn test_lance_from_source() {
+        let dir = TestDir::new("lance");
+        let uri = dir.join("pageviews.lance");
+        let uri = uri.to_str().unwrap();
+        let source = Source::File(PathBuf::from("tests/files/pageviews-20240803-060000.gz"));
+        let filter = FilterBuilder::new().languages(["de", "ja"]).build();
+        let config = StreamConfig::default();
+
+        let appended = lance_from_source(&source, uri, &filter, &config).unwrap();
+
+        let expected: Vec<_> = stream_from_source(&source, &filter, &config)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(appended, expected.len());
+
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        let batch = runtime.block_on(async {
+            let dataset = Dataset::open(uri).await.unwrap();
+            dataset.scan().try_into_batch().await.unwrap()
+        });
+        let views: &UInt32Array = batch.column_by_name("views").unwrap().as_primitive();
+        let views: u64 = views.values().iter().copied().map(u64::from).sum();
+        assert_eq!(
+            views,
+            expected.iter().map(|row| u64::from(row.views)).sum::<u64>()
+        );
+
+        // A second file is appended to the same dataset as a new version
+        let appended = lance_from_source(&source, uri, &filter, &config).unwrap();
+        assert_eq!(appended, expected.len());
+        let (rows, version) = runtime.block_on(async {
+            let dataset = Dataset::open(uri).await.unwrap();
+            (
+                dataset.count_rows(None).await.unwrap(),
+                dataset.version().version,
+            )
+        });
+        assert_eq!(rows, 2 * expected.len());
+        assert_eq!(version, 2);
+    }
+}
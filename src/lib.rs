@@ -34,26 +34,216 @@
 //! }
 //! ```
 
+pub mod aggregate;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod cache;
+pub mod cancel;
+pub mod checksum;
+#[cfg(feature = "cloud")]
+mod cloud;
+pub mod complete;
+pub mod dataset;
+#[cfg(feature = "duckdb")]
+pub mod duckdb;
 pub mod filter;
+pub mod follow;
+pub mod index;
+pub mod join;
+#[cfg(feature = "lance")]
+pub mod lance;
+pub mod metrics;
+pub mod multi;
+mod parallel;
 pub mod parse;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod progress;
+pub mod seek;
+pub mod sitematrix;
+pub mod sites;
+pub mod sort;
 mod store;
 pub mod stream;
+pub mod testing;
+mod trace;
+mod transform;
+pub mod urls;
+pub mod writer;
 
 #[cfg(feature = "pyo3")]
 pub mod python;
 
-use crate::parse::{Pageviews, ParseError, parse_line};
-use filter::{Filter, post_filter, pre_filter};
+use crate::parse::{
+    ErrorSummary, Pageviews, PageviewsRef, ParseError, ParseMode, decode_page_title, decode_title,
+    parse_line_bytes, parse_line_with,
+};
+use aggregate::ViewsOverflow;
+pub use arrow;
+use arrow::array::RecordBatch;
+use arrow::error::ArrowError;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use filter::{Filter, LineFilter, end_of_range, post_filter, pre_filter, start_of_range};
+use join::join_page_ids;
+use metrics::{Metrics, count_filtered, count_parsed};
+use parallel::parse_in_parallel;
+pub use parquet;
+use progress::{ProgressHook, Tracker};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs::rename;
+use std::io::{Error as IoError, Write};
+use std::iter::once;
 use std::path::PathBuf;
-use store::{arrow_chunks_from_structs, parquet_from_arrow};
-use stream::{StreamError, lines_from_file, lines_from_url};
+use std::sync::Arc;
+use std::sync::mpsc::sync_channel;
+use std::thread;
+use std::time::Instant;
+pub use store::{
+    ColumnEncoding, OutputFormat, ParquetOptions, SchemaLayout, WriteReport, arrow_schema,
+};
+use store::{
+    SkippedErrors, arrow_chunks_from_structs, pageviews_from_parquet, pageviews_gz_from_structs,
+    parquet_from_arrow_counted, write_rows,
+};
+use stream::{
+    ErrorPolicy, LineBuffer, LineReader, Source, StreamConfig, StreamError, hours_in_range,
+};
+pub use stream::{http_to_file, http_to_file_with_config};
+use transform::{
+    Residuals, custom_rows, filter_rows, merge_mobile, rank_rows, residual_rows, rollup_namespaces,
+    transform_titles,
+};
 use url::Url;
+use urls::{dump_url, pageviews_urls_for_day};
 
 /// Iterator type returned by streaming functions.
 ///
 /// Yields `Result<Pageviews, ParseError>` for each line in the pageviews file.
 pub type RowIterator = Box<dyn Iterator<Item = Result<Pageviews, ParseError>> + Send + 'static>;
 
+/// Iterator type returned by the `arrow_from_` functions.
+///
+/// Yields Arrow record batches with the columns described by `arrow_schema`.
+pub type ChunkIterator = Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>> + Send + 'static>;
+
+/// Lines with their number in the file, `None` if it isn't known.
+type NumberedLines = Box<dyn Iterator<Item = (Option<u64>, Result<String, IoError>)> + Send>;
+
+/// Parses, filters, and transforms pre-filtered lines from a pageviews file.
+///
+/// This is the common pipeline behind all entry points, regardless of where
+/// the lines come from or where the rows end up. Each row is stamped with
+/// `timestamp`, the hour of the file.
+fn rows_from_lines(
+    lines: NumberedLines,
+    filter: &Filter,
+    timestamp: Option<DateTime<Utc>>,
+    config: &StreamConfig,
+) -> RowIterator {
+    let metrics = config.metrics.as_ref();
+    let rows = parse_lines(lines, filter, timestamp, config.parse_mode, metrics);
+    transform_rows(Box::new(rows), filter)
+}
+
+/// Parses lines and applies the row level filters.
+///
+/// Each line is handled on its own, so this part of the pipeline can be split
+/// across threads. Rows and parse errors get the number of their line. With
+/// `residual_rows`, filtering is left to the transforms.
+fn parse_lines<L>(
+    lines: L,
+    filter: &Filter,
+    timestamp: Option<DateTime<Utc>>,
+    mode: ParseMode,
+    metrics: Option<&Arc<dyn Metrics>>,
+) -> impl Iterator<Item = Result<Pageviews, ParseError>> + use<L>
+where
+    L: Iterator<Item = (Option<u64>, Result<String, IoError>)>,
+{
+    let post_filter = post_filter(filter);
+    let residual_rows = filter.residual_rows;
+    let keep = count_filtered(metrics, move |row| residual_rows || post_filter(row));
+    let parse_metrics = metrics.cloned();
+    lines
+        .map(move |(number, line)| {
+            let line = line.map_err(ParseError::ReadError)?;
+            let mut row = parse_line_with(line, mode).map_err(|err| err.at_line(number))?;
+            row.timestamp = timestamp;
+            row.line_number = number;
+            Ok(row)
+        })
+        .inspect(move |row| count_parsed(parse_metrics.as_ref(), row))
+        .filter(keep)
+}
+
+/// Applies the transforms depending on the order of the rows.
+///
+/// With `residual_rows`, the rows rejected by the filters are summed here
+/// instead, so the transforms only see the rows passing them, and the rows
+/// dropped by `custom` and the ranks are summed as well.
+fn transform_rows(rows: RowIterator, filter: &Filter) -> RowIterator {
+    let residuals = filter.residual_rows.then(Residuals::default);
+    let rows: RowIterator = match &residuals {
+        Some(residuals) => Box::new(filter_rows(rows, filter.clone(), residuals.clone())),
+        None => rows,
+    };
+    let rows: RowIterator = match &filter.custom {
+        Some(custom) => Box::new(custom_rows(rows, custom.clone(), residuals.clone())),
+        None => rows,
+    };
+    let rows: RowIterator = if filter.merge_mobile {
+        Box::new(merge_mobile(rows))
+    } else {
+        rows
+    };
+    let rows: RowIterator = if filter.min_rank.is_some() || filter.max_rank.is_some() {
+        Box::new(rank_rows(
+            rows,
+            filter.min_rank,
+            filter.max_rank,
+            residuals.clone(),
+        ))
+    } else {
+        rows
+    };
+    let rows: RowIterator = match &filter.page_ids {
+        Some(page_ids) => match page_ids.open() {
+            Ok(mapping) => Box::new(join_page_ids(rows, mapping)),
+            Err(err) => Box::new(once(Err(ParseError::ReadError(err))).chain(rows)),
+        },
+        None => rows,
+    };
+    let rows: RowIterator = match residuals {
+        Some(residuals) => Box::new(residual_rows(rows, residuals)),
+        None => rows,
+    };
+
+    let rows: RowIterator = match &filter.rollup_namespaces {
+        Some(namespaces) => Box::new(rollup_namespaces(rows, namespaces.clone())),
+        None => rows,
+    };
+
+    match &filter.title_transform {
+        Some(transform) => Box::new(transform_titles(rows, transform.clone())),
+        None => rows,
+    }
+}
+
+/// Decodes the page titles of the rows, if set in `config`.
+fn decode_titles(rows: RowIterator, config: &StreamConfig) -> RowIterator {
+    if !config.decode_titles {
+        return rows;
+    }
+    Box::new(rows.map(|row| {
+        row.map(|mut row| {
+            decode_page_title(&mut row);
+            row
+        })
+    }))
+}
+
 /// Decompress, stream, and parse lines from a local pageviews file
 ///
 /// The function will return a `StreamError` if it fails to read the file.
@@ -76,12 +266,7 @@ pub type RowIterator = Box<dyn Iterator<Item = Result<Pageviews, ParseError>> +
 /// # Ok::<(), Box<dyn std::error::Error>>(())
 /// ```
 pub fn stream_from_file(path: PathBuf, filter: &Filter) -> Result<RowIterator, StreamError> {
-    Ok(Box::new(
-        lines_from_file(&path)?
-            .filter(pre_filter(filter))
-            .map(|line| line.map_err(ParseError::ReadError).and_then(parse_line))
-            .filter(post_filter(filter)),
-    ))
+    stream_from_source(&Source::File(path), filter, &StreamConfig::default())
 }
 
 /// Decompress, stream, and parse lines from a remote pageviews file
@@ -107,12 +292,57 @@ pub fn stream_from_file(path: PathBuf, filter: &Filter) -> Result<RowIterator, S
 /// # Ok::<(), Box<dyn std::error::Error>>(())
 /// ```
 pub fn stream_from_url(url: Url, filter: &Filter) -> Result<RowIterator, StreamError> {
-    Ok(Box::new(
-        lines_from_url(url)?
-            .filter(pre_filter(filter))
-            .map(|line| line.map_err(ParseError::ReadError).and_then(parse_line))
-            .filter(post_filter(filter)),
-    ))
+    stream_from_source(&Source::Url(url), filter, &StreamConfig::default())
+}
+
+/// Download a remote pageviews file to `cache_path`, and stream, parse, and
+/// filter the local copy.
+///
+/// Meant for reading the same hour more than once, e.g. with different
+/// filters: the file is only downloaded if there's nothing at `cache_path`
+/// yet, so later calls read the local copy without touching the network.
+/// The download is resumed where it left off if the connection drops, and
+/// written next to `cache_path` first, so a failed download never leaves a
+/// partial file behind to be read later. Keep the file name of the dump in
+/// `cache_path` to have `timestamp` set on the rows.
+///
+/// Use `http_to_file_with_config` and `stream_from_source` to tune the
+/// download and the stream, or `cache::stream_from_url_cached` to check the
+/// local copy against the server every time.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::{download_and_stream, filter::FilterBuilder};
+/// use std::path::PathBuf;
+/// use url::Url;
+///
+/// let url = Url::parse("https://dumps.wikimedia.org/other/pageviews/2024/2024-08/pageviews-20240818-080000.gz")?;
+/// let path = PathBuf::from("pageviews-20240818-080000.gz");
+///
+/// for language in ["nn", "nb"] {
+///     let filter = FilterBuilder::new().languages([language]).build();
+///     let views: u64 = download_and_stream(&url, path.clone(), &filter)?
+///         .filter_map(Result::ok)
+///         .map(|row| u64::from(row.views))
+///         .sum();
+///     println!("{language}: {views}");
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn download_and_stream(
+    url: &Url,
+    cache_path: PathBuf,
+    filter: &Filter,
+) -> Result<RowIterator, StreamError> {
+    if !cache_path.is_file() {
+        let mut partial = cache_path.clone().into_os_string();
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+        http_to_file(url, &partial)?;
+        rename(&partial, &cache_path)?;
+    }
+    stream_from_file(cache_path, filter)
 }
 
 /// Parse a local pageviews file and write filtered results to a Parquet file.
@@ -125,6 +355,10 @@ pub fn stream_from_url(url: Url, filter: &Filter) -> Result<RowIterator, StreamE
 /// which causes memory requirements of about 100MB. Lower this to sacrifice
 /// performance for lower memory requirements, or vice versa.
 ///
+/// Lines failing to parse are skipped, and counted in the returned
+/// `WriteReport`. Use `parquet_from_source` with `ParquetOptions::error_file`
+/// to list them in a sidecar file.
+///
 /// # Example
 ///
 /// ```no_run
@@ -136,12 +370,13 @@ pub fn stream_from_url(url: Url, filter: &Filter) -> Result<RowIterator, StreamE
 ///     .languages(["en", "de", "fr"])
 ///     .build();
 ///
-/// parquet_from_file(
+/// let report = parquet_from_file(
 ///     PathBuf::from("pageviews-20240818-080000.gz"),
 ///     PathBuf::from("output.parquet"),
 ///     &filter,
 ///     None, // Use default batch size
 /// )?;
+/// println!("{} rows written in {:?}", report.rows_written, report.elapsed);
 /// # Ok::<(), Box<dyn std::error::Error>>(())
 /// ```
 pub fn parquet_from_file(
@@ -149,18 +384,17 @@ pub fn parquet_from_file(
     output_path: PathBuf,
     filter: &Filter,
     batch_size: Option<usize>,
-) -> Result<(), StreamError> {
-    let iterator = lines_from_file(&input_path)?
-        .filter(pre_filter(filter))
-        .map(|line| line.map_err(ParseError::ReadError).and_then(parse_line))
-        .filter(post_filter(filter));
-
-    parquet_from_arrow(
-        &output_path,
-        arrow_chunks_from_structs(iterator, batch_size),
-    )?;
-
-    Ok(())
+) -> Result<WriteReport, StreamError> {
+    parquet_from_source(
+        &Source::File(input_path),
+        output_path,
+        filter,
+        &StreamConfig::default(),
+        &ParquetOptions {
+            batch_size,
+            ..Default::default()
+        },
+    )
 }
 
 /// Download a remote pageviews file and write filtered results to a Parquet file.
@@ -199,16 +433,1593 @@ pub fn parquet_from_url(
     output_path: PathBuf,
     filter: &Filter,
     batch_size: Option<usize>,
-) -> Result<(), StreamError> {
-    let iterator = lines_from_url(url)?
-        .filter(pre_filter(filter))
-        .map(|line| line.map_err(ParseError::ReadError).and_then(parse_line))
-        .filter(post_filter(filter));
+) -> Result<WriteReport, StreamError> {
+    parquet_from_source(
+        &Source::Url(url),
+        output_path,
+        filter,
+        &StreamConfig::default(),
+        &ParquetOptions {
+            batch_size,
+            ..Default::default()
+        },
+    )
+}
+
+/// Stream, parse, and filter every hourly pageviews file in a time range.
+///
+/// Files are streamed one by one from Wikimedia's servers, from `start`
+/// (inclusive) to `end` (exclusive). Each row has the hour it was recorded in
+/// its `timestamp` field. If a file can't be downloaded, the error is yielded as a
+/// `ParseError::ReadError` and the iterator moves on to the next hour.
+///
+/// # Example
+///
+/// ```no_run
+/// use chrono::{TimeZone, Utc};
+/// use pvstream::{stream_from_range, filter::FilterBuilder};
+///
+/// let start = Utc.with_ymd_and_hms(2024, 8, 18, 0, 0, 0).unwrap();
+/// let end = Utc.with_ymd_and_hms(2024, 8, 19, 0, 0, 0).unwrap();
+/// let filter = FilterBuilder::new().page_title("^Rust$").build();
+///
+/// for result in stream_from_range(start, end, &filter) {
+///     let row = result?;
+///     println!("{}: {}", row.timestamp.unwrap(), row.views);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn stream_from_range(start: DateTime<Utc>, end: DateTime<Utc>, filter: &Filter) -> RowIterator {
+    let filter = filter.clone();
+    Box::new(hours_in_range(start, end).flat_map(move |hour| {
+        let source = Source::Url(dump_url(&hour));
+        match stream_from_source(&source, &filter, &StreamConfig::default()) {
+            Ok(rows) => rows,
+            Err(err) => Box::new(once(Err(ParseError::ReadError(IoError::other(err))))),
+        }
+    }))
+}
+
+/// Stream, parse, and filter a pageviews file from any source.
+///
+/// This is the general version of `stream_from_file` and `stream_from_url`,
+/// which also makes it possible to tune how the file is read.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::{stream_from_source, filter::FilterBuilder};
+/// use pvstream::stream::{Source, StreamConfig};
+/// use std::path::PathBuf;
+///
+/// let source = Source::File(PathBuf::from("pageviews-20240818-080000.gz"));
+/// let config = StreamConfig {
+///     buffer_bytes: 1 << 20,
+///     ..Default::default()
+/// };
+/// let rows = stream_from_source(&source, &FilterBuilder::new().build(), &config)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn stream_from_source(
+    source: &Source,
+    filter: &Filter,
+    config: &StreamConfig,
+) -> Result<RowIterator, StreamError> {
+    let span = trace::parse_span(source);
+    let tracker = Tracker::for_config(config);
+    let lines = span.in_scope(|| filtered_lines(source, filter, config, tracker.as_ref()))?;
+    let rows = rows_from_lines(lines, filter, source.timestamp(), config);
+    let rows = decode_titles(rows, config);
+    let rows = config.limit_rows(config.error_policy.apply(rows));
+    Ok(Box::new(span.wrap(track_emitted(rows, tracker))))
+}
+
+/// Stream, parse, and filter a pageviews file, calling `visit` with each row.
+///
+/// Lines are read into a reused buffer, and rows borrowed from the line they
+/// were parsed from, which saves allocating their strings, and makes
+/// full-file scans a good deal faster than `stream_from_source`. Call `PageviewsRef::to_owned` to keep a row.
+/// Filters with transforms, like `merge_mobile`, need owned rows, so these
+/// are streamed with `stream_from_source` and lent to `visit` instead.
+///
+/// Lines which fail to parse are skipped like in the functions writing
+/// files, unless they are fatal under the `error_policy` of `config`. Page
+/// titles are only checked for valid UTF-8 in rows passing the filters on
+/// the domain code and views, and a title with invalid UTF-8 fails to parse
+/// on its own instead of stopping the stream.
+/// Returns the number of rows visited.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::{filter::FilterBuilder, for_each_row};
+/// use pvstream::stream::{Source, StreamConfig};
+/// use std::path::PathBuf;
+///
+/// let source = Source::File(PathBuf::from("pageviews-20240818-080000.gz"));
+/// let filter = FilterBuilder::new().languages(["nn"]).build();
+///
+/// let mut views = 0;
+/// for_each_row(&source, &filter, &StreamConfig::default(), |row| {
+///     views += u64::from(row.views);
+/// })?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn for_each_row<F>(
+    source: &Source,
+    filter: &Filter,
+    config: &StreamConfig,
+    mut visit: F,
+) -> Result<u64, StreamError>
+where
+    F: FnMut(PageviewsRef<'_>),
+{
+    let mut visited = 0;
+    if filter.has_transforms() {
+        for row in stream_from_source(source, filter, config)? {
+            match row {
+                Ok(row) => {
+                    visit(PageviewsRef::from(&row));
+                    visited += 1;
+                }
+                Err(err) if config.error_policy.is_fatal(&err) => return Err(err.into()),
+                Err(_) => {}
+            }
+        }
+        return Ok(visited);
+    }
+
+    let span = trace::parse_span(source);
+    let tracker = Tracker::for_config(config);
+    let mut lines = span.in_scope(|| buffered_lines(source, filter, config, tracker.as_ref()))?;
+    let keep_line = LineFilter::new(filter);
+    let numbered = !(config.assume_sorted && start_of_range(filter).is_some());
+    let timestamp = source.timestamp();
+    let metrics = config.metrics.as_ref();
+    let mut number = 0;
+    let mut skip = config.skip;
+    let mut remaining = config.limit.unwrap_or(usize::MAX);
+    let result = span.in_scope(|| {
+        while remaining > 0
+            && let Some(line) = lines.next_line()
+        {
+            let line = line?;
+            number += 1;
+            let number = numbered.then_some(number);
+            if let Some(keep_line) = &keep_line
+                && !keep_line.matches(line)
+            {
+                if let Some(metrics) = metrics {
+                    metrics.rows_filtered(1);
+                }
+                continue;
+            }
+            // The title is only read for rows which pass the other filters
+            let row = parse_line_bytes(line, config.parse_mode)
+                .and_then(|row| filter.may_match(&row).then(|| row.into_row()).transpose())
+                .map_err(|err| err.at_line(number));
+            count_parsed(metrics, &row);
+            let mut row = match row {
+                Ok(Some(row)) if filter.matches_row(&row) => row,
+                Ok(_) => {
+                    if let Some(metrics) = metrics {
+                        metrics.rows_filtered(1);
+                    }
+                    continue;
+                }
+                Err(err) => {
+                    match &config.error_policy {
+                        ErrorPolicy::FailFast => return Err(err.into()),
+                        ErrorPolicy::CollectTo(sink) => sink.send(err),
+                        ErrorPolicy::Yield => {
+                            if let Some(tracker) = &tracker {
+                                tracker.count_item(false);
+                            }
+                        }
+                        ErrorPolicy::Skip => {}
+                    }
+                    continue;
+                }
+            };
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+
+            row.timestamp = timestamp;
+            row.line_number = number;
+            if config.decode_titles
+                && let Cow::Owned(title) = decode_title(&row.page_title)
+            {
+                row.page_title = Cow::Owned(title);
+            }
+            visit(row);
+            visited += 1;
+            remaining -= 1;
+            if let Some(tracker) = &tracker {
+                tracker.count_item(true);
+            }
+        }
+        Ok::<_, StreamError>(())
+    });
+    if let Some(tracker) = &tracker {
+        tracker.finish();
+    }
+    result.map(|()| visited)
+}
+
+/// Decompress, stream, and parse a local pageviews file on multiple threads.
+///
+/// Same as `stream_from_file`, but parsing and filtering is spread across
+/// `threads` worker threads. See `stream_from_source_parallel` for details.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::{stream_from_file_parallel, filter::FilterBuilder};
+/// use std::path::PathBuf;
+///
+/// let filter = FilterBuilder::new().page_title("(?i)olympic").build();
+/// let rows = stream_from_file_parallel(PathBuf::from("pageviews-20240818-080000.gz"), &filter, 4)?;
+///
+/// for result in rows {
+///     println!("{:?}", result?);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn stream_from_file_parallel(
+    path: PathBuf,
+    filter: &Filter,
+    threads: usize,
+) -> Result<RowIterator, StreamError> {
+    stream_from_source_parallel(
+        &Source::File(path),
+        filter,
+        &StreamConfig::default(),
+        threads,
+    )
+}
+
+/// Stream, parse, and filter a pageviews file from any source on multiple threads.
+///
+/// Decompression happens on one thread, as gzip can't be split, while the
+/// line filters, parsing, and row filters run on `threads` worker threads.
+/// This pays off when the filters are expensive, e.g. complex regexes, or
+/// when most rows are parsed. Rows are returned in the same order as
+/// `stream_from_source`.
+pub fn stream_from_source_parallel(
+    source: &Source,
+    filter: &Filter,
+    config: &StreamConfig,
+    threads: usize,
+) -> Result<RowIterator, StreamError> {
+    let span = trace::parse_span(source);
+    let tracker = Tracker::for_config(config);
+    let timestamp = source.timestamp();
+    if let Some(timestamp) = timestamp
+        && !filter.matches_time(&timestamp)
+    {
+        return Ok(track_emitted(Box::new(std::iter::empty()), tracker));
+    }
+
+    let lines = span.in_scope(|| numbered_lines(source, filter, config, tracker.as_ref()))?;
+    let rows = parse_in_parallel(lines, filter, timestamp, threads, config);
+    let rows = transform_rows(Box::new(rows), filter);
+    let rows = decode_titles(rows, config);
+    let rows = config.limit_rows(config.error_policy.apply(rows));
+    Ok(Box::new(span.wrap(track_emitted(rows, tracker))))
+}
+
+/// Decompress and stream raw lines matching the line level filters.
+///
+/// Only `line_regex` and the time filters are applied, as the rest of the
+/// filters require parsing. This is the fastest way to extract matching
+/// lines, e.g. to pipe them into another parser or tool.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::{lines_from_source, filter::FilterBuilder};
+/// use pvstream::stream::{Source, StreamConfig};
+/// use std::path::PathBuf;
+///
+/// let source = Source::File(PathBuf::from("pageviews-20240818-080000.gz"));
+/// let filter = FilterBuilder::new().line_regex("^sv ").build();
+///
+/// for line in lines_from_source(&source, &filter, &StreamConfig::default())? {
+///     println!("{}", line?);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn lines_from_source(
+    source: &Source,
+    filter: &Filter,
+    config: &StreamConfig,
+) -> Result<LineReader, StreamError> {
+    let span = trace::parse_span(source);
+    let tracker = Tracker::for_config(config);
+    let lines = span.in_scope(|| filtered_lines(source, filter, config, tracker.as_ref()))?;
+    let lines: LineReader = Box::new(lines.map(|(_, line)| line));
+    Ok(Box::new(span.wrap(track_emitted(lines, tracker))))
+}
+
+/// Lends out the lines of `sorted_lines`, reading them straight into a
+/// reused buffer unless `config` says the lines are sorted.
+fn buffered_lines(
+    source: &Source,
+    filter: &Filter,
+    config: &StreamConfig,
+    tracker: Option<&Tracker>,
+) -> Result<LineBuffer, StreamError> {
+    if let Some(timestamp) = source.timestamp()
+        && !filter.matches_time(&timestamp)
+    {
+        let lines: LineReader = Box::new(std::iter::empty());
+        return Ok(lines.into());
+    }
+    if config.assume_sorted {
+        return Ok(sorted_lines(source, filter, config, tracker)?.into());
+    }
+    source.tracked_line_buffer(config, tracker)
+}
+
+/// Streams numbered lines matching the line level filters, counting them in
+/// `tracker`.
+fn filtered_lines(
+    source: &Source,
+    filter: &Filter,
+    config: &StreamConfig,
+    tracker: Option<&Tracker>,
+) -> Result<NumberedLines, StreamError> {
+    if let Some(timestamp) = source.timestamp()
+        && !filter.matches_time(&timestamp)
+    {
+        return Ok(Box::new(std::iter::empty()));
+    }
+    let lines = numbered_lines(source, filter, config, tracker)?;
+    let keep = count_filtered(config.metrics.as_ref(), pre_filter(filter));
+    Ok(Box::new(lines.filter(move |(_, line)| keep(line))))
+}
+
+/// Streams lines from `sorted_lines`, numbered from 1 unless reading may
+/// start in the middle of the file.
+fn numbered_lines(
+    source: &Source,
+    filter: &Filter,
+    config: &StreamConfig,
+    tracker: Option<&Tracker>,
+) -> Result<NumberedLines, StreamError> {
+    let lines = sorted_lines(source, filter, config, tracker)?;
+    if config.assume_sorted && start_of_range(filter).is_some() {
+        return Ok(Box::new(lines.map(|line| (None, line))));
+    }
+    Ok(Box::new((1..).map(Some).zip(lines)))
+}
+
+/// Streams lines, only reading the domain codes `filter` can match if
+/// `config` says the lines are sorted.
+///
+/// Reading stops once the lines are past the last of them, and uncompressed
+/// files are read from the first of them.
+fn sorted_lines(
+    source: &Source,
+    filter: &Filter,
+    config: &StreamConfig,
+    tracker: Option<&Tracker>,
+) -> Result<LineReader, StreamError> {
+    if !config.assume_sorted {
+        return source.tracked_lines(config, tracker);
+    }
+    let lines = match start_of_range(filter) {
+        Some(domain_code) => source.tracked_lines_from(&domain_code, config, tracker)?,
+        None => source.tracked_lines(config, tracker)?,
+    };
+    Ok(match end_of_range(filter) {
+        Some(past) => Box::new(lines.take_while(move |line| !past(line))),
+        None => lines,
+    })
+}
+
+/// Counts the items returned to the caller, if progress is tracked.
+fn track_emitted<T, E>(
+    items: Box<dyn Iterator<Item = Result<T, E>> + Send>,
+    tracker: Option<Tracker>,
+) -> Box<dyn Iterator<Item = Result<T, E>> + Send>
+where
+    T: 'static,
+    E: 'static,
+{
+    match tracker {
+        Some(tracker) => Box::new(tracker.count_emitted(items)),
+        None => items,
+    }
+}
+
+/// Parse a pageviews file from any source and write the results to Parquet.
+///
+/// This is the general version of `parquet_from_file` and `parquet_from_url`,
+/// which also makes it possible to tune how the file is read and written.
+///
+/// Returns a `WriteReport` with the number of rows written, and the lines
+/// skipped because they failed to parse. Lines dropped by the `error_policy`
+/// of `config` never reach the writer, and are counted as filtered rather than
+/// failed, without being written to `ParquetOptions::error_file`.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::{ParquetOptions, SchemaLayout, parquet_from_source};
+/// use pvstream::{filter::FilterBuilder, stream::{Source, StreamConfig}};
+/// use std::path::PathBuf;
+///
+/// let options = ParquetOptions {
+///     layout: SchemaLayout::Nested,
+///     ..Default::default()
+/// };
+/// parquet_from_source(
+///     &Source::File(PathBuf::from("pageviews-20240818-080000.gz")),
+///     PathBuf::from("output.parquet"),
+///     &FilterBuilder::new().build(),
+///     &StreamConfig::default(),
+///     &options,
+/// )?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn parquet_from_source(
+    source: &Source,
+    output_path: PathBuf,
+    filter: &Filter,
+    config: &StreamConfig,
+    options: &ParquetOptions,
+) -> Result<WriteReport, StreamError> {
+    let started = Instant::now();
+    let (hook, progress) = ProgressHook::recording(config.progress.clone());
+    let config = StreamConfig {
+        progress: Some(hook),
+        ..config.clone()
+    };
+    let mut iterator = stream_from_source(source, filter, &config)?;
+    if let Some(top_k) = options.top_k {
+        iterator = top_k.apply(iterator);
+    }
+    if let Some(sort) = &options.sort {
+        iterator = sort.apply(iterator);
+    }
+    let policy = &config.error_policy;
+    let mut skipped = SkippedErrors::new(options.error_file.as_deref())?;
+    let mut rows_matched = 0;
+    let rows = iterator.filter_map(|row| match row {
+        Err(err) if !policy.is_fatal(&err) => {
+            skipped.record(err);
+            None
+        }
+        row => {
+            rows_matched += u64::from(row.is_ok());
+            Some(row)
+        }
+    });
+
+    let rows_written = parquet_from_arrow_counted(
+        &output_path,
+        arrow_chunks_from_structs(rows, options.batch_size, policy),
+        options,
+        policy,
+        config.metrics.as_ref(),
+    )?;
+
+    let errors = skipped.finish()?;
+    let rows_errored = errors.total() as u64;
+    let progress = *progress.lock().unwrap();
+    Ok(WriteReport {
+        rows_written,
+        rows_filtered: progress
+            .lines_read
+            .saturating_sub(rows_matched + rows_errored),
+        rows_errored,
+        rows_dropped: rows_matched - rows_written,
+        bytes_read: progress.bytes_read,
+        elapsed: started.elapsed(),
+        errors,
+    })
+}
+
+/// Download all 24 hourly files of a day, and write the daily views of each
+/// page to a Parquet file.
+///
+/// Views are summed per domain code and page title, and each row is stamped
+/// with midnight (UTC) of `date`. The totals of every page matching `filter`
+/// are kept in memory until the last hour has been read, so filter to a
+/// subset of the wikis unless there's plenty of memory available.
+///
+/// # Example
+///
+/// ```no_run
+/// use chrono::NaiveDate;
+/// use pvstream::{daily_parquet, filter::FilterBuilder};
+/// use std::path::PathBuf;
+///
+/// let date = NaiveDate::from_ymd_opt(2024, 8, 18).unwrap();
+/// daily_parquet(
+///     date,
+///     PathBuf::from("pageviews-2024-08-18.parquet"),
+///     &FilterBuilder::new().languages(["nn", "nb"]).build(),
+/// )?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn daily_parquet(
+    date: NaiveDate,
+    output_path: PathBuf,
+    filter: &Filter,
+) -> Result<WriteReport, StreamError> {
+    let sources: Vec<_> = pageviews_urls_for_day(date)
+        .into_iter()
+        .map(Source::Url)
+        .collect();
+    daily_parquet_from_sources(
+        &sources,
+        date,
+        output_path,
+        filter,
+        &StreamConfig::default(),
+        &ParquetOptions::default(),
+    )
+}
+
+/// Sum the views per page of pageviews files from any source, and write the
+/// totals to a Parquet file as rows for `date`.
+///
+/// This is the general version of `daily_parquet`, reading any list of files,
+/// e.g. a cached copy of the hours of a day. The rows are written sorted by
+/// domain code and page title, like the dumps, unless `ParquetOptions::sort`
+/// is set. `ParquetOptions::top_k` picks the top pages of the day.
+///
+/// Files are read one at a time. Lines failing to parse are skipped, and
+/// counted in the `WriteReport`, while read errors stop the aggregation and
+/// are returned before anything is written, as is a page with more than
+/// `u32::MAX` views, the largest value of the `views` column, see
+/// `aggregate::ViewsOverflow`.
+pub fn daily_parquet_from_sources(
+    sources: &[Source],
+    date: NaiveDate,
+    output_path: PathBuf,
+    filter: &Filter,
+    config: &StreamConfig,
+    options: &ParquetOptions,
+) -> Result<WriteReport, StreamError> {
+    let started = Instant::now();
+    let policy = &config.error_policy;
+    let mut skipped = SkippedErrors::new(options.error_file.as_deref())?;
+    let mut totals: HashMap<(String, String), Pageviews> = HashMap::new();
+    let (mut lines_read, mut bytes_read) = (0, 0);
 
-    parquet_from_arrow(
+    for source in sources {
+        let (hook, progress) = ProgressHook::recording(config.progress.clone());
+        let config = StreamConfig {
+            progress: Some(hook),
+            ..config.clone()
+        };
+        for row in stream_from_source(source, filter, &config)? {
+            let row = match row {
+                Ok(row) => row,
+                Err(err) if policy.is_fatal(&err) => return Err(err.into()),
+                Err(err) => {
+                    skipped.record(err);
+                    continue;
+                }
+            };
+            match totals.entry((row.domain_code.clone(), row.page_title.clone())) {
+                Entry::Occupied(mut entry) => {
+                    let total = entry.get_mut();
+                    total.views =
+                        total
+                            .views
+                            .checked_add(row.views)
+                            .ok_or_else(|| ViewsOverflow {
+                                group: format!("{} {}", row.domain_code, row.page_title),
+                            })?;
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(row);
+                }
+            }
+        }
+        let progress = *progress.lock().unwrap();
+        lines_read += progress.lines_read;
+        bytes_read += progress.bytes_read;
+    }
+
+    let timestamp = date.and_time(NaiveTime::MIN).and_utc();
+    let mut rows: Vec<_> = totals.into_iter().collect();
+    rows.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    let mut rows: RowIterator = Box::new(rows.into_iter().map(move |(_, mut row)| {
+        row.timestamp = Some(timestamp);
+        row.response_size = None;
+        Ok(row)
+    }));
+    if let Some(top_k) = options.top_k {
+        rows = top_k.apply(rows);
+    }
+    if let Some(sort) = &options.sort {
+        rows = sort.apply(rows);
+    }
+    let mut rows_matched = 0;
+    let rows = rows.inspect(|_| rows_matched += 1);
+
+    let rows_written = parquet_from_arrow_counted(
         &output_path,
-        arrow_chunks_from_structs(iterator, batch_size),
+        arrow_chunks_from_structs(rows, options.batch_size, policy),
+        options,
+        policy,
+        config.metrics.as_ref(),
     )?;
 
+    let errors = skipped.finish()?;
+    let rows_errored = errors.total() as u64;
+    Ok(WriteReport {
+        rows_written,
+        rows_filtered: lines_read.saturating_sub(rows_matched + rows_errored),
+        rows_errored,
+        rows_dropped: rows_matched - rows_written,
+        bytes_read,
+        elapsed: started.elapsed(),
+        errors,
+    })
+}
+
+/// Filter a local pageviews file and write the rows back in the dump format.
+///
+/// The output is a gzip compressed file with the same four space separated
+/// columns as the Wikimedia dumps, so a smaller, pre-filtered copy of a dump
+/// can be used with any tool reading the originals. Keep the timestamp in
+/// the output file name if it should be picked up when reading it again.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::{pageviews_gz_from_file, filter::FilterBuilder};
+/// use std::path::PathBuf;
+///
+/// let filter = FilterBuilder::new().languages(["nn", "nb"]).build();
+///
+/// pageviews_gz_from_file(
+///     PathBuf::from("pageviews-20240818-080000.gz"),
+///     PathBuf::from("no/pageviews-20240818-080000.gz"),
+///     &filter,
+/// )?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn pageviews_gz_from_file(
+    input_path: PathBuf,
+    output_path: PathBuf,
+    filter: &Filter,
+) -> Result<(), StreamError> {
+    pageviews_gz_from_source(
+        &Source::File(input_path),
+        output_path,
+        filter,
+        &StreamConfig::default(),
+    )
+}
+
+/// Filter a remote pageviews file and write the rows back in the dump format.
+///
+/// See `pageviews_gz_from_file` for details.
+pub fn pageviews_gz_from_url(
+    url: Url,
+    output_path: PathBuf,
+    filter: &Filter,
+) -> Result<(), StreamError> {
+    pageviews_gz_from_source(
+        &Source::Url(url),
+        output_path,
+        filter,
+        &StreamConfig::default(),
+    )
+}
+
+/// Filter a pageviews file from any source and write the rows back in the
+/// dump format.
+///
+/// Strings are quoted the same way as in the dumps, so every row reads back
+/// the same. Titles are written as in the dumps, regardless of
+/// `StreamConfig::decode_titles`. Rows failing to parse are skipped, unless
+/// `StreamConfig::error_policy` says otherwise.
+pub fn pageviews_gz_from_source(
+    source: &Source,
+    output_path: PathBuf,
+    filter: &Filter,
+    config: &StreamConfig,
+) -> Result<(), StreamError> {
+    let config = StreamConfig {
+        decode_titles: false,
+        ..config.clone()
+    };
+    let iterator = stream_from_source(source, filter, &config)?;
+    pageviews_gz_from_structs(&output_path, iterator, &config.error_policy)?;
     Ok(())
 }
+
+/// Filter a pageviews file from any source and write the rows as text to any
+/// `Write`, e.g. stdout, a socket, or the stdin of another process.
+///
+/// Rows are written as they're read, in the chosen `OutputFormat`, so the
+/// output can be piped into tools like `sort` or `psql` without an
+/// intermediate file. Returns the number of rows written. Rows failing to
+/// parse are skipped, unless `StreamConfig::error_policy` says otherwise. A
+/// broken pipe, e.g. from piping into `head`, stops reading without an
+/// error. Wrap unbuffered writers like `Stdout` in a `BufWriter`.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::{OutputFormat, filter::FilterBuilder, write_to_writer};
+/// use pvstream::stream::{Source, StreamConfig};
+/// use std::io::{BufWriter, stdout};
+/// use std::path::PathBuf;
+///
+/// let rows = write_to_writer(
+///     &Source::File(PathBuf::from("pageviews-20240818-080000.gz")),
+///     BufWriter::new(stdout().lock()),
+///     OutputFormat::Jsonl,
+///     &FilterBuilder::new().languages(["nn"]).build(),
+///     &StreamConfig::default(),
+/// )?;
+/// eprintln!("{rows} rows written");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn write_to_writer<W: Write>(
+    source: &Source,
+    mut writer: W,
+    format: OutputFormat,
+    filter: &Filter,
+    config: &StreamConfig,
+) -> Result<u64, StreamError> {
+    let iterator = stream_from_source(source, filter, config)?;
+    Ok(write_rows(
+        &mut writer,
+        iterator,
+        format,
+        &config.error_policy,
+    )?)
+}
+
+/// Decompress, stream, and parse a local pageviews file into Arrow chunks.
+///
+/// Use this to hand the data to Arrow based tools like Polars or DataFusion
+/// without a round trip through a Parquet file. See `arrow_from_source` for
+/// details.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::{arrow_from_file, arrow_schema, filter::FilterBuilder};
+/// use std::path::PathBuf;
+///
+/// let filter = FilterBuilder::new().languages(["fi"]).build();
+/// let chunks = arrow_from_file(PathBuf::from("pageviews-20240818-080000.gz"), &filter)?;
+///
+/// let schema = arrow_schema();
+/// for chunk in chunks {
+///     println!("{} rows with {} columns", chunk?.num_rows(), schema.fields.len());
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn arrow_from_file(path: PathBuf, filter: &Filter) -> Result<ChunkIterator, StreamError> {
+    arrow_from_source(&Source::File(path), filter, &StreamConfig::default(), None)
+}
+
+/// Decompress, stream, and parse a remote pageviews file into Arrow chunks.
+///
+/// See `arrow_from_source` for details.
+pub fn arrow_from_url(url: Url, filter: &Filter) -> Result<ChunkIterator, StreamError> {
+    arrow_from_source(&Source::Url(url), filter, &StreamConfig::default(), None)
+}
+
+/// Stream, parse, and filter a pageviews file from any source into Arrow chunks.
+///
+/// Each chunk has up to `batch_size` rows, defaulting to the parquet row
+/// group size of 122 880, and the columns described by `arrow_schema`. Rows
+/// failing to parse are skipped, unless `StreamConfig::error_policy` makes
+/// them fatal, in which case the stream ends with an error.
+pub fn arrow_from_source(
+    source: &Source,
+    filter: &Filter,
+    config: &StreamConfig,
+    batch_size: Option<usize>,
+) -> Result<ChunkIterator, StreamError> {
+    let iterator = stream_from_source(source, filter, config)?;
+    Ok(Box::new(arrow_chunks_from_structs(
+        iterator,
+        batch_size,
+        &config.error_policy,
+    )))
+}
+
+/// Reads `Pageviews` back from a parquet file written by this crate.
+///
+/// Both the flat and nested layouts are supported. Only the raw columns are
+/// read, the fields parsed from the domain code are parsed again.
+pub fn stream_from_parquet(path: PathBuf) -> Result<RowIterator, StreamError> {
+    Ok(Box::new(pageviews_from_parquet(&path)?))
+}
+
+/// Collects filtered rows in a `Vec`, summarizing errors on the side.
+///
+/// Convenient for quick scripts and tests, where handling a `Result` for each
+/// row is more noise than help. Stops after `limit` rows if set. A read error
+/// is recorded in the summary and ends the collection, as the rest of the
+/// file can't be trusted.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::{collect_rows, filter::FilterBuilder, stream::Source};
+/// use std::path::PathBuf;
+///
+/// let source = Source::File(PathBuf::from("pageviews-20240818-080000.gz"));
+/// let filter = FilterBuilder::new().languages(["nn"]).build();
+/// let (rows, errors) = collect_rows(&source, &filter, Some(100))?;
+///
+/// println!("{} rows, {} errors", rows.len(), errors.total());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn collect_rows(
+    source: &Source,
+    filter: &Filter,
+    limit: Option<usize>,
+) -> Result<(Vec<Pageviews>, ErrorSummary), StreamError> {
+    let mut rows = Vec::new();
+    let mut errors = ErrorSummary::default();
+
+    for result in stream_from_source(source, filter, &StreamConfig::default())? {
+        if limit.is_some_and(|limit| rows.len() >= limit) {
+            break;
+        }
+        match result {
+            Ok(row) => rows.push(row),
+            Err(err @ ParseError::ReadError(_)) => {
+                errors.record(err);
+                break;
+            }
+            Err(err) => errors.record(err),
+        }
+    }
+
+    Ok((rows, errors))
+}
+
+/// Reads rows ahead of the consumer in a background thread.
+///
+/// Up to `capacity` rows are parsed and kept in a bounded queue, so that
+/// downloading and parsing can overlap with slow per-row processing on the
+/// consuming side. The background thread stops as soon as the returned
+/// iterator is dropped.
+pub fn prefetch(rows: RowIterator, capacity: usize) -> RowIterator {
+    let (sender, receiver) = sync_channel(capacity);
+
+    thread::spawn(move || {
+        for row in rows {
+            if sender.send(row).is_err() {
+                break; // Receiver dropped, no need to read further
+            }
+        }
+    });
+
+    Box::new(receiver.into_iter())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+    use crate::join::PageIds;
+    use crate::testing::TestDir;
+
+    fn test_file() -> Source {
+        let base = std::env::current_dir().unwrap();
+        Source::File(base.join("tests/files/pageviews-20240803-060000.gz"))
+    }
+
+    #[test]
+    fn test_stream_from_source() {
+        let filter = FilterBuilder::new().languages(["ko"]).build();
+        let rows = stream_from_source(&test_file(), &filter, &StreamConfig::default()).unwrap();
+        let rows: Vec<_> = rows.map(Result::unwrap).collect();
+
+        assert!(!rows.is_empty());
+        assert!(rows.iter().all(|r| r.parsed_domain_code.language == "ko"));
+        assert!(rows.iter().all(|r| r.timestamp == test_file().timestamp()));
+    }
+
+    #[test]
+    fn test_skip_and_limit() {
+        let filter = FilterBuilder::new().languages(["ko"]).build();
+        let titles = |rows: RowIterator| -> Vec<String> {
+            rows.map(|row| row.unwrap().page_title).collect()
+        };
+        let all =
+            titles(stream_from_source(&test_file(), &filter, &StreamConfig::default()).unwrap());
+        let config = StreamConfig {
+            skip: 2,
+            limit: Some(3),
+            ..Default::default()
+        };
+
+        let rows = titles(stream_from_source(&test_file(), &filter, &config).unwrap());
+        assert_eq!(rows, all[2..5]);
+
+        let rows = titles(stream_from_source_parallel(&test_file(), &filter, &config, 2).unwrap());
+        assert_eq!(rows, all[2..5]);
+    }
+
+    #[test]
+    fn test_for_each_row() {
+        let filter = FilterBuilder::new().languages(["ko"]).build();
+        let config = StreamConfig {
+            skip: 2,
+            limit: Some(3),
+            decode_titles: true,
+            ..Default::default()
+        };
+        let expected: Vec<_> = stream_from_source(&test_file(), &filter, &config)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        let mut rows = Vec::new();
+        let visited = for_each_row(&test_file(), &filter, &config, |row| {
+            rows.push(row.to_owned());
+        })
+        .unwrap();
+
+        assert_eq!(visited, 3);
+        assert_eq!(rows.len(), expected.len());
+        for (row, expected) in rows.iter().zip(&expected) {
+            assert_eq!(row.page_title, expected.page_title);
+            assert_eq!(row.views, expected.views);
+            assert_eq!(row.line_number, expected.line_number);
+            assert_eq!(row.timestamp, expected.timestamp);
+        }
+    }
+
+    #[test]
+    fn test_for_each_row_with_transforms() {
+        let filter = FilterBuilder::new()
+            .languages(["ko"])
+            .merge_mobile(true)
+            .build();
+        let expected: u64 = stream_from_source(&test_file(), &filter, &StreamConfig::default())
+            .unwrap()
+            .map(|row| u64::from(row.unwrap().views))
+            .sum();
+
+        let mut views = 0;
+        for_each_row(&test_file(), &filter, &StreamConfig::default(), |row| {
+            assert!(!row.parsed_domain_code.mobile);
+            views += u64::from(row.views);
+        })
+        .unwrap();
+        assert_eq!(views, expected);
+    }
+
+    #[test]
+    fn test_line_numbers() {
+        let dir = TestDir::new("line-numbers");
+        let input = dir.join("pageviews.txt");
+        std::fs::write(&input, "da A 1 0\nde B 1 0\nde C x 0\nde D 1 0\n").unwrap();
+        let source = Source::File(input.clone());
+        let filter = FilterBuilder::new().languages(["de"]).build();
+
+        let rows: Vec<_> = stream_from_source(&source, &filter, &StreamConfig::default())
+            .unwrap()
+            .collect();
+        assert_eq!(rows[0].as_ref().unwrap().line_number, Some(2));
+        let err = rows[1].as_ref().unwrap_err();
+        assert_eq!(err.line_number(), Some(3));
+        assert!(err.to_string().ends_with("line 3: de C x 0"));
+        assert_eq!(rows[2].as_ref().unwrap().line_number, Some(4));
+
+        // Reading may start mid-file, so the lines can't be numbered
+        let config = StreamConfig {
+            assume_sorted: true,
+            ..Default::default()
+        };
+        let row = stream_from_source(&source, &filter, &config)
+            .unwrap()
+            .next();
+        assert_eq!(row.unwrap().unwrap().line_number, None);
+    }
+
+    #[test]
+    fn test_assume_sorted() {
+        let dir = TestDir::new("assume-sorted");
+        let input = dir.join("pageviews.txt");
+        // The last line is out of order, and only read without `assume_sorted`
+        std::fs::write(
+            &input,
+            "da A 1 0\nde B 1 0\nde.m C 1 0\nen D 1 0\nde E 1 0\n",
+        )
+        .unwrap();
+        let source = Source::File(input.clone());
+        let filter = FilterBuilder::new().languages(["de"]).build();
+        let titles = |config: &StreamConfig| -> Vec<String> {
+            stream_from_source(&source, &filter, config)
+                .unwrap()
+                .map(|row| row.unwrap().page_title)
+                .collect()
+        };
+
+        let config = StreamConfig {
+            assume_sorted: true,
+            ..Default::default()
+        };
+        assert_eq!(titles(&config), ["B", "C"]);
+        assert_eq!(titles(&StreamConfig::default()), ["B", "C", "E"]);
+    }
+
+    #[test]
+    fn test_decode_titles() {
+        let dir = TestDir::new("decode-titles");
+        let input = dir.join("pageviews.txt");
+        std::fs::write(&input, "fr Caf%C3%A9_de_Flore 3 0\nfr Caf%C3%A9 1 0\n").unwrap();
+        let source = Source::File(input.clone());
+        // Filters see the titles as they are in the dump
+        let filter = FilterBuilder::new().page_title("_de_").build();
+        let config = StreamConfig {
+            decode_titles: true,
+            ..Default::default()
+        };
+
+        let rows: Vec<_> = stream_from_source(&source, &filter, &config)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].page_title, "Café de Flore");
+
+        let rows: Vec<_> = stream_from_source_parallel(&source, &filter, &config, 2)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows[0].page_title, "Café de Flore");
+    }
+
+    #[test]
+    fn test_page_ids() {
+        let dir = TestDir::new("page-ids");
+        let input = dir.join("pageviews.txt");
+        let mapping = dir.join("page-ids.tsv");
+        let output = dir.join("pageviews.parquet");
+        std::fs::write(&input, "de Berlin 3 0\nde Bonn 1 0\nde.m Berlin 2 0\n").unwrap();
+        std::fs::write(&mapping, "de\tBerlin\tQ64\nen\tBerlin\tQ64\n").unwrap();
+
+        // Mobile rows are merged before the join, and found as desktop rows
+        let filter = FilterBuilder::new()
+            .merge_mobile(true)
+            .page_ids(PageIds::from_file(&mapping).unwrap())
+            .build();
+        let source = Source::File(input.clone());
+        let options = ParquetOptions::default();
+        parquet_from_source(
+            &source,
+            output.clone(),
+            &filter,
+            &Default::default(),
+            &options,
+        )
+        .unwrap();
+
+        let rows: Vec<_> = stream_from_parquet(output.clone())
+            .unwrap()
+            .map(|row| row.map(|r| (r.page_title, r.views, r.page_id)).unwrap())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("Berlin".into(), 5, Some("Q64".into())),
+                ("Bonn".into(), 1, None),
+            ]
+        );
+    }
+
+    /// Streams `lines` with `filter`, as domain code, title, and views.
+    fn stream_lines(name: &str, lines: &str, filter: &Filter) -> Vec<(String, String, u32)> {
+        let dir = TestDir::new(name);
+        let input = dir.join("pageviews.txt");
+        std::fs::write(&input, lines).unwrap();
+        stream_from_source(&Source::File(input), filter, &StreamConfig::default())
+            .unwrap()
+            .map(|row| row.map(|r| (r.domain_code, r.page_title, r.views)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_residual_rows_with_transforms() {
+        // Only rows passing the filter are merged, and the rows rejected by
+        // the custom filter are summed with the rest
+        let filter = FilterBuilder::new()
+            .page_title("^(A|B)$")
+            .custom(|row| row.page_title != "B")
+            .merge_mobile(true)
+            .residual_rows(true)
+            .build();
+        let lines = "de A 1 0\nde B 5 0\nde.m A 2 0\nde.m C 4 0\nen X 3 0\n";
+        assert_eq!(
+            stream_lines("residual-transforms", lines, &filter),
+            vec![
+                ("de".into(), "A".into(), 3),
+                ("*".into(), "*".into(), 5),
+                ("*".into(), "*".into(), 4),
+                ("*".into(), "*".into(), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_residual_rows_with_ranks() {
+        // Only rows passing the filter are ranked, and the rows ranked out
+        // are summed with the rows rejected by the filter
+        let filter = FilterBuilder::new()
+            .page_title("^A")
+            .max_rank(1)
+            .residual_rows(true)
+            .build();
+        assert_eq!(
+            stream_lines("residual-ranks", "en A 1 0\nen B 5 0\n", &filter),
+            vec![("en".into(), "A".into(), 1), ("*".into(), "*".into(), 5)]
+        );
+        let lines = "en A 1 0\nen Ab 2 0\nen B 5 0\n";
+        assert_eq!(
+            stream_lines("residual-ranks", lines, &filter),
+            vec![("en".into(), "Ab".into(), 2), ("*".into(), "*".into(), 6)]
+        );
+    }
+
+    #[test]
+    fn test_residual_rows_with_sort() {
+        use crate::sort::{Sort, SortBy};
+        use arrow::array::{Array, ArrayRef, AsArray};
+        use arrow::compute::cast;
+        use arrow::datatypes::{DataType, UInt32Type};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let dir = TestDir::new("residual-sort");
+        let filter = FilterBuilder::new()
+            .languages(["de"])
+            .residual_rows(true)
+            .build();
+        // Rows spilled to disk come back as written, whatever the limit
+        let written = |max_rows_in_memory| {
+            let output = dir.join(format!("sorted-{max_rows_in_memory}.parquet"));
+            let options = ParquetOptions {
+                sort: Some(Sort {
+                    max_rows_in_memory,
+                    spill_dir: Some(dir.path().to_path_buf()),
+                    ..Sort::new(SortBy::ViewsDescending)
+                }),
+                ..Default::default()
+            };
+            parquet_from_source(
+                &test_file(),
+                output.clone(),
+                &filter,
+                &StreamConfig::default(),
+                &options,
+            )
+            .unwrap();
+
+            let file = std::fs::File::open(output).unwrap();
+            let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+                .unwrap()
+                .build()
+                .unwrap();
+            let mut rows = Vec::new();
+            for batch in reader {
+                let batch = batch.unwrap();
+                let text =
+                    |name| cast(batch.column_by_name(name).unwrap(), &DataType::Utf8).unwrap();
+                let (domain_code, language, domain, project) = (
+                    text("domain_code"),
+                    text("language"),
+                    text("domain"),
+                    text("project"),
+                );
+                let mobile = batch.column_by_name("mobile").unwrap().as_boolean().clone();
+                let views = batch.column_by_name("views").unwrap();
+                let views = views.as_primitive::<UInt32Type>();
+                for i in 0..batch.num_rows() {
+                    let text = |array: &ArrayRef| {
+                        let array = array.as_string::<i32>();
+                        array.is_valid(i).then(|| array.value(i).to_string())
+                    };
+                    rows.push((
+                        text(&domain_code).unwrap(),
+                        text(&language).unwrap(),
+                        text(&domain),
+                        mobile.value(i),
+                        text(&project),
+                        views.value(i),
+                    ));
+                }
+            }
+            rows
+        };
+
+        let in_memory = written(100_000);
+        let spilled = written(1);
+        assert_eq!(spilled, in_memory);
+        let residuals: Vec<_> = spilled.iter().filter(|row| row.0 == "*").collect();
+        assert!(!residuals.is_empty());
+        assert!(residuals.iter().all(|row| row.1 != "de" && row.1 != "*"));
+        assert!(
+            residuals
+                .iter()
+                .all(|row| row.2.is_none() && row.4.is_none())
+        );
+        assert!(residuals.iter().any(|row| row.3));
+    }
+
+    #[test]
+    fn test_title_transform() {
+        let dir = TestDir::new("title-transform");
+        let input = dir.join("pageviews.txt");
+        let lines = "de Paris_(Begriffsklärung) 2 0\nde Paris_(Stadt) 3 0\nde Rom 1 0\n";
+        std::fs::write(&input, lines).unwrap();
+        let source = Source::File(input.clone());
+
+        // Filters match the original titles, and equal titles are not merged
+        let filter = FilterBuilder::new()
+            .page_title(r"_\(")
+            .title_transform(r"^(\w+)_\((\w+)\)$", "$2:$1")
+            .build();
+        let rows: Vec<_> = stream_from_source(&source, &filter, &StreamConfig::default())
+            .unwrap()
+            .map(|row| row.map(|r| (r.page_title, r.views)).unwrap())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("Begriffsklärung:Paris".into(), 2),
+                ("Stadt:Paris".into(), 3)
+            ]
+        );
+
+        let mut titles = Vec::new();
+        let filter = FilterBuilder::new()
+            .title_transform(r"_\(.*\)$", "")
+            .build();
+        for_each_row(&source, &filter, &StreamConfig::default(), |row| {
+            titles.push(row.page_title.to_string());
+        })
+        .unwrap();
+        assert_eq!(titles, ["Paris", "Paris", "Rom"]);
+    }
+
+    #[test]
+    fn test_download_and_stream_reuses_file() {
+        let dir = TestDir::new("download-and-stream");
+        let path = dir.join("pageviews-20240803-060000.gz");
+        std::fs::copy("tests/files/pageviews-20240803-060000.gz", &path).unwrap();
+
+        // Nothing listens on the URL, so the rows must come from the copy
+        let url = Url::parse("http://127.0.0.1:1/pageviews-20240803-060000.gz").unwrap();
+        let filter = FilterBuilder::new().languages(["de"]).build();
+        let rows: Vec<_> = download_and_stream(&url, path.clone(), &filter)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let expected = stream_from_source(&test_file(), &filter, &StreamConfig::default())
+            .unwrap()
+            .count();
+        assert_eq!(rows.len(), expected);
+        assert!(rows.iter().all(|row| row.timestamp.is_some()));
+    }
+
+    #[test]
+    fn test_parquet_error_file() {
+        let dir = TestDir::new("parquet-error-file");
+        let input = dir.join("pageviews.txt");
+        let output = input.with_extension("parquet");
+        let error_file = input.with_extension("errors.tsv");
+        let contents = "en A 1 0\nen B x 0\nen C 3 0\nen\n";
+        std::fs::write(&input, contents).unwrap();
+
+        let options = ParquetOptions {
+            error_file: Some(error_file.clone()),
+            ..Default::default()
+        };
+        let report = parquet_from_source(
+            &Source::File(input.clone()),
+            output.clone(),
+            &FilterBuilder::new().min_views(2).build(),
+            &StreamConfig::default(),
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(report.rows_written, 1);
+        assert_eq!(report.rows_filtered, 1);
+        assert_eq!(report.rows_errored, 2);
+        assert_eq!(report.bytes_read, contents.len() as u64);
+        assert_eq!(report.errors.counts["invalid views"], 1);
+        assert_eq!(
+            std::fs::read_to_string(&error_file).unwrap(),
+            "invalid views\ten B x 0\nmissing page title\ten\n"
+        );
+        let rows = stream_from_parquet(output.clone()).unwrap();
+        assert_eq!(rows.count(), 1);
+    }
+
+    #[test]
+    fn test_daily_parquet_from_sources() {
+        let dir = TestDir::new("daily-parquet-from-sources");
+        let hours: Vec<_> = [
+            "en A 1 0\nen B 2 0\nde C 3 0\n",
+            "en B 5 0\nen x\nen.m A 4 0\n",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(hour, lines)| {
+            let path = dir.join(format!("pageviews-20240818-0{hour}0000"));
+            std::fs::write(&path, lines).unwrap();
+            Source::File(path)
+        })
+        .collect();
+        let output = dir.join("pageviews-20240818.parquet");
+        let date = NaiveDate::from_ymd_opt(2024, 8, 18).unwrap();
+
+        let report = daily_parquet_from_sources(
+            &hours,
+            date,
+            output.clone(),
+            &FilterBuilder::new().languages(["en"]).build(),
+            &StreamConfig::default(),
+            &ParquetOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.rows_written, 3);
+        assert_eq!(report.rows_errored, 1);
+        assert_eq!(report.rows_filtered, 6 - 3 - 1);
+
+        let rows: Vec<_> = stream_from_parquet(output.clone())
+            .unwrap()
+            .map(|row| {
+                let row = row.unwrap();
+                assert_eq!(row.timestamp, Some(date.and_time(NaiveTime::MIN).and_utc()));
+                (row.domain_code, row.page_title, row.views)
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("en".into(), "A".into(), 1),
+                ("en".into(), "B".into(), 7),
+                ("en.m".into(), "A".into(), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_to_writer() {
+        let filter = FilterBuilder::new().languages(["ja"]).build();
+        let (expected, _) = collect_rows(&test_file(), &filter, None).unwrap();
+
+        for format in [OutputFormat::Dump, OutputFormat::Tsv, OutputFormat::Jsonl] {
+            let mut output = Vec::new();
+            let rows = write_to_writer(
+                &test_file(),
+                &mut output,
+                format,
+                &filter,
+                &Default::default(),
+            )
+            .unwrap();
+            let output = String::from_utf8(output).unwrap();
+            let lines: Vec<_> = output.lines().collect();
+
+            assert_eq!(rows, expected.len() as u64);
+            assert_eq!(lines.len(), expected.len());
+            assert_eq!(lines[0], format.format(&expected[0]));
+        }
+    }
+
+    #[test]
+    fn test_pageviews_gz_from_source() {
+        let dir = TestDir::new("pageviews-gz-from-source");
+        let output = dir.join("pageviews-20240803-060000.gz");
+        let filter = FilterBuilder::new().languages(["de", "ja"]).build();
+        pageviews_gz_from_source(
+            &test_file(),
+            output.clone(),
+            &filter,
+            &StreamConfig::default(),
+        )
+        .unwrap();
+
+        let (expected, _) = collect_rows(&test_file(), &filter, None).unwrap();
+        let (written, errors) =
+            collect_rows(&Source::File(output.clone()), &Filter::default(), None).unwrap();
+
+        assert!(!written.is_empty());
+        assert!(errors.is_empty());
+        assert_eq!(written.len(), expected.len());
+        for (written, expected) in written.iter().zip(&expected) {
+            assert_eq!(written.domain_code, expected.domain_code);
+            assert_eq!(written.page_title, expected.page_title);
+            assert_eq!(written.views, expected.views);
+            assert_eq!(written.timestamp, expected.timestamp);
+        }
+    }
+
+    #[test]
+    fn test_collect_rows() {
+        let filter = FilterBuilder::new().build();
+        let (rows, errors) = collect_rows(&test_file(), &filter, None).unwrap();
+
+        assert_eq!(rows.len(), 1000);
+        assert!(errors.is_empty());
+
+        let (rows, _) = collect_rows(&test_file(), &filter, Some(10)).unwrap();
+        assert_eq!(rows.len(), 10);
+    }
+
+    #[test]
+    fn test_lines_from_source() {
+        let filter = FilterBuilder::new().line_regex("^ko ").build();
+        let lines = lines_from_source(&test_file(), &filter, &StreamConfig::default()).unwrap();
+        let lines: Vec<_> = lines.map(Result::unwrap).collect();
+
+        assert_eq!(lines[0], "ko 서울_지하철_7호선 2 0");
+        assert!(lines.iter().all(|line| line.starts_with("ko ")));
+    }
+
+    #[test]
+    fn test_skip_by_time() {
+        // The test file is from 06:00 on a Saturday
+        let filter = FilterBuilder::new().hours([6]).build();
+        let (rows, _) = collect_rows(&test_file(), &filter, None).unwrap();
+        assert_eq!(rows.len(), 1000);
+
+        let filter = FilterBuilder::new()
+            .weekdays([chrono::Weekday::Sun])
+            .build();
+        let (rows, _) = collect_rows(&test_file(), &filter, None).unwrap();
+        assert!(rows.is_empty());
+    }
+
+    #[test]
+    fn test_parquet_round_trip() {
+        let dir = TestDir::new("parquet-round-trip");
+        let output = dir.join("pageviews.parquet");
+        let filter = FilterBuilder::new().build();
+
+        for layout in [SchemaLayout::Flat, SchemaLayout::Nested] {
+            let options = ParquetOptions {
+                batch_size: Some(300),
+                layout,
+                ..Default::default()
+            };
+            parquet_from_source(
+                &test_file(),
+                output.clone(),
+                &filter,
+                &StreamConfig::default(),
+                &options,
+            )
+            .unwrap();
+
+            let (original, _) = collect_rows(&test_file(), &filter, None).unwrap();
+            let read: Vec<_> = stream_from_parquet(output.clone())
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+
+            assert_eq!(read.len(), original.len());
+            for (a, b) in read.iter().zip(&original) {
+                assert_eq!(a.domain_code, b.domain_code);
+                assert_eq!(a.page_title, b.page_title);
+                assert_eq!(a.views, b.views);
+                assert_eq!(a.parsed_domain_code.domain, b.parsed_domain_code.domain);
+                assert_eq!(a.timestamp, b.timestamp);
+            }
+        }
+    }
+
+    #[test]
+    fn test_arrow_from_source() {
+        let filter = FilterBuilder::new().build();
+        let chunks: Vec<_> =
+            arrow_from_source(&test_file(), &filter, &StreamConfig::default(), Some(300))
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+
+        let lengths: Vec<_> = chunks.iter().map(|chunk| chunk.num_rows()).collect();
+        assert_eq!(lengths, [300, 300, 300, 100]);
+        assert!(
+            chunks
+                .iter()
+                .all(|chunk| chunk.num_columns() == arrow_schema().fields.len())
+        );
+    }
+
+    #[test]
+    fn test_stream_parallel() {
+        let config = StreamConfig::default();
+        let filters = [
+            FilterBuilder::new().build(),
+            FilterBuilder::new()
+                .line_regex("^(en|de)")
+                .min_views(2)
+                .build(),
+            FilterBuilder::new()
+                .languages(["en"])
+                .residual_rows(true)
+                .rollup_namespaces(["Special", "File"])
+                .build(),
+        ];
+
+        for filter in filters {
+            let sequential: Vec<_> = stream_from_source(&test_file(), &filter, &config)
+                .unwrap()
+                .map(|row| format!("{:?}", row.unwrap()))
+                .collect();
+
+            for threads in [1, 3] {
+                let parallel: Vec<_> =
+                    stream_from_source_parallel(&test_file(), &filter, &config, threads)
+                        .unwrap()
+                        .map(|row| format!("{:?}", row.unwrap()))
+                        .collect();
+                assert_eq!(parallel, sequential);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_progress() {
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let hook = {
+            let reports = Arc::clone(&reports);
+            progress::ProgressHook::new(move |p| reports.lock().unwrap().push(*p))
+        };
+        let config = StreamConfig {
+            progress: Some(hook),
+            ..Default::default()
+        };
+        let filter = FilterBuilder::new().languages(["ko"]).build();
+
+        let rows = stream_from_source(&test_file(), &filter, &config)
+            .unwrap()
+            .count() as u64;
+
+        let reports = reports.lock().unwrap();
+        let size = std::fs::metadata("tests/files/pageviews-20240803-060000.gz")
+            .unwrap()
+            .len();
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].finished);
+        assert_eq!(reports[0].total_bytes, Some(size));
+        assert_eq!(reports[0].bytes_read, size);
+        assert!(reports[0].bytes_decompressed > size);
+        assert_eq!(reports[0].lines_read, 1000);
+        assert_eq!(reports[0].rows_emitted, rows);
+    }
+
+    #[test]
+    fn test_prefetch() {
+        let filter = FilterBuilder::new().build();
+        let config = StreamConfig::default();
+        let direct = stream_from_source(&test_file(), &filter, &config).unwrap();
+        let prefetched = prefetch(
+            stream_from_source(&test_file(), &filter, &config).unwrap(),
+            8,
+        );
+
+        let direct: Vec<_> = direct.map(|r| r.unwrap().page_title).collect();
+        let prefetched: Vec<_> = prefetched.map(|r| r.unwrap().page_title).collect();
+
+        assert_eq!(direct.len(), 1000);
+        assert_eq!(direct, prefetched);
+    }
+}
@@ -0,0 +1,230 @@
+//! Hooks feeding counters to a metrics registry.
+//!
+//! Implement `Metrics` to count the rows, errors, and bytes of every stream in
+//! Prometheus or any other metrics system, and set it as
+//! `StreamConfig::metrics`. Counters are incremented while the rows are
+//! consumed, instead of being parsed out of logs afterwards.
+
+use crate::parse::ParseError;
+use std::fmt;
+use std::io::{Error as IoError, Read};
+use std::sync::Arc;
+
+/// Counters of a stream, incremented as it's read and written.
+///
+/// Every method does nothing by default, so only the counters of interest
+/// need to be implemented. They may be called from background threads, e.g.
+/// when parsing in parallel, and for every row, so keep them cheap, like
+/// incrementing an atomic counter.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::metrics::Metrics;
+/// use pvstream::stream::{Source, StreamConfig};
+/// use pvstream::{filter::FilterBuilder, stream_from_source};
+/// use std::path::PathBuf;
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+///
+/// #[derive(Default)]
+/// struct Counters {
+///     rows_parsed: AtomicU64,
+///     parse_errors: AtomicU64,
+/// }
+///
+/// impl Metrics for Counters {
+///     fn rows_parsed(&self, rows: u64) {
+///         self.rows_parsed.fetch_add(rows, Relaxed);
+///     }
+///
+///     fn parse_errors(&self, errors: u64) {
+///         self.parse_errors.fetch_add(errors, Relaxed);
+///     }
+/// }
+///
+/// let counters = Arc::new(Counters::default());
+/// let config = StreamConfig {
+///     metrics: Some(counters.clone()),
+///     ..Default::default()
+/// };
+/// let source = Source::File(PathBuf::from("pageviews-20240818-080000.gz"));
+/// for row in stream_from_source(&source, &FilterBuilder::new().build(), &config)? {
+///     let _ = row;
+/// }
+/// println!("{} rows parsed", counters.rows_parsed.load(Relaxed));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub trait Metrics: Send + Sync {
+    /// Lines parsed into rows, before the filters which need parsing.
+    fn rows_parsed(&self, _rows: u64) {}
+
+    /// Lines and rows rejected by the filters.
+    fn rows_filtered(&self, _rows: u64) {}
+
+    /// Lines failing to parse.
+    fn parse_errors(&self, _errors: u64) {}
+
+    /// Compressed bytes received over HTTP or from object storage.
+    fn bytes_downloaded(&self, _bytes: u64) {}
+
+    /// Parquet row groups, or batches appended to a database, written.
+    fn batches_written(&self, _batches: u64) {}
+}
+
+impl fmt::Debug for dyn Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Metrics(..)")
+    }
+}
+
+/// Wraps a filter predicate, counting what it rejects as filtered rows.
+pub(crate) fn count_filtered<T, F>(
+    metrics: Option<&Arc<dyn Metrics>>,
+    keep: F,
+) -> impl Fn(&T) -> bool + Send + Sync + use<T, F>
+where
+    F: Fn(&T) -> bool + Send + Sync,
+{
+    let metrics = metrics.cloned();
+    move |item| {
+        let kept = keep(item);
+        if !kept && let Some(metrics) = &metrics {
+            metrics.rows_filtered(1);
+        }
+        kept
+    }
+}
+
+/// Counts a parsed line as a row or a parse error. Read errors are neither.
+pub(crate) fn count_parsed<T>(metrics: Option<&Arc<dyn Metrics>>, row: &Result<T, ParseError>) {
+    match (metrics, row) {
+        (Some(metrics), Ok(_)) => metrics.rows_parsed(1),
+        (Some(_), Err(ParseError::ReadError(_))) | (None, _) => {}
+        (Some(metrics), Err(_)) => metrics.parse_errors(1),
+    }
+}
+
+/// Reader counting the bytes read through it as downloaded.
+pub(crate) struct DownloadMeter<R> {
+    inner: R,
+    metrics: Option<Arc<dyn Metrics>>,
+}
+
+impl<R> DownloadMeter<R> {
+    pub(crate) fn new(inner: R, metrics: Option<&Arc<dyn Metrics>>) -> Self {
+        Self {
+            inner,
+            metrics: metrics.cloned(),
+        }
+    }
+}
+
+impl<R: Read> Read for DownloadMeter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        let n = self.inner.read(buf)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.bytes_downloaded(n as u64);
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+    use crate::stream::{Source, StreamConfig};
+    use crate::testing::TestDir;
+    use crate::{ParquetOptions, parquet_from_source, stream_from_source};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+    #[derive(Default)]
+    struct Counters {
+        rows_parsed: AtomicU64,
+        rows_filtered: AtomicU64,
+        parse_errors: AtomicU64,
+        bytes_downloaded: AtomicU64,
+        batches_written: AtomicU64,
+    }
+
+    impl Metrics for Counters {
+        fn rows_parsed(&self, rows: u64) {
+            self.rows_parsed.fetch_add(rows, Relaxed);
+        }
+
+        fn rows_filtered(&self, rows: u64) {
+            self.rows_filtered.fetch_add(rows, Relaxed);
+        }
+
+        fn parse_errors(&self, errors: u64) {
+            self.parse_errors.fetch_add(errors, Relaxed);
+        }
+
+        fn bytes_downloaded(&self, bytes: u64) {
+            self.bytes_downloaded.fetch_add(bytes, Relaxed);
+        }
+
+        fn batches_written(&self, batches: u64) {
+            self.batches_written.fetch_add(batches, Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_stream_metrics() {
+        let source = Source::File(PathBuf::from("tests/files/pageviews-20240803-060000.gz"));
+        // `languages` is checked before parsing, `min_views` after
+        let filter = FilterBuilder::new().languages(["ja"]).min_views(2).build();
+        let counters = Arc::new(Counters::default());
+        let config = StreamConfig {
+            metrics: Some(counters.clone()),
+            ..Default::default()
+        };
+
+        let lines = stream_from_source(&source, &FilterBuilder::new().build(), &config)
+            .unwrap()
+            .count() as u64;
+        assert_eq!(counters.rows_parsed.load(Relaxed), lines);
+        assert_eq!(counters.rows_filtered.load(Relaxed), 0);
+
+        let counters = Arc::new(Counters::default());
+        let config = StreamConfig {
+            metrics: Some(counters.clone()),
+            ..Default::default()
+        };
+        let rows = stream_from_source(&source, &filter, &config)
+            .unwrap()
+            .count() as u64;
+        let parsed = counters.rows_parsed.load(Relaxed);
+        assert!(rows > 0 && parsed > rows);
+        assert_eq!(counters.rows_filtered.load(Relaxed), lines - rows);
+        assert_eq!(counters.parse_errors.load(Relaxed), 0);
+        assert_eq!(counters.bytes_downloaded.load(Relaxed), 0);
+
+        let dir = TestDir::new("metrics");
+        let path = dir.join("pageviews.parquet");
+        let options = ParquetOptions {
+            batch_size: Some(10),
+            ..Default::default()
+        };
+        parquet_from_source(&source, path.clone(), &filter, &config, &options).unwrap();
+        assert_eq!(counters.batches_written.load(Relaxed), rows.div_ceil(10));
+    }
+
+    #[test]
+    fn test_count_parsed() {
+        let counters = Arc::new(Counters::default());
+        let metrics: Arc<dyn Metrics> = counters.clone();
+        let rows: [Result<(), ParseError>; 3] = [
+            Ok(()),
+            Err(crate::parse::invalid("views", "en Main_Page x 0")),
+            Err(ParseError::ReadError(IoError::other("truncated"))),
+        ];
+        for row in &rows {
+            count_parsed(Some(&metrics), row);
+        }
+        assert_eq!(counters.rows_parsed.load(Relaxed), 1);
+        assert_eq!(counters.parse_errors.load(Relaxed), 1);
+    }
+}
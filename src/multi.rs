@@ -0,0 +1,390 @@
+//! Parquet exports of many pageviews files at once.
+//!
+//! Reading one hourly file at a time leaves most of the bandwidth and cores
+//! idle, as a single download is limited by the server and decompression
+//! runs on one thread. These functions read several files concurrently, each
+//! on its own worker thread, and write them to a single Parquet file, or to
+//! one Parquet file per input.
+
+use crate::filter::Filter;
+use crate::progress::{Progress, ProgressHook};
+use crate::store::{
+    ParquetFile, ParquetOptions, SkippedErrors, WriteReport, arrow_chunks_from_structs,
+    is_skipped_batch,
+};
+use crate::stream::{Source, StreamConfig, StreamError};
+use crate::{parquet_from_source, stream_from_source};
+use arrow::array::RecordBatch;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::sync_channel;
+use std::thread;
+use std::time::Instant;
+use url::Url;
+
+/// Download remote pageviews files concurrently and write the filtered rows
+/// to a single Parquet file.
+///
+/// Up to `concurrency` files are downloaded and parsed at the same time, see
+/// `parquet_from_sources`.
+///
+/// # Example
+///
+/// ```no_run
+/// use chrono::NaiveDate;
+/// use pvstream::filter::FilterBuilder;
+/// use pvstream::multi::parquet_from_urls;
+/// use pvstream::urls::pageviews_urls_for_day;
+/// use std::path::Path;
+///
+/// let date = NaiveDate::from_ymd_opt(2024, 8, 18).unwrap();
+/// let report = parquet_from_urls(
+///     &pageviews_urls_for_day(date),
+///     Path::new("2024-08-18.parquet"),
+///     &FilterBuilder::new().languages(["nb"]).build(),
+///     4,
+/// )?;
+/// println!("{} rows written in {:?}", report.rows_written, report.elapsed);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn parquet_from_urls(
+    urls: &[Url],
+    output_path: &Path,
+    filter: &Filter,
+    concurrency: usize,
+) -> Result<WriteReport, StreamError> {
+    let sources: Vec<_> = urls.iter().cloned().map(Source::Url).collect();
+    parquet_from_sources(
+        &sources,
+        output_path,
+        filter,
+        &StreamConfig::default(),
+        &ParquetOptions::default(),
+        concurrency,
+    )
+}
+
+/// Read pageviews files from any source concurrently and write the filtered
+/// rows to a single Parquet file.
+///
+/// Each worker reads one file at a time, and converts its rows to row groups
+/// of `ParquetOptions::batch_size` rows, which are written as they're ready.
+/// The rows of one file stay in order, but the files are interleaved in the
+/// output. Rows keep the hour of their file in `timestamp`, to tell them apart.
+/// `ParquetOptions::top_k` picks the top rows of each input on its own, and
+/// `ParquetOptions::sort` sorts each input, not the whole file.
+///
+/// Returns a `WriteReport` with the totals of all files. The first read
+/// error, or parse error with `ErrorPolicy::FailFast`, stops all workers and
+/// is returned, leaving the file unfinished.
+pub fn parquet_from_sources(
+    sources: &[Source],
+    output_path: &Path,
+    filter: &Filter,
+    config: &StreamConfig,
+    options: &ParquetOptions,
+    concurrency: usize,
+) -> Result<WriteReport, StreamError> {
+    let started = Instant::now();
+    let mut file =
+        ParquetFile::create(output_path, options, &[])?.with_metrics(config.metrics.as_ref());
+    let skipped = Mutex::new(SkippedErrors::new(options.error_file.as_deref())?);
+    let totals = Mutex::new(Progress::default());
+    let rows_matched = AtomicU64::new(0);
+    let next = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+    let (sender, receiver) =
+        sync_channel::<Result<RecordBatch, StreamError>>(concurrency.max(1) * 2);
+
+    let written = thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, sources.len().max(1)) {
+            let sender = sender.clone();
+            let (skipped, totals, next, stopped) = (&skipped, &totals, &next, &stopped);
+            let rows_matched = &rows_matched;
+            scope.spawn(move || {
+                while !stopped.load(Ordering::Relaxed) {
+                    let Some(source) = sources.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    let result =
+                        chunks_from_source(source, filter, config, options, skipped, |chunk| {
+                            sender.send(Ok(chunk)).is_ok()
+                        });
+                    match result {
+                        Ok((progress, matched)) => {
+                            rows_matched.fetch_add(matched, Ordering::Relaxed);
+                            let mut totals = totals.lock().unwrap();
+                            totals.lines_read += progress.lines_read;
+                            totals.bytes_read += progress.bytes_read;
+                        }
+                        Err(err) => {
+                            let _ = sender.send(Err(err));
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut rows_written = 0;
+        for chunk in receiver {
+            let written = chunk.and_then(|chunk| {
+                rows_written += chunk.num_rows() as u64;
+                Ok(file.write(chunk)?)
+            });
+            if let Err(err) = written {
+                // Dropping the receiver makes the workers' sends fail
+                stopped.store(true, Ordering::Relaxed);
+                return Err(err);
+            }
+        }
+        Ok(rows_written)
+    });
+    let rows_written = written?;
+    file.finish()?;
+
+    let errors = skipped.into_inner().unwrap().finish()?;
+    let totals = totals.into_inner().unwrap();
+    let rows_errored = errors.total() as u64;
+    let rows_matched = rows_matched.into_inner();
+    Ok(WriteReport {
+        rows_written,
+        rows_filtered: totals
+            .lines_read
+            .saturating_sub(rows_matched + rows_errored),
+        rows_errored,
+        rows_dropped: rows_matched - rows_written,
+        bytes_read: totals.bytes_read,
+        elapsed: started.elapsed(),
+        errors,
+    })
+}
+
+/// Streams the rows of `source` as arrow chunks, passed to `send` until it
+/// returns false. Failed chunks are skipped as allowed by the error policy.
+///
+/// Returns the last progress report of the file, with the lines and bytes
+/// read, and the number of rows matching the filters.
+fn chunks_from_source(
+    source: &Source,
+    filter: &Filter,
+    config: &StreamConfig,
+    options: &ParquetOptions,
+    skipped: &Mutex<SkippedErrors>,
+    mut send: impl FnMut(RecordBatch) -> bool,
+) -> Result<(Progress, u64), StreamError> {
+    let (hook, progress) = ProgressHook::recording(config.progress.clone());
+    let config = StreamConfig {
+        progress: Some(hook),
+        ..config.clone()
+    };
+    let mut rows = stream_from_source(source, filter, &config)?;
+    if let Some(top_k) = options.top_k {
+        rows = top_k.apply(rows);
+    }
+    if let Some(sort) = &options.sort {
+        rows = sort.apply(rows);
+    }
+    let policy = &config.error_policy;
+    let mut rows_matched = 0;
+    let rows = rows.filter_map(|row| match row {
+        Err(err) if !policy.is_fatal(&err) => {
+            skipped.lock().unwrap().record(err);
+            None
+        }
+        row => {
+            rows_matched += u64::from(row.is_ok());
+            Some(row)
+        }
+    });
+    for chunk in arrow_chunks_from_structs(rows, options.batch_size, policy) {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) if is_skipped_batch(policy, &err) => continue,
+            Err(err) => return Err(err.into()),
+        };
+        if !send(chunk) {
+            break;
+        }
+    }
+
+    let progress = *progress.lock().unwrap();
+    Ok((progress, rows_matched))
+}
+
+/// Read pageviews files from any source concurrently and write the filtered
+/// rows of each to its own Parquet file in `output_dir`.
+///
+/// Up to `concurrency` files are written at a time, each as with
+/// `parquet_from_source`. The output files are named after the inputs, e.g.
+/// `pageviews-20240818-080000.parquet`, or `part-000.parquet` by position for
+/// inputs without a file name. With `ParquetOptions::error_file`, each output
+/// gets its own error file, with `.errors.tsv` in place of `.parquet`.
+///
+/// Returns the path and report of each file, in the order of the inputs. The
+/// first error stops the workers from starting on more files, and is returned.
+pub fn parquet_files_from_sources(
+    sources: &[Source],
+    output_dir: &Path,
+    filter: &Filter,
+    config: &StreamConfig,
+    options: &ParquetOptions,
+    concurrency: usize,
+) -> Result<Vec<(PathBuf, WriteReport)>, StreamError> {
+    let paths: Vec<_> = sources
+        .iter()
+        .enumerate()
+        .map(|(index, source)| output_dir.join(output_name(source, index)))
+        .collect();
+    let results: Vec<Mutex<Option<Result<WriteReport, StreamError>>>> =
+        sources.iter().map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, sources.len().max(1)) {
+            scope.spawn(|| {
+                while !stopped.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(source) = sources.get(index) else {
+                        break;
+                    };
+                    let options = ParquetOptions {
+                        error_file: options
+                            .error_file
+                            .as_ref()
+                            .map(|_| paths[index].with_extension("errors.tsv")),
+                        ..options.clone()
+                    };
+                    let result =
+                        parquet_from_source(source, paths[index].clone(), filter, config, &options);
+                    stopped.fetch_or(result.is_err(), Ordering::Relaxed);
+                    *results[index].lock().unwrap() = Some(result);
+                }
+            });
+        }
+    });
+
+    let mut written = Vec::with_capacity(sources.len());
+    for (path, result) in paths.into_iter().zip(results) {
+        match result.into_inner().unwrap() {
+            Some(result) => written.push((path, result?)),
+            None => break,
+        }
+    }
+    Ok(written)
+}
+
+/// Name of the Parquet file written for the input at `index`.
+fn output_name(source: &Source, index: usize) -> String {
+    match source.file_name().and_then(|name| name.split('.').next()) {
+        Some(stem) if !stem.is_empty() => format!("{stem}.parquet"),
+        _ => format!("part-{index:03}.parquet"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+    use crate::stream_from_parquet;
+    use crate::testing::TestDir;
+
+    /// Writes three small pageviews files, and returns their sources.
+    fn write_inputs(name: &str) -> (TestDir, Vec<Source>) {
+        let dir = TestDir::new(name);
+        let sources = (0..3)
+            .map(|hour| {
+                let path = dir.join(format!("pageviews-20240818-0{hour}0000"));
+                let lines: String = (0..100)
+                    .map(|i| format!("de Page_{hour}_{i} {i} 0\nen Page_{hour}_{i} 1 0\n"))
+                    .collect();
+                std::fs::write(&path, lines + "de broken\n").unwrap();
+                Source::File(path)
+            })
+            .collect();
+        (dir, sources)
+    }
+
+    #[test]
+    fn test_parquet_from_sources() {
+        let (dir, sources) = write_inputs("multi");
+        let output = dir.join("merged.parquet");
+        let filter = FilterBuilder::new().languages(["de"]).min_views(50).build();
+        let options = ParquetOptions {
+            batch_size: Some(10),
+            ..Default::default()
+        };
+
+        let report = parquet_from_sources(
+            &sources,
+            &output,
+            &filter,
+            &StreamConfig::default(),
+            &options,
+            2,
+        )
+        .unwrap();
+        assert_eq!(report.rows_written, 150);
+        assert_eq!(report.rows_errored, 3);
+        assert_eq!(report.rows_filtered, 3 * 201 - 150 - 3);
+
+        let mut titles: Vec<String> = stream_from_parquet(output)
+            .unwrap()
+            .map(|row| row.unwrap().page_title)
+            .collect();
+        titles.sort();
+        let mut expected: Vec<String> = (0..3)
+            .flat_map(|hour| (50..100).map(move |i| format!("Page_{hour}_{i}")))
+            .collect();
+        expected.sort();
+        assert_eq!(titles, expected);
+    }
+
+    #[test]
+    fn test_parquet_files_from_sources() {
+        let (dir, mut sources) = write_inputs("multi-files");
+        let filter = FilterBuilder::new().languages(["en"]).build();
+
+        let written = parquet_files_from_sources(
+            &sources,
+            dir.path(),
+            &filter,
+            &StreamConfig::default(),
+            &ParquetOptions::default(),
+            3,
+        )
+        .unwrap();
+        let names: Vec<_> = written
+            .iter()
+            .map(|(path, report)| {
+                assert_eq!(report.rows_written, 100);
+                path.file_name().unwrap().to_str().unwrap()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "pageviews-20240818-000000.parquet",
+                "pageviews-20240818-010000.parquet",
+                "pageviews-20240818-020000.parquet",
+            ]
+        );
+
+        // A missing file is returned as an error
+        sources.push(Source::File(dir.join("missing")));
+        assert!(
+            parquet_files_from_sources(
+                &sources,
+                dir.path(),
+                &filter,
+                &StreamConfig::default(),
+                &ParquetOptions::default(),
+                2,
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use crate::filter::{Filter, pre_filter};
+use crate::metrics::count_filtered;
+use crate::parse::{Pageviews, ParseError};
+use crate::stream::StreamConfig;
+use crate::{NumberedLines, parse_lines};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::io::Error as IoError;
+use std::iter::from_fn;
+use std::sync::mpsc::{Receiver, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type RowResult = Result<Pageviews, ParseError>;
+
+/// Number of lines handed to a worker at a time.
+///
+/// Large enough to make the channel overhead negligible, small enough to keep
+/// memory use and latency for the first rows down.
+const BATCH_LINES: usize = 4096;
+
+/// Returns batches from the workers in the order they were read.
+///
+/// Workers finish in any order, so batches arriving early are kept until it's
+/// their turn. The number of batches in flight is bounded by the channels, so
+/// this stays small.
+struct OrderedBatches {
+    receiver: Receiver<(usize, Vec<RowResult>)>,
+    next: usize,
+    pending: BTreeMap<usize, Vec<RowResult>>,
+    current: std::vec::IntoIter<RowResult>,
+}
+
+impl Iterator for OrderedBatches {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.current.next() {
+                return Some(row);
+            }
+            if let Some(batch) = self.pending.remove(&self.next) {
+                self.next += 1;
+                self.current = batch.into_iter();
+                continue;
+            }
+            let (index, batch) = self.receiver.recv().ok()?;
+            self.pending.insert(index, batch);
+        }
+    }
+}
+
+/// Pre-filters, parses, and post-filters lines on `threads` worker threads.
+///
+/// One thread reads and decompresses lines in batches, which are parsed by
+/// the workers and returned in the original order. Dropping the iterator
+/// stops all threads.
+pub fn parse_in_parallel(
+    lines: NumberedLines,
+    filter: &Filter,
+    timestamp: Option<DateTime<Utc>>,
+    threads: usize,
+    config: &StreamConfig,
+) -> impl Iterator<Item = RowResult> + Send + use<> {
+    let threads = threads.max(1);
+    let (batch_sender, batch_receiver) = sync_channel(threads * 2);
+    let (row_sender, row_receiver) = sync_channel(threads * 2);
+
+    thread::spawn(move || {
+        let mut lines = lines;
+        let batches = from_fn(|| {
+            let batch: Vec<(Option<u64>, Result<String, IoError>)> =
+                lines.by_ref().take(BATCH_LINES).collect();
+            (!batch.is_empty()).then_some(batch)
+        });
+        for batch in batches.enumerate() {
+            if batch_sender.send(batch).is_err() {
+                break;
+            }
+        }
+    });
+
+    let batch_receiver = Arc::new(Mutex::new(batch_receiver));
+    for _ in 0..threads {
+        let batch_receiver = Arc::clone(&batch_receiver);
+        let row_sender = row_sender.clone();
+        let filter = filter.clone();
+        let metrics = config.metrics.clone();
+        let mode = config.parse_mode;
+        thread::spawn(move || {
+            let keep = count_filtered(metrics.as_ref(), pre_filter(&filter));
+            loop {
+                let received = batch_receiver.lock().unwrap().recv();
+                let Ok((index, batch)) = received else {
+                    break;
+                };
+                let lines = batch.into_iter().filter(|(_, line)| keep(line));
+                let rows = parse_lines(lines, &filter, timestamp, mode, metrics.as_ref()).collect();
+                if row_sender.send((index, rows)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    OrderedBatches {
+        receiver: row_receiver,
+        next: 0,
+        pending: BTreeMap::new(),
+        current: Vec::new().into_iter(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+
+    #[test]
+    fn test_order_across_batches() {
+        let count = BATCH_LINES * 5 + 7;
+        let lines: NumberedLines =
+            Box::new((0..count).map(|i| (None, Ok(format!("en Page_{i} {i} 0")))));
+        let filter = FilterBuilder::new().min_views(1).build();
+
+        let views: Vec<u32> = parse_in_parallel(lines, &filter, None, 4, &StreamConfig::default())
+            .map(|row| row.unwrap().views)
+            .collect();
+
+        assert_eq!(views, (1..count as u32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_errors_in_order() {
+        let lines: NumberedLines = Box::new(
+            ["en A 1 0", "en B", "en C 3 0"]
+                .into_iter()
+                .zip(1..)
+                .map(|(line, number)| (Some(number), Ok(line.to_string()))),
+        );
+        let rows: Vec<_> =
+            parse_in_parallel(lines, &Filter::default(), None, 2, &StreamConfig::default())
+                .collect();
+
+        assert!(rows[0].is_ok());
+        assert!(matches!(rows[1], Err(ParseError::MissingField(..))));
+        assert_eq!(rows[1].as_ref().unwrap_err().line_number(), Some(2));
+        assert_eq!(rows[2].as_ref().unwrap().page_title, "C");
+        assert_eq!(rows[2].as_ref().unwrap().line_number, Some(3));
+    }
+}
@@ -1,61 +1,140 @@
+use crate::sites::{Project, resolve_domain_code, special_wiki, suffix, suffix_code};
+use chrono::{DateTime, Utc};
+use memchr::memchr;
+use percent_encoding::percent_decode_str;
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::sync::LazyLock;
+use std::fmt;
+use std::ops::Range;
 use thiserror::Error;
 
-/// Hard coded map of domain code -> domain mappings
-static DOMAINS: LazyLock<HashMap<&'static str, &'static str>> = LazyLock::new(|| {
-    HashMap::from([
-        ("b", "wikibooks.org"),
-        ("d", "wiktionary.org"),
-        ("f", "wikimediafoundation.org"),
-        ("m", "wikimedia.org"),
-        ("n", "wikinews.org"),
-        ("q", "wikiquote.org"),
-        ("s", "wikisource.org"),
-        ("v", "wikiversity.org"),
-        ("voy", "wikivoyage.org"),
-        ("w", "mediawiki.org"),
-        ("wd", "wikidata.org"),
-    ])
-});
-
-/// Some white listed Wikimedia projects use a different format for the
-/// domain codes. This is a map of all the current exceptions.
-static WIKIMEDIA_PROJECTS: LazyLock<HashMap<&'static str, &'static str>> = LazyLock::new(|| {
-    HashMap::from([
-        ("commons", "commons.wikimedia.org"),
-        ("meta", "meta.wikimedia.org"),
-        ("incubator", "incubator.wikimedia.org"),
-        ("species", "species.wikimedia.org"),
-        ("strategy", "strategy.wikimedia.org"),
-        ("outreach", "outreach.wikimedia.org"),
-        ("usability", "usability.wikimedia.org"),
-        ("quality", "quality.wikimedia.org"),
-    ])
-});
+/// A line which failed to parse, with its position in the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawLine {
+    /// Number of the line in the decompressed file, starting at 1
+    ///
+    /// `None` if the line was parsed on its own with `parse_line`, or read
+    /// from a file which wasn't read from the start, see
+    /// `StreamConfig::assume_sorted`.
+    pub number: Option<u64>,
+    /// The line as it is in the file
+    pub text: String,
+}
+
+impl From<String> for RawLine {
+    fn from(text: String) -> Self {
+        Self { number: None, text }
+    }
+}
+
+impl fmt::Display for RawLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.number {
+            Some(number) => write!(f, "line {number}: {}", self.text),
+            None => f.write_str(&self.text),
+        }
+    }
+}
 
 #[derive(Debug, Error)]
 pub enum ParseError {
     #[error("Field '{0}' was not found:\n{1}")]
-    MissingField(&'static str, String),
+    MissingField(&'static str, RawLine),
 
     #[error("Invalid '{0}':\n{1}")]
-    InvalidField(&'static str, String),
+    InvalidField(&'static str, RawLine),
 
     #[error(transparent)]
     ReadError(#[from] std::io::Error),
 }
 
-fn missing(field: &'static str, line: &str) -> ParseError {
-    ParseError::MissingField(field, line.to_string())
+impl ParseError {
+    /// Short description of the kind of error, without the offending line.
+    pub fn kind(&self) -> String {
+        match self {
+            ParseError::MissingField(field, _) => format!("missing {field}"),
+            ParseError::InvalidField(field, _) => format!("invalid {field}"),
+            ParseError::ReadError(_) => "read error".to_string(),
+        }
+    }
+
+    /// The line which failed to parse, `None` for read errors.
+    pub fn line(&self) -> Option<&str> {
+        match self {
+            ParseError::MissingField(_, line) | ParseError::InvalidField(_, line) => {
+                Some(&line.text)
+            }
+            ParseError::ReadError(_) => None,
+        }
+    }
+
+    /// Number of the line which failed to parse, if known, see
+    /// `RawLine::number`.
+    pub fn line_number(&self) -> Option<u64> {
+        match self {
+            ParseError::MissingField(_, line) | ParseError::InvalidField(_, line) => line.number,
+            ParseError::ReadError(_) => None,
+        }
+    }
+
+    /// Sets the number of the line which failed to parse.
+    pub(crate) fn at_line(mut self, number: Option<u64>) -> Self {
+        if let ParseError::MissingField(_, line) | ParseError::InvalidField(_, line) = &mut self {
+            line.number = number;
+        }
+        self
+    }
+}
+
+/// Summary of the errors encountered while reading a file.
+///
+/// Keeps a count for each kind of error, and the first few errors in full,
+/// so they can be inspected without keeping all of them in memory.
+#[derive(Debug, Default)]
+pub struct ErrorSummary {
+    /// Number of errors for each kind, e.g. "invalid views"
+    pub counts: HashMap<String, usize>,
+    /// The first errors encountered, in order
+    pub examples: Vec<ParseError>,
+}
+
+impl ErrorSummary {
+    /// Number of errors to keep in full.
+    const MAX_EXAMPLES: usize = 10;
+
+    /// Adds an error to the summary.
+    pub fn record(&mut self, err: ParseError) {
+        *self.counts.entry(err.kind()).or_default() += 1;
+        if self.examples.len() < Self::MAX_EXAMPLES {
+            self.examples.push(err);
+        }
+    }
+
+    /// Total number of errors recorded.
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// Checks if no errors were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+}
+
+pub(crate) fn missing(field: &'static str, line: &str) -> ParseError {
+    ParseError::MissingField(field, line.to_string().into())
 }
 
-fn invalid(field: &'static str, line: &str) -> ParseError {
-    ParseError::InvalidField(field, line.to_string())
+pub(crate) fn invalid(field: &'static str, line: &str) -> ParseError {
+    ParseError::InvalidField(field, line.to_string().into())
 }
 
 /// Parsed domain code components from a Wikimedia pageviews file.
-#[derive(Debug)]
+///
+/// With the `serde` feature, only domains known to the parser can be
+/// deserialized, as `domain` is a static string.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct DomainCode {
     /// Language code (e.g., "en", "de", "ja")
     pub language: String,
@@ -63,10 +142,16 @@ pub struct DomainCode {
     pub domain: Option<&'static str>,
     /// Whether this is a mobile site
     pub mobile: bool,
+    /// Project family if recognized, e.g. `Project::Wiktionary` for `de.d`
+    pub project: Option<Project>,
 }
 
 /// A single row from a Wikimedia pageviews file.
-#[derive(Debug)]
+///
+/// Implements `Serialize` and `Deserialize` with the `serde` feature, with the
+/// timestamp in RFC 3339 format.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pageviews {
     /// Raw domain code from the file (e.g., "en", "de.m", "fr.b")
     pub domain_code: String,
@@ -76,6 +161,143 @@ pub struct Pageviews {
     pub views: u32,
     /// Parsed components of the domain code
     pub parsed_domain_code: DomainCode,
+    /// Start of the hour the views were recorded, from the file name
+    ///
+    /// `None` if the file name doesn't follow the Wikimedia naming scheme, or
+    /// if the row was parsed on its own with `parse_line`.
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Response size in bytes, from the fourth column
+    ///
+    /// Always 0 in the pageviews files, but set in the older `pagecounts-raw`
+    /// files. `None` if the column is missing or not a number.
+    pub response_size: Option<u64>,
+    /// Views per hour, from the fourth column of the `pagecounts-ez` files
+    ///
+    /// See `parse_hourly_views` for the format. `None` for other files.
+    pub hourly_views: Option<Vec<u32>>,
+    /// ID of the page, e.g. a Wikidata QID, from a mapping file
+    ///
+    /// Only set when joining against a mapping with `Filter::page_ids`, see
+    /// `join`. `None` for pages missing from the mapping.
+    pub page_id: Option<String>,
+    /// Number of the line the row was parsed from, starting at 1
+    ///
+    /// Useful to find a row in the original dump. `None` for rows combined
+    /// from several lines, and where `RawLine::number` is `None`.
+    pub line_number: Option<u64>,
+}
+
+impl Pageviews {
+    /// Full host name of the site, e.g. `de.m.wikipedia.org` for `de.m`.
+    ///
+    /// Computed from the domain code, see `sites::resolve_domain_code`.
+    /// `None` for unknown projects and combined rows, like residual rows.
+    pub fn hostname(&self) -> Option<String> {
+        resolve_domain_code(&self.domain_code).map(|site| site.hostname)
+    }
+}
+
+/// Parsed domain code components borrowed from a line, see `DomainCode`.
+#[derive(Clone, Debug)]
+pub struct DomainCodeRef<'a> {
+    /// Language code (e.g., "en", "de", "ja")
+    pub language: Cow<'a, str>,
+    /// Wikimedia domain if recognized (e.g., "wikipedia.org", "wikibooks.org")
+    pub domain: Option<&'static str>,
+    /// Whether this is a mobile site
+    pub mobile: bool,
+    /// Project family if recognized, e.g. `Project::Wiktionary` for `de.d`
+    pub project: Option<Project>,
+}
+
+impl DomainCodeRef<'_> {
+    /// Copies the borrowed strings into a `DomainCode`.
+    pub fn into_owned(self) -> DomainCode {
+        DomainCode {
+            language: self.language.into_owned(),
+            domain: self.domain,
+            mobile: self.mobile,
+            project: self.project,
+        }
+    }
+}
+
+impl<'a> From<&'a DomainCode> for DomainCodeRef<'a> {
+    fn from(code: &'a DomainCode) -> Self {
+        Self {
+            language: Cow::Borrowed(&code.language),
+            domain: code.domain,
+            mobile: code.mobile,
+            project: code.project,
+        }
+    }
+}
+
+/// A single row from a Wikimedia pageviews file, borrowing its strings from
+/// the line it was parsed from.
+///
+/// Returned by `parse_line_ref` and passed to the visitor of `for_each_row`,
+/// to scan files without allocating for each row. The strings are only
+/// copied when they had to be unquoted, or the title decoded. Use `to_owned`
+/// to keep a row around.
+#[derive(Clone, Debug)]
+pub struct PageviewsRef<'a> {
+    /// Raw domain code from the file (e.g., "en", "de.m", "fr.b")
+    pub domain_code: Cow<'a, str>,
+    /// Page title (URL-encoded underscores preserved)
+    pub page_title: Cow<'a, str>,
+    /// Number of views for this page in this hour
+    pub views: u32,
+    /// Parsed components of the domain code
+    pub parsed_domain_code: DomainCodeRef<'a>,
+    /// Start of the hour the views were recorded, see `Pageviews::timestamp`
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Response size in bytes, see `Pageviews::response_size`
+    pub response_size: Option<u64>,
+    /// Views per hour, see `Pageviews::hourly_views`
+    pub hourly_views: Option<Cow<'a, [u32]>>,
+    /// ID of the page from a mapping file, see `Pageviews::page_id`
+    pub page_id: Option<&'a str>,
+    /// Number of the line the row was parsed from, starting at 1
+    pub line_number: Option<u64>,
+}
+
+impl PageviewsRef<'_> {
+    /// Copies the row into an owned `Pageviews`.
+    pub fn to_owned(&self) -> Pageviews {
+        Pageviews {
+            domain_code: self.domain_code.clone().into_owned(),
+            page_title: self.page_title.clone().into_owned(),
+            views: self.views,
+            parsed_domain_code: self.parsed_domain_code.clone().into_owned(),
+            timestamp: self.timestamp,
+            response_size: self.response_size,
+            hourly_views: self.hourly_views.clone().map(Cow::into_owned),
+            page_id: self.page_id.map(str::to_string),
+            line_number: self.line_number,
+        }
+    }
+
+    /// Full host name of the site, see `Pageviews::hostname`.
+    pub fn hostname(&self) -> Option<String> {
+        resolve_domain_code(&self.domain_code).map(|site| site.hostname)
+    }
+}
+
+impl<'a> From<&'a Pageviews> for PageviewsRef<'a> {
+    fn from(row: &'a Pageviews) -> Self {
+        Self {
+            domain_code: Cow::Borrowed(&row.domain_code),
+            page_title: Cow::Borrowed(&row.page_title),
+            views: row.views,
+            parsed_domain_code: DomainCodeRef::from(&row.parsed_domain_code),
+            timestamp: row.timestamp,
+            response_size: row.response_size,
+            hourly_views: row.hourly_views.as_deref().map(Cow::Borrowed),
+            page_id: row.page_id.as_deref(),
+            line_number: row.line_number,
+        }
+    }
 }
 
 /// Normalizes a string in the Wikimedia custom file format.
@@ -84,19 +306,205 @@ pub struct Pageviews {
 /// be contained in a "". This only appears to happen for some empty strings and
 /// for strings containing a ", which is escaped to \". This behavior is not
 /// explicitly documented, so this function may have to be revised.
-fn normalize_string(value: &str) -> String {
-    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
-        value[1..value.len() - 1].replace(r#"\""#, r#"""#)
+pub(crate) fn normalize_string(value: &str) -> String {
+    normalize_str(value).into_owned()
+}
+
+/// Normalizes a string like `normalize_string`, borrowing it unless it
+/// contains escaped quotes.
+fn normalize_str(value: &str) -> Cow<'_, str> {
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return Cow::Borrowed(value);
+    }
+    let value = &value[1..value.len() - 1];
+    if value.contains(r#"\""#) {
+        Cow::Owned(value.replace(r#"\""#, r#"""#))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Quotes a string for the Wikimedia custom file format.
+///
+/// The inverse of `normalize_string`. Empty strings and strings containing
+/// a " are wrapped in "", with the " escaped to \".
+pub(crate) fn quote_string(value: &str) -> String {
+    if value.is_empty() || value.contains('"') {
+        format!(r#""{}""#, value.replace('"', r#"\""#))
     } else {
         value.to_string()
     }
 }
 
+/// Number of hours in the longest month, the length of `hourly_views` in
+/// monthly `pagecounts-ez` files.
+pub const HOURS_PER_MONTH: usize = 31 * 24;
+
+/// Letter encoding the first hour and day in `pagecounts-ez` files.
+const FIRST_LETTER: u8 = b'A';
+
+/// Parses the hourly breakdown in the fourth column of `pagecounts-ez` files.
+///
+/// Each hour with views is written as a letter for the hour, `A` for 00:00 to
+/// `X` for 23:00, followed by the number of views, e.g. `A3C1` for 3 views at
+/// 00:00 and 1 at 02:00. Monthly files add a letter for the day in front of
+/// it, `A` for the 1st to `_` for the 31st.
+///
+/// Daily breakdowns are returned with 24 hours, monthly breakdowns with
+/// `HOURS_PER_MONTH` hours, starting at 00:00 on the 1st. Returns `None` if
+/// the value is not in this format, e.g. a plain number, or if the views of
+/// an hour listed more than once overflow.
+pub fn parse_hourly_views(value: &str) -> Option<Vec<u32>> {
+    let bytes = value.as_bytes();
+    let letters = bytes
+        .iter()
+        .take_while(|b| b.is_ascii_uppercase() || **b == b'_');
+    let (len, width) = match letters.count() {
+        1 => (24, 1),
+        2 => (HOURS_PER_MONTH, 2),
+        _ => return None,
+    };
+
+    let mut hours = vec![0u32; len];
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let (key, tail) = rest.split_at_checked(width)?;
+        let digits = tail.iter().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return None;
+        }
+        let views: u32 = std::str::from_utf8(&tail[..digits]).ok()?.parse().ok()?;
+
+        let hour = usize::from(key[width - 1].checked_sub(FIRST_LETTER)?);
+        let day = match width {
+            2 => usize::from(key[0].checked_sub(FIRST_LETTER)?),
+            _ => 0,
+        };
+        if hour >= 24 || day >= 31 {
+            return None;
+        }
+        let total = &mut hours[day * 24 + hour];
+        *total = total.checked_add(views)?;
+        rest = &tail[digits..];
+    }
+    Some(hours)
+}
+
+/// Formats an hourly breakdown in the `pagecounts-ez` format.
+///
+/// The inverse of `parse_hourly_views`. Breakdowns of 24 hours are written
+/// in the daily format, longer ones in the monthly format.
+pub fn format_hourly_views(hours: &[u32]) -> String {
+    let letter = |i: usize| char::from(FIRST_LETTER + i as u8);
+    let mut value = String::new();
+    for (i, views) in hours.iter().enumerate().filter(|(_, views)| **views > 0) {
+        if hours.len() > 24 {
+            value.push(letter(i / 24));
+        }
+        value.push(letter(i % 24));
+        value.push_str(&views.to_string());
+    }
+    value
+}
+
+/// Finds the static name of a domain the parser can return.
+pub(crate) fn known_domain(domain: &str) -> Option<&'static str> {
+    crate::sites::known_domains()
+        .find(|known| *known == domain)
+        .or_else(|| crate::sitematrix::lookup_host(domain))
+}
+
+/// `DomainCode` as deserialized, before looking up the static domain.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct OwnedDomainCode {
+    language: String,
+    domain: Option<String>,
+    mobile: bool,
+    #[serde(default)]
+    project: Option<Project>,
+}
+
+// Implemented by hand, as derived implementations borrow `&'static str`
+// fields from the input, which only works for static input.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for DomainCode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = OwnedDomainCode::deserialize(deserializer)?;
+        let domain = value
+            .domain
+            .map(|domain| {
+                known_domain(&domain).ok_or_else(|| {
+                    serde::de::Error::custom(format!("unknown Wikimedia domain '{domain}'"))
+                })
+            })
+            .transpose()?;
+        Ok(DomainCode {
+            language: value.language,
+            domain,
+            mobile: value.mobile,
+            project: value.project,
+        })
+    }
+}
+
+/// Looks up the domain of a white listed Wikimedia project, see
+/// `sites::special_wiki`.
+fn wikimedia_project(project: &str) -> Option<&'static str> {
+    special_wiki(project).map(|(hostname, _)| hostname)
+}
+
+/// Translates a wiki code to the domain code used in the pageviews files.
+///
+/// Other datasets, like `pageview_complete`, name wikis by a wiki code such
+/// as `en.wikipedia`, `de.wikibooks`, or `commons.wikimedia`, with the mobile
+/// site in a separate column. These are `en`, `de.b`, and `commons.m` in the
+/// pageviews files, or `en.m`, `de.m.b`, and `commons.m.m` on mobile. Wiki
+/// codes of unknown projects are returned as is.
+pub fn domain_code_from_wiki_code(wiki_code: &str, mobile: bool) -> String {
+    let Some((prefix, project)) = wiki_code.rsplit_once('.') else {
+        return wiki_code.to_string();
+    };
+    let mobile = if mobile { ".m" } else { "" };
+    if project == "wikipedia" {
+        return format!("{prefix}{mobile}");
+    }
+    match suffix_code(&format!("{project}.org")) {
+        Some(code) => format!("{prefix}{mobile}.{code}"),
+        None => wiki_code.to_string(),
+    }
+}
+
+/// Translates a domain code to the domain code of the desktop site.
+///
+/// The mobile marker is removed, e.g. `en.m` becomes `en`, `de.m.b` becomes
+/// `de.b`, and `commons.m.m` becomes `commons.m`. Desktop domain codes are
+/// returned as is.
+pub fn desktop_domain_code(domain_code: &str) -> String {
+    let mut parts = domain_code.splitn(3, '.');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(project), Some(site), Some(_)) if wikimedia_project(project).is_some() => {
+            format!("{project}.{site}")
+        }
+        (Some(project), Some(_), None) if wikimedia_project(project).is_some() => {
+            domain_code.to_string()
+        }
+        (Some(language), Some("m" | "zero"), None) => language.to_string(),
+        (Some(language), Some(_), Some(code)) => format!("{language}.{code}"),
+        _ => domain_code.to_string(),
+    }
+}
+
 /// Parses a Wikimedia domain code into language, project domain, and mobile flag.
 ///
 /// Domain codes follow the pattern defined by the Wikimedia traffic pipeline:
 /// https://wikitech.wikimedia.org/wiki/Data_Platform/Data_Lake/Traffic/Pageviews
-fn parse_domain_code(domain_code: &str) -> Result<DomainCode, ParseError> {
+pub(crate) fn parse_domain_code(domain_code: &str) -> Result<DomainCode, ParseError> {
+    parse_domain_code_ref(domain_code).map(DomainCodeRef::into_owned)
+}
+
+/// Parses a domain code like `parse_domain_code`, borrowing the language.
+fn parse_domain_code_ref(domain_code: &str) -> Result<DomainCodeRef<'_>, ParseError> {
     // The domain code is split in 1-3 parts, separated by periods. These parts
     // will not always have the same meaning, hence the non-descriptive names.
     let mut parts = domain_code.splitn(3, '.');
@@ -110,85 +518,327 @@ fn parse_domain_code(domain_code: &str) -> Result<DomainCode, ParseError> {
     match (first, second, third) {
         // A weird edge case where the domain_code is only a quoted blank
         // string. It appears to be wikifunctions, but is not documented.
-        ("", None, None) => Ok(DomainCode {
-            language: "en".to_string(),
+        ("", None, None) => Ok(DomainCodeRef {
+            language: Cow::Borrowed("en"),
             domain: Some("wikifunctions.org"),
             mobile: false,
+            project: Some(Project::Wikifunctions),
         }),
         // If we only get one part, it's always a language code from a
         // non-mobile wikipedia.org page, e.g. "en" or "no".
-        (language, None, None) => Ok(DomainCode {
-            language: language.into(),
+        (language, None, None) => Ok(DomainCodeRef {
+            language: Cow::Borrowed(language),
             domain: Some("wikipedia.org"),
             mobile: false,
+            project: Some(Project::Wikipedia),
         }),
         // As an edge case, domain codes starting with a white listed Wikimedia
         // project name follows a separate pattern, e.g. "commons.m" for the
         // non-mobile site or "commons.m.m" for the mobile site.
-        (project, _, _) if WIKIMEDIA_PROJECTS.contains_key(project) => Ok(DomainCode {
-            language: "en".to_string(),
-            domain: WIKIMEDIA_PROJECTS.get(project).copied(),
-            mobile: third.is_some(),
-        }),
+        (project, _, _) if let Some((domain, project)) = special_wiki(project) => {
+            Ok(DomainCodeRef {
+                language: Cow::Borrowed("en"),
+                domain: Some(domain),
+                mobile: third.is_some(),
+                project: Some(project),
+            })
+        }
         // Two parts, one of which is "m" or "zero", is a mobile page on
         // wikipedia.org, e.g. "en.m" or "no.zero".
-        (language, Some("m" | "zero"), None) => Ok(DomainCode {
-            language: language.into(),
+        (language, Some("m" | "zero"), None) => Ok(DomainCodeRef {
+            language: Cow::Borrowed(language),
             domain: Some("wikipedia.org"),
             mobile: true,
+            project: Some(Project::Wikipedia),
         }),
         // Two parts without one of the mobile markers is a non-mobile page
         // from a Wikimedia project other than wikipedia.org, e.g. "en.b"
         // for "en.wikibooks.org".
-        (language, Some(code), None) => Ok(DomainCode {
-            language: language.into(),
-            domain: DOMAINS.get(code).copied(),
+        (language, Some(code), None) => Ok(DomainCodeRef {
+            language: Cow::Borrowed(language),
+            domain: suffix(code).map(|(domain, _)| domain),
             mobile: false,
+            project: suffix(code).map(|(_, project)| project),
         }),
         // Three parts is a mobile page from a Wikimedia project other than
         // wikipedia.org, e.g. "en.m.b" for "en.m.wikibooks.org".
-        (language, Some(_), Some(code)) => Ok(DomainCode {
-            language: language.into(),
-            domain: DOMAINS.get(code).copied(),
+        (language, Some(_), Some(code)) => Ok(DomainCodeRef {
+            language: Cow::Borrowed(language),
+            domain: suffix(code).map(|(domain, _)| domain),
             mobile: true,
+            project: suffix(code).map(|(_, project)| project),
         }),
         // Unreachable fallback.
         _ => Err(invalid("domain code", domain_code)),
     }
 }
 
+/// How strictly domain codes are parsed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Domain codes with an unknown project suffix, e.g. `en.x`, are parsed
+    /// with `DomainCode::domain` set to `None`.
+    #[default]
+    Lenient,
+    /// Domain codes with an unknown project suffix are a
+    /// `ParseError::InvalidField` for the `domain code`, to catch changes in
+    /// the dumps or corrupted data.
+    Strict,
+}
+
 /// Parses a single line from a Wikimedia pageviews file.
 ///
 /// The file is space separated with four columns, two strings and two
 /// numbers. The strings can be quoted with escapes for the quote sign.
 /// The first column, domain code, is a dot separated string, which is
-/// broken into subcomponents in the returned struct.
+/// broken into subcomponents in the returned struct. The last column is
+/// the response size, or an hourly breakdown in `pagecounts-ez` files.
 pub fn parse_line(line: String) -> Result<Pageviews, ParseError> {
-    let mut parts = line.splitn(4, ' ');
+    parse_line_with(line, ParseMode::Lenient)
+}
 
-    let domain_code = parts
-        .next()
-        .ok_or_else(|| missing("domain code", &line))?
-        .to_owned();
-    let page_title_raw = parts.next().ok_or_else(|| missing("page title", &line))?;
-    let views = parts
-        .next()
-        .ok_or_else(|| missing("views", &line))?
-        .parse()
-        .map_err(|_| invalid("views", &line))?;
+/// Parses a single line like `parse_line`, with domain codes parsed as
+/// strictly as `mode` says.
+///
+/// ```
+/// use pvstream::parse::{ParseMode, parse_line_with};
+///
+/// assert!(parse_line_with("en.b Main_Page 1 0".into(), ParseMode::Strict).is_ok());
+/// assert!(parse_line_with("en.x Main_Page 1 0".into(), ParseMode::Strict).is_err());
+/// ```
+pub fn parse_line_with(line: String, mode: ParseMode) -> Result<Pageviews, ParseError> {
+    parse_line_ref(&line, mode).map(|row| row.to_owned())
+}
+
+/// Parses a single line like `parse_line_with`, borrowing the strings of the
+/// row from `line`.
+///
+/// Saves allocating the domain code, language, and page title of each row,
+/// e.g. when most rows are filtered out or only counted.
+///
+/// ```
+/// use pvstream::parse::{ParseMode, parse_line_ref};
+///
+/// let line = "de.m Startseite 500 0";
+/// let row = parse_line_ref(line, ParseMode::Lenient)?;
+/// assert_eq!(row.page_title, "Startseite");
+/// assert_eq!(row.parsed_domain_code.language, "de");
+/// assert_eq!(row.to_owned().domain_code, "de.m");
+/// # Ok::<(), pvstream::parse::ParseError>(())
+/// ```
+pub fn parse_line_ref(line: &str, mode: ParseMode) -> Result<PageviewsRef<'_>, ParseError> {
+    let [domain_code, page_title, views, last_column] =
+        split_columns(line.as_bytes()).map(|column| column.map(|range| &line[range]));
 
-    let domain_code = normalize_string(&domain_code);
-    let page_title = normalize_string(page_title_raw);
-    let parsed_domain_code = parse_domain_code(&domain_code)?;
+    let domain_code = domain_code.ok_or_else(|| missing("domain code", line))?;
+    let page_title = page_title.ok_or_else(|| missing("page title", line))?;
+    let views = parse_views(views, || line)?;
+    let (domain_code, parsed_domain_code) = parse_domain(domain_code, mode, || line)?;
 
-    Ok(Pageviews {
+    Ok(PageviewsRef {
         domain_code,
-        page_title,
+        page_title: normalize_str(page_title),
         views,
         parsed_domain_code,
+        timestamp: None,
+        response_size: None,
+        hourly_views: None,
+        page_id: None,
+        line_number: None,
+    }
+    .with_last_column(last_column))
+}
+
+/// A row parsed from the bytes of a line, with the page title left as bytes.
+///
+/// The domain code and views are enough for most filters, so rows can be
+/// rejected before the title is checked for valid UTF-8, see
+/// `Filter::may_match`. Call `into_row` for the rest of the row.
+pub(crate) struct RawPageviews<'a> {
+    pub(crate) domain_code: Cow<'a, str>,
+    pub(crate) views: u32,
+    pub(crate) parsed_domain_code: DomainCodeRef<'a>,
+    page_title: &'a [u8],
+    last_column: Option<&'a [u8]>,
+    line: &'a [u8],
+}
+
+impl<'a> RawPageviews<'a> {
+    /// Validates and unquotes the page title, and parses the last column.
+    pub(crate) fn into_row(self) -> Result<PageviewsRef<'a>, ParseError> {
+        let line = || String::from_utf8_lossy(self.line);
+        let page_title =
+            std::str::from_utf8(self.page_title).map_err(|_| invalid("page title", &line()))?;
+        let last_column = self
+            .last_column
+            .and_then(|column| std::str::from_utf8(column).ok());
+
+        Ok(PageviewsRef {
+            domain_code: self.domain_code,
+            page_title: normalize_str(page_title),
+            views: self.views,
+            parsed_domain_code: self.parsed_domain_code,
+            timestamp: None,
+            response_size: None,
+            hourly_views: None,
+            page_id: None,
+            line_number: None,
+        }
+        .with_last_column(last_column))
+    }
+}
+
+/// Parses the bytes of a line up to the page title, see `RawPageviews`.
+///
+/// Columns are found with `memchr`, and only the domain code and views are
+/// checked for valid UTF-8. Unlike reading lines as strings, a line with
+/// invalid UTF-8 fails to parse on its own instead of stopping the stream.
+pub(crate) fn parse_line_bytes(
+    line: &[u8],
+    mode: ParseMode,
+) -> Result<RawPageviews<'_>, ParseError> {
+    let text = || String::from_utf8_lossy(line);
+    let [domain_code, page_title, views, last_column] =
+        split_columns(line).map(|column| column.map(|range| &line[range]));
+
+    let domain_code = domain_code.ok_or_else(|| missing("domain code", &text()))?;
+    let domain_code =
+        std::str::from_utf8(domain_code).map_err(|_| invalid("domain code", &text()))?;
+    let page_title = page_title.ok_or_else(|| missing("page title", &text()))?;
+    let views = views
+        .map(|views| std::str::from_utf8(views).map_err(|_| invalid("views", &text())))
+        .transpose()?;
+    let views = parse_views(views, text)?;
+    let (domain_code, parsed_domain_code) = parse_domain(domain_code, mode, text)?;
+
+    Ok(RawPageviews {
+        domain_code,
+        views,
+        parsed_domain_code,
+        page_title,
+        last_column,
+        line,
     })
 }
 
+/// Byte ranges of the four columns of a line, split at the first three
+/// spaces with `memchr`. Like `splitn(4, ' ')`, the last column keeps any
+/// spaces after them, and missing columns are `None`.
+fn split_columns(line: &[u8]) -> [Option<Range<usize>>; 4] {
+    let mut columns = [None, None, None, None];
+    let mut start = 0;
+    for column in &mut columns[..3] {
+        match memchr(b' ', &line[start..]) {
+            Some(end) => {
+                *column = Some(start..start + end);
+                start += end + 1;
+            }
+            None => {
+                *column = Some(start..line.len());
+                return columns;
+            }
+        }
+    }
+    columns[3] = Some(start..line.len());
+    columns
+}
+
+/// Parses the views column, with `line` for the error message.
+fn parse_views<L: AsRef<str>>(
+    views: Option<&str>,
+    line: impl Fn() -> L,
+) -> Result<u32, ParseError> {
+    views
+        .ok_or_else(|| missing("views", line().as_ref()))?
+        .parse()
+        .map_err(|_| invalid("views", line().as_ref()))
+}
+
+/// Unquotes and parses the domain code column, with `line` for the error
+/// message.
+fn parse_domain<'a, L: AsRef<str>>(
+    domain_code: &'a str,
+    mode: ParseMode,
+    line: impl Fn() -> L,
+) -> Result<(Cow<'a, str>, DomainCodeRef<'a>), ParseError> {
+    let domain_code = normalize_str(domain_code);
+    let parsed_domain_code = match &domain_code {
+        Cow::Borrowed(domain_code) => parse_domain_code_ref(domain_code)?,
+        // Unquoting copied the domain code, so the language must be copied too
+        Cow::Owned(domain_code) => {
+            let parsed = parse_domain_code_ref(domain_code)?;
+            DomainCodeRef {
+                language: Cow::Owned(parsed.language.into_owned()),
+                ..parsed
+            }
+        }
+    };
+    if mode == ParseMode::Strict && parsed_domain_code.domain.is_none() {
+        return Err(invalid("domain code", line().as_ref()));
+    }
+    Ok((domain_code, parsed_domain_code))
+}
+
+impl PageviewsRef<'_> {
+    /// Sets the response size or hourly views from the last column.
+    fn with_last_column(mut self, last_column: Option<&str>) -> Self {
+        let last_column = last_column.map(str::trim_end);
+        self.response_size = last_column.and_then(|value| value.parse().ok());
+        self.hourly_views = last_column.and_then(parse_hourly_views).map(Cow::Owned);
+        self
+    }
+}
+
+/// Decodes a page title from the dumps into the title shown on the wiki.
+///
+/// Percent-encoded characters, like `%C3%A9` for `é`, are decoded, and
+/// underscores are replaced with spaces. Escapes which don't decode to valid
+/// UTF-8 are kept as they are. Borrows the title if there is nothing to decode.
+///
+/// ```
+/// use pvstream::parse::decode_title;
+///
+/// assert_eq!(decode_title("Caf%C3%A9_de_Flore"), "Café de Flore");
+/// assert_eq!(decode_title("100%_Pure"), "100% Pure");
+/// ```
+pub fn decode_title(title: &str) -> Cow<'_, str> {
+    let decoded = match percent_decode_str(title).decode_utf8() {
+        Ok(decoded) => decoded,
+        Err(_) => Cow::Borrowed(title),
+    };
+    match decoded {
+        Cow::Borrowed(title) if !title.contains('_') => Cow::Borrowed(title),
+        decoded => Cow::Owned(decoded.replace('_', " ")),
+    }
+}
+
+/// Replaces the page title of a row with its decoded form, see `decode_title`.
+pub(crate) fn decode_page_title(row: &mut Pageviews) {
+    if let Cow::Owned(title) = decode_title(&row.page_title) {
+        row.page_title = title;
+    }
+}
+
+/// Formats a row as a line in a Wikimedia pageviews file.
+///
+/// The inverse of `parse_line`, quoting strings where needed. The last
+/// column is the hourly breakdown if set, or else the response size, which
+/// is always 0 in the pageviews files.
+pub fn format_line(row: &Pageviews) -> String {
+    let last_column = row
+        .hourly_views
+        .as_deref()
+        .map(format_hourly_views)
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| row.response_size.unwrap_or(0).to_string());
+    format!(
+        "{} {} {} {last_column}",
+        quote_string(&row.domain_code),
+        quote_string(&row.page_title),
+        row.views
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -217,12 +867,95 @@ mod tests {
         assert_eq!(result, r#"Pryp"jat'"#);
     }
 
+    #[test]
+    fn test_parse_line_ref() {
+        let line = "de.m.b Startseite 500 0";
+        let row = parse_line_ref(line, ParseMode::Lenient).unwrap();
+        assert!(matches!(row.domain_code, Cow::Borrowed("de.m.b")));
+        assert!(matches!(row.page_title, Cow::Borrowed("Startseite")));
+        assert!(matches!(
+            row.parsed_domain_code.language,
+            Cow::Borrowed("de")
+        ));
+        assert_eq!(row.parsed_domain_code.project, Some(Project::Wikibooks));
+
+        let owned = row.to_owned();
+        assert_eq!(owned.domain_code, "de.m.b");
+        assert_eq!(owned.page_title, "Startseite");
+        assert_eq!(owned.views, 500);
+        assert!(owned.parsed_domain_code.mobile);
+        assert_eq!(owned.response_size, Some(0));
+
+        let line = r#""en" "Pryp\"jat'" 3 0"#;
+        let row = parse_line_ref(line, ParseMode::Lenient).unwrap();
+        assert!(matches!(row.domain_code, Cow::Borrowed("en")));
+        assert!(matches!(row.page_title, Cow::Owned(_)));
+        assert_eq!(row.page_title, r#"Pryp"jat'"#);
+
+        assert!(parse_line_ref("en.x Main_Page 1 0", ParseMode::Strict).is_err());
+        assert!(parse_line_ref("en Main_Page", ParseMode::Lenient).is_err());
+    }
+
+    #[test]
+    fn test_split_columns() {
+        fn columns(line: &str) -> [Option<&str>; 4] {
+            split_columns(line.as_bytes()).map(|column| column.map(|range| &line[range]))
+        }
+        assert_eq!(
+            columns("en Main_Page 1 0"),
+            [Some("en"), Some("Main_Page"), Some("1"), Some("0")]
+        );
+        assert_eq!(
+            columns("en A 1 B2 C3"),
+            [Some("en"), Some("A"), Some("1"), Some("B2 C3")]
+        );
+        assert_eq!(columns("en A"), [Some("en"), Some("A"), None, None]);
+        assert_eq!(columns(""), [Some(""), None, None, None]);
+    }
+
+    #[test]
+    fn test_parse_line_bytes() {
+        let row = parse_line_bytes(br#"de.m "Pryp\"jat'" 500 0"#, ParseMode::Lenient).unwrap();
+        assert_eq!(row.domain_code, "de.m");
+        assert_eq!(row.views, 500);
+        let row = row.into_row().unwrap();
+        assert_eq!(row.page_title, r#"Pryp"jat'"#);
+        assert_eq!(row.response_size, Some(0));
+
+        // Invalid UTF-8 in the title is only an error once the title is read
+        let row = parse_line_bytes(b"en \xffMain 1 0", ParseMode::Lenient).unwrap();
+        assert_eq!(row.parsed_domain_code.language, "en");
+        let err = row.into_row().unwrap_err();
+        assert_eq!(err.kind(), "invalid page title");
+
+        let err = parse_line_bytes(b"\xff Main 1 0", ParseMode::Lenient)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), "invalid domain code");
+        let err = parse_line_bytes(b"en Main x 0", ParseMode::Lenient)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), "invalid views");
+    }
+
+    #[test]
+    fn test_desktop_domain_code() {
+        assert_eq!(desktop_domain_code("en"), "en");
+        assert_eq!(desktop_domain_code("en.m"), "en");
+        assert_eq!(desktop_domain_code("no.zero"), "no");
+        assert_eq!(desktop_domain_code("de.b"), "de.b");
+        assert_eq!(desktop_domain_code("de.m.b"), "de.b");
+        assert_eq!(desktop_domain_code("commons.m"), "commons.m");
+        assert_eq!(desktop_domain_code("commons.m.m"), "commons.m");
+    }
+
     #[test]
     fn test_wikipedia_plain() {
         let result = parse_domain_code("en").unwrap();
         assert_eq!(result.language, "en");
         assert_eq!(result.domain, Some("wikipedia.org"));
         assert!(!result.mobile);
+        assert_eq!(result.project, Some(Project::Wikipedia));
     }
 
     #[test]
@@ -247,6 +980,7 @@ mod tests {
         assert_eq!(result.language, "fr");
         assert_eq!(result.domain, Some("wikiversity.org"));
         assert!(result.mobile);
+        assert_eq!(result.project, Some(Project::Wikiversity));
     }
 
     #[test]
@@ -255,6 +989,7 @@ mod tests {
         assert_eq!(result.language, "en");
         assert_eq!(result.domain, Some("commons.wikimedia.org"));
         assert!(!result.mobile);
+        assert_eq!(result.project, Some(Project::Commons));
     }
 
     #[test]
@@ -271,6 +1006,7 @@ mod tests {
         assert_eq!(result.language, "en");
         assert_eq!(result.domain, Some("wikifunctions.org"));
         assert!(!result.mobile);
+        assert_eq!(result.project, Some(Project::Wikifunctions));
     }
 
     #[test]
@@ -278,9 +1014,25 @@ mod tests {
         let result = parse_domain_code("xx.unknown").unwrap();
         assert_eq!(result.language, "xx");
         assert_eq!(result.domain, None);
+        assert_eq!(result.project, None);
         assert!(!result.mobile);
     }
 
+    #[test]
+    fn test_strict_mode() {
+        for line in [
+            "en Main_Page 1 0",
+            "en.m.voy Oslo 1 0",
+            "commons.m File 1 0",
+        ] {
+            assert!(parse_line_with(line.into(), ParseMode::Strict).is_ok());
+        }
+        let err = parse_line_with("xx.unknown Page 1 0".into(), ParseMode::Strict).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidField("domain code", _)));
+        assert_eq!(err.line(), Some("xx.unknown Page 1 0"));
+        assert!(parse_line_with("xx.m.unknown Page 1 0".into(), ParseMode::Lenient).is_ok());
+    }
+
     #[test]
     fn test_simple_line() {
         let result = parse_line("en.m Copenhagen 54 0".into()).unwrap();
@@ -318,6 +1070,81 @@ mod tests {
         assert!(result.parsed_domain_code.mobile);
     }
 
+    #[test]
+    fn test_format_line() {
+        for line in [
+            "uk.b Ядро_Linux/Модулі 2 0",
+            r#"vi.m "\"Hello,_World!\"_(chương_trình_máy_tính)" 1 0"#,
+            r#"en "" 5 0"#,
+            r#"de "A_\"B" 3 0"#,
+        ] {
+            assert_eq!(format_line(&parse_line(line.into()).unwrap()), line);
+        }
+    }
+
+    #[test]
+    fn test_domain_code_from_wiki_code() {
+        for (wiki_code, desktop, mobile) in [
+            ("en.wikipedia", "en", "en.m"),
+            ("de.wikibooks", "de.b", "de.m.b"),
+            ("commons.wikimedia", "commons.m", "commons.m.m"),
+            ("www.wikidata", "www.wd", "www.m.wd"),
+            ("xx.unknown", "xx.unknown", "xx.unknown"),
+        ] {
+            assert_eq!(domain_code_from_wiki_code(wiki_code, false), desktop);
+            assert_eq!(domain_code_from_wiki_code(wiki_code, true), mobile);
+        }
+        let parsed = parse_domain_code(&domain_code_from_wiki_code("fr.wikivoyage", true)).unwrap();
+        assert_eq!(parsed.domain, Some("wikivoyage.org"));
+        assert!(parsed.mobile);
+    }
+
+    #[test]
+    fn test_last_column() {
+        let pageviews = parse_line("en Main_Page 10 0".into()).unwrap();
+        assert_eq!(pageviews.response_size, Some(0));
+        assert_eq!(pageviews.hourly_views, None);
+
+        let pagecounts = parse_line("en Main_Page 10 53421".into()).unwrap();
+        assert_eq!(pagecounts.response_size, Some(53421));
+
+        let missing = parse_line("en Main_Page 10".into()).unwrap();
+        assert_eq!(missing.response_size, None);
+        assert_eq!(missing.hourly_views, None);
+    }
+
+    #[test]
+    fn test_hourly_views() {
+        let daily = parse_line("en.z Main_Page 10 A3C1X6".into()).unwrap();
+        let hours = daily.hourly_views.as_deref().unwrap();
+        assert_eq!(daily.response_size, None);
+        assert_eq!(hours.len(), 24);
+        assert_eq!((hours[0], hours[1], hours[2], hours[23]), (3, 0, 1, 6));
+        assert_eq!(format_line(&daily), "en.z Main_Page 10 A3C1X6");
+
+        let monthly = parse_hourly_views("AA3BX12_A1").unwrap();
+        assert_eq!(monthly.len(), HOURS_PER_MONTH);
+        assert_eq!(monthly[0], 3);
+        assert_eq!(monthly[24 + 23], 12);
+        assert_eq!(monthly[30 * 24], 1);
+        assert_eq!(monthly.iter().sum::<u32>(), 16);
+        assert_eq!(format_hourly_views(&monthly), "AA3BX12_A1");
+
+        for invalid in [
+            "",
+            "0",
+            "A",
+            "3A",
+            "Y1",
+            "AB",
+            "ABC1",
+            "A1BB2",
+            "A4294967295A1",
+        ] {
+            assert_eq!(parse_hourly_views(invalid), None, "{invalid}");
+        }
+    }
+
     #[test]
     fn test_wikibooks_line() {
         let result = parse_line("uk.b Ядро_Linux/Модулі 2 0".into()).unwrap();
@@ -344,6 +1171,54 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_error_summary() {
+        let mut summary = ErrorSummary::default();
+        assert!(summary.is_empty());
+
+        for line in ["", "en.m Hello_World", "en.m Hello_World x 0", "en.m A b 0"] {
+            summary.record(parse_line(line.into()).unwrap_err());
+        }
+
+        assert_eq!(summary.total(), 4);
+        assert_eq!(summary.counts["missing page title"], 1);
+        assert_eq!(summary.counts["missing views"], 1);
+        assert_eq!(summary.counts["invalid views"], 2);
+        assert_eq!(summary.examples.len(), 4);
+    }
+
+    #[test]
+    fn test_decode_title() {
+        assert!(matches!(decode_title("Main"), Cow::Borrowed("Main")));
+        assert_eq!(decode_title("Main_Page"), "Main Page");
+        assert_eq!(decode_title("%E6%97%A5%E6%9C%AC"), "日本");
+        assert_eq!(decode_title("50%25_off"), "50% off");
+        // Invalid UTF-8 after decoding is kept as is
+        assert_eq!(decode_title("Bad_%FF"), "Bad %FF");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let mut row = parse_line("de.m.b Startseite 5 0".into()).unwrap();
+        row.timestamp = "2024-08-18T08:00:00Z".parse().ok();
+
+        let json = serde_json::to_string(&row).unwrap();
+        assert!(json.contains(r#""domain":"wikibooks.org""#));
+        assert!(json.contains(r#""timestamp":"2024-08-18T08:00:00Z""#));
+
+        let back: Pageviews = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.domain_code, row.domain_code);
+        assert_eq!(back.page_title, row.page_title);
+        assert_eq!(back.parsed_domain_code.domain, Some("wikibooks.org"));
+        assert!(back.parsed_domain_code.mobile);
+        assert_eq!(back.timestamp, row.timestamp);
+
+        let unknown = json.replace("wikibooks.org", "example.org");
+        let err = serde_json::from_str::<Pageviews>(&unknown).unwrap_err();
+        assert!(err.to_string().contains("unknown Wikimedia domain"));
+    }
+
     #[test]
     fn test_invalid_fields() {
         // Invalid domain code is currently unreachable. Maybe we should be
@@ -0,0 +1,211 @@
+//! Streaming into PostgreSQL tables.
+//!
+//! Requires the `postgres` feature. Rows are sent to a table with the binary
+//! `COPY ... FROM STDIN` protocol as they're read, which is the fastest way
+//! to load rows into PostgreSQL, without an intermediate file.
+//!
+//! The table is created if it doesn't exist, with the columns of the flat
+//! Parquet layout:
+//!
+//! ```sql
+//! domain_code TEXT NOT NULL,
+//! page_title TEXT NOT NULL,
+//! views BIGINT NOT NULL,
+//! language TEXT NOT NULL,
+//! domain TEXT,
+//! mobile BOOLEAN NOT NULL,
+//! project TEXT,
+//! timestamp TIMESTAMPTZ,
+//! response_size BIGINT,
+//! hourly_views BIGINT[],
+//! page_id TEXT
+//! ```
+//!
+//! PostgreSQL has no unsigned integers, so the counts are stored as `BIGINT`.
+//! Existing tables must have columns of the same types and names. Connections
+//! opened from a connection string don't use TLS. To use TLS, or a connection
+//! pool, open the `Client` yourself and use `postgres_from_source`.
+
+use crate::filter::Filter;
+use crate::parse::Pageviews;
+use crate::stream::{Source, StreamConfig, StreamError};
+use crate::stream_from_source;
+use ::postgres::binary_copy::BinaryCopyInWriter;
+use ::postgres::types::{ToSql, Type};
+use ::postgres::{Client, NoTls};
+
+/// Column definitions of the tables created by this module.
+const COLUMNS: &str = "\
+    domain_code TEXT NOT NULL, \
+    page_title TEXT NOT NULL, \
+    views BIGINT NOT NULL, \
+    language TEXT NOT NULL, \
+    domain TEXT, \
+    mobile BOOLEAN NOT NULL, \
+    project TEXT, \
+    timestamp TIMESTAMPTZ, \
+    response_size BIGINT, \
+    hourly_views BIGINT[], \
+    page_id TEXT";
+
+/// Types of `COLUMNS`, in the same order.
+const TYPES: [Type; 11] = [
+    Type::TEXT,
+    Type::TEXT,
+    Type::INT8,
+    Type::TEXT,
+    Type::TEXT,
+    Type::BOOL,
+    Type::TEXT,
+    Type::TIMESTAMPTZ,
+    Type::INT8,
+    Type::INT8_ARRAY,
+    Type::TEXT,
+];
+
+/// Parse a pageviews file from any source and copy the filtered rows to a
+/// PostgreSQL table.
+///
+/// Connects to the database with `conn_str`, e.g.
+/// `host=localhost user=postgres dbname=wiki` or
+/// `postgresql://postgres@localhost/wiki`, without TLS. The table is created
+/// if it doesn't exist. Returns the number of rows copied.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::filter::FilterBuilder;
+/// use pvstream::postgres::copy_to_postgres;
+/// use pvstream::stream::Source;
+/// use std::path::PathBuf;
+///
+/// let filter = FilterBuilder::new().languages(["nn", "nb"]).build();
+/// let rows = copy_to_postgres(
+///     &Source::File(PathBuf::from("pageviews-20240818-080000.gz")),
+///     "host=localhost user=postgres dbname=wiki",
+///     "pageviews",
+///     &filter,
+/// )?;
+/// println!("{rows} rows copied");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn copy_to_postgres(
+    source: &Source,
+    conn_str: &str,
+    table: &str,
+    filter: &Filter,
+) -> Result<u64, StreamError> {
+    let mut client = Client::connect(conn_str, NoTls)?;
+    postgres_from_source(source, &mut client, table, filter, &StreamConfig::default())
+}
+
+/// Copy the filtered rows of a pageviews file from any source to a table.
+///
+/// Takes an open client, which makes it possible to use TLS, or to copy
+/// several files in one transaction. The rows are copied in one `COPY`
+/// statement, so they're all committed or none are: rows failing to parse are
+/// skipped, while a read error, or a parse error with
+/// `ErrorPolicy::FailFast`, aborts the copy and is returned.
+pub fn postgres_from_source(
+    source: &Source,
+    client: &mut Client,
+    table: &str,
+    filter: &Filter,
+    config: &StreamConfig,
+) -> Result<u64, StreamError> {
+    let table = quote_identifier(table);
+    client.batch_execute(&format!("CREATE TABLE IF NOT EXISTS {table} ({COLUMNS})"))?;
+
+    let rows = stream_from_source(source, filter, config)?;
+    let sink = client.copy_in(&format!("COPY {table} FROM STDIN (FORMAT binary)"))?;
+    let mut writer = BinaryCopyInWriter::new(sink, &TYPES);
+    for row in rows {
+        let row = match row {
+            Ok(row) => row,
+            // Dropping the writer without finishing aborts the copy
+            Err(err) if config.error_policy.is_fatal(&err) => return Err(err.into()),
+            Err(_) => continue,
+        };
+        write_row(&mut writer, &row)?;
+    }
+    Ok(writer.finish()?)
+}
+
+/// Quotes a table name, so any name can be used as is.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Writes a row with the columns of the table.
+fn write_row(writer: &mut BinaryCopyInWriter, row: &Pageviews) -> Result<(), ::postgres::Error> {
+    let response_size = row.response_size.map(|size| size as i64);
+    let hourly_views: Option<Vec<i64>> = row
+        .hourly_views
+        .as_ref()
+        .map(|hours| hours.iter().copied().map(i64::from).collect());
+    let project = row
+        .parsed_domain_code
+        .project
+        .map(|project| project.as_str());
+    let values: [&(dyn ToSql + Sync); 11] = [
+        &row.domain_code,
+        &row.page_title,
+        &i64::from(row.views),
+        &row.parsed_domain_code.language,
+        &row.parsed_domain_code.domain,
+        &row.parsed_domain_code.mobile,
+        &project,
+        &row.timestamp,
+        &response_size,
+        &hourly_views,
+        &row.page_id,
+    ];
+    writer.write(&values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+    use std::path::PathBuf;
+
+    #[test]
+    #[ignore = "needs a PostgreSQL server at PVSTREAM_TEST_POSTGRES"]
+    fn test_postgres_from_source() {
+        let conn_str = std::env::var("PVSTREAM_TEST_POSTGRES").unwrap();
+        let source = Source::File(PathBuf::from("tests/files/pageviews-20240803-060000.gz"));
+        let filter = FilterBuilder::new().languages(["de", "ja"]).build();
+        let table = format!("page views {}", std::process::id());
+
+        let mut client = Client::connect(&conn_str, NoTls).unwrap();
+        let copied = copy_to_postgres(&source, &conn_str, &table, &filter).unwrap();
+        let row = client
+            .query_one(
+                &format!(
+                    "SELECT count(*), sum(views)::BIGINT, max(extract(epoch FROM timestamp))::BIGINT \
+                     FROM {} WHERE language IN ('de', 'ja')",
+                    quote_identifier(&table)
+                ),
+                &[],
+            )
+            .unwrap();
+        client
+            .batch_execute(&format!("DROP TABLE {}", quote_identifier(&table)))
+            .unwrap();
+
+        let expected: Vec<_> = stream_from_source(&source, &filter, &StreamConfig::default())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(copied, expected.len() as u64);
+        assert_eq!(row.get::<_, i64>(0), expected.len() as i64);
+        assert_eq!(
+            row.get::<_, i64>(1),
+            expected.iter().map(|row| i64::from(row.views)).sum::<i64>()
+        );
+        assert_eq!(
+            Some(row.get::<_, i64>(2)),
+            source.timestamp().map(|ts| ts.timestamp())
+        );
+    }
+}
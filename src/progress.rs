@@ -0,0 +1,298 @@
+//! Progress reporting for long running streams.
+//!
+//! Set `StreamConfig::progress` to a `ProgressHook` to get regular snapshots
+//! of how far a stream has come, e.g. to drive a progress bar.
+
+use crate::stream::StreamConfig;
+use std::fmt;
+use std::io::{Error as IoError, Read};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Number of lines read between each progress report.
+const LINES_PER_REPORT: u64 = 100_000;
+
+/// Snapshot of the progress of a stream, passed to a `ProgressHook`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Size of the compressed file, if known from the file system or the
+    /// `Content-Length` header.
+    pub total_bytes: Option<u64>,
+    /// Compressed bytes read from the file or downloaded so far.
+    pub bytes_read: u64,
+    /// Bytes after decompression so far.
+    pub bytes_decompressed: u64,
+    /// Lines read from the file so far, before any filtering.
+    pub lines_read: u64,
+    /// Rows (or lines, when not parsing) returned to the caller so far.
+    pub rows_emitted: u64,
+    /// Errors returned to the caller so far, in place of rows.
+    pub rows_errored: u64,
+    /// Set on the last report, once the stream is exhausted.
+    pub finished: bool,
+}
+
+/// Callback receiving `Progress` reports.
+///
+/// The hook is called every 100 000 lines read, and once more when the stream
+/// is exhausted. It may be called from a background thread, e.g. when parsing
+/// in parallel, so keep it short.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::progress::ProgressHook;
+/// use pvstream::stream::{Source, StreamConfig};
+/// use pvstream::{filter::FilterBuilder, stream_from_source};
+/// use std::path::PathBuf;
+///
+/// let config = StreamConfig {
+///     progress: Some(ProgressHook::new(|progress| {
+///         eprintln!("{} lines, {} rows", progress.lines_read, progress.rows_emitted);
+///     })),
+///     ..Default::default()
+/// };
+/// let source = Source::File(PathBuf::from("pageviews-20240818-080000.gz"));
+/// let filter = FilterBuilder::new().languages(["de"]).build();
+///
+/// for row in stream_from_source(&source, &filter, &config)? {
+///     let _ = row?;
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone)]
+pub struct ProgressHook(Arc<dyn Fn(&Progress) + Send + Sync>);
+
+impl ProgressHook {
+    pub fn new(hook: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    pub(crate) fn call(&self, progress: &Progress) {
+        (self.0)(progress);
+    }
+
+    /// Hook keeping the latest report, and passing it on to `inner`, if any.
+    pub(crate) fn recording(inner: Option<ProgressHook>) -> (Self, Arc<Mutex<Progress>>) {
+        let latest = Arc::new(Mutex::new(Progress::default()));
+        let hook = {
+            let latest = Arc::clone(&latest);
+            Self::new(move |progress| {
+                *latest.lock().unwrap() = *progress;
+                if let Some(inner) = &inner {
+                    inner.call(progress);
+                }
+            })
+        };
+        (hook, latest)
+    }
+}
+
+impl fmt::Debug for ProgressHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressHook(..)")
+    }
+}
+
+/// Running totals of a single stream.
+#[derive(Default)]
+struct Counters {
+    total_bytes: OnceLock<u64>,
+    bytes_read: AtomicU64,
+    bytes_decompressed: AtomicU64,
+    lines_read: AtomicU64,
+    rows_emitted: AtomicU64,
+    rows_errored: AtomicU64,
+    finished: AtomicBool,
+}
+
+/// Which byte counter a `CountingReader` adds to.
+#[derive(Clone, Copy)]
+pub(crate) enum ByteCount {
+    Read,
+    Decompressed,
+}
+
+/// Counts the progress of a single stream, and reports it to the hook.
+///
+/// Cheap to clone, all clones share the same counters.
+#[derive(Clone)]
+pub(crate) struct Tracker {
+    counters: Arc<Counters>,
+    hook: ProgressHook,
+}
+
+impl Tracker {
+    pub(crate) fn new(hook: &ProgressHook) -> Self {
+        Self {
+            counters: Arc::default(),
+            hook: hook.clone(),
+        }
+    }
+
+    /// Tracker for a stream read with `config`, if its progress is reported,
+    /// or logged with the `tracing` feature.
+    pub(crate) fn for_config(config: &StreamConfig) -> Option<Self> {
+        #[cfg(feature = "tracing")]
+        return Some(Self::new(&crate::trace::logging_hook(
+            config.progress.clone(),
+        )));
+        #[cfg(not(feature = "tracing"))]
+        config.progress.as_ref().map(Self::new)
+    }
+
+    /// Sets the size of the compressed file, if not set already.
+    pub(crate) fn set_total_bytes(&self, total: u64) {
+        let _ = self.counters.total_bytes.set(total);
+    }
+
+    fn snapshot(&self) -> Progress {
+        let counters = &self.counters;
+        Progress {
+            total_bytes: counters.total_bytes.get().copied(),
+            bytes_read: counters.bytes_read.load(Relaxed),
+            bytes_decompressed: counters.bytes_decompressed.load(Relaxed),
+            lines_read: counters.lines_read.load(Relaxed),
+            rows_emitted: counters.rows_emitted.load(Relaxed),
+            rows_errored: counters.rows_errored.load(Relaxed),
+            finished: counters.finished.load(Relaxed),
+        }
+    }
+
+    fn report(&self) {
+        self.hook.call(&self.snapshot());
+    }
+
+    /// Sends the final report, only once.
+    pub(crate) fn finish(&self) {
+        if !self.counters.finished.swap(true, Relaxed) {
+            self.report();
+        }
+    }
+
+    /// Counts the bytes read through `inner`.
+    pub(crate) fn count_bytes<R: Read>(&self, inner: R, count: ByteCount) -> CountingReader<R> {
+        CountingReader {
+            inner,
+            tracker: self.clone(),
+            count,
+        }
+    }
+
+    /// Counts lines, reporting every `LINES_PER_REPORT` lines.
+    pub(crate) fn count_lines<I: Iterator>(
+        &self,
+        lines: I,
+    ) -> impl Iterator<Item = I::Item> + use<I> {
+        let tracker = self.clone();
+        lines.inspect(move |_| tracker.count_line())
+    }
+
+    /// Counts a line read, see `count_lines`.
+    pub(crate) fn count_line(&self) {
+        let lines_read = self.counters.lines_read.fetch_add(1, Relaxed) + 1;
+        if lines_read.is_multiple_of(LINES_PER_REPORT) {
+            self.report();
+        }
+    }
+
+    /// Counts the items returned to the caller, with a final report once
+    /// they run out.
+    pub(crate) fn count_emitted<I, T, E>(
+        &self,
+        mut items: I,
+    ) -> impl Iterator<Item = Result<T, E>> + use<I, T, E>
+    where
+        I: Iterator<Item = Result<T, E>>,
+    {
+        let tracker = self.clone();
+        std::iter::from_fn(move || {
+            let item = items.next();
+            match &item {
+                Some(item) => tracker.count_item(item.is_ok()),
+                None => tracker.finish(),
+            }
+            item
+        })
+    }
+
+    /// Counts a row, or an error if not `ok`, returned to the caller.
+    pub(crate) fn count_item(&self, ok: bool) {
+        let counter = if ok {
+            &self.counters.rows_emitted
+        } else {
+            &self.counters.rows_errored
+        };
+        counter.fetch_add(1, Relaxed);
+    }
+}
+
+/// Reader adding the number of bytes read to one of the counters.
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    tracker: Tracker,
+    count: ByteCount,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        let n = self.inner.read(buf)?;
+        let counter = match self.count {
+            ByteCount::Read => &self.tracker.counters.bytes_read,
+            ByteCount::Decompressed => &self.tracker.counters.bytes_decompressed,
+        };
+        counter.fetch_add(n as u64, Relaxed);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_tracker_reports() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let hook = {
+            let reports = Arc::clone(&reports);
+            ProgressHook::new(move |progress| reports.lock().unwrap().push(*progress))
+        };
+        let tracker = Tracker::new(&hook);
+        tracker.set_total_bytes(10);
+
+        let mut bytes = Vec::new();
+        tracker
+            .count_bytes(&b"abc"[..], ByteCount::Read)
+            .read_to_end(&mut bytes)
+            .unwrap();
+
+        let lines = tracker.count_lines((0..LINES_PER_REPORT + 1).map(Ok::<_, ()>));
+        let emitted = tracker
+            .count_emitted(lines.filter_map(|line| match line {
+                Ok(i) if i.is_multiple_of(2) => Some(Ok(i)),
+                Ok(i) if i % 3 == 1 => Some(Err(())),
+                _ => None,
+            }))
+            .filter(Result::is_ok)
+            .count();
+        assert_eq!(emitted as u64, LINES_PER_REPORT / 2 + 1);
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].lines_read, LINES_PER_REPORT);
+        assert!(!reports[0].finished);
+        assert_eq!(
+            reports[1],
+            Progress {
+                total_bytes: Some(10),
+                bytes_read: 3,
+                bytes_decompressed: 0,
+                lines_read: LINES_PER_REPORT + 1,
+                rows_emitted: LINES_PER_REPORT / 2 + 1,
+                rows_errored: (0..=LINES_PER_REPORT).filter(|i| i % 6 == 1).count() as u64,
+                finished: true,
+            }
+        );
+    }
+}
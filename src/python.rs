@@ -1,23 +1,65 @@
-use crate::filter::Filter;
+use crate::aggregate::{
+    Aggregation, GroupTotals, Summary, TopK, TopN, ViewTotals, sum_views, summarize,
+};
+use crate::cache::cached_file;
+use crate::cancel::CancellationToken;
+use crate::dataset::{PartitionBy, parquet_dataset_from_sources};
+use crate::filter::{
+    Blocklist, CustomFilter, Filter, Sample, TitleMatching, TitleTransform, titles_from_file,
+};
+use crate::follow::{FollowOptions, follow};
+use crate::join::PageIds;
+use crate::multi::{parquet_files_from_sources, parquet_from_sources};
 use crate::parse::{Pageviews, ParseError};
-use crate::stream::StreamError;
-use crate::{RowIterator, parquet_from_file, parquet_from_url, stream_from_file, stream_from_url};
-use pyo3::exceptions::{PyIOError, PyIndexError, PyValueError};
+use crate::progress::{Progress, ProgressHook};
+use crate::sitematrix::{refresh_domains, refresh_domains_cached};
+use crate::sites::{Project, resolve_domain_code};
+use crate::sort::{Sort, SortBy};
+use crate::store::arrow_chunks_from_structs;
+use crate::stream::{
+    ErrorPolicy, ErrorSink, LineReader, Source, StreamConfig, StreamError, hours_in_range,
+    object_store_url,
+};
+use crate::testing::{FakeDumpSpec, write_fake_pageviews_gz};
+use crate::urls::Mirror;
+use crate::{
+    ChunkIterator, ParquetOptions, RowIterator, SchemaLayout, WriteReport, arrow_schema,
+    daily_parquet_from_sources, lines_from_source, pageviews_gz_from_source, parquet_from_source,
+    prefetch, stream_from_source, stream_from_source_parallel,
+};
+use arrow::array::RecordBatchIterator;
+use arrow::ffi_stream::FFI_ArrowArrayStream;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc, Weekday};
+use pyo3::exceptions::{
+    PyIOError, PyIndexError, PyInterruptedError, PyOverflowError, PyStopAsyncIteration,
+    PyValueError,
+};
 use pyo3::prelude::*;
-use regex::Regex;
-use std::path::PathBuf;
-use std::sync::Mutex;
+use pyo3::types::{PyCFunction, PyCapsule, PyDict, PyIterator, PyList, PyTuple};
+use regex::{Regex, RegexSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::create_dir_all;
+use std::io::Error as IoError;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{RecvTimeoutError, channel};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 use url::Url;
 
 /// Represents a single row from a pageviews file.
 ///
 /// `domain_code`, `page_title`, and `views` are the three columns from the
-/// file itself. `language`, `domain`, and `mobile` are parsed from the
-/// domain code.
+/// file itself. `language`, `domain`, `mobile`, and `project` are parsed
+/// from the domain code. `timestamp` is the hour of the file, from its name.
+/// `response_size` and `hourly_views` are parsed from the last column.
+/// `page_id` is set from a mapping file with `Filter.page_ids`.
 ///
 /// The struct has been flattened from the internal representation for a
-/// simpler representation in python, where we don't need internals.
-#[pyclass(name = "Pageviews")]
+/// simpler representation in python, where we don't need internals. Rows are
+/// immutable, compare equal when all fields are equal, and can be hashed.
+#[pyclass(name = "Pageviews", eq, hash, frozen)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct PyPageviews {
     #[pyo3(get)]
     pub domain_code: String,
@@ -31,28 +73,86 @@ pub struct PyPageviews {
     pub domain: Option<String>,
     #[pyo3(get)]
     pub mobile: bool,
+    #[pyo3(get)]
+    pub project: Option<String>,
+    #[pyo3(get)]
+    pub timestamp: Option<DateTime<Utc>>,
+    #[pyo3(get)]
+    pub response_size: Option<u64>,
+    #[pyo3(get)]
+    pub hourly_views: Option<Vec<u32>>,
+    #[pyo3(get)]
+    pub page_id: Option<String>,
 }
 
 #[pymethods]
 impl PyPageviews {
     /// Ensures returns objects can be printed in a pythonic way.
     fn __repr__(&self) -> PyResult<String> {
+        let text = |value: Option<&str>| value.map_or("None".to_string(), |v| format!("{v:?}"));
         Ok(format!(
             "Pageviews(\
                 domain_code={:?}, \
                 page_title={:?}, \
                 views={}, \
                 language={:?}, \
-                domain={:?}, \
-                mobile={:?})",
+                domain={}, \
+                mobile={}, \
+                project={}, \
+                timestamp={}, \
+                response_size={}, \
+                hourly_views={}, \
+                page_id={})",
             self.domain_code,
             self.page_title,
             self.views,
             self.language,
-            self.domain.as_deref().unwrap_or("None"),
-            self.mobile,
+            text(self.domain.as_deref()),
+            if self.mobile { "True" } else { "False" },
+            text(self.project.as_deref()),
+            self.timestamp
+                .map_or("None".to_string(), |ts| format!("{:?}", ts.to_rfc3339())),
+            self.response_size
+                .map_or("None".to_string(), |size| size.to_string()),
+            self.hourly_views
+                .as_ref()
+                .map_or("None".to_string(), |views| format!("{views:?}")),
+            text(self.page_id.as_deref()),
         ))
     }
+
+    /// Full host name of the site, e.g. "de.m.wikipedia.org", computed from
+    /// the domain code. None for unknown projects.
+    #[getter]
+    fn hostname(&self) -> Option<String> {
+        resolve_domain_code(&self.domain_code).map(|site| site.hostname)
+    }
+
+    /// Converts the row to a dict with the fields as keys.
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("domain_code", &self.domain_code)?;
+        dict.set_item("page_title", &self.page_title)?;
+        dict.set_item("views", self.views)?;
+        dict.set_item("language", &self.language)?;
+        dict.set_item("domain", &self.domain)?;
+        dict.set_item("mobile", self.mobile)?;
+        dict.set_item("project", &self.project)?;
+        dict.set_item("timestamp", self.timestamp)?;
+        dict.set_item("response_size", self.response_size)?;
+        dict.set_item("hourly_views", &self.hourly_views)?;
+        dict.set_item("page_id", &self.page_id)?;
+        Ok(dict)
+    }
+
+    /// Iterates over the three columns of the file, `domain_code`,
+    /// `page_title`, and `views`, so rows can be unpacked like tuples. This
+    /// is stable, and won't grow as fields are added to the row.
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        (&self.domain_code, &self.page_title, self.views)
+            .into_pyobject(py)?
+            .try_iter()
+    }
 }
 
 impl From<Pageviews> for PyPageviews {
@@ -64,6 +164,14 @@ impl From<Pageviews> for PyPageviews {
             language: inner.parsed_domain_code.language,
             domain: inner.parsed_domain_code.domain.map(str::to_owned),
             mobile: inner.parsed_domain_code.mobile,
+            project: inner
+                .parsed_domain_code
+                .project
+                .map(|project| project.to_string()),
+            timestamp: inner.timestamp,
+            response_size: inner.response_size,
+            hourly_views: inner.hourly_views,
+            page_id: inner.page_id,
         }
     }
 }
@@ -74,7 +182,20 @@ impl From<StreamError> for PyErr {
             StreamError::Http(e) => PyIOError::new_err(e.to_string()),
             StreamError::Url(e) => PyIOError::new_err(e.to_string()),
             StreamError::Io(e) => PyIOError::new_err(e.to_string()),
+            StreamError::SizeLimitExceeded(e) => PyIOError::new_err(e.to_string()),
+            StreamError::Cancelled(e) => PyInterruptedError::new_err(e.to_string()),
+            StreamError::ChecksumMismatch(e) => PyIOError::new_err(e.to_string()),
             StreamError::Arrow(e) => PyIOError::new_err(e.to_string()),
+            StreamError::Parquet(e) => PyIOError::new_err(e.to_string()),
+            StreamError::Json(e) => PyValueError::new_err(e.to_string()),
+            StreamError::Parse(e) => e.into(),
+            StreamError::Overflow(e) => PyOverflowError::new_err(e.to_string()),
+            #[cfg(feature = "duckdb")]
+            StreamError::DuckDb(e) => PyIOError::new_err(e.to_string()),
+            #[cfg(feature = "postgres")]
+            StreamError::Postgres(e) => PyIOError::new_err(e.to_string()),
+            #[cfg(feature = "lance")]
+            StreamError::Lance(e) => PyIOError::new_err(e.to_string()),
         }
     }
 }
@@ -89,10 +210,442 @@ impl From<ParseError> for PyErr {
     }
 }
 
+/// Filters for the streaming and export functions, built step by step.
+///
+/// Every method returns a new `Filter`, so a filter can be shared and
+/// extended without changing the original. Pass it with `filter=` to the
+/// streaming and export functions. Their keyword arguments only cover the
+/// most common filters, `line_regex`, `domain_codes`, `page_title`,
+/// `min_views`, `max_views`, `languages`, `domains`, and `mobile`, which take
+/// precedence over the filter when passed along with it.
+///
+/// Example:
+///     >>> english = Filter().languages(["en"])
+///     >>> rows = stream_from_file("pageviews.gz", filter=english.min_views(100))
+#[pyclass(name = "Filter")]
+#[derive(Clone, Default)]
+struct PyFilter {
+    line_regex: Option<String>,
+    domain_codes: Option<Vec<String>>,
+    page_title: Option<String>,
+    min_views: Option<u32>,
+    max_views: Option<u32>,
+    languages: Option<Vec<String>>,
+    domains: Option<Vec<String>>,
+    mobile: Option<bool>,
+    page_title_by_language: Option<HashMap<String, String>>,
+    page_title_exact: Option<Vec<String>>,
+    page_titles_file: Option<String>,
+    page_title_prefix: Option<String>,
+    page_titles_any: Option<Vec<String>>,
+    ignore_title_case: Option<bool>,
+    normalize_titles: Option<bool>,
+    blocklist: Option<String>,
+    rollup_namespaces: Option<Vec<String>>,
+    residual_rows: Option<bool>,
+    hours: Option<Vec<u32>>,
+    weekdays: Option<Vec<u8>>,
+    exclude_languages: Option<Vec<String>>,
+    exclude_domains: Option<Vec<String>>,
+    exclude_page_title: Option<String>,
+    negate: Option<bool>,
+    projects: Option<Vec<Project>>,
+    hostnames: Option<Vec<String>>,
+    sample: Option<Sample>,
+    merge_mobile: Option<bool>,
+    min_rank: Option<usize>,
+    max_rank: Option<usize>,
+    page_ids: Option<String>,
+    title_transform: Option<(String, String)>,
+    custom: Option<(Arc<Py<PyAny>>, usize)>,
+}
+
+#[pymethods]
+impl PyFilter {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only parses lines matching the regex `pattern`.
+    fn line_regex(&self, pattern: String) -> Self {
+        Self {
+            line_regex: Some(pattern),
+            ..self.clone()
+        }
+    }
+
+    /// Only keeps rows with one of these domain codes.
+    fn domain_codes(&self, codes: Vec<String>) -> Self {
+        Self {
+            domain_codes: Some(codes),
+            ..self.clone()
+        }
+    }
+
+    /// Only keeps rows with page titles matching the regex `pattern`.
+    fn page_title(&self, pattern: String) -> Self {
+        Self {
+            page_title: Some(pattern),
+            ..self.clone()
+        }
+    }
+
+    /// Only keeps rows with at least this many views.
+    fn min_views(&self, views: u32) -> Self {
+        Self {
+            min_views: Some(views),
+            ..self.clone()
+        }
+    }
+
+    /// Only keeps rows with at most this many views.
+    fn max_views(&self, views: u32) -> Self {
+        Self {
+            max_views: Some(views),
+            ..self.clone()
+        }
+    }
+
+    /// Only keeps rows from these languages, e.g. `["en", "de"]`.
+    fn languages(&self, languages: Vec<String>) -> Self {
+        Self {
+            languages: Some(languages),
+            ..self.clone()
+        }
+    }
+
+    /// Only keeps rows from these domains, e.g. `["wikibooks.org"]`.
+    fn domains(&self, domains: Vec<String>) -> Self {
+        Self {
+            domains: Some(domains),
+            ..self.clone()
+        }
+    }
+
+    /// Only keeps rows from the mobile sites, or from the desktop sites.
+    fn mobile(&self, mobile: bool) -> Self {
+        Self {
+            mobile: Some(mobile),
+            ..self.clone()
+        }
+    }
+
+    /// Only keeps rows from these project families, e.g. `["wiktionary"]`.
+    fn projects(&self, projects: Vec<String>) -> PyResult<Self> {
+        let projects = projects
+            .iter()
+            .map(|project| project.parse())
+            .collect::<Result<_, String>>()
+            .map_err(PyValueError::new_err)?;
+        Ok(Self {
+            projects: Some(projects),
+            ..self.clone()
+        })
+    }
+
+    /// Only keeps rows from these sites, e.g. `["en.m.wikipedia.org"]`.
+    fn hostnames(&self, hostnames: Vec<String>) -> Self {
+        Self {
+            hostnames: Some(hostnames),
+            ..self.clone()
+        }
+    }
+
+    /// Maps language codes to page title regexes. Rows from other languages
+    /// are rejected.
+    fn page_title_by_language(&self, patterns: HashMap<String, String>) -> Self {
+        Self {
+            page_title_by_language: Some(patterns),
+            ..self.clone()
+        }
+    }
+
+    /// Only keeps rows with one of these page titles. Faster than an anchored
+    /// `page_title` regex.
+    fn page_title_exact(&self, titles: Vec<String>) -> Self {
+        Self {
+            page_title_exact: Some(titles),
+            ..self.clone()
+        }
+    }
+
+    /// Only keeps rows with one of the page titles in this file, one per line,
+    /// in addition to those of `page_title_exact`.
+    fn page_titles_file(&self, path: String) -> Self {
+        Self {
+            page_titles_file: Some(path),
+            ..self.clone()
+        }
+    }
+
+    /// Only keeps rows with page titles starting with `prefix`, without the
+    /// cost of a regex.
+    fn page_title_prefix(&self, prefix: String) -> Self {
+        Self {
+            page_title_prefix: Some(prefix),
+            ..self.clone()
+        }
+    }
+
+    /// Only keeps rows with page titles matching any of these regexes.
+    fn page_titles_any(&self, patterns: Vec<String>) -> Self {
+        Self {
+            page_titles_any: Some(patterns),
+            ..self.clone()
+        }
+    }
+
+    /// Compares page titles ignoring case in all title filters.
+    #[pyo3(signature = (ignore=true))]
+    fn ignore_title_case(&self, ignore: bool) -> Self {
+        Self {
+            ignore_title_case: Some(ignore),
+            ..self.clone()
+        }
+    }
+
+    /// Compares page titles in Unicode NFC in all title filters.
+    #[pyo3(signature = (normalize=true))]
+    fn normalize_titles(&self, normalize: bool) -> Self {
+        Self {
+            normalize_titles: Some(normalize),
+            ..self.clone()
+        }
+    }
+
+    /// Rejects the titles and patterns listed in a blocklist file.
+    fn blocklist(&self, path: String) -> Self {
+        Self {
+            blocklist: Some(path),
+            ..self.clone()
+        }
+    }
+
+    /// Collapses these namespaces into one row per domain code, e.g.
+    /// `["Special"]` gives a `Special:*` row.
+    fn rollup_namespaces(&self, namespaces: Vec<String>) -> Self {
+        Self {
+            rollup_namespaces: Some(namespaces),
+            ..self.clone()
+        }
+    }
+
+    /// Sums the rows rejected by the filters into one residual row per
+    /// language and mobile flag.
+    #[pyo3(signature = (residual=true))]
+    fn residual_rows(&self, residual: bool) -> Self {
+        Self {
+            residual_rows: Some(residual),
+            ..self.clone()
+        }
+    }
+
+    /// Only reads files from these hours (UTC), based on the timestamp in the
+    /// file name.
+    fn hours(&self, hours: Vec<u32>) -> Self {
+        Self {
+            hours: Some(hours),
+            ..self.clone()
+        }
+    }
+
+    /// Only reads files from these weekdays, where Monday is 0 and Sunday is
+    /// 6, as in `datetime.weekday()`.
+    fn weekdays(&self, weekdays: Vec<u8>) -> Self {
+        Self {
+            weekdays: Some(weekdays),
+            ..self.clone()
+        }
+    }
+
+    /// Rejects rows from these languages.
+    fn exclude_languages(&self, languages: Vec<String>) -> Self {
+        Self {
+            exclude_languages: Some(languages),
+            ..self.clone()
+        }
+    }
+
+    /// Rejects rows from these domains.
+    fn exclude_domains(&self, domains: Vec<String>) -> Self {
+        Self {
+            exclude_domains: Some(domains),
+            ..self.clone()
+        }
+    }
+
+    /// Rejects rows with page titles matching the regex `pattern`.
+    fn exclude_page_title(&self, pattern: String) -> Self {
+        Self {
+            exclude_page_title: Some(pattern),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the rows rejected by the post-parsing filters instead.
+    #[pyo3(signature = (negate=true))]
+    fn negate(&self, negate: bool) -> Self {
+        Self {
+            negate: Some(negate),
+            ..self.clone()
+        }
+    }
+
+    /// Merges mobile rows into desktop rows with the same title.
+    #[pyo3(signature = (merge=true))]
+    fn merge_mobile(&self, merge: bool) -> Self {
+        Self {
+            merge_mobile: Some(merge),
+            ..self.clone()
+        }
+    }
+
+    /// Drops the rows ranked above `rank` by views within their domain code,
+    /// with 1 for the most viewed.
+    fn min_rank(&self, rank: usize) -> Self {
+        Self {
+            min_rank: Some(rank),
+            ..self.clone()
+        }
+    }
+
+    /// Keeps the `rank` rows with most views of each domain code.
+    fn max_rank(&self, rank: usize) -> Self {
+        Self {
+            max_rank: Some(rank),
+            ..self.clone()
+        }
+    }
+
+    /// Sets `page_id` of the rows from a sorted mapping file of domain code,
+    /// page title, and ID, separated by tabs.
+    fn page_ids(&self, path: String) -> Self {
+        Self {
+            page_ids: Some(path),
+            ..self.clone()
+        }
+    }
+
+    /// Replaces the matches of `pattern` in the page titles of the rows
+    /// returned with `replacement`, which can refer to capture groups as `$1`.
+    fn title_transform(&self, pattern: String, replacement: String) -> Self {
+        Self {
+            title_transform: Some((pattern, replacement)),
+            ..self.clone()
+        }
+    }
+
+    /// Keeps the rows for which `predicate`, called with each `Pageviews`
+    /// row, returns a truthy value. The GIL is taken once per `batch_size`
+    /// rows, instead of once per row.
+    #[pyo3(signature = (predicate, batch_size=CustomFilter::DEFAULT_BATCH_SIZE))]
+    fn custom(&self, py: Python<'_>, predicate: Py<PyAny>, batch_size: usize) -> PyResult<Self> {
+        if !predicate.bind(py).is_callable() {
+            return Err(PyValueError::new_err("Custom filter must be callable"));
+        }
+        if batch_size == 0 {
+            return Err(PyValueError::new_err("Batch size must be at least 1"));
+        }
+        Ok(Self {
+            custom: Some((Arc::new(predicate), batch_size)),
+            ..self.clone()
+        })
+    }
+
+    /// Keeps a deterministic fraction of the rows, picked by `seed`.
+    #[pyo3(signature = (fraction, seed=0))]
+    fn sample(&self, fraction: f64, seed: u64) -> PyResult<Self> {
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(PyValueError::new_err(
+                "Sample fraction must be between 0 and 1",
+            ));
+        }
+        Ok(Self {
+            sample: Some(Sample { fraction, seed }),
+            ..self.clone()
+        })
+    }
+}
+
+/// Wraps a python predicate in a `CustomFilter`, calling it with the GIL
+/// taken once per batch.
+///
+/// Exceptions raised by the predicate end the stream with an `IOError`.
+fn custom_filter(predicate: Arc<Py<PyAny>>, batch_size: usize) -> CustomFilter {
+    CustomFilter::batched(batch_size, move |rows| {
+        Python::attach(|py| {
+            let predicate = predicate.bind(py);
+            rows.iter()
+                .map(|row| {
+                    predicate
+                        .call1((PyPageviews::from(row.clone()),))?
+                        .is_truthy()
+                })
+                .collect::<PyResult<Vec<bool>>>()
+        })
+        .map_err(|err| IoError::other(format!("custom filter failed: {err}")))
+    })
+}
+
+/// Parameters section of the docstrings for the filter arguments shared by
+/// the streaming and export functions, with `$views` appended to the
+/// descriptions of `min_views` and `max_views`.
+///
+/// Any other filter is set with the `Filter` class, keeping the argument
+/// lists of the functions short.
+macro_rules! filter_params_doc {
+    () => {
+        filter_params_doc!("")
+    };
+    ($views:literal) => {
+        concat!(
+            "    line_regex (str | None): Optional regex to match lines before parsing.\n",
+            "    filter (Filter | None): Filters built with the `Filter` class, for\n",
+            "        anything not covered by the arguments below, which take precedence.\n",
+            "    domain_codes (list[str] | None): List of domain codes to match exactly.\n",
+            "    page_title (str | None): Optional regex to match parsed page title.\n",
+            "    min_views (int | None): Minimum number of views",
+            $views,
+            ".\n",
+            "    max_views (int | None): Maximum number of views",
+            $views,
+            ".\n",
+            "    languages (list[str] | None): Filter by language codes.\n",
+            "    domains (list[str] | None): Filter by Wikimedia domain.\n",
+            "    mobile (bool | None): Filter mobile or desktop traffic."
+        )
+    };
+}
+
+/// Parameters section of the docstrings for the parquet options shared by
+/// the export functions.
+macro_rules! parquet_params_doc {
+    () => {
+        concat!(
+            "    columns (list[str] | None): Only write these top level columns, e.g.\n",
+            "        `[\"language\", \"page_title\", \"views\"]`. Raises a ValueError for\n",
+            "        columns not in the layout.\n",
+            "    sort (str | None): Sort the rows by \"views\", most viewed first, or by\n",
+            "        \"title\". Rows which don't fit in memory are spilled to temporary\n",
+            "        files.\n",
+            "    statistics (bool | None): Write min/max statistics of each column,\n",
+            "        letting query engines skip row groups.\n",
+            "    bloom_filters (bool | None): Write bloom filters of `page_title` and\n",
+            "        `language` for each row group.\n",
+            "    encodings (dict[str, str] | None): Encoding of individual columns,\n",
+            "        \"plain\" or \"dictionary\", e.g. `{\"page_title\": \"dictionary\"}` when\n",
+            "        only a few titles are written. By default `domain_code`,\n",
+            "        `language`, `domain`, and `project` are dictionary encoded."
+        )
+    };
+}
+
 /// Converts python input to a `Filters` struct.
 #[allow(clippy::too_many_arguments)]
 fn filter_from_input(
     line_regex: Option<String>,
+    filter: Option<PyFilter>,
     domain_codes: Option<Vec<String>>,
     page_title: Option<String>,
     min_views: Option<u32>,
@@ -101,6 +654,43 @@ fn filter_from_input(
     domains: Option<Vec<String>>,
     mobile: Option<bool>,
 ) -> Result<Filter, PyErr> {
+    // Keyword arguments take precedence over the `Filter` passed along
+    let base = filter.unwrap_or_default();
+    let line_regex = line_regex.or(base.line_regex);
+    let domain_codes = domain_codes.or(base.domain_codes);
+    let page_title = page_title.or(base.page_title);
+    let min_views = min_views.or(base.min_views);
+    let max_views = max_views.or(base.max_views);
+    let languages = languages.or(base.languages);
+    let domains = domains.or(base.domains);
+    let mobile = mobile.or(base.mobile);
+    let page_title_by_language = base.page_title_by_language;
+    let page_title_exact = base.page_title_exact;
+    let page_titles_file = base.page_titles_file;
+    let page_title_prefix = base.page_title_prefix;
+    let page_titles_any = base.page_titles_any;
+    let ignore_title_case = base.ignore_title_case;
+    let normalize_titles = base.normalize_titles;
+    let blocklist = base.blocklist;
+    let rollup_namespaces = base.rollup_namespaces;
+    let residual_rows = base.residual_rows;
+    let hours = base.hours;
+    let weekdays = base.weekdays;
+    let exclude_languages = base.exclude_languages;
+    let exclude_domains = base.exclude_domains;
+    let exclude_page_title = base.exclude_page_title;
+    let negate = base.negate;
+    let projects = base.projects;
+    let hostnames = base.hostnames;
+    let sample = base.sample;
+    let merge_mobile = base.merge_mobile;
+    let (min_rank, max_rank) = (base.min_rank, base.max_rank);
+    let page_ids = base.page_ids;
+    let title_transform = base.title_transform;
+    let custom = base
+        .custom
+        .map(|(predicate, batch_size)| custom_filter(predicate, batch_size));
+
     let line_regex = line_regex
         .map(|pattern| Regex::new(&pattern))
         .transpose()
@@ -111,24 +701,280 @@ fn filter_from_input(
         .transpose()
         .map_err(|e| PyValueError::new_err(e.to_string()))?;
 
+    let page_titles_any = page_titles_any
+        .map(RegexSet::new)
+        .transpose()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+    let exclude_page_title = exclude_page_title
+        .map(|pattern| Regex::new(&pattern))
+        .transpose()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+    let title_transform = title_transform
+        .map(|(pattern, replacement)| TitleTransform::new(&pattern, replacement))
+        .transpose()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+    let page_title_by_language = page_title_by_language
+        .map(|patterns| {
+            patterns
+                .into_iter()
+                .map(|(lang, pattern)| Ok((lang, Regex::new(&pattern)?)))
+                .collect::<Result<HashMap<_, _>, regex::Error>>()
+        })
+        .transpose()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+    let mut page_title_exact: Option<HashSet<String>> =
+        page_title_exact.map(|titles| titles.into_iter().collect());
+    if let Some(path) = page_titles_file {
+        let titles =
+            titles_from_file(Path::new(&path)).map_err(|e| PyIOError::new_err(e.to_string()))?;
+        page_title_exact.get_or_insert_default().extend(titles);
+    }
+
+    let blocklist = blocklist
+        .map(|path| Blocklist::from_file(Path::new(&path)))
+        .transpose()
+        .map_err(|e| PyIOError::new_err(e.to_string()))?;
+
+    let page_ids = page_ids
+        .map(|path| PageIds::from_file(Path::new(&path)))
+        .transpose()
+        .map_err(|e| PyIOError::new_err(e.to_string()))?;
+
+    let weekdays = weekdays
+        .map(|days| {
+            days.into_iter()
+                .map(Weekday::try_from)
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
     Ok(Filter {
         line_regex,
         domain_codes,
         page_title,
+        page_title_by_language,
+        page_title_exact,
+        page_title_prefix,
+        page_titles_any,
+        title_matching: TitleMatching::default(),
         min_views,
         max_views,
         languages,
         domains,
+        projects,
+        hostnames,
         mobile,
+        blocklist,
+        rollup_namespaces,
+        residual_rows: residual_rows.unwrap_or(false),
+        merge_mobile: merge_mobile.unwrap_or(false),
+        min_rank,
+        max_rank,
+        page_ids,
+        title_transform,
+        hours,
+        weekdays,
+        exclude_languages,
+        exclude_domains,
+        exclude_page_title,
+        negate: negate.unwrap_or(false),
+        agents: None,
+        sample,
+        custom,
+    }
+    .with_title_matching(TitleMatching {
+        case_insensitive: ignore_title_case.unwrap_or(false),
+        normalize_unicode: normalize_titles.unwrap_or(false),
+    }))
+}
+
+/// How often a long running export checks for `KeyboardInterrupt`.
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs an export with the GIL released, cancelling it on `KeyboardInterrupt`.
+///
+/// Signals are only handled while holding the GIL, so the export runs on a
+/// worker thread, while this thread checks for signals in between waits. On
+/// an interrupt, `cancel` stops the export, which is waited for before the
+/// `KeyboardInterrupt` is raised, so the output files are closed.
+fn detach_interruptible<T, E, F>(
+    py: Python<'_>,
+    cancel: &CancellationToken,
+    export: F,
+) -> PyResult<T>
+where
+    T: Send,
+    E: Send,
+    F: FnOnce() -> Result<T, E> + Send,
+    PyErr: From<E>,
+{
+    let (sender, receiver) = channel();
+    let receiver = Mutex::new(receiver);
+    thread::scope(|scope| {
+        scope.spawn(move || sender.send(export()));
+        loop {
+            let result = py.detach(|| receiver.lock().unwrap().recv_timeout(SIGNAL_CHECK_INTERVAL));
+            match result {
+                Ok(result) => return result.map_err(PyErr::from),
+                // The export panicked, which the scope raises once it ends
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(PyInterruptedError::new_err("export stopped"));
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            if let Err(interrupt) = py.check_signals() {
+                cancel.cancel();
+                let _ = py.detach(|| receiver.lock().unwrap().recv());
+                return Err(interrupt);
+            }
+        }
     })
 }
 
+/// Converts a timeout in seconds from python, with 0 for no timeout.
+fn timeout_from_input(seconds: f64) -> PyResult<Option<Duration>> {
+    let timeout =
+        Duration::try_from_secs_f64(seconds).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    Ok((!timeout.is_zero()).then_some(timeout))
+}
+
+/// Converts python input to an `ErrorPolicy`.
+///
+/// "raise" is the python name for `ErrorPolicy::Yield`, as errors returned by
+/// the iterators are raised as exceptions. Functions writing files skip them.
+fn error_policy_from_input(
+    on_error: Option<String>,
+    error_file: Option<String>,
+) -> PyResult<ErrorPolicy> {
+    match (on_error.as_deref(), error_file) {
+        (None, Some(path)) => Ok(ErrorPolicy::CollectTo(ErrorSink::file(Path::new(&path))?)),
+        (Some(_), Some(_)) => Err(PyValueError::new_err(
+            "`on_error` and `error_file` can't be combined",
+        )),
+        (None | Some("raise"), None) => Ok(ErrorPolicy::Yield),
+        (Some("skip"), None) => Ok(ErrorPolicy::Skip),
+        (Some("fail"), None) => Ok(ErrorPolicy::FailFast),
+        (Some(other), None) => Err(PyValueError::new_err(format!(
+            "`on_error` must be \"raise\", \"skip\", or \"fail\", not {other:?}"
+        ))),
+    }
+}
+
+/// Converts python input to `ParquetOptions`.
+#[allow(clippy::too_many_arguments)]
+fn parquet_options_from_input(
+    batch_size: Option<usize>,
+    layout: Option<String>,
+    error_file: Option<String>,
+    top_k: Option<usize>,
+    top_k_per_language: Option<bool>,
+    columns: Option<Vec<String>>,
+    sort: Option<String>,
+    statistics: Option<bool>,
+    bloom_filters: Option<bool>,
+    encodings: Option<HashMap<String, String>>,
+) -> Result<ParquetOptions, PyErr> {
+    let layout = match layout.as_deref() {
+        None | Some("flat") => SchemaLayout::Flat,
+        Some("nested") => SchemaLayout::Nested,
+        Some(other) => {
+            return Err(PyValueError::new_err(format!(
+                "`layout` must be \"flat\" or \"nested\", not {other:?}"
+            )));
+        }
+    };
+    let sort = match sort.as_deref() {
+        None => None,
+        Some("views") => Some(Sort::new(SortBy::ViewsDescending)),
+        Some("title") => Some(Sort::new(SortBy::Title)),
+        Some(other) => {
+            return Err(PyValueError::new_err(format!(
+                "`sort` must be \"views\" or \"title\", not {other:?}"
+            )));
+        }
+    };
+
+    let encodings = encodings
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(column, encoding)| Ok((column, encoding.parse().map_err(PyValueError::new_err)?)))
+        .collect::<PyResult<_>>()?;
+
+    let options = ParquetOptions {
+        batch_size,
+        layout,
+        error_file: error_file.map(PathBuf::from),
+        top_k: top_k.map(|n| TopK {
+            n,
+            per_language: top_k_per_language.unwrap_or(false),
+        }),
+        columns,
+        sort,
+        statistics: statistics.unwrap_or(false),
+        bloom_filters: bloom_filters.unwrap_or(false),
+        encodings,
+    };
+    options
+        .validate()
+        .map_err(|err| PyValueError::new_err(err.to_string()))?;
+    Ok(options)
+}
+
 /// Maps our rust iterator to a standard Python setup for iterators.
 /// This class should not be used directly, go through the convenience
 /// functions below instead.
 #[pyclass(name = "RowIterator")]
 struct PyRowIterator {
     iterator: Mutex<RowIterator>,
+    progress: Option<Arc<PyProgress>>,
+    error_policy: ErrorPolicy,
+}
+
+/// Python callable receiving progress reports, and the latest report.
+///
+/// The stream may report from a background thread, which can't call into
+/// python while `__next__` holds the GIL. Reports are stored instead, and
+/// handed to the callable on the thread iterating over the rows.
+struct PyProgress {
+    callback: Py<PyAny>,
+    latest: Arc<Mutex<Option<Progress>>>,
+}
+
+impl PyProgress {
+    fn new(callback: Py<PyAny>) -> Self {
+        Self {
+            callback,
+            latest: Arc::default(),
+        }
+    }
+
+    /// Hook storing the latest report, to be delivered by `deliver`.
+    fn hook(&self) -> ProgressHook {
+        let latest = Arc::clone(&self.latest);
+        ProgressHook::new(move |progress| *latest.lock().unwrap() = Some(*progress))
+    }
+
+    /// Calls the python callable with the latest report, if there is one.
+    fn deliver(&self, py: Python<'_>) -> PyResult<()> {
+        let Some(progress) = self.latest.lock().unwrap().take() else {
+            return Ok(());
+        };
+        let report = PyDict::new(py);
+        report.set_item("total_bytes", progress.total_bytes)?;
+        report.set_item("bytes_read", progress.bytes_read)?;
+        report.set_item("bytes_decompressed", progress.bytes_decompressed)?;
+        report.set_item("lines_read", progress.lines_read)?;
+        report.set_item("rows_emitted", progress.rows_emitted)?;
+        report.set_item("rows_errored", progress.rows_errored)?;
+        report.set_item("finished", progress.finished)?;
+        self.callback.call1(py, (report,))?;
+        Ok(())
+    }
 }
 
 #[pymethods]
@@ -136,9 +982,11 @@ impl PyRowIterator {
     #[new]
     #[allow(clippy::too_many_arguments)]
     fn new(
+        py: Python<'_>,
         path: Option<String>,
         url: Option<String>,
         line_regex: Option<String>,
+        filter: Option<PyFilter>,
         domain_codes: Option<Vec<String>>,
         page_title: Option<String>,
         min_views: Option<u32>,
@@ -146,9 +994,29 @@ impl PyRowIterator {
         languages: Option<Vec<String>>,
         domains: Option<Vec<String>>,
         mobile: Option<bool>,
+        prefetch_rows: Option<usize>,
+        buffer_bytes: Option<usize>,
+        max_download_bytes: Option<u64>,
+        max_decompressed_bytes: Option<u64>,
+        threads: Option<usize>,
+        mirrors: Option<Vec<String>>,
+        proxy: Option<String>,
+        ca_bundle: Option<String>,
+        connect_timeout: Option<f64>,
+        read_timeout: Option<f64>,
+        user_agent: Option<String>,
+        progress: Option<Py<PyAny>>,
+        cache_dir: Option<String>,
+        on_error: Option<String>,
+        error_file: Option<String>,
+        decode_titles: Option<bool>,
+        skip: Option<usize>,
+        limit: Option<usize>,
+        assume_sorted: Option<bool>,
     ) -> PyResult<Self> {
         let filter = filter_from_input(
             line_regex,
+            filter,
             domain_codes,
             page_title,
             min_views,
@@ -158,14 +1026,10 @@ impl PyRowIterator {
             mobile,
         )?;
 
-        let iterator = match (path, url) {
-            (Some(path), None) => {
-                let path = PathBuf::from(path);
-                stream_from_file(path, &filter)?
-            }
+        let source = match (path, url) {
+            (Some(path), None) => Source::File(PathBuf::from(path)),
             (None, Some(url)) => {
-                let url = Url::parse(&url).map_err(|e| PyValueError::new_err(e.to_string()))?;
-                stream_from_url(url, &filter)?
+                Source::Url(Url::parse(&url).map_err(|e| PyValueError::new_err(e.to_string()))?)
             }
             _ => {
                 return Err(PyValueError::new_err(
@@ -174,8 +1038,65 @@ impl PyRowIterator {
             }
         };
 
+        let mut config = StreamConfig::default();
+        if let Some(buffer_bytes) = buffer_bytes {
+            config.buffer_bytes = buffer_bytes;
+        }
+        if let Some(max_download_bytes) = max_download_bytes {
+            config.max_download_bytes = Some(max_download_bytes);
+        }
+        if let Some(max_decompressed_bytes) = max_decompressed_bytes {
+            config.max_decompressed_bytes = Some(max_decompressed_bytes);
+        }
+        config.error_policy = error_policy_from_input(on_error, error_file)?;
+        config.decode_titles = decode_titles.unwrap_or(false);
+        config.skip = skip.unwrap_or(0);
+        config.limit = limit;
+        config.assume_sorted = assume_sorted.unwrap_or(false);
+        if let Some(mirrors) = mirrors {
+            config.mirrors = mirrors
+                .iter()
+                .map(|base| Mirror::new(base))
+                .collect::<Result<_, _>>()
+                .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        }
+        if let Some(proxy) = proxy {
+            config.client.proxy =
+                Some(Url::parse(&proxy).map_err(|e| PyValueError::new_err(e.to_string()))?);
+        }
+        config.client.ca_bundle = ca_bundle.map(PathBuf::from);
+        if let Some(timeout) = connect_timeout {
+            config.client.connect_timeout = timeout_from_input(timeout)?;
+        }
+        if let Some(timeout) = read_timeout {
+            config.client.read_timeout = timeout_from_input(timeout)?;
+        }
+        config.client.user_agent = user_agent;
+
+        let source = match (source, cache_dir) {
+            (Source::Url(url), Some(cache_dir)) => {
+                let cache_dir = Path::new(&cache_dir);
+                Source::File(py.detach(|| cached_file(&url, cache_dir, &config))?)
+            }
+            (source, _) => source,
+        };
+
+        let progress = progress.map(|callback| Arc::new(PyProgress::new(callback)));
+        config.progress = progress.as_deref().map(PyProgress::hook);
+
+        let iterator = py.detach(|| match threads {
+            Some(threads) => stream_from_source_parallel(&source, &filter, &config, threads),
+            None => stream_from_source(&source, &filter, &config),
+        })?;
+        let iterator = match prefetch_rows {
+            Some(capacity) => prefetch(iterator, capacity),
+            None => iterator,
+        };
+
         Ok(Self {
             iterator: Mutex::new(iterator),
+            progress,
+            error_policy: config.error_policy,
         })
     }
 
@@ -183,27 +1104,660 @@ impl PyRowIterator {
         slf
     }
 
-    fn __next__(slf: PyRefMut<'_, Self>) -> PyResult<Option<PyPageviews>> {
-        match slf.iterator.lock().unwrap().next() {
-            Some(Ok(row)) => Ok(Some(row.into())),
-            Some(Err(err)) => Err(err.into()),
-            None => Ok(None),
-        }
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
     }
-}
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, PyTuple>) -> bool {
+        self.close();
+        false
+    }
+
+    /// Stops reading, closing the file or HTTP connection right away.
+    ///
+    /// The iterator is empty afterwards. Called when leaving a `with` block,
+    /// and otherwise left to the garbage collector.
+    fn close(&self) {
+        // Dropping the iterator drops the reader, and stops background threads
+        drop(std::mem::replace(
+            &mut *self.iterator.lock().unwrap(),
+            Box::new(std::iter::empty()),
+        ));
+    }
+
+    fn __next__(slf: PyRef<'_, Self>) -> PyResult<Option<PyPageviews>> {
+        // Reading and parsing may block on the network, let other threads run
+        let iterator = &slf.iterator;
+        let next = slf.py().detach(|| iterator.lock().unwrap().next());
+        if let Some(progress) = &slf.progress {
+            progress.deliver(slf.py())?;
+        }
+        match next {
+            Some(Ok(row)) => Ok(Some(row.into())),
+            Some(Err(err)) => Err(err.into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Converts the remaining rows to a stream of Arrow record batches.
+    ///
+    /// The rows are only parsed once the stream is consumed, e.g. by
+    /// `pyarrow.table()` or `polars.DataFrame()`, and the iterator is empty
+    /// afterwards. Rows failing to parse are skipped, unless `on_error` is
+    /// "fail".
+    #[pyo3(signature = (batch_size=None))]
+    fn to_arrow(slf: PyRef<'_, Self>, batch_size: Option<usize>) -> PyArrowStream {
+        let rows = std::mem::replace(
+            &mut *slf.iterator.lock().unwrap(),
+            Box::new(std::iter::empty()),
+        );
+        let chunks = arrow_chunks_from_structs(rows, batch_size, &slf.error_policy);
+
+        PyArrowStream {
+            chunks: Mutex::new(Some(Box::new(chunks))),
+        }
+    }
+
+    /// Reads the remaining rows into a `pandas.DataFrame`, consuming the
+    /// iterator.
+    ///
+    /// Requires `pandas` to be installed, but not `pyarrow`. The columns are
+    /// the fields of `Pageviews`.
+    fn to_pandas<'py>(slf: PyRef<'py, Self>) -> PyResult<Bound<'py, PyAny>> {
+        let py = slf.py();
+        let pandas = py.import("pandas")?;
+        let iterator = &slf.iterator;
+        let rows = py.detach(|| {
+            iterator
+                .lock()
+                .unwrap()
+                .by_ref()
+                .map(|row| row.map(PyPageviews::from))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+        let columns = PyDict::new(py);
+        columns.set_item(
+            "domain_code",
+            rows.iter().map(|r| &r.domain_code).collect::<Vec<_>>(),
+        )?;
+        columns.set_item(
+            "page_title",
+            rows.iter().map(|r| &r.page_title).collect::<Vec<_>>(),
+        )?;
+        columns.set_item("views", rows.iter().map(|r| r.views).collect::<Vec<_>>())?;
+        columns.set_item(
+            "language",
+            rows.iter().map(|r| &r.language).collect::<Vec<_>>(),
+        )?;
+        columns.set_item("domain", rows.iter().map(|r| &r.domain).collect::<Vec<_>>())?;
+        columns.set_item("mobile", rows.iter().map(|r| r.mobile).collect::<Vec<_>>())?;
+        columns.set_item(
+            "project",
+            rows.iter().map(|r| &r.project).collect::<Vec<_>>(),
+        )?;
+        columns.set_item(
+            "timestamp",
+            rows.iter().map(|r| r.timestamp).collect::<Vec<_>>(),
+        )?;
+        columns.set_item(
+            "response_size",
+            rows.iter().map(|r| r.response_size).collect::<Vec<_>>(),
+        )?;
+        columns.set_item(
+            "hourly_views",
+            rows.iter().map(|r| &r.hourly_views).collect::<Vec<_>>(),
+        )?;
+        columns.set_item(
+            "page_id",
+            rows.iter().map(|r| &r.page_id).collect::<Vec<_>>(),
+        )?;
+
+        pandas.call_method1("DataFrame", (columns,))
+    }
+
+    /// Reads the remaining rows into a `polars.DataFrame`, consuming the
+    /// iterator.
+    ///
+    /// Requires `polars` to be installed. The rows are passed to polars as
+    /// Arrow record batches, see `to_arrow()`.
+    #[pyo3(signature = (batch_size=None))]
+    fn to_polars<'py>(
+        slf: PyRef<'py, Self>,
+        batch_size: Option<usize>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let py = slf.py();
+        let polars = py.import("polars")?;
+        let stream = Self::to_arrow(slf, batch_size);
+        polars.call_method1("DataFrame", (stream,))
+    }
+
+    /// Returns an iterator over the remaining rows as plain dicts.
+    ///
+    /// The dicts have the fields of `Pageviews` as keys. Both iterators
+    /// share the same rows, so each row is only returned by one of them.
+    fn as_dicts(slf: PyRef<'_, Self>) -> PyDictIterator {
+        PyDictIterator { rows: slf.into() }
+    }
+
+    /// Finds the `n` rows with most views per group, consuming the iterator.
+    ///
+    /// `by` is one of "language", "domain", or "domain_code". Returns a dict
+    /// from group to a list of rows, sorted by views, descending.
+    #[pyo3(signature = (n, by="language"))]
+    fn top_n(
+        slf: PyRef<'_, Self>,
+        n: usize,
+        by: &str,
+    ) -> PyResult<BTreeMap<Option<String>, Vec<PyPageviews>>> {
+        let key: fn(&Pageviews) -> Option<String> = match by {
+            "language" => |row| Some(row.parsed_domain_code.language.clone()),
+            "domain" => |row| row.parsed_domain_code.domain.map(str::to_owned),
+            "domain_code" => |row| Some(row.domain_code.clone()),
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "`by` must be \"language\", \"domain\", or \"domain_code\", not {other:?}"
+                )));
+            }
+        };
+
+        let iterator = &slf.iterator;
+        let top = slf.py().detach(|| {
+            let mut top = TopN::new(key, n);
+            for row in iterator.lock().unwrap().by_ref() {
+                top.push(row?);
+            }
+            Ok::<_, ParseError>(top)
+        })?;
+
+        Ok(top
+            .into_sorted()
+            .into_iter()
+            .map(|(key, rows)| (key, rows.into_iter().map(PyPageviews::from).collect()))
+            .collect())
+    }
+
+    /// Reads up to `n` rows from the iterator into a `Preview`.
+    ///
+    /// Rows returned by `head()` are consumed, iterating afterwards
+    /// continues after them.
+    #[pyo3(signature = (n=5))]
+    fn head(slf: PyRef<'_, Self>, n: usize) -> PyResult<PyPreview> {
+        let iterator = &slf.iterator;
+        let rows = slf.py().detach(|| {
+            iterator
+                .lock()
+                .unwrap()
+                .by_ref()
+                .take(n)
+                .map(|row| row.map(PyPageviews::from))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+        Ok(PyPreview { rows })
+    }
+}
+
+/// Iterator over rows as dicts, returned by `RowIterator.as_dicts()`.
+#[pyclass(name = "DictIterator")]
+struct PyDictIterator {
+    rows: Py<PyRowIterator>,
+}
+
+#[pymethods]
+impl PyDictIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(slf: PyRef<'py, Self>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let py = slf.py();
+        let row = PyRowIterator::__next__(slf.rows.bind(py).borrow())?;
+        row.map(|row| row.to_dict(py)).transpose()
+    }
+}
+
+/// Async iterator over rows, returned by `stream_from_url_async()`.
+///
+/// Each row is read in the default executor of the event loop, so the loop
+/// keeps running while waiting for the network.
+#[pyclass(name = "AsyncRowIterator")]
+struct PyAsyncRowIterator {
+    iterator: Arc<Mutex<RowIterator>>,
+    progress: Option<Arc<PyProgress>>,
+}
+
+#[pymethods]
+impl PyAsyncRowIterator {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let iterator = Arc::clone(&self.iterator);
+        let progress = self.progress.clone();
+        let read_next = PyCFunction::new_closure(py, None, None, move |args, _| {
+            let py = args.py();
+            let next = py.detach(|| iterator.lock().unwrap().next());
+            if let Some(progress) = &progress {
+                progress.deliver(py)?;
+            }
+            match next {
+                Some(Ok(row)) => Ok(PyPageviews::from(row)),
+                Some(Err(err)) => Err(err.into()),
+                None => Err(PyStopAsyncIteration::new_err(())),
+            }
+        })?;
+
+        let event_loop = py.import("asyncio")?.call_method0("get_running_loop")?;
+        event_loop.call_method1("run_in_executor", (py.None(), read_next))
+    }
+
+    /// Stops reading, closing the HTTP connection right away.
+    fn close(&self) {
+        drop(std::mem::replace(
+            &mut *self.iterator.lock().unwrap(),
+            Box::new(std::iter::empty()),
+        ));
+    }
+}
+
+/// A stream of Arrow record batches returned by `RowIterator.to_arrow()`.
+///
+/// Implements the Arrow PyCapsule interface, so it can be passed directly to
+/// any library supporting it, e.g. `pyarrow.table()` or `polars.DataFrame()`.
+/// The data is moved to the consumer without copying, through the Arrow C
+/// Data Interface. A stream can only be consumed once.
+#[pyclass(name = "ArrowStream")]
+struct PyArrowStream {
+    chunks: Mutex<Option<ChunkIterator>>,
+}
+
+#[pymethods]
+impl PyArrowStream {
+    /// Exports the stream as an `arrow_array_stream` capsule.
+    ///
+    /// Each record batch is a struct array with the columns as fields.
+    #[pyo3(signature = (requested_schema=None))]
+    fn __arrow_c_stream__<'py>(
+        &self,
+        py: Python<'py>,
+        requested_schema: Option<Bound<'py, PyAny>>,
+    ) -> PyResult<Bound<'py, PyCapsule>> {
+        // We only have one schema, and the caller is expected to cast it
+        let _ = requested_schema;
+
+        let chunks = self
+            .chunks
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| PyIOError::new_err("the stream has already been consumed"))?;
+
+        let batches = RecordBatchIterator::new(chunks, Arc::new(arrow_schema()));
+        let stream = FFI_ArrowArrayStream::new(Box::new(batches));
+
+        PyCapsule::new(py, stream, Some(c"arrow_array_stream".to_owned()))
+    }
+
+    /// Reads the whole stream into a `pyarrow.Table`.
+    ///
+    /// Requires `pyarrow` to be installed.
+    fn to_pyarrow_table<'py>(slf: Bound<'py, Self>) -> PyResult<Bound<'py, PyAny>> {
+        let pyarrow = slf.py().import("pyarrow")?;
+        pyarrow
+            .getattr("RecordBatchReader")?
+            .call_method1("from_stream", (slf,))?
+            .call_method0("read_all")
+    }
+}
+
+/// A small list of rows returned by `RowIterator.head()`.
+///
+/// Behaves like a read only list, and renders as a table in Jupyter.
+#[pyclass(name = "Preview")]
+struct PyPreview {
+    rows: Vec<PyPageviews>,
+}
+
+#[pymethods]
+impl PyPreview {
+    fn __len__(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn __getitem__(&self, index: isize) -> PyResult<PyPageviews> {
+        let len = self.rows.len() as isize;
+        let index = if index < 0 { index + len } else { index };
+        if !(0..len).contains(&index) {
+            return Err(PyIndexError::new_err("Preview index out of range"));
+        }
+        Ok(self.rows[index as usize].clone())
+    }
+
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        PyList::new(py, self.rows.clone())?.try_iter()
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        let rows = self
+            .rows
+            .iter()
+            .map(PyPageviews::__repr__)
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(format!("Preview([{}])", rows.join(", ")))
+    }
+
+    /// Renders the rows as an HTML table for Jupyter notebooks.
+    fn _repr_html_(&self) -> String {
+        let mut html = String::from(
+            "<table>\
+                <thead><tr>\
+                    <th>domain_code</th>\
+                    <th>page_title</th>\
+                    <th>views</th>\
+                    <th>language</th>\
+                    <th>domain</th>\
+                    <th>mobile</th>\
+                    <th>timestamp</th>\
+                </tr></thead>\
+                <tbody>",
+        );
+        for row in &self.rows {
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(&row.domain_code),
+                escape_html(&row.page_title),
+                row.views,
+                escape_html(&row.language),
+                escape_html(row.domain.as_deref().unwrap_or("")),
+                row.mobile,
+                row.timestamp.map(|ts| ts.to_rfc3339()).unwrap_or_default(),
+            ));
+        }
+        html.push_str("</tbody></table>");
+        html
+    }
+}
+
+/// Escapes text for use inside HTML elements.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Summary of a parquet file, returned by `parquet_from_file`/`parquet_from_url`.
+///
+/// `errors` counts the lines skipped because they failed to parse, for each
+/// kind of error. `rows_dropped` counts the rows left out because their batch
+/// failed to convert to Arrow.
+#[pyclass(name = "WriteReport")]
+struct PyWriteReport {
+    #[pyo3(get)]
+    rows_written: u64,
+    #[pyo3(get)]
+    rows_filtered: u64,
+    #[pyo3(get)]
+    rows_errored: u64,
+    #[pyo3(get)]
+    rows_dropped: u64,
+    #[pyo3(get)]
+    bytes_read: u64,
+    #[pyo3(get)]
+    elapsed: Duration,
+    #[pyo3(get)]
+    errors: HashMap<String, usize>,
+}
+
+impl From<WriteReport> for PyWriteReport {
+    fn from(report: WriteReport) -> Self {
+        Self {
+            rows_written: report.rows_written,
+            rows_filtered: report.rows_filtered,
+            rows_errored: report.rows_errored,
+            rows_dropped: report.rows_dropped,
+            bytes_read: report.bytes_read,
+            elapsed: report.elapsed,
+            errors: report.errors.counts,
+        }
+    }
+}
+
+#[pymethods]
+impl PyWriteReport {
+    fn __repr__(&self) -> String {
+        format!(
+            "WriteReport(\
+                rows_written={}, \
+                rows_filtered={}, \
+                rows_errored={}, \
+                rows_dropped={}, \
+                bytes_read={}, \
+                elapsed={:.3}s)",
+            self.rows_written,
+            self.rows_filtered,
+            self.rows_errored,
+            self.rows_dropped,
+            self.bytes_read,
+            self.elapsed.as_secs_f64(),
+        )
+    }
+}
+
+/// Rows and views per language and per domain, returned by `summarize`.
+///
+/// `languages` and `domains` map each group to a tuple of its number of rows
+/// and total views, sorted by group. Rows from unknown domains are counted
+/// under "-".
+#[pyclass(name = "Summary")]
+struct PySummary {
+    #[pyo3(get)]
+    rows: u64,
+    #[pyo3(get)]
+    views: u64,
+    #[pyo3(get)]
+    languages: BTreeMap<String, (u64, u64)>,
+    #[pyo3(get)]
+    domains: BTreeMap<String, (u64, u64)>,
+}
+
+impl From<Summary> for PySummary {
+    fn from(summary: Summary) -> Self {
+        let totals = |groups: BTreeMap<String, GroupTotals>| {
+            groups
+                .into_iter()
+                .map(|(group, totals)| (group, (totals.rows, totals.views)))
+                .collect()
+        };
+        Self {
+            rows: summary.total.rows,
+            views: summary.total.views,
+            languages: totals(summary.languages),
+            domains: totals(summary.domains),
+        }
+    }
+}
+
+#[pymethods]
+impl PySummary {
+    fn __repr__(&self) -> String {
+        format!(
+            "Summary(rows={}, views={}, languages={}, domains={})",
+            self.rows,
+            self.views,
+            self.languages.len(),
+            self.domains.len(),
+        )
+    }
+}
+
+/// Python iterator over raw lines, returned by `lines_from_file`/`lines_from_url`.
+#[pyclass(name = "LineIterator")]
+struct PyLineIterator {
+    iterator: Mutex<LineReader>,
+}
+
+impl PyLineIterator {
+    fn new(py: Python<'_>, source: Source, line_regex: Option<String>) -> PyResult<Self> {
+        let line_regex = line_regex
+            .map(|pattern| Regex::new(&pattern))
+            .transpose()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let filter = Filter {
+            line_regex,
+            ..Default::default()
+        };
+        let iterator =
+            py.detach(|| lines_from_source(&source, &filter, &StreamConfig::default()))?;
+
+        Ok(Self {
+            iterator: Mutex::new(iterator),
+        })
+    }
+}
+
+#[pymethods]
+impl PyLineIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, PyTuple>) -> bool {
+        self.close();
+        false
+    }
+
+    /// Stops reading, closing the file or HTTP connection right away.
+    fn close(&self) {
+        drop(std::mem::replace(
+            &mut *self.iterator.lock().unwrap(),
+            Box::new(std::iter::empty()),
+        ));
+    }
+
+    fn __next__(slf: PyRef<'_, Self>) -> PyResult<Option<String>> {
+        let iterator = &slf.iterator;
+        match slf.py().detach(|| iterator.lock().unwrap().next()) {
+            Some(Ok(line)) => Ok(Some(line)),
+            Some(Err(err)) => Err(PyIOError::new_err(err.to_string())),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Streams raw lines from a pageviews file on disk, without parsing them.
+///
+/// Parameters:
+///     path (str): Path to the pageviews file.
+///     line_regex (str | None): Optional regex to match lines.
+///
+/// Returns:
+///     LineIterator: An iterator over the matching lines.
+///
+/// Raises:
+///     IOError: If the file can't be read.
+///
+/// Example:
+///     >>> lines_from_file("pageviews.gz", line_regex="^sv ")
+#[pyfunction]
+#[pyo3(name = "lines_from_file", signature = (path, line_regex=None))]
+fn py_lines_from_file(
+    py: Python<'_>,
+    path: String,
+    line_regex: Option<String>,
+) -> PyResult<PyLineIterator> {
+    PyLineIterator::new(py, Source::File(PathBuf::from(path)), line_regex)
+}
+
+/// Streams raw lines from a remote pageviews file, without parsing them.
+///
+/// Parameters:
+///     url (str): URL to the pageviews file.
+///     line_regex (str | None): Optional regex to match lines.
+///
+/// Returns:
+///     LineIterator: An iterator over the matching lines.
+///
+/// Raises:
+///     IOError: If the file can't be read.
+///
+/// Example:
+///     >>> lines_from_url("http://127.0.0.1/pageviews.gz", line_regex="^sv ")
+#[pyfunction]
+#[pyo3(name = "lines_from_url", signature = (url, line_regex=None))]
+fn py_lines_from_url(
+    py: Python<'_>,
+    url: String,
+    line_regex: Option<String>,
+) -> PyResult<PyLineIterator> {
+    let url = Url::parse(&url).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    PyLineIterator::new(py, Source::Url(url), line_regex)
+}
 
 /// Streams a pageviews file from disk with optional filters.
 ///
 /// Parameters:
 ///     path (str): Path to the pageviews file.
-///     line_regex (str | None): Optional regex to match lines before parsing.
-///     domain_codes (list[str] | None): List of domain codes to match exactly.
-///     page_title (str | None): Optional regex to match parsed page title.
-///     min_views (int | None): Minimum number of views.
-///     max_views (int | None): Maximum number of views.
-///     languages (list[str] | None): Filter by language codes.
-///     domains (list[str] | None): Filter by Wikimedia domain.
-///     mobile (bool | None): Filter mobile or desktop traffic.
+#[doc = filter_params_doc!()]
+///     prefetch_rows (int | None): Parse up to this many rows ahead in a
+///         background thread, so per-row work in python overlaps with
+///         downloading and parsing.
+///     buffer_bytes (int | None): Size of the buffer used when reading the
+///         decompressed file. Default is 256 KiB.
+///     max_download_bytes (int | None): Maximum number of bytes to
+///         download over HTTP, defaults to 1 GiB.
+///     max_decompressed_bytes (int | None): Maximum number of bytes
+///         after decompression, defaults to 8 GiB.
+///     threads (int | None): Parse and filter rows on this many
+///         worker threads. Rows are returned in the same order.
+///     mirrors (list[str] | None): Base URLs of mirrors to fail over to,
+///         in order, when downloading from Wikimedia or a mirror keeps failing.
+///     proxy (str | None): Proxy for HTTP requests, e.g.
+///         `http://proxy.example.com:3128`. Defaults to the `HTTP_PROXY` and
+///         `HTTPS_PROXY` environment variables.
+///     ca_bundle (str | None): PEM file with root certificates to trust on
+///         top of the system ones, e.g. of a proxy inspecting TLS traffic.
+///     connect_timeout (float | None): Seconds to wait for a connection,
+///         defaults to 10. 0 waits as long as the operating system does.
+///     read_timeout (float | None): Seconds to wait for a response and for
+///         each read. A download stalled for longer is resumed where it left
+///         off. Defaults to 30, 0 waits forever.
+///     user_agent (str | None): `User-Agent` header of HTTP requests.
+///     progress (Callable[[dict[str, Any]], None] | None): Called with a dict of progress
+///         counters (`total_bytes`, `bytes_read`, `bytes_decompressed`,
+///         `lines_read`, `rows_emitted`, `rows_errored`, `finished`) every
+///         100 000 lines, and once the stream is exhausted.
+///     cache_dir (str | None): Directory to cache downloaded files in.
+///         A cached file is reused as long as the server reports it unchanged.
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "raise" (default) raises each error, "skip" drops the lines,
+///         and "fail" raises the first error and ends the iteration.
+///     error_file (str | None): Append lines that fail to parse to this
+///         file, after the kind of error and a tab, instead of raising them.
+///     decode_titles (bool | None): Decode the page titles, e.g.
+///         `Caf%C3%A9_de_Flore` to `Café de Flore`, after filtering.
+///     skip (int | None): Number of rows to skip before returning any.
+///     limit (int | None): Maximum number of rows to return. Reading stops
+///         once the last row is returned, without downloading the rest.
+///     assume_sorted (bool | None): Stop reading once past the domain codes
+///         and languages the filters can match, assuming the file is sorted by
+///         domain code like the Wikimedia dumps.
 ///
 /// Returns:
 ///     RowIterator: An iterator over parsed Pageviews.
@@ -218,14 +1772,21 @@ impl PyRowIterator {
 #[pyo3(
     name="stream_from_file",
     signature = (
-        path, line_regex=None, domain_codes=None, page_title=None,
+        path, line_regex=None, filter=None, domain_codes=None, page_title=None,
         min_views=None, max_views=None, languages=None, domains=None,
-        mobile=None)
+        mobile=None, prefetch_rows=None, buffer_bytes=None,
+        max_download_bytes=None, max_decompressed_bytes=None, threads=None,
+        mirrors=None, proxy=None, ca_bundle=None, connect_timeout=None,
+        read_timeout=None, user_agent=None, progress=None, cache_dir=None,
+        on_error=None, error_file=None, decode_titles=None, skip=None,
+        limit=None, assume_sorted=None)
 )]
 #[allow(clippy::too_many_arguments)]
 fn py_stream_from_file(
+    py: Python<'_>,
     path: String,
     line_regex: Option<String>,
+    filter: Option<PyFilter>,
     domain_codes: Option<Vec<String>>,
     page_title: Option<String>,
     min_views: Option<u32>,
@@ -233,11 +1794,32 @@ fn py_stream_from_file(
     languages: Option<Vec<String>>,
     domains: Option<Vec<String>>,
     mobile: Option<bool>,
+    prefetch_rows: Option<usize>,
+    buffer_bytes: Option<usize>,
+    max_download_bytes: Option<u64>,
+    max_decompressed_bytes: Option<u64>,
+    threads: Option<usize>,
+    mirrors: Option<Vec<String>>,
+    proxy: Option<String>,
+    ca_bundle: Option<String>,
+    connect_timeout: Option<f64>,
+    read_timeout: Option<f64>,
+    user_agent: Option<String>,
+    progress: Option<Py<PyAny>>,
+    cache_dir: Option<String>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    decode_titles: Option<bool>,
+    skip: Option<usize>,
+    limit: Option<usize>,
+    assume_sorted: Option<bool>,
 ) -> PyResult<PyRowIterator> {
     PyRowIterator::new(
+        py,
         Some(path),
         None,
         line_regex,
+        filter,
         domain_codes,
         page_title,
         min_views,
@@ -245,6 +1827,25 @@ fn py_stream_from_file(
         languages,
         domains,
         mobile,
+        prefetch_rows,
+        buffer_bytes,
+        max_download_bytes,
+        max_decompressed_bytes,
+        threads,
+        mirrors,
+        proxy,
+        ca_bundle,
+        connect_timeout,
+        read_timeout,
+        user_agent,
+        progress,
+        cache_dir,
+        on_error,
+        error_file,
+        decode_titles,
+        skip,
+        limit,
+        assume_sorted,
     )
 }
 
@@ -252,14 +1853,50 @@ fn py_stream_from_file(
 ///
 /// Parameters:
 ///     url (str): URL to the pageviews file.
-///     line_regex (str | None): Optional regex to match lines before parsing.
-///     domain_codes (list[str] | None): List of domain codes to match exactly.
-///     page_title (str | None): Optional regex to match parsed page title.
-///     min_views (int | None): Minimum number of views.
-///     max_views (int | None): Maximum number of views.
-///     languages (list[str] | None): Filter by language codes.
-///     domains (list[str] | None): Filter by Wikimedia domain.
-///     mobile (bool | None): Filter mobile or desktop traffic.
+#[doc = filter_params_doc!()]
+///     prefetch_rows (int | None): Parse up to this many rows ahead in a
+///         background thread, so per-row work in python overlaps with
+///         downloading and parsing.
+///     buffer_bytes (int | None): Size of the buffer used when reading the
+///         decompressed file. Default is 256 KiB.
+///     max_download_bytes (int | None): Maximum number of bytes to
+///         download over HTTP, defaults to 1 GiB.
+///     max_decompressed_bytes (int | None): Maximum number of bytes
+///         after decompression, defaults to 8 GiB.
+///     threads (int | None): Parse and filter rows on this many
+///         worker threads. Rows are returned in the same order.
+///     mirrors (list[str] | None): Base URLs of mirrors to fail over to,
+///         in order, when downloading from Wikimedia or a mirror keeps failing.
+///     proxy (str | None): Proxy for HTTP requests, e.g.
+///         `http://proxy.example.com:3128`. Defaults to the `HTTP_PROXY` and
+///         `HTTPS_PROXY` environment variables.
+///     ca_bundle (str | None): PEM file with root certificates to trust on
+///         top of the system ones, e.g. of a proxy inspecting TLS traffic.
+///     connect_timeout (float | None): Seconds to wait for a connection,
+///         defaults to 10. 0 waits as long as the operating system does.
+///     read_timeout (float | None): Seconds to wait for a response and for
+///         each read. A download stalled for longer is resumed where it left
+///         off. Defaults to 30, 0 waits forever.
+///     user_agent (str | None): `User-Agent` header of HTTP requests.
+///     progress (Callable[[dict[str, Any]], None] | None): Called with a dict of progress
+///         counters (`total_bytes`, `bytes_read`, `bytes_decompressed`,
+///         `lines_read`, `rows_emitted`, `rows_errored`, `finished`) every
+///         100 000 lines, and once the stream is exhausted.
+///     cache_dir (str | None): Directory to cache downloaded files in.
+///         A cached file is reused as long as the server reports it unchanged.
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "raise" (default) raises each error, "skip" drops the lines,
+///         and "fail" raises the first error and ends the iteration.
+///     error_file (str | None): Append lines that fail to parse to this
+///         file, after the kind of error and a tab, instead of raising them.
+///     decode_titles (bool | None): Decode the page titles, e.g.
+///         `Caf%C3%A9_de_Flore` to `Café de Flore`, after filtering.
+///     skip (int | None): Number of rows to skip before returning any.
+///     limit (int | None): Maximum number of rows to return. Reading stops
+///         once the last row is returned, without downloading the rest.
+///     assume_sorted (bool | None): Stop reading once past the domain codes
+///         and languages the filters can match, assuming the file is sorted by
+///         domain code like the Wikimedia dumps.
 ///
 /// Returns:
 ///     RowIterator: An iterator over parsed Pageviews.
@@ -274,14 +1911,21 @@ fn py_stream_from_file(
 #[pyo3(
     name="stream_from_url",
     signature = (
-        url, line_regex=None, domain_codes=None, page_title=None,
+        url, line_regex=None, filter=None, domain_codes=None, page_title=None,
         min_views=None, max_views=None, languages=None, domains=None,
-        mobile=None)
+        mobile=None, prefetch_rows=None, buffer_bytes=None,
+        max_download_bytes=None, max_decompressed_bytes=None, threads=None,
+        mirrors=None, proxy=None, ca_bundle=None, connect_timeout=None,
+        read_timeout=None, user_agent=None, progress=None, cache_dir=None,
+        on_error=None, error_file=None, decode_titles=None, skip=None,
+        limit=None, assume_sorted=None)
 )]
 #[allow(clippy::too_many_arguments)]
 fn py_stream_from_url(
+    py: Python<'_>,
     url: String,
     line_regex: Option<String>,
+    filter: Option<PyFilter>,
     domain_codes: Option<Vec<String>>,
     page_title: Option<String>,
     min_views: Option<u32>,
@@ -289,11 +1933,32 @@ fn py_stream_from_url(
     languages: Option<Vec<String>>,
     domains: Option<Vec<String>>,
     mobile: Option<bool>,
+    prefetch_rows: Option<usize>,
+    buffer_bytes: Option<usize>,
+    max_download_bytes: Option<u64>,
+    max_decompressed_bytes: Option<u64>,
+    threads: Option<usize>,
+    mirrors: Option<Vec<String>>,
+    proxy: Option<String>,
+    ca_bundle: Option<String>,
+    connect_timeout: Option<f64>,
+    read_timeout: Option<f64>,
+    user_agent: Option<String>,
+    progress: Option<Py<PyAny>>,
+    cache_dir: Option<String>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    decode_titles: Option<bool>,
+    skip: Option<usize>,
+    limit: Option<usize>,
+    assume_sorted: Option<bool>,
 ) -> PyResult<PyRowIterator> {
     PyRowIterator::new(
+        py,
         None,
         Some(url),
         line_regex,
+        filter,
         domain_codes,
         page_title,
         min_views,
@@ -301,9 +1966,142 @@ fn py_stream_from_url(
         languages,
         domains,
         mobile,
+        prefetch_rows,
+        buffer_bytes,
+        max_download_bytes,
+        max_decompressed_bytes,
+        threads,
+        mirrors,
+        proxy,
+        ca_bundle,
+        connect_timeout,
+        read_timeout,
+        user_agent,
+        progress,
+        cache_dir,
+        on_error,
+        error_file,
+        decode_titles,
+        skip,
+        limit,
+        assume_sorted,
     )
 }
 
+/// Reads the filtered rows of a pageviews file from disk into a DataFrame.
+///
+/// Parameters:
+///     path (str): Path to the pageviews file.
+///     library (str): Either "pandas" (default) or "polars".
+///     **kwargs: Filters and options, as for `stream_from_file`.
+///
+/// Returns:
+///     pandas.DataFrame | polars.DataFrame: The rows, with the fields of
+///         `Pageviews` as columns.
+///
+/// Raises:
+///     ImportError: If the DataFrame library isn't installed.
+///     IOError: If the file can't be read.
+///     ParseError: If parsing fails.
+///
+/// Example:
+///     >>> dataframe_from_file("pageviews.gz", languages=["de"], min_views=10)
+#[pyfunction]
+#[pyo3(name = "dataframe_from_file", signature = (path, library="pandas", **kwargs))]
+fn py_dataframe_from_file<'py>(
+    py: Python<'py>,
+    path: String,
+    library: &str,
+    kwargs: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let method = dataframe_method(library)?;
+    let rows = wrap_pyfunction!(py_stream_from_file, py)?.call((path,), kwargs)?;
+    rows.call_method0(method)
+}
+
+/// Downloads a remote pageviews file and reads the filtered rows into a
+/// DataFrame.
+///
+/// Parameters:
+///     url (str): URL to the pageviews file.
+///     library (str): Either "pandas" (default) or "polars".
+///     **kwargs: Filters and options, as for `stream_from_url`.
+///
+/// Returns:
+///     pandas.DataFrame | polars.DataFrame: The rows, with the fields of
+///         `Pageviews` as columns.
+///
+/// Raises:
+///     ImportError: If the DataFrame library isn't installed.
+///     IOError: If the file can't be downloaded.
+///     ParseError: If parsing fails.
+///
+/// Example:
+///     >>> dataframe_from_url(pageviews_url(date(2024, 8, 18), 8), library="polars")
+#[pyfunction]
+#[pyo3(name = "dataframe_from_url", signature = (url, library="pandas", **kwargs))]
+fn py_dataframe_from_url<'py>(
+    py: Python<'py>,
+    url: String,
+    library: &str,
+    kwargs: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let method = dataframe_method(library)?;
+    let rows = wrap_pyfunction!(py_stream_from_url, py)?.call((url,), kwargs)?;
+    rows.call_method0(method)
+}
+
+/// Streams a pageviews file from a remote server into an async iterator.
+///
+/// Meant for asyncio applications, which can `async for` over the rows
+/// while other tasks keep running. The rows are read in the default executor
+/// of the running event loop.
+///
+/// Parameters:
+///     url (str): URL to the pageviews file.
+///     **kwargs: Filters and options, as for `stream_from_url`.
+///
+/// Returns:
+///     AsyncRowIterator: An async iterator over parsed Pageviews.
+///
+/// Raises:
+///     IOError: If the file can't be read.
+///     ParseError: If parsing fails, when the row is awaited.
+///
+/// Example:
+///     >>> async for row in stream_from_url_async(url, languages=["de"]):
+///     ...     print(row.page_title)
+#[pyfunction]
+#[pyo3(name = "stream_from_url_async", signature = (url, **kwargs))]
+fn py_stream_from_url_async(
+    py: Python<'_>,
+    url: String,
+    kwargs: Option<&Bound<'_, PyDict>>,
+) -> PyResult<PyAsyncRowIterator> {
+    let rows = wrap_pyfunction!(py_stream_from_url, py)?.call((url,), kwargs)?;
+    let rows = rows.downcast::<PyRowIterator>()?.borrow();
+    let iterator = std::mem::replace(
+        &mut *rows.iterator.lock().unwrap(),
+        Box::new(std::iter::empty()),
+    );
+
+    Ok(PyAsyncRowIterator {
+        iterator: Arc::new(Mutex::new(iterator)),
+        progress: rows.progress.clone(),
+    })
+}
+
+/// Name of the `RowIterator` method building a DataFrame with `library`.
+fn dataframe_method(library: &str) -> PyResult<&'static str> {
+    match library {
+        "pandas" => Ok("to_pandas"),
+        "polars" => Ok("to_polars"),
+        other => Err(PyValueError::new_err(format!(
+            "`library` must be \"pandas\" or \"polars\", not {other:?}"
+        ))),
+    }
+}
+
 /// Creates a parquet file based on the parsed and filtered content of the file.
 ///
 /// Parameters:
@@ -315,14 +2113,33 @@ fn py_stream_from_url(
 ///         size of a row group in a parquet file. Increase to lower run time at
 ///         the cost of more memory, or decrease to lower memory requirements at
 ///         the cost of execution speed. Default is fine for most use cases.
-///     line_regex (str | None): Optional regex to match lines before parsing.
-///     domain_codes (list[str] | None): List of domain codes to match exactly.
-///     page_title (str | None): Optional regex to match parsed page title.
-///     min_views (int | None): Minimum number of views.
-///     max_views (int | None): Maximum number of views.
-///     languages (list[str] | None): Filter by language codes.
-///     domains (list[str] | None): Filter by Wikimedia domain.
-///     mobile (bool | None): Filter mobile or desktop traffic.
+#[doc = filter_params_doc!()]
+///     layout (str | None): Either "flat" (default) with all fields as top
+///         level columns, or "nested" with `language`, `domain`, and `mobile`
+///         in a `parsed_domain_code` struct column.
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "skip" (default) drops them, while "fail" stops at the first
+///         one and raises it.
+///     error_file (str | None): Write lines that fail to parse to this file,
+///         after the kind of error and a tab. The file will be overwritten if
+///         it already exists.
+///     top_k (int | None): Only write the top_k rows with most views,
+///         sorted by views.
+///     top_k_per_language (bool | None): Keep the top_k rows of each language
+///         instead of overall.
+#[doc = parquet_params_doc!()]
+///     decode_titles (bool | None): Decode the page titles, e.g.
+///         `Caf%C3%A9_de_Flore` to `Café de Flore`, after filtering.
+///     skip (int | None): Number of rows to skip before writing any.
+///     limit (int | None): Maximum number of rows to write. Reading stops
+///         once the last row is read, without downloading the rest.
+///     assume_sorted (bool | None): Stop reading once past the domain codes
+///         and languages the filters can match, assuming the file is sorted by
+///         domain code like the Wikimedia dumps.
+///
+/// Returns:
+///     WriteReport: Rows written, filtered out, and skipped because they failed
+///         to parse, bytes read, and the time taken.
 ///
 /// Raises:
 ///     IOError: If the file can't be read.
@@ -334,14 +2151,20 @@ fn py_stream_from_url(
 #[pyo3(name = "parquet_from_file",
        signature = (
            input_path, output_path, batch_size=None, line_regex=None,
-           domain_codes=None, page_title=None, min_views=None, max_views=None,
-           languages=None, domains=None, mobile=None))]
+           filter=None, domain_codes=None, page_title=None, min_views=None,
+           max_views=None, languages=None, domains=None, mobile=None,
+           layout=None, on_error=None, error_file=None, top_k=None,
+           top_k_per_language=None, columns=None, sort=None, statistics=None,
+           bloom_filters=None, encodings=None, decode_titles=None, skip=None,
+           limit=None, assume_sorted=None))]
 #[allow(clippy::too_many_arguments)]
 fn py_parquet_from_file(
+    py: Python<'_>,
     input_path: String,
     output_path: String,
     batch_size: Option<usize>,
     line_regex: Option<String>,
+    filter: Option<PyFilter>,
     domain_codes: Option<Vec<String>>,
     page_title: Option<String>,
     min_views: Option<u32>,
@@ -349,9 +2172,24 @@ fn py_parquet_from_file(
     languages: Option<Vec<String>>,
     domains: Option<Vec<String>>,
     mobile: Option<bool>,
-) -> PyResult<()> {
+    layout: Option<String>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    top_k: Option<usize>,
+    top_k_per_language: Option<bool>,
+    columns: Option<Vec<String>>,
+    sort: Option<String>,
+    statistics: Option<bool>,
+    bloom_filters: Option<bool>,
+    encodings: Option<HashMap<String, String>>,
+    decode_titles: Option<bool>,
+    skip: Option<usize>,
+    limit: Option<usize>,
+    assume_sorted: Option<bool>,
+) -> PyResult<PyWriteReport> {
     let filter = filter_from_input(
         line_regex,
+        filter,
         domain_codes,
         page_title,
         min_views,
@@ -360,13 +2198,41 @@ fn py_parquet_from_file(
         domains,
         mobile,
     )?;
+    let cancel = CancellationToken::new();
+    let config = StreamConfig {
+        cancel: Some(cancel.clone()),
+        error_policy: error_policy_from_input(on_error, None)?,
+        decode_titles: decode_titles.unwrap_or(false),
+        skip: skip.unwrap_or(0),
+        limit,
+        assume_sorted: assume_sorted.unwrap_or(false),
+        ..Default::default()
+    };
 
-    Ok(parquet_from_file(
-        PathBuf::from(input_path),
-        PathBuf::from(output_path),
-        &filter,
+    let options = parquet_options_from_input(
         batch_size,
-    )?)
+        layout,
+        error_file,
+        top_k,
+        top_k_per_language,
+        columns,
+        sort,
+        statistics,
+        bloom_filters,
+        encodings,
+    )?;
+
+    let report = detach_interruptible(py, &cancel, || {
+        parquet_from_source(
+            &Source::File(PathBuf::from(input_path)),
+            PathBuf::from(output_path),
+            &filter,
+            &config,
+            &options,
+        )
+    })?;
+
+    Ok(report.into())
 }
 
 /// Creates a parquet file based on the parsed and filtered content of the file.
@@ -380,14 +2246,33 @@ fn py_parquet_from_file(
 ///         size of a row group in a parquet file. Increase to lower run time at
 ///         the cost of more memory, or decrease to lower memory requirements at
 ///         the cost of execution speed. Default is fine for most use cases.
-///     line_regex (str | None): Optional regex to match lines before parsing.
-///     domain_codes (list[str] | None): List of domain codes to match exactly.
-///     page_title (str | None): Optional regex to match parsed page title.
-///     min_views (int | None): Minimum number of views.
-///     max_views (int | None): Maximum number of views.
-///     languages (list[str] | None): Filter by language codes.
-///     domains (list[str] | None): Filter by Wikimedia domain.
-///     mobile (bool | None): Filter mobile or desktop traffic.
+#[doc = filter_params_doc!()]
+///     layout (str | None): Either "flat" (default) with all fields as top
+///         level columns, or "nested" with `language`, `domain`, and `mobile`
+///         in a `parsed_domain_code` struct column.
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "skip" (default) drops them, while "fail" stops at the first
+///         one and raises it.
+///     error_file (str | None): Write lines that fail to parse to this file,
+///         after the kind of error and a tab. The file will be overwritten if
+///         it already exists.
+///     top_k (int | None): Only write the top_k rows with most views,
+///         sorted by views.
+///     top_k_per_language (bool | None): Keep the top_k rows of each language
+///         instead of overall.
+#[doc = parquet_params_doc!()]
+///     decode_titles (bool | None): Decode the page titles, e.g.
+///         `Caf%C3%A9_de_Flore` to `Café de Flore`, after filtering.
+///     skip (int | None): Number of rows to skip before writing any.
+///     limit (int | None): Maximum number of rows to write. Reading stops
+///         once the last row is read, without downloading the rest.
+///     assume_sorted (bool | None): Stop reading once past the domain codes
+///         and languages the filters can match, assuming the file is sorted by
+///         domain code like the Wikimedia dumps.
+///
+/// Returns:
+///     WriteReport: Rows written, filtered out, and skipped because they failed
+///         to parse, bytes read, and the time taken.
 ///
 /// Raises:
 ///     IOError: If the file can't be read.
@@ -398,15 +2283,21 @@ fn py_parquet_from_file(
 #[pyfunction]
 #[pyo3(name = "parquet_from_url",
        signature = (
-           url, output_path, batch_size=None, line_regex=None,
+           url, output_path, batch_size=None, line_regex=None, filter=None,
            domain_codes=None, page_title=None, min_views=None, max_views=None,
-           languages=None, domains=None, mobile=None))]
+           languages=None, domains=None, mobile=None, layout=None,
+           on_error=None, error_file=None, top_k=None, top_k_per_language=None,
+           columns=None, sort=None, statistics=None, bloom_filters=None,
+           encodings=None, decode_titles=None, skip=None, limit=None,
+           assume_sorted=None))]
 #[allow(clippy::too_many_arguments)]
 fn py_parquet_from_url(
+    py: Python<'_>,
     url: String,
     output_path: String,
     batch_size: Option<usize>,
     line_regex: Option<String>,
+    filter: Option<PyFilter>,
     domain_codes: Option<Vec<String>>,
     page_title: Option<String>,
     min_views: Option<u32>,
@@ -414,10 +2305,25 @@ fn py_parquet_from_url(
     languages: Option<Vec<String>>,
     domains: Option<Vec<String>>,
     mobile: Option<bool>,
-) -> PyResult<()> {
+    layout: Option<String>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    top_k: Option<usize>,
+    top_k_per_language: Option<bool>,
+    columns: Option<Vec<String>>,
+    sort: Option<String>,
+    statistics: Option<bool>,
+    bloom_filters: Option<bool>,
+    encodings: Option<HashMap<String, String>>,
+    decode_titles: Option<bool>,
+    skip: Option<usize>,
+    limit: Option<usize>,
+    assume_sorted: Option<bool>,
+) -> PyResult<PyWriteReport> {
     let url = Url::parse(&url).map_err(|e| PyValueError::new_err(e.to_string()))?;
     let filter = filter_from_input(
         line_regex,
+        filter,
         domain_codes,
         page_title,
         min_views,
@@ -426,21 +2332,1392 @@ fn py_parquet_from_url(
         domains,
         mobile,
     )?;
+    let cancel = CancellationToken::new();
+    let config = StreamConfig {
+        cancel: Some(cancel.clone()),
+        error_policy: error_policy_from_input(on_error, None)?,
+        decode_titles: decode_titles.unwrap_or(false),
+        skip: skip.unwrap_or(0),
+        limit,
+        assume_sorted: assume_sorted.unwrap_or(false),
+        ..Default::default()
+    };
 
-    Ok(parquet_from_url(
-        url,
-        PathBuf::from(output_path),
-        &filter,
+    let options = parquet_options_from_input(
         batch_size,
-    )?)
+        layout,
+        error_file,
+        top_k,
+        top_k_per_language,
+        columns,
+        sort,
+        statistics,
+        bloom_filters,
+        encodings,
+    )?;
+
+    let report = detach_interruptible(py, &cancel, || {
+        parquet_from_source(
+            &Source::Url(url),
+            PathBuf::from(output_path),
+            &filter,
+            &config,
+            &options,
+        )
+    })?;
+
+    Ok(report.into())
+}
+
+/// Writes the filtered rows of several files to a partitioned parquet dataset.
+///
+/// The dataset is a directory in the Hive layout, e.g.
+/// `language=en/date=2024-08-18/part-000.parquet`, with one part file per
+/// input file in each partition. It can be read as one table with
+/// `pyarrow.dataset`, Polars, DuckDB, or Spark. The partition columns are
+/// taken from the directory names, and are left out of the files.
+///
+/// Parameters:
+///     urls (list[str]): URLs to remote pageviews files.
+///     output_dir (str): Directory to write the dataset to. Part files from
+///         earlier runs with the same inputs are overwritten.
+///     partition_by (list[str]): Columns to partition by, in order, any of
+///         "language", "date", and "domain".
+///     batch_size (int | None): How many rows to include in each batch written
+///         to the parquet file. By default, it is 122 880, which is the default
+///         size of a row group in a parquet file. Increase to lower run time at
+///         the cost of more memory, or decrease to lower memory requirements at
+///         the cost of execution speed. Default is fine for most use cases.
+#[doc = filter_params_doc!()]
+///     layout (str | None): Either "flat" (default) with all fields as top
+///         level columns, or "nested" with `language`, `domain`, and `mobile`
+///         in a `parsed_domain_code` struct column.
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "skip" (default) drops them, while "fail" stops at the first
+///         one and raises it.
+///     error_file (str | None): Write lines that fail to parse to this file,
+///         after the kind of error and a tab. The file will be overwritten if
+///         it already exists.
+///     top_k (int | None): Only write the top_k rows with most views of
+///         each input file.
+///     top_k_per_language (bool | None): Keep the top_k rows of each language
+///         instead of overall.
+#[doc = parquet_params_doc!()]
+///     decode_titles (bool | None): Decode the page titles, e.g.
+///         `Caf%C3%A9_de_Flore` to `Café de Flore`, after filtering.
+///
+/// Returns:
+///     list[str]: Paths of the part files written, relative to `output_dir`.
+///
+/// Raises:
+///     IOError: If one of the files can't be read.
+///     ValueError: If a partition column is unknown.
+///
+/// Example:
+///     >>> parquet_dataset_from_urls(pageviews_urls_for_day(date(2024, 8, 18)), "pageviews", ["language", "date"])
+#[pyfunction]
+#[pyo3(name = "parquet_dataset_from_urls",
+       signature = (
+           urls, output_dir, partition_by, batch_size=None, line_regex=None,
+           filter=None, domain_codes=None, page_title=None, min_views=None,
+           max_views=None, languages=None, domains=None, mobile=None,
+           layout=None, on_error=None, error_file=None, top_k=None,
+           top_k_per_language=None, columns=None, sort=None, statistics=None,
+           bloom_filters=None, encodings=None, decode_titles=None))]
+#[allow(clippy::too_many_arguments)]
+fn py_parquet_dataset_from_urls(
+    py: Python<'_>,
+    urls: Vec<String>,
+    output_dir: String,
+    partition_by: Vec<String>,
+    batch_size: Option<usize>,
+    line_regex: Option<String>,
+    filter: Option<PyFilter>,
+    domain_codes: Option<Vec<String>>,
+    page_title: Option<String>,
+    min_views: Option<u32>,
+    max_views: Option<u32>,
+    languages: Option<Vec<String>>,
+    domains: Option<Vec<String>>,
+    mobile: Option<bool>,
+    layout: Option<String>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    top_k: Option<usize>,
+    top_k_per_language: Option<bool>,
+    columns: Option<Vec<String>>,
+    sort: Option<String>,
+    statistics: Option<bool>,
+    bloom_filters: Option<bool>,
+    encodings: Option<HashMap<String, String>>,
+    decode_titles: Option<bool>,
+) -> PyResult<Vec<String>> {
+    let sources = urls
+        .iter()
+        .map(|url| Url::parse(url).map(Source::Url))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let partition_by = partition_by
+        .iter()
+        .map(|column| column.parse())
+        .collect::<Result<Vec<PartitionBy>, _>>()
+        .map_err(PyValueError::new_err)?;
+    let filter = filter_from_input(
+        line_regex,
+        filter,
+        domain_codes,
+        page_title,
+        min_views,
+        max_views,
+        languages,
+        domains,
+        mobile,
+    )?;
+    let cancel = CancellationToken::new();
+    let config = StreamConfig {
+        cancel: Some(cancel.clone()),
+        error_policy: error_policy_from_input(on_error, None)?,
+        decode_titles: decode_titles.unwrap_or(false),
+        ..Default::default()
+    };
+
+    let options = parquet_options_from_input(
+        batch_size,
+        layout,
+        error_file,
+        top_k,
+        top_k_per_language,
+        columns,
+        sort,
+        statistics,
+        bloom_filters,
+        encodings,
+    )?;
+
+    let written = detach_interruptible(py, &cancel, || {
+        parquet_dataset_from_sources(
+            &sources,
+            Path::new(&output_dir),
+            &partition_by,
+            &filter,
+            &config,
+            &options,
+        )
+    })?;
+
+    Ok(written
+        .into_iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Downloads several files concurrently and writes the filtered rows to one
+/// parquet file.
+///
+/// Up to `concurrency` files are downloaded and parsed at the same time, on
+/// separate threads. The rows of each file stay in order, but the files are
+/// interleaved in the output, with the hour of each row in `timestamp`.
+///
+/// Parameters:
+///     urls (list[str]): URLs to remote pageviews files.
+///     output_path (str): Path to the parquet file. The file will be
+///         overwritten if it already exists.
+///     concurrency (int): Number of files to read at a time. Default is 4.
+///     batch_size (int | None): How many rows to include in each batch written
+///         to the parquet file. By default, it is 122 880, which is the default
+///         size of a row group in a parquet file. Increase to lower run time at
+///         the cost of more memory, or decrease to lower memory requirements at
+///         the cost of execution speed. Default is fine for most use cases.
+#[doc = filter_params_doc!()]
+///     layout (str | None): Either "flat" (default) with all fields as top
+///         level columns, or "nested" with `language`, `domain`, and `mobile`
+///         in a `parsed_domain_code` struct column.
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "skip" (default) drops them, while "fail" stops at the first
+///         one and raises it.
+///     error_file (str | None): Write lines that fail to parse to this file,
+///         after the kind of error and a tab. The file will be overwritten if
+///         it already exists.
+///     top_k (int | None): Only write the top_k rows with most views of
+///         each input file.
+///     top_k_per_language (bool | None): Keep the top_k rows of each language
+///         instead of overall.
+#[doc = parquet_params_doc!()]
+///     decode_titles (bool | None): Decode the page titles, e.g.
+///         `Caf%C3%A9_de_Flore` to `Café de Flore`, after filtering.
+///
+/// Returns:
+///     WriteReport: Totals of rows written, filtered out, and skipped because
+///         they failed to parse, bytes read, and elapsed time.
+///
+/// Raises:
+///     IOError: If one of the files can't be read.
+///
+/// Example:
+///     >>> parquet_from_urls(pageviews_urls_for_day(date(2024, 8, 18)), "2024-08-18.parquet", languages=["nb"])
+#[pyfunction]
+#[pyo3(name = "parquet_from_urls",
+       signature = (
+           urls, output_path, concurrency=4, batch_size=None, line_regex=None,
+           filter=None, domain_codes=None, page_title=None, min_views=None,
+           max_views=None, languages=None, domains=None, mobile=None,
+           layout=None, on_error=None, error_file=None, top_k=None,
+           top_k_per_language=None, columns=None, sort=None, statistics=None,
+           bloom_filters=None, encodings=None, decode_titles=None))]
+#[allow(clippy::too_many_arguments)]
+fn py_parquet_from_urls(
+    py: Python<'_>,
+    urls: Vec<String>,
+    output_path: String,
+    concurrency: usize,
+    batch_size: Option<usize>,
+    line_regex: Option<String>,
+    filter: Option<PyFilter>,
+    domain_codes: Option<Vec<String>>,
+    page_title: Option<String>,
+    min_views: Option<u32>,
+    max_views: Option<u32>,
+    languages: Option<Vec<String>>,
+    domains: Option<Vec<String>>,
+    mobile: Option<bool>,
+    layout: Option<String>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    top_k: Option<usize>,
+    top_k_per_language: Option<bool>,
+    columns: Option<Vec<String>>,
+    sort: Option<String>,
+    statistics: Option<bool>,
+    bloom_filters: Option<bool>,
+    encodings: Option<HashMap<String, String>>,
+    decode_titles: Option<bool>,
+) -> PyResult<PyWriteReport> {
+    let sources = urls
+        .iter()
+        .map(|url| Url::parse(url).map(Source::Url))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let filter = filter_from_input(
+        line_regex,
+        filter,
+        domain_codes,
+        page_title,
+        min_views,
+        max_views,
+        languages,
+        domains,
+        mobile,
+    )?;
+    let cancel = CancellationToken::new();
+    let config = StreamConfig {
+        cancel: Some(cancel.clone()),
+        error_policy: error_policy_from_input(on_error, None)?,
+        decode_titles: decode_titles.unwrap_or(false),
+        ..Default::default()
+    };
+
+    let options = parquet_options_from_input(
+        batch_size,
+        layout,
+        error_file,
+        top_k,
+        top_k_per_language,
+        columns,
+        sort,
+        statistics,
+        bloom_filters,
+        encodings,
+    )?;
+
+    let report = detach_interruptible(py, &cancel, || {
+        parquet_from_sources(
+            &sources,
+            Path::new(&output_path),
+            &filter,
+            &config,
+            &options,
+            concurrency,
+        )
+    })?;
+
+    Ok(report.into())
+}
+
+/// Downloads the hourly files from `start` up to `end`, and writes the
+/// filtered rows to parquet files in `output_dir`.
+///
+/// Each hour is written to its own file, named after the input, e.g.
+/// `pageviews-20240801-000000.parquet`. With `merge`, all hours are written
+/// to one file named after the range instead, e.g.
+/// `pageviews-20240801-000000-20240802-000000.parquet`, with the hour of each
+/// row in `timestamp`. Up to `concurrency` files are downloaded and parsed at
+/// the same time, on separate threads.
+///
+/// Parameters:
+///     start (str): First hour to read (UTC), e.g. "2024-08-01T00". Dates
+///         and ISO 8601 timestamps are accepted too, rounded down to the hour.
+///     end (str): Hour to stop at (UTC), not included, e.g. "2024-08-02T00"
+///         for all of August 1st.
+///     output_dir (str): Directory of the parquet files, created if it doesn't
+///         exist. Existing files with the same names are overwritten.
+///     merge (bool): Write all hours to one file. Default is False.
+///     mirror (str | None): Base URL of the pageviews directory on a mirror.
+///         Defaults to Wikimedia's own servers.
+///     concurrency (int): Number of files to read at a time. Default is 4.
+///     batch_size (int | None): How many rows to include in each batch written
+///         to the parquet file. By default, it is 122 880, which is the default
+///         size of a row group in a parquet file. Increase to lower run time at
+///         the cost of more memory, or decrease to lower memory requirements at
+///         the cost of execution speed. Default is fine for most use cases.
+#[doc = filter_params_doc!()]
+///     layout (str | None): Either "flat" (default) with all fields as top
+///         level columns, or "nested" with `language`, `domain`, and `mobile`
+///         in a `parsed_domain_code` struct column.
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "skip" (default) drops them, while "fail" stops at the first
+///         one and raises it.
+///     error_file (str | None): Write lines that fail to parse to this file,
+///         after the kind of error and a tab. The file will be overwritten if
+///         it already exists. Without `merge`, each hour gets its own error
+///         file instead, with `.errors.tsv` in place of `.parquet`.
+///     top_k (int | None): Only write the top_k rows with most views of
+///         each input file.
+///     top_k_per_language (bool | None): Keep the top_k rows of each language
+///         instead of overall.
+#[doc = parquet_params_doc!()]
+///     decode_titles (bool | None): Decode the page titles, e.g.
+///         `Caf%C3%A9_de_Flore` to `Café de Flore`, after filtering.
+///
+/// Returns:
+///     dict[str, WriteReport]: Report of each parquet file written, by path,
+///         in order.
+///
+/// Raises:
+///     ValueError: If `start` or `end` is invalid, or `end` is not after
+///         `start`.
+///     IOError: If one of the files can't be read.
+///
+/// Example:
+///     >>> parquet_from_range("2024-08-01T00", "2024-08-02T00", "pageviews", languages=["nb"])
+#[pyfunction]
+#[pyo3(name = "parquet_from_range",
+       signature = (
+           start, end, output_dir, merge=false, mirror=None, concurrency=4,
+           batch_size=None, line_regex=None, filter=None, domain_codes=None,
+           page_title=None, min_views=None, max_views=None, languages=None,
+           domains=None, mobile=None, layout=None, on_error=None,
+           error_file=None, top_k=None, top_k_per_language=None, columns=None,
+           sort=None, statistics=None, bloom_filters=None, encodings=None,
+           decode_titles=None))]
+#[allow(clippy::too_many_arguments)]
+fn py_parquet_from_range(
+    py: Python<'_>,
+    start: &str,
+    end: &str,
+    output_dir: String,
+    merge: bool,
+    mirror: Option<String>,
+    concurrency: usize,
+    batch_size: Option<usize>,
+    line_regex: Option<String>,
+    filter: Option<PyFilter>,
+    domain_codes: Option<Vec<String>>,
+    page_title: Option<String>,
+    min_views: Option<u32>,
+    max_views: Option<u32>,
+    languages: Option<Vec<String>>,
+    domains: Option<Vec<String>>,
+    mobile: Option<bool>,
+    layout: Option<String>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    top_k: Option<usize>,
+    top_k_per_language: Option<bool>,
+    columns: Option<Vec<String>>,
+    sort: Option<String>,
+    statistics: Option<bool>,
+    bloom_filters: Option<bool>,
+    encodings: Option<HashMap<String, String>>,
+    decode_titles: Option<bool>,
+) -> PyResult<BTreeMap<String, PyWriteReport>> {
+    let (start, end) = (hour_from_input(start)?, hour_from_input(end)?);
+    if end <= start {
+        return Err(PyValueError::new_err("`end` must be after `start`"));
+    }
+    let mirror = mirror_from_input(mirror)?;
+    let sources: Vec<_> = hours_in_range(start, end)
+        .map(|hour| Source::Url(mirror.dump_url(&hour)))
+        .collect();
+    let filter = filter_from_input(
+        line_regex,
+        filter,
+        domain_codes,
+        page_title,
+        min_views,
+        max_views,
+        languages,
+        domains,
+        mobile,
+    )?;
+    let cancel = CancellationToken::new();
+    let config = StreamConfig {
+        cancel: Some(cancel.clone()),
+        error_policy: error_policy_from_input(on_error, None)?,
+        decode_titles: decode_titles.unwrap_or(false),
+        ..Default::default()
+    };
+
+    let options = parquet_options_from_input(
+        batch_size,
+        layout,
+        error_file,
+        top_k,
+        top_k_per_language,
+        columns,
+        sort,
+        statistics,
+        bloom_filters,
+        encodings,
+    )?;
+
+    let output_dir = Path::new(&output_dir);
+    let written = detach_interruptible(py, &cancel, || {
+        // Buckets have no directories to create
+        if object_store_url(output_dir).is_none() {
+            create_dir_all(output_dir)?;
+        }
+        if !merge {
+            return parquet_files_from_sources(
+                &sources,
+                output_dir,
+                &filter,
+                &config,
+                &options,
+                concurrency,
+            );
+        }
+        let name = format!(
+            "{}-{}.parquet",
+            start.format("pageviews-%Y%m%d-%H0000"),
+            end.format("%Y%m%d-%H0000"),
+        );
+        let path = output_dir.join(name);
+        let report =
+            parquet_from_sources(&sources, &path, &filter, &config, &options, concurrency)?;
+        Ok(vec![(path, report)])
+    })?;
+
+    Ok(written
+        .into_iter()
+        .map(|(path, report)| (path.to_string_lossy().into_owned(), report.into()))
+        .collect())
+}
+
+/// Parses an hour like "2024-08-01T00", a date, or an ISO 8601 timestamp,
+/// in UTC unless it has an offset.
+fn hour_from_input(value: &str) -> PyResult<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.to_utc());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    NaiveDateTime::parse_from_str(&format!("{value}:00"), "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .map(|time| time.and_utc())
+        .map_err(|_| {
+            PyValueError::new_err(format!(
+                "invalid hour '{value}', expected e.g. 2024-08-01T00"
+            ))
+        })
+}
+
+/// Downloads all 24 hourly files of a day, and writes the daily views of each
+/// page to a parquet file.
+///
+/// Views are summed per domain code and page title, with midnight (UTC) of
+/// the day as `timestamp`. The totals are kept in memory until the last hour
+/// has been read, so filter to a subset of the wikis.
+///
+/// Parameters:
+///     date (date): Day to sum the views of.
+///     output_path (str): Path to the parquet file. The file will be
+///         overwritten if it already exists.
+///     mirror (str | None): Base URL of the pageviews directory on a mirror.
+///         Defaults to Wikimedia's own servers.
+///     batch_size (int | None): How many rows to include in each batch written
+///         to the parquet file. By default, it is 122 880, which is the default
+///         size of a row group in a parquet file. Increase to lower run time at
+///         the cost of more memory, or decrease to lower memory requirements at
+///         the cost of execution speed. Default is fine for most use cases.
+#[doc = filter_params_doc!()]
+///     layout (str | None): Either "flat" (default) with all fields as top
+///         level columns, or "nested" with `language`, `domain`, and `mobile`
+///         in a `parsed_domain_code` struct column.
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "skip" (default) drops them, while "fail" stops at the first
+///         one and raises it.
+///     error_file (str | None): Write lines that fail to parse to this file,
+///         after the kind of error and a tab. The file will be overwritten if
+///         it already exists.
+///     top_k (int | None): Only write the top_k pages with most views of
+///         the day.
+///     top_k_per_language (bool | None): Keep the top_k rows of each language
+///         instead of overall.
+#[doc = parquet_params_doc!()]
+///     decode_titles (bool | None): Decode the page titles, e.g.
+///         `Caf%C3%A9_de_Flore` to `Café de Flore`, after filtering.
+///
+/// Returns:
+///     WriteReport: Rows written, filtered out, and skipped because they failed
+///         to parse, bytes read, and the time taken.
+///
+/// Raises:
+///     IOError: If one of the files can't be read.
+///     ValueError: If the mirror URL is invalid.
+///     OverflowError: If a page has more views than fit in the `views` column.
+///
+/// Example:
+///     >>> daily_parquet(date(2024, 8, 18), "2024-08-18.parquet", languages=["nb", "nn"])
+#[pyfunction]
+#[pyo3(name = "daily_parquet",
+       signature = (
+           date, output_path, mirror=None, batch_size=None, line_regex=None,
+           filter=None, domain_codes=None, page_title=None, min_views=None,
+           max_views=None, languages=None, domains=None, mobile=None,
+           layout=None, on_error=None, error_file=None, top_k=None,
+           top_k_per_language=None, columns=None, sort=None, statistics=None,
+           bloom_filters=None, encodings=None, decode_titles=None))]
+#[allow(clippy::too_many_arguments)]
+fn py_daily_parquet(
+    py: Python<'_>,
+    date: NaiveDate,
+    output_path: String,
+    mirror: Option<String>,
+    batch_size: Option<usize>,
+    line_regex: Option<String>,
+    filter: Option<PyFilter>,
+    domain_codes: Option<Vec<String>>,
+    page_title: Option<String>,
+    min_views: Option<u32>,
+    max_views: Option<u32>,
+    languages: Option<Vec<String>>,
+    domains: Option<Vec<String>>,
+    mobile: Option<bool>,
+    layout: Option<String>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    top_k: Option<usize>,
+    top_k_per_language: Option<bool>,
+    columns: Option<Vec<String>>,
+    sort: Option<String>,
+    statistics: Option<bool>,
+    bloom_filters: Option<bool>,
+    encodings: Option<HashMap<String, String>>,
+    decode_titles: Option<bool>,
+) -> PyResult<PyWriteReport> {
+    let sources: Vec<_> = mirror_from_input(mirror)?
+        .pageviews_urls_for_day(date)
+        .into_iter()
+        .map(Source::Url)
+        .collect();
+    let filter = filter_from_input(
+        line_regex,
+        filter,
+        domain_codes,
+        page_title,
+        min_views,
+        max_views,
+        languages,
+        domains,
+        mobile,
+    )?;
+    let cancel = CancellationToken::new();
+    let config = StreamConfig {
+        cancel: Some(cancel.clone()),
+        error_policy: error_policy_from_input(on_error, None)?,
+        decode_titles: decode_titles.unwrap_or(false),
+        ..Default::default()
+    };
+
+    let options = parquet_options_from_input(
+        batch_size,
+        layout,
+        error_file,
+        top_k,
+        top_k_per_language,
+        columns,
+        sort,
+        statistics,
+        bloom_filters,
+        encodings,
+    )?;
+
+    let report = detach_interruptible(py, &cancel, || {
+        daily_parquet_from_sources(
+            &sources,
+            date,
+            PathBuf::from(output_path),
+            &filter,
+            &config,
+            &options,
+        )
+    })?;
+
+    Ok(report.into())
+}
+
+/// Writes the filtered rows back to a gzip file in the pageviews dump format.
+///
+/// The output has the same columns and quoting as the Wikimedia dumps, so it
+/// can be read by this or any other tool reading the original files.
+///
+/// Parameters:
+///     input_path (str): Path to the pageviews file on the local file system.
+///     output_path (str): Path to the gzip file. The file will be overwritten
+///         if it already exists.
+#[doc = filter_params_doc!()]
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "skip" (default) drops them, while "fail" stops at the first
+///         one and raises it.
+///     error_file (str | None): Append lines that fail to parse to this
+///         file, after the kind of error and a tab.
+///     skip (int | None): Number of rows to skip before writing any.
+///     limit (int | None): Maximum number of rows to write. Reading stops
+///         once the last row is read, without downloading the rest.
+///     assume_sorted (bool | None): Stop reading once past the domain codes
+///         and languages the filters can match, assuming the file is sorted by
+///         domain code like the Wikimedia dumps.
+///
+/// Raises:
+///     IOError: If the file can't be read.
+///     ParseError: If parsing fails.
+///
+/// Example:
+///     >>> pageviews_gz_from_file("pageviews-20240818-080000.gz", "wikibooks/pageviews-20240818-080000.gz", domains=["wikibooks.org"])
+#[pyfunction]
+#[pyo3(name = "pageviews_gz_from_file",
+       signature = (
+           input_path, output_path, line_regex=None, filter=None,
+           domain_codes=None, page_title=None, min_views=None, max_views=None,
+           languages=None, domains=None, mobile=None, on_error=None,
+           error_file=None, skip=None, limit=None, assume_sorted=None))]
+#[allow(clippy::too_many_arguments)]
+fn py_pageviews_gz_from_file(
+    py: Python<'_>,
+    input_path: String,
+    output_path: String,
+    line_regex: Option<String>,
+    filter: Option<PyFilter>,
+    domain_codes: Option<Vec<String>>,
+    page_title: Option<String>,
+    min_views: Option<u32>,
+    max_views: Option<u32>,
+    languages: Option<Vec<String>>,
+    domains: Option<Vec<String>>,
+    mobile: Option<bool>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    skip: Option<usize>,
+    limit: Option<usize>,
+    assume_sorted: Option<bool>,
+) -> PyResult<()> {
+    let filter = filter_from_input(
+        line_regex,
+        filter,
+        domain_codes,
+        page_title,
+        min_views,
+        max_views,
+        languages,
+        domains,
+        mobile,
+    )?;
+    let cancel = CancellationToken::new();
+    let config = StreamConfig {
+        cancel: Some(cancel.clone()),
+        error_policy: error_policy_from_input(on_error, error_file)?,
+        skip: skip.unwrap_or(0),
+        limit,
+        assume_sorted: assume_sorted.unwrap_or(false),
+        ..Default::default()
+    };
+
+    detach_interruptible(py, &cancel, || {
+        pageviews_gz_from_source(
+            &Source::File(PathBuf::from(input_path)),
+            PathBuf::from(output_path),
+            &filter,
+            &config,
+        )
+    })
+}
+
+/// Writes the filtered rows back to a gzip file in the pageviews dump format.
+///
+/// The output has the same columns and quoting as the Wikimedia dumps, so it
+/// can be read by this or any other tool reading the original files.
+///
+/// Parameters:
+///     url (str): URL to a remote pageviews file.
+///     output_path (str): Path to the gzip file. The file will be overwritten
+///         if it already exists.
+#[doc = filter_params_doc!()]
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "skip" (default) drops them, while "fail" stops at the first
+///         one and raises it.
+///     error_file (str | None): Append lines that fail to parse to this
+///         file, after the kind of error and a tab.
+///     skip (int | None): Number of rows to skip before writing any.
+///     limit (int | None): Maximum number of rows to write. Reading stops
+///         once the last row is read, without downloading the rest.
+///     assume_sorted (bool | None): Stop reading once past the domain codes
+///         and languages the filters can match, assuming the file is sorted by
+///         domain code like the Wikimedia dumps.
+///
+/// Raises:
+///     IOError: If the file can't be read.
+///     ParseError: If parsing fails.
+///
+/// Example:
+///     >>> pageviews_gz_from_url("http://127.0.0.1/pageviews-20240818-080000.gz", "wikibooks/pageviews-20240818-080000.gz", domains=["wikibooks.org"])
+#[pyfunction]
+#[pyo3(name = "pageviews_gz_from_url",
+       signature = (
+           url, output_path, line_regex=None, filter=None, domain_codes=None,
+           page_title=None, min_views=None, max_views=None, languages=None,
+           domains=None, mobile=None, on_error=None, error_file=None,
+           skip=None, limit=None, assume_sorted=None))]
+#[allow(clippy::too_many_arguments)]
+fn py_pageviews_gz_from_url(
+    py: Python<'_>,
+    url: String,
+    output_path: String,
+    line_regex: Option<String>,
+    filter: Option<PyFilter>,
+    domain_codes: Option<Vec<String>>,
+    page_title: Option<String>,
+    min_views: Option<u32>,
+    max_views: Option<u32>,
+    languages: Option<Vec<String>>,
+    domains: Option<Vec<String>>,
+    mobile: Option<bool>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    skip: Option<usize>,
+    limit: Option<usize>,
+    assume_sorted: Option<bool>,
+) -> PyResult<()> {
+    let url = Url::parse(&url).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let filter = filter_from_input(
+        line_regex,
+        filter,
+        domain_codes,
+        page_title,
+        min_views,
+        max_views,
+        languages,
+        domains,
+        mobile,
+    )?;
+    let cancel = CancellationToken::new();
+    let config = StreamConfig {
+        cancel: Some(cancel.clone()),
+        error_policy: error_policy_from_input(on_error, error_file)?,
+        skip: skip.unwrap_or(0),
+        limit,
+        assume_sorted: assume_sorted.unwrap_or(false),
+        ..Default::default()
+    };
+
+    detach_interruptible(py, &cancel, || {
+        pageviews_gz_from_source(
+            &Source::Url(url),
+            PathBuf::from(output_path),
+            &filter,
+            &config,
+        )
+    })
+}
+
+/// Streams the rows of every hour since `start`, waiting for new hours to be
+/// published.
+///
+/// Catches up on the hours already published, then polls the server for the
+/// next one, blocking until it appears. Hours which are never published are
+/// skipped once a later hour shows up. Each row has the hour it was recorded
+/// in its `timestamp` field.
+///
+/// Parameters:
+///     start (datetime): First hour to stream, timezone aware. Rounded down
+///         to the start of its hour.
+///     until (datetime | None): Stop before this hour, instead of following
+///         the dumps forever.
+///     poll_interval (float): Seconds to wait between checks for a new hour,
+///         and after a failed check.
+///     mirror (str | None): Base URL of the pageviews directory on a mirror.
+///         Defaults to Wikimedia's own servers.
+#[doc = filter_params_doc!(", per file")]
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "skip" (default) drops them, while "fail" stops at the first
+///         one and raises it.
+///     error_file (str | None): Append lines that fail to parse to this
+///         file, after the kind of error and a tab.
+///     decode_titles (bool | None): Decode the page titles, e.g.
+///         `Caf%C3%A9_de_Flore` to `Café de Flore`, after filtering.
+///
+/// Returns:
+///     RowIterator: An iterator over parsed Pageviews, which only ends at
+///         `until`.
+///
+/// Raises:
+///     ValueError: If the mirror URL or the poll interval is invalid.
+///     IOError: If checking for a new hour or downloading it fails, when the
+///         row is read. Iterating again carries on with the next check.
+///     ParseError: If parsing fails.
+///
+/// Example:
+///     >>> start = datetime.now(timezone.utc) - timedelta(hours=6)
+///     >>> for row in follow(start, languages=["nb"]):
+///     ...     print(row.timestamp, row.page_title, row.views)
+#[pyfunction]
+#[pyo3(
+    name="follow",
+    signature = (
+        start, until=None, poll_interval=300.0, mirror=None, line_regex=None,
+        filter=None, domain_codes=None, page_title=None, min_views=None,
+        max_views=None, languages=None, domains=None, mobile=None,
+        on_error=None, error_file=None, decode_titles=None)
+)]
+#[allow(clippy::too_many_arguments)]
+fn py_follow(
+    start: DateTime<Utc>,
+    until: Option<DateTime<Utc>>,
+    poll_interval: f64,
+    mirror: Option<String>,
+    line_regex: Option<String>,
+    filter: Option<PyFilter>,
+    domain_codes: Option<Vec<String>>,
+    page_title: Option<String>,
+    min_views: Option<u32>,
+    max_views: Option<u32>,
+    languages: Option<Vec<String>>,
+    domains: Option<Vec<String>>,
+    mobile: Option<bool>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    decode_titles: Option<bool>,
+) -> PyResult<PyRowIterator> {
+    let filter = filter_from_input(
+        line_regex,
+        filter,
+        domain_codes,
+        page_title,
+        min_views,
+        max_views,
+        languages,
+        domains,
+        mobile,
+    )?;
+    let config = StreamConfig {
+        error_policy: error_policy_from_input(on_error, error_file)?,
+        decode_titles: decode_titles.unwrap_or(false),
+        ..Default::default()
+    };
+
+    let poll_interval = Duration::try_from_secs_f64(poll_interval)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let options = FollowOptions {
+        mirror: mirror_from_input(mirror)?,
+        poll_interval,
+        until,
+        config,
+    };
+
+    Ok(PyRowIterator {
+        error_policy: options.config.error_policy.clone(),
+        iterator: Mutex::new(follow(start, &filter, options)),
+        progress: None,
+    })
+}
+
+/// Sums views per domain code and page title across multiple files.
+///
+/// Files are streamed one at a time, and only the totals are kept in memory.
+///
+/// Parameters:
+///     paths (list[str] | None): Paths to pageviews files.
+///     urls (list[str] | None): URLs to pageviews files, read after `paths`.
+#[doc = filter_params_doc!(", per file")]
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "skip" (default) drops them, while "fail" stops at the first
+///         one and raises it.
+///     error_file (str | None): Append lines that fail to parse to this
+///         file, after the kind of error and a tab.
+///     decode_titles (bool | None): Decode the page titles, e.g.
+///         `Caf%C3%A9_de_Flore` to `Café de Flore`, after filtering.
+///
+/// Returns:
+///     dict[tuple[str, str], int]: Total views per (domain_code, page_title).
+///
+/// Raises:
+///     IOError: If one of the files can't be read.
+///     OverflowError: If the total of a page overflows a 64 bit integer.
+///
+/// Example:
+///     >>> sum_views(paths=["pageviews-20240818-080000.gz", "pageviews-20240818-090000.gz"])
+#[pyfunction]
+#[pyo3(
+    name="sum_views",
+    signature = (
+        paths=None, urls=None, line_regex=None, filter=None, domain_codes=None,
+        page_title=None, min_views=None, max_views=None, languages=None,
+        domains=None, mobile=None, on_error=None, error_file=None,
+        decode_titles=None)
+)]
+#[allow(clippy::too_many_arguments)]
+fn py_sum_views(
+    py: Python<'_>,
+    paths: Option<Vec<String>>,
+    urls: Option<Vec<String>>,
+    line_regex: Option<String>,
+    filter: Option<PyFilter>,
+    domain_codes: Option<Vec<String>>,
+    page_title: Option<String>,
+    min_views: Option<u32>,
+    max_views: Option<u32>,
+    languages: Option<Vec<String>>,
+    domains: Option<Vec<String>>,
+    mobile: Option<bool>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    decode_titles: Option<bool>,
+) -> PyResult<ViewTotals> {
+    let filter = filter_from_input(
+        line_regex,
+        filter,
+        domain_codes,
+        page_title,
+        min_views,
+        max_views,
+        languages,
+        domains,
+        mobile,
+    )?;
+    let cancel = CancellationToken::new();
+    let config = StreamConfig {
+        cancel: Some(cancel.clone()),
+        error_policy: error_policy_from_input(on_error, error_file)?,
+        decode_titles: decode_titles.unwrap_or(false),
+        ..Default::default()
+    };
+
+    let mut sources: Vec<Source> = paths
+        .unwrap_or_default()
+        .into_iter()
+        .map(|path| Source::File(PathBuf::from(path)))
+        .collect();
+    for url in urls.unwrap_or_default() {
+        let url = Url::parse(&url).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        sources.push(Source::Url(url));
+    }
+
+    detach_interruptible(py, &cancel, || sum_views(&sources, &filter, &config))
+}
+
+/// Counts rows and sums views per language and per domain across multiple
+/// files, in one pass.
+///
+/// A quick profile of the files, before deciding on the filters of a full
+/// export. Only the totals are kept in memory.
+///
+/// Parameters:
+///     paths (list[str] | None): Paths to pageviews files.
+///     urls (list[str] | None): URLs to pageviews files, read after `paths`.
+#[doc = filter_params_doc!(", per file")]
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "skip" (default) drops them, while "fail" stops at the first
+///         one and raises it.
+///     error_file (str | None): Append lines that fail to parse to this
+///         file, after the kind of error and a tab.
+///     decode_titles (bool | None): Decode the page titles, e.g.
+///         `Caf%C3%A9_de_Flore` to `Café de Flore`, after filtering.
+///
+/// Returns:
+///     Summary: Rows and views in total, per language, and per domain.
+///
+/// Raises:
+///     IOError: If one of the files can't be read.
+///     OverflowError: If the total views overflow a 64 bit integer.
+///
+/// Example:
+///     >>> summarize(paths=["pageviews-20240818-080000.gz"]).languages["en"]
+#[pyfunction]
+#[pyo3(
+    name="summarize",
+    signature = (
+        paths=None, urls=None, line_regex=None, filter=None, domain_codes=None,
+        page_title=None, min_views=None, max_views=None, languages=None,
+        domains=None, mobile=None, on_error=None, error_file=None,
+        decode_titles=None)
+)]
+#[allow(clippy::too_many_arguments)]
+fn py_summarize(
+    py: Python<'_>,
+    paths: Option<Vec<String>>,
+    urls: Option<Vec<String>>,
+    line_regex: Option<String>,
+    filter: Option<PyFilter>,
+    domain_codes: Option<Vec<String>>,
+    page_title: Option<String>,
+    min_views: Option<u32>,
+    max_views: Option<u32>,
+    languages: Option<Vec<String>>,
+    domains: Option<Vec<String>>,
+    mobile: Option<bool>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    decode_titles: Option<bool>,
+) -> PyResult<PySummary> {
+    let filter = filter_from_input(
+        line_regex,
+        filter,
+        domain_codes,
+        page_title,
+        min_views,
+        max_views,
+        languages,
+        domains,
+        mobile,
+    )?;
+    let cancel = CancellationToken::new();
+    let config = StreamConfig {
+        cancel: Some(cancel.clone()),
+        error_policy: error_policy_from_input(on_error, error_file)?,
+        decode_titles: decode_titles.unwrap_or(false),
+        ..Default::default()
+    };
+
+    let mut sources: Vec<Source> = paths
+        .unwrap_or_default()
+        .into_iter()
+        .map(|path| Source::File(PathBuf::from(path)))
+        .collect();
+    for url in urls.unwrap_or_default() {
+        let url = Url::parse(&url).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        sources.push(Source::Url(url));
+    }
+
+    detach_interruptible(py, &cancel, || summarize(&sources, &filter, &config)).map(Into::into)
+}
+
+/// Combines the views of rows grouped by a field, across multiple files.
+///
+/// Files are streamed one at a time, and only one value per group is kept in
+/// memory.
+///
+/// Parameters:
+///     paths (list[str] | None): Paths to pageviews files.
+///     urls (list[str] | None): URLs to pageviews files, read after `paths`.
+///     group_by (str): Field to group rows by, one of "language" (default),
+///         "domain", "domain_code", and "title".
+///     agg (str): How to combine the views of a group, one of "sum"
+///         (default), "count", "max", and "min".
+#[doc = filter_params_doc!(", per file")]
+///     on_error (str | None): What to do with lines that fail to
+///         parse: "skip" (default) drops them, while "fail" stops at the first
+///         one and raises it.
+///     error_file (str | None): Append lines that fail to parse to this
+///         file, after the kind of error and a tab.
+///     decode_titles (bool | None): Decode the page titles, e.g.
+///         `Caf%C3%A9_de_Flore` to `Café de Flore`, after filtering.
+///
+/// Returns:
+///     dict[str, int]: Value of each group, sorted by group.
+///
+/// Raises:
+///     IOError: If one of the files can't be read.
+///     ValueError: If `group_by` or `agg` is unknown.
+///     OverflowError: If the value of a group overflows a 64 bit integer.
+///
+/// Example:
+///     >>> aggregate_views(paths=["pageviews-20240818-080000.gz"], group_by="domain")
+#[pyfunction]
+#[pyo3(
+    name="aggregate_views",
+    signature = (
+        paths=None, urls=None, group_by="language", agg="sum", line_regex=None,
+        filter=None, domain_codes=None, page_title=None, min_views=None,
+        max_views=None, languages=None, domains=None, mobile=None,
+        on_error=None, error_file=None, decode_titles=None)
+)]
+#[allow(clippy::too_many_arguments)]
+fn py_aggregate_views(
+    py: Python<'_>,
+    paths: Option<Vec<String>>,
+    urls: Option<Vec<String>>,
+    group_by: &str,
+    agg: &str,
+    line_regex: Option<String>,
+    filter: Option<PyFilter>,
+    domain_codes: Option<Vec<String>>,
+    page_title: Option<String>,
+    min_views: Option<u32>,
+    max_views: Option<u32>,
+    languages: Option<Vec<String>>,
+    domains: Option<Vec<String>>,
+    mobile: Option<bool>,
+    on_error: Option<String>,
+    error_file: Option<String>,
+    decode_titles: Option<bool>,
+) -> PyResult<BTreeMap<String, u64>> {
+    let mut aggregation = Aggregation::new(
+        group_by.parse().map_err(PyValueError::new_err)?,
+        agg.parse().map_err(PyValueError::new_err)?,
+    );
+    let filter = filter_from_input(
+        line_regex,
+        filter,
+        domain_codes,
+        page_title,
+        min_views,
+        max_views,
+        languages,
+        domains,
+        mobile,
+    )?;
+    let cancel = CancellationToken::new();
+    let config = StreamConfig {
+        cancel: Some(cancel.clone()),
+        error_policy: error_policy_from_input(on_error, error_file)?,
+        decode_titles: decode_titles.unwrap_or(false),
+        ..Default::default()
+    };
+
+    let mut sources: Vec<Source> = paths
+        .unwrap_or_default()
+        .into_iter()
+        .map(|path| Source::File(PathBuf::from(path)))
+        .collect();
+    for url in urls.unwrap_or_default() {
+        let url = Url::parse(&url).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        sources.push(Source::Url(url));
+    }
+
+    detach_interruptible(py, &cancel, || -> Result<(), StreamError> {
+        for source in &sources {
+            for row in stream_from_source(source, &filter, &config)? {
+                match row {
+                    Ok(row) => aggregation.push(&row)?,
+                    Err(err) if config.error_policy.is_fatal(&err) => return Err(err.into()),
+                    Err(_) => continue,
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    Ok(aggregation.into_values())
+}
+
+/// Converts an optional python mirror base URL to a `Mirror`.
+fn mirror_from_input(mirror: Option<String>) -> PyResult<Mirror> {
+    mirror
+        .map(|base| Mirror::new(&base))
+        .transpose()
+        .map(Option::unwrap_or_default)
+        .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Builds the URL of the pageviews file for an hour of a day.
+///
+/// Args:
+///     date (date): Day of the file.
+///     hour (int): Hour of the day, 0-23 (UTC).
+///     mirror (str | None): Base URL of the pageviews directory on a mirror.
+///         Defaults to Wikimedia's own servers.
+///
+/// Returns:
+///     str: URL of the pageviews file.
+///
+/// Raises:
+///     ValueError: If the hour or the mirror URL is invalid.
+#[pyfunction]
+#[pyo3(name = "pageviews_url", signature = (date, hour, mirror=None))]
+fn py_pageviews_url(date: NaiveDate, hour: u32, mirror: Option<String>) -> PyResult<String> {
+    if hour >= 24 {
+        return Err(PyValueError::new_err("`hour` must be between 0 and 23"));
+    }
+    Ok(mirror_from_input(mirror)?
+        .pageviews_url(date, hour)
+        .to_string())
+}
+
+/// Builds the URLs of all 24 pageviews files of a day, in order.
+///
+/// Args:
+///     date (date): Day of the files.
+///     mirror (str | None): Base URL of the pageviews directory on a mirror.
+///         Defaults to Wikimedia's own servers.
+///
+/// Returns:
+///     list[str]: URLs of the pageviews files.
+///
+/// Raises:
+///     ValueError: If the mirror URL is invalid.
+#[pyfunction]
+#[pyo3(name = "pageviews_urls_for_day", signature = (date, mirror=None))]
+fn py_pageviews_urls_for_day(date: NaiveDate, mirror: Option<String>) -> PyResult<Vec<String>> {
+    Ok(mirror_from_input(mirror)?
+        .pageviews_urls_for_day(date)
+        .into_iter()
+        .map(String::from)
+        .collect())
+}
+
+/// Lists the hours of a month with a pageviews file on the server.
+///
+/// Reads the directory listing of the month with a single request, so a
+/// scheduler can find out if a new hour has been published.
+///
+/// Args:
+///     year (int): Year of the month.
+///     month (int): Month to list, 1-12.
+///     mirror (str | None): Base URL of the pageviews directory on a mirror.
+///         Defaults to Wikimedia's own servers.
+///
+/// Returns:
+///     list[datetime]: Timestamps of the available files, in order. Empty
+///         if the month has no directory yet.
+///
+/// Raises:
+///     ValueError: If the month or the mirror URL is invalid.
+///     IOError: If the listing can't be downloaded.
+///
+/// Examples:
+///     >>> hours = list_available_hours(2024, 8)
+///     >>> hours[-1]
+///     datetime.datetime(2024, 8, 31, 23, 0, tzinfo=datetime.timezone.utc)
+#[pyfunction]
+#[pyo3(name = "list_available_hours", signature = (year, month, mirror=None))]
+fn py_list_available_hours(
+    year: i32,
+    month: u32,
+    mirror: Option<String>,
+) -> PyResult<Vec<DateTime<Utc>>> {
+    if !(1..=12).contains(&month) {
+        return Err(PyValueError::new_err("`month` must be between 1 and 12"));
+    }
+    Ok(mirror_from_input(mirror)?.list_available_hours(year, month, &StreamConfig::default())?)
+}
+
+/// Fetches Wikimedia's sitematrix to resolve projects unknown to pvstream.
+///
+/// Projects using their own domain code format (e.g. `commons.m`) are hard
+/// coded. Call this once before streaming to resolve newer projects as well.
+/// If it fails, the hard coded list is still used.
+///
+/// Args:
+///     cache_path: File to save the sitematrix in, reused while it's newer
+///         than `max_age`. Downloaded every time if not set.
+///     max_age: Seconds a sitematrix saved in `cache_path` is reused.
+///
+/// Raises:
+///     IOError: If the sitematrix can't be downloaded.
+#[pyfunction]
+#[pyo3(name = "refresh_domains", signature = (cache_path=None, max_age=86400.0))]
+fn py_refresh_domains(cache_path: Option<String>, max_age: f64) -> PyResult<()> {
+    match cache_path {
+        Some(path) => {
+            let max_age = Duration::try_from_secs_f64(max_age)
+                .map_err(|e| PyValueError::new_err(e.to_string()))?;
+            Ok(refresh_domains_cached(Path::new(&path), max_age)?)
+        }
+        None => Ok(refresh_domains()?),
+    }
+}
+
+/// Writes a gzipped fake pageviews file, to test code reading the dumps.
+///
+/// The file has the format and edge cases of the real dumps, sorted lines
+/// in several languages and projects, quoted page titles, and lines that
+/// fail to parse, without downloading a real file. The same arguments always
+/// write the same file.
+///
+/// Args:
+///     path: File to write.
+///     rows: Number of valid rows with regular page titles.
+///     seed: Seed of the random generator.
+///     languages: Languages of the domain codes, each with a mix of
+///         projects. A realistic mix of languages and projects if not set.
+///     quoted_rows: Number of extra valid rows with quoted page titles.
+///     invalid_lines: Number of lines that fail to parse.
+///
+/// Raises:
+///     IOError: If the file can't be written.
+///
+/// Example:
+///     >>> write_fake_pageviews_gz("pageviews-20240101-000000.gz", rows=100, invalid_lines=2)
+///     >>> rows = list(stream_from_file("pageviews-20240101-000000.gz", on_error="skip"))
+///     >>> len(rows)
+///     100
+#[pyfunction]
+#[pyo3(
+    name = "write_fake_pageviews_gz",
+    signature = (path, rows=1000, seed=0, languages=None, quoted_rows=0, invalid_lines=0)
+)]
+fn py_write_fake_pageviews_gz(
+    path: String,
+    rows: usize,
+    seed: u64,
+    languages: Option<Vec<String>>,
+    quoted_rows: usize,
+    invalid_lines: usize,
+) -> PyResult<()> {
+    let spec = FakeDumpSpec {
+        rows,
+        seed,
+        languages,
+        quoted_rows,
+        invalid_lines,
+    };
+    Ok(write_fake_pageviews_gz(Path::new(&path), &spec)?)
 }
 
 #[pymodule]
 fn pvstream(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<PyPageviews>()?;
+    m.add_class::<PyFilter>()?;
+    m.add_class::<PyRowIterator>()?;
+    m.add_class::<PyDictIterator>()?;
+    m.add_class::<PyAsyncRowIterator>()?;
+    m.add_class::<PyPreview>()?;
+    m.add_class::<PyArrowStream>()?;
+    m.add_class::<PyWriteReport>()?;
+    m.add_class::<PySummary>()?;
     m.add_function(wrap_pyfunction!(py_stream_from_file, m)?)?;
     m.add_function(wrap_pyfunction!(py_stream_from_url, m)?)?;
+    m.add_function(wrap_pyfunction!(py_stream_from_url_async, m)?)?;
+    m.add_function(wrap_pyfunction!(py_dataframe_from_file, m)?)?;
+    m.add_function(wrap_pyfunction!(py_dataframe_from_url, m)?)?;
     m.add_function(wrap_pyfunction!(py_parquet_from_file, m)?)?;
     m.add_function(wrap_pyfunction!(py_parquet_from_url, m)?)?;
+    m.add_function(wrap_pyfunction!(py_parquet_dataset_from_urls, m)?)?;
+    m.add_function(wrap_pyfunction!(py_parquet_from_urls, m)?)?;
+    m.add_function(wrap_pyfunction!(py_parquet_from_range, m)?)?;
+    m.add_function(wrap_pyfunction!(py_daily_parquet, m)?)?;
+    m.add_function(wrap_pyfunction!(py_pageviews_gz_from_file, m)?)?;
+    m.add_function(wrap_pyfunction!(py_pageviews_gz_from_url, m)?)?;
+    m.add_function(wrap_pyfunction!(py_lines_from_file, m)?)?;
+    m.add_function(wrap_pyfunction!(py_lines_from_url, m)?)?;
+    m.add_function(wrap_pyfunction!(py_follow, m)?)?;
+    m.add_function(wrap_pyfunction!(py_sum_views, m)?)?;
+    m.add_function(wrap_pyfunction!(py_summarize, m)?)?;
+    m.add_function(wrap_pyfunction!(py_aggregate_views, m)?)?;
+    m.add_function(wrap_pyfunction!(py_pageviews_url, m)?)?;
+    m.add_function(wrap_pyfunction!(py_pageviews_urls_for_day, m)?)?;
+    m.add_function(wrap_pyfunction!(py_list_available_hours, m)?)?;
+    m.add_function(wrap_pyfunction!(py_refresh_domains, m)?)?;
+    m.add_function(wrap_pyfunction!(py_write_fake_pageviews_gz, m)?)?;
     Ok(())
 }
@@ -0,0 +1,100 @@
+//! Seeking in uncompressed files sorted by domain code.
+//!
+//! The dumps are sorted by domain code, so the lines of one domain code or
+//! language sit together. In an uncompressed file, the first of them can be
+//! found with a binary search over byte offsets, reading only a few lines
+//! instead of everything before them. Compressed files can't be searched this
+//! way, and are always read from the start.
+
+use std::io::{BufRead, BufReader, Error as IoError, Read, Seek, SeekFrom};
+
+/// Moves `reader` to the first line with a domain code of at least
+/// `domain_code`, and returns its offset.
+///
+/// The file must be sorted by domain code. If all domain codes are smaller,
+/// `reader` is moved to the end of the file.
+///
+/// # Example
+///
+/// ```
+/// use pvstream::seek::seek_domain_code;
+/// use std::io::Cursor;
+///
+/// let mut file = Cursor::new("da Forside 1 0\nde Hauptseite 2 0\nen Main_Page 3 0\n");
+/// assert_eq!(seek_domain_code(&mut file, "de")?, 15);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn seek_domain_code<R: Read + Seek>(reader: &mut R, domain_code: &str) -> Result<u64, IoError> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        match line_at(reader, mid)? {
+            Some((_, code)) if code.as_slice() < domain_code.as_bytes() => low = mid + 1,
+            _ => high = mid,
+        }
+    }
+
+    let offset = line_at(reader, low)?.map_or(len, |(offset, _)| offset);
+    reader.seek(SeekFrom::Start(offset))?;
+    Ok(offset)
+}
+
+/// Finds the first line starting at or after `offset`.
+///
+/// Returns the offset of the line and its domain code, or `None` if there
+/// are no more lines.
+fn line_at<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<Option<(u64, Vec<u8>)>, IoError> {
+    // Starts one byte early, to tell if `offset` is the start of a line
+    let start = offset.saturating_sub(1);
+    reader.seek(SeekFrom::Start(start))?;
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+
+    let mut line_start = start;
+    if offset > 0 {
+        line_start += reader.read_until(b'\n', &mut line)? as u64;
+        line.clear();
+    }
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    let code = line.split(|&byte| byte == b' ' || byte == b'\n').next();
+    Ok(Some((line_start, code.unwrap_or_default().to_vec())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    const LINES: &str = "aa A 1 0\nde B 1 0\nde C 1 0\nde.m D 1 0\nen E 1 0\n";
+
+    fn seek(domain_code: &str) -> (u64, String) {
+        let mut file = Cursor::new(LINES);
+        let offset = seek_domain_code(&mut file, domain_code).unwrap();
+        let mut rest = String::new();
+        file.read_to_string(&mut rest).unwrap();
+        (offset, rest)
+    }
+
+    #[test]
+    fn test_seek_domain_code() {
+        assert_eq!(seek(""), (0, LINES.to_string()));
+        assert_eq!(seek("aa").0, 0);
+        assert_eq!(seek("de").1, "de B 1 0\nde C 1 0\nde.m D 1 0\nen E 1 0\n");
+        assert_eq!(seek("de.m").1, "de.m D 1 0\nen E 1 0\n");
+        assert_eq!(seek("dz").1, "en E 1 0\n");
+        assert_eq!(seek("zz"), (LINES.len() as u64, String::new()));
+    }
+
+    #[test]
+    fn test_seek_without_trailing_newline() {
+        let mut file = Cursor::new("aa A 1 0\nde B 1 0");
+        assert_eq!(seek_domain_code(&mut file, "de").unwrap(), 9);
+        assert_eq!(seek_domain_code(&mut file, "en").unwrap(), 17);
+
+        let mut file = Cursor::new("");
+        assert_eq!(seek_domain_code(&mut file, "de").unwrap(), 0);
+    }
+}
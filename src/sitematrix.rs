@@ -0,0 +1,229 @@
+//! Runtime resolution of Wikimedia projects from the sitematrix API.
+//!
+//! The domain code parser has a hard coded list of Wikimedia projects using
+//! their own domain code format (e.g. `commons.m`). New projects resolve to
+//! `None` until the list is updated. Fetching the sitematrix at runtime adds
+//! all current projects, while the hard coded list remains as a fallback.
+
+use crate::stream::StreamError;
+use reqwest::blocking;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, RwLock};
+use std::time::Duration;
+
+/// Location of the sitematrix API listing all Wikimedia wikis.
+pub const SITEMATRIX_URL: &str =
+    "https://meta.wikimedia.org/w/api.php?action=sitematrix&smtype=special&format=json";
+
+/// Projects installed at runtime, consulted when the hard coded list misses.
+static PROJECTS: LazyLock<RwLock<HashMap<String, &'static str>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Lets the parser skip the lock entirely until a sitematrix is installed.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Wikimedia projects listed in the sitematrix.
+#[derive(Clone, Debug, Default)]
+pub struct SiteMatrix {
+    /// Map of project code to host name, e.g. "wikitech" -> "wikitech.wikimedia.org"
+    pub projects: HashMap<String, String>,
+}
+
+impl SiteMatrix {
+    /// Parses the JSON output of the sitematrix API.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let value: Value = serde_json::from_str(json)?;
+        let specials = value["sitematrix"]["specials"].as_array();
+
+        let projects = specials
+            .into_iter()
+            .flatten()
+            .filter_map(|site| {
+                let code = site["code"].as_str()?;
+                let url = site["url"].as_str()?;
+                let host = url.split_once("://").map_or(url, |(_, host)| host);
+                Some((code.to_string(), host.trim_end_matches('/').to_string()))
+            })
+            .collect();
+
+        Ok(Self { projects })
+    }
+
+    /// Reads a sitematrix saved from the API, e.g. with `curl`, to resolve
+    /// new projects without network access when streaming.
+    pub fn from_file(path: &Path) -> Result<Self, StreamError> {
+        Ok(Self::from_json(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Downloads and parses the current sitematrix.
+    pub fn fetch() -> Result<Self, StreamError> {
+        Ok(Self::from_json(&fetch_json()?)?)
+    }
+
+    /// Reads the sitematrix saved at `path` if it was saved within
+    /// `max_age`, or downloads it and saves it there otherwise.
+    ///
+    /// Useful to only ask Wikimedia for the sitematrix once a day, even if
+    /// the program runs every hour.
+    pub fn fetch_cached(path: &Path, max_age: Duration) -> Result<Self, StreamError> {
+        let fresh = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age < max_age));
+        if fresh && let Ok(matrix) = Self::from_file(path) {
+            return Ok(matrix);
+        }
+
+        let json = fetch_json()?;
+        let matrix = Self::from_json(&json)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, json)?;
+        Ok(matrix)
+    }
+
+    /// Makes the domain code parser resolve the projects in this sitematrix.
+    ///
+    /// Replaces any previously installed sitematrix. The host names are
+    /// leaked to get static lifetimes, which is fine as there are only a few
+    /// hundred of them, but this should not be called in a loop.
+    ///
+    /// Only wikis on wikimedia.org are installed, as the `commons.m` format
+    /// names the first part of a wikimedia.org host name. Others, like
+    /// `test.wikipedia.org`, are left to the language based domain codes.
+    pub fn install(self) {
+        let projects = self
+            .projects
+            .into_iter()
+            .filter(|(code, host)| host.strip_prefix(code.as_str()) == Some(".wikimedia.org"))
+            .map(|(code, host)| (code, &*Box::leak(host.into_boxed_str())))
+            .collect();
+        *PROJECTS.write().unwrap_or_else(|e| e.into_inner()) = projects;
+        INSTALLED.store(true, Ordering::Release);
+    }
+}
+
+/// Downloads the JSON output of the sitematrix API.
+fn fetch_json() -> Result<String, StreamError> {
+    Ok(blocking::Client::new()
+        .get(SITEMATRIX_URL)
+        .header(
+            "User-Agent",
+            concat!("pvstream/", env!("CARGO_PKG_VERSION")),
+        )
+        .send()?
+        .error_for_status()?
+        .text()?)
+}
+
+/// Fetches the sitematrix and installs it for the domain code parser.
+///
+/// If this fails, the hard coded list of projects is still used, so it's
+/// safe to ignore the error if you only want to make a best effort.
+pub fn refresh_domains() -> Result<(), StreamError> {
+    SiteMatrix::fetch()?.install();
+    Ok(())
+}
+
+/// Like `refresh_domains`, but reusing a copy of the sitematrix saved at
+/// `path` within the last `max_age`, see `SiteMatrix::fetch_cached`.
+pub fn refresh_domains_cached(path: &Path, max_age: Duration) -> Result<(), StreamError> {
+    SiteMatrix::fetch_cached(path, max_age)?.install();
+    Ok(())
+}
+
+/// Looks up a project code in the installed sitematrix.
+pub(crate) fn lookup_project(code: &str) -> Option<&'static str> {
+    if !INSTALLED.load(Ordering::Acquire) {
+        return None;
+    }
+    PROJECTS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(code)
+        .copied()
+}
+
+/// Finds a host name in the installed sitematrix, with a static lifetime.
+pub(crate) fn lookup_host(host: &str) -> Option<&'static str> {
+    PROJECTS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .values()
+        .find(|known| **known == host)
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    const SITEMATRIX_JSON: &str = r#"{
+        "sitematrix": {
+            "count": 2,
+            "0": {
+                "code": "aa",
+                "name": "Qafár af",
+                "site": [{"url": "https://aa.wikipedia.org", "dbname": "aawiki", "code": "wiki"}]
+            },
+            "specials": [
+                {"url": "https://wikitech.wikimedia.org", "dbname": "labswiki", "code": "wikitech"},
+                {"url": "https://commons.wikimedia.org", "dbname": "commonswiki", "code": "commons"},
+                {"url": "https://fixcopyright.wikimedia.org", "dbname": "fixcopyrightwiki", "code": "fixcopyright"},
+                {"url": "https://test.wikipedia.org", "dbname": "testwiki", "code": "test"}
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn test_from_json() {
+        let matrix = SiteMatrix::from_json(SITEMATRIX_JSON).unwrap();
+
+        assert_eq!(matrix.projects.len(), 4);
+        assert_eq!(matrix.projects["wikitech"], "wikitech.wikimedia.org");
+        assert_eq!(matrix.projects["commons"], "commons.wikimedia.org");
+    }
+
+    #[test]
+    fn test_from_invalid_json() {
+        assert!(SiteMatrix::from_json("{").is_err());
+        assert!(SiteMatrix::from_json("{}").unwrap().projects.is_empty());
+    }
+
+    #[test]
+    fn test_install() {
+        SiteMatrix::from_json(SITEMATRIX_JSON).unwrap().install();
+
+        assert_eq!(
+            lookup_project("fixcopyright"),
+            Some("fixcopyright.wikimedia.org")
+        );
+        assert_eq!(lookup_project("en"), None);
+        assert_eq!(lookup_project("test"), None);
+
+        let row = crate::parse::parse_line("fixcopyright.m Main_Page 1 0".into()).unwrap();
+        assert_eq!(
+            row.parsed_domain_code.domain,
+            Some("fixcopyright.wikimedia.org")
+        );
+        assert!(!row.parsed_domain_code.mobile);
+
+        let site = crate::sites::resolve_domain_code("fixcopyright.m.m").unwrap();
+        assert_eq!(site.hostname, "fixcopyright.m.wikimedia.org");
+        assert_eq!(site.project_family, crate::sites::Project::Wikimedia);
+    }
+
+    #[test]
+    fn test_fetch_cached() {
+        let dir = TestDir::new("fetch-cached");
+        let path = dir.join("sitematrix.json");
+        std::fs::write(&path, SITEMATRIX_JSON).unwrap();
+
+        let matrix = SiteMatrix::fetch_cached(&path, Duration::from_secs(3600)).unwrap();
+        assert_eq!(matrix.projects["commons"], "commons.wikimedia.org");
+    }
+}
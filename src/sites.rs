@@ -0,0 +1,403 @@
+//! Wikimedia sites the domain codes in the pageviews files refer to.
+//!
+//! Domain codes are a language followed by a suffix for the project, e.g.
+//! `de.b` for `de.wikibooks.org`, with an `m` in the middle for mobile sites.
+//! Some wikis without a language, like `commons.m` for Commons, are instead
+//! named by the first part of their host name. Both are listed in the tables
+//! below, and `resolve_domain_code` turns a domain code into a `SiteInfo`.
+//!
+//! Wikis created after this version of pvstream can be added at runtime from
+//! Wikimedia's sitematrix, see `sitematrix::refresh_domains`, or
+//! `SiteMatrix::from_file` for a copy saved earlier.
+
+use crate::sitematrix::lookup_project;
+use std::fmt;
+use std::str::FromStr;
+
+/// Family of Wikimedia projects a site belongs to, e.g. all Wiktionaries.
+///
+/// Named by the lower case variant name with `as_str` and `FromStr`, e.g.
+/// `wiktionary` or `mediawiki`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Project {
+    Wikipedia,
+    Wiktionary,
+    Wikibooks,
+    Wikinews,
+    Wikiquote,
+    Wikisource,
+    Wikiversity,
+    Wikivoyage,
+    Wikidata,
+    Wikifunctions,
+    /// www.mediawiki.org
+    MediaWiki,
+    /// commons.wikimedia.org
+    Commons,
+    /// meta.wikimedia.org
+    Meta,
+    /// incubator.wikimedia.org
+    Incubator,
+    /// species.wikimedia.org
+    Wikispecies,
+    /// wikitech.wikimedia.org, and the older labs wikis
+    Wikitech,
+    /// wikimediafoundation.org and foundation.wikimedia.org
+    Foundation,
+    /// Other wikis on wikimedia.org, e.g. chapter wikis and outreach
+    Wikimedia,
+}
+
+impl Project {
+    /// Every project family.
+    pub const ALL: [Project; 18] = [
+        Project::Wikipedia,
+        Project::Wiktionary,
+        Project::Wikibooks,
+        Project::Wikinews,
+        Project::Wikiquote,
+        Project::Wikisource,
+        Project::Wikiversity,
+        Project::Wikivoyage,
+        Project::Wikidata,
+        Project::Wikifunctions,
+        Project::MediaWiki,
+        Project::Commons,
+        Project::Meta,
+        Project::Incubator,
+        Project::Wikispecies,
+        Project::Wikitech,
+        Project::Foundation,
+        Project::Wikimedia,
+    ];
+
+    /// Name of the project family, e.g. "wiktionary".
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Project::Wikipedia => "wikipedia",
+            Project::Wiktionary => "wiktionary",
+            Project::Wikibooks => "wikibooks",
+            Project::Wikinews => "wikinews",
+            Project::Wikiquote => "wikiquote",
+            Project::Wikisource => "wikisource",
+            Project::Wikiversity => "wikiversity",
+            Project::Wikivoyage => "wikivoyage",
+            Project::Wikidata => "wikidata",
+            Project::Wikifunctions => "wikifunctions",
+            Project::MediaWiki => "mediawiki",
+            Project::Commons => "commons",
+            Project::Meta => "meta",
+            Project::Incubator => "incubator",
+            Project::Wikispecies => "wikispecies",
+            Project::Wikitech => "wikitech",
+            Project::Foundation => "foundation",
+            Project::Wikimedia => "wikimedia",
+        }
+    }
+}
+
+impl fmt::Display for Project {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Project {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Project::ALL
+            .into_iter()
+            .find(|project| project.as_str() == value)
+            .ok_or_else(|| {
+                format!("unknown project '{value}', expected e.g. wikipedia or wiktionary")
+            })
+    }
+}
+
+/// Every project suffix in use in the pageviews files, e.g. `b` in `de.b` or
+/// `de.m.b`, with the domain the language is prepended to and the name of the
+/// project family.
+static SUFFIXES: &[(&str, &str, Project)] = &[
+    ("b", "wikibooks.org", Project::Wikibooks),
+    ("d", "wiktionary.org", Project::Wiktionary),
+    ("f", "wikimediafoundation.org", Project::Foundation),
+    ("m", "wikimedia.org", Project::Wikimedia),
+    ("n", "wikinews.org", Project::Wikinews),
+    ("q", "wikiquote.org", Project::Wikiquote),
+    ("s", "wikisource.org", Project::Wikisource),
+    ("v", "wikiversity.org", Project::Wikiversity),
+    ("voy", "wikivoyage.org", Project::Wikivoyage),
+    ("w", "mediawiki.org", Project::MediaWiki),
+    ("wd", "wikidata.org", Project::Wikidata),
+];
+
+/// Wikis without a language, using their own domain code format, e.g.
+/// `commons.m` for the desktop site and `commons.m.m` for the mobile site.
+/// Listed with the host name of the desktop site and the project family.
+static SPECIAL_WIKIS: &[(&str, &str, Project)] = &[
+    ("commons", "commons.wikimedia.org", Project::Commons),
+    ("meta", "meta.wikimedia.org", Project::Meta),
+    ("incubator", "incubator.wikimedia.org", Project::Incubator),
+    ("species", "species.wikimedia.org", Project::Wikispecies),
+    ("wikitech", "wikitech.wikimedia.org", Project::Wikitech),
+    (
+        "foundation",
+        "foundation.wikimedia.org",
+        Project::Foundation,
+    ),
+    ("labs", "labs.wikimedia.org", Project::Wikitech),
+    (
+        "labsconsole",
+        "labsconsole.wikimedia.org",
+        Project::Wikitech,
+    ),
+    ("strategy", "strategy.wikimedia.org", Project::Wikimedia),
+    ("outreach", "outreach.wikimedia.org", Project::Wikimedia),
+    ("usability", "usability.wikimedia.org", Project::Wikimedia),
+    ("quality", "quality.wikimedia.org", Project::Wikimedia),
+    ("wikimania", "wikimania.wikimedia.org", Project::Wikimedia),
+    ("login", "login.wikimedia.org", Project::Wikimedia),
+    ("api", "api.wikimedia.org", Project::Wikimedia),
+    ("vote", "vote.wikimedia.org", Project::Wikimedia),
+    ("office", "office.wikimedia.org", Project::Wikimedia),
+    ("board", "board.wikimedia.org", Project::Wikimedia),
+    ("affcom", "affcom.wikimedia.org", Project::Wikimedia),
+    ("donate", "donate.wikimedia.org", Project::Wikimedia),
+    ("collab", "collab.wikimedia.org", Project::Wikimedia),
+];
+
+/// Host name of the site behind the quoted blank domain code.
+const WIKIFUNCTIONS: &str = "www.wikifunctions.org";
+
+/// Looks up the domain and family of a project suffix, e.g. `b` or `voy`.
+pub(crate) fn suffix(code: &str) -> Option<(&'static str, Project)> {
+    SUFFIXES
+        .iter()
+        .find(|(known, _, _)| *known == code)
+        .map(|(_, domain, family)| (*domain, *family))
+}
+
+/// Finds the project suffix of a domain, e.g. `b` for "wikibooks.org".
+pub(crate) fn suffix_code(domain: &str) -> Option<&'static str> {
+    SUFFIXES
+        .iter()
+        .find(|(_, known, _)| *known == domain)
+        .map(|(code, _, _)| *code)
+}
+
+/// Looks up the host name and family of a wiki without a language.
+///
+/// The hard coded list is checked first, falling back to any sitematrix
+/// installed at runtime.
+///
+/// Wikis only in the sitematrix are in the `Project::Wikimedia` family.
+pub(crate) fn special_wiki(code: &str) -> Option<(&'static str, Project)> {
+    SPECIAL_WIKIS
+        .iter()
+        .find(|(known, _, _)| *known == code)
+        .map(|(_, hostname, family)| (*hostname, *family))
+        .or_else(|| lookup_project(code).map(|hostname| (hostname, Project::Wikimedia)))
+}
+
+/// Every domain the parser can set in `DomainCode::domain` without a
+/// sitematrix installed at runtime.
+pub(crate) fn known_domains() -> impl Iterator<Item = &'static str> {
+    ["wikipedia.org", "wikifunctions.org"]
+        .into_iter()
+        .chain(SUFFIXES.iter().map(|(_, domain, _)| *domain))
+        .chain(SPECIAL_WIKIS.iter().map(|(_, hostname, _)| *hostname))
+}
+
+/// The site a domain code refers to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SiteInfo {
+    /// Full host name, e.g. "de.m.wikibooks.org"
+    pub hostname: String,
+    /// Project family, e.g. `Project::Wikibooks`
+    pub project_family: Project,
+    /// Language code, "en" for wikis without a language
+    pub language: String,
+    /// Whether this is a mobile site
+    pub mobile: bool,
+}
+
+/// Resolves a domain code from the pageviews files to the site it refers to.
+///
+/// Returns `None` for domain codes with an unknown project suffix.
+///
+/// ```
+/// use pvstream::sites::{Project, resolve_domain_code};
+///
+/// let site = resolve_domain_code("de.m.b").unwrap();
+/// assert_eq!(site.hostname, "de.m.wikibooks.org");
+/// assert_eq!(site.project_family, Project::Wikibooks);
+/// assert_eq!(site.language, "de");
+/// assert!(site.mobile);
+///
+/// assert_eq!(resolve_domain_code("commons.m").unwrap().hostname, "commons.wikimedia.org");
+/// assert_eq!(resolve_domain_code("en.x"), None);
+/// ```
+pub fn resolve_domain_code(domain_code: &str) -> Option<SiteInfo> {
+    let site = |hostname: String, project_family, language: &str, mobile| SiteInfo {
+        hostname,
+        project_family,
+        language: language.to_string(),
+        mobile,
+    };
+
+    // The parts have the same meaning as in `parse::parse_domain_code`.
+    let mut parts = domain_code.splitn(3, '.');
+    match (parts.next()?, parts.next(), parts.next()) {
+        ("", None, None) => Some(site(
+            WIKIFUNCTIONS.into(),
+            Project::Wikifunctions,
+            "en",
+            false,
+        )),
+        (language, None, None) => Some(site(
+            format!("{language}.wikipedia.org"),
+            Project::Wikipedia,
+            language,
+            false,
+        )),
+        (project, _, third) if special_wiki(project).is_some() => {
+            let (hostname, family) = special_wiki(project)?;
+            let hostname = match (third, hostname.split_once('.')) {
+                (Some(_), Some((name, domain))) => format!("{name}.m.{domain}"),
+                _ => hostname.to_string(),
+            };
+            Some(site(hostname, family, "en", third.is_some()))
+        }
+        (language, Some(marker @ ("m" | "zero")), None) => Some(site(
+            format!("{language}.{marker}.wikipedia.org"),
+            Project::Wikipedia,
+            language,
+            true,
+        )),
+        (language, Some(code), None) => {
+            let (domain, family) = suffix(code)?;
+            Some(site(
+                format!("{language}.{domain}"),
+                family,
+                language,
+                false,
+            ))
+        }
+        (language, Some(marker), Some(code)) => {
+            let (domain, family) = suffix(code)?;
+            Some(site(
+                format!("{language}.{marker}.{domain}"),
+                family,
+                language,
+                true,
+            ))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_domain_code() {
+        let cases = [
+            ("en", "en.wikipedia.org", Project::Wikipedia, "en", false),
+            (
+                "no.zero",
+                "no.zero.wikipedia.org",
+                Project::Wikipedia,
+                "no",
+                true,
+            ),
+            (
+                "fr.voy",
+                "fr.wikivoyage.org",
+                Project::Wikivoyage,
+                "fr",
+                false,
+            ),
+            (
+                "www.wd",
+                "www.wikidata.org",
+                Project::Wikidata,
+                "www",
+                false,
+            ),
+            (
+                "ja.m.d",
+                "ja.m.wiktionary.org",
+                Project::Wiktionary,
+                "ja",
+                true,
+            ),
+            (
+                "nl.f",
+                "nl.wikimediafoundation.org",
+                Project::Foundation,
+                "nl",
+                false,
+            ),
+            (
+                "wikitech.m",
+                "wikitech.wikimedia.org",
+                Project::Wikitech,
+                "en",
+                false,
+            ),
+            (
+                "species.m.m",
+                "species.m.wikimedia.org",
+                Project::Wikispecies,
+                "en",
+                true,
+            ),
+            (
+                "",
+                "www.wikifunctions.org",
+                Project::Wikifunctions,
+                "en",
+                false,
+            ),
+        ];
+        for (code, hostname, project_family, language, mobile) in cases {
+            assert_eq!(
+                resolve_domain_code(code),
+                Some(SiteInfo {
+                    hostname: hostname.to_string(),
+                    project_family,
+                    language: language.to_string(),
+                    mobile,
+                }),
+                "{code}"
+            );
+        }
+        assert_eq!(resolve_domain_code("en.m.x"), None);
+    }
+
+    #[test]
+    fn test_tables_are_unique() {
+        for (i, (code, _, _)) in SUFFIXES.iter().enumerate() {
+            assert!(SUFFIXES[i + 1..].iter().all(|other| other.0 != *code));
+        }
+        for (i, (code, _, _)) in SPECIAL_WIKIS.iter().enumerate() {
+            assert!(SPECIAL_WIKIS[i + 1..].iter().all(|other| other.0 != *code));
+            assert!(suffix(code).is_none());
+        }
+    }
+
+    #[test]
+    fn test_project_names() {
+        for project in Project::ALL {
+            assert_eq!(project.as_str().parse(), Ok(project));
+        }
+        assert!("wiki".parse::<Project>().is_err());
+    }
+}
@@ -0,0 +1,535 @@
+//! Sorting of rows before they are written, larger than memory if need be.
+//!
+//! Rows are collected up to `Sort::max_rows_in_memory` at a time. Each full
+//! batch is sorted and spilled to a temporary Arrow IPC file, and the files
+//! are merged back in order at the end, so a whole dump can be sorted with a
+//! bounded amount of memory. Inputs smaller than one batch never touch the
+//! disk. The temporary files are removed once the rows are read, or when the
+//! rows are dropped.
+//!
+//! Every field of the rows is spilled and read back as written, so rows
+//! which don't come straight from the parser, like residual rows, are
+//! returned unchanged whatever the memory limit.
+
+use crate::RowIterator;
+use crate::arrow_schema;
+use crate::parse::{DomainCode, Pageviews, ParseError, known_domain};
+use crate::store::arrow_chunks_from_structs;
+use crate::stream::ErrorPolicy;
+use arrow::array::{
+    Array, ArrayAccessor, ArrayRef, AsArray, RecordBatch, StringArray, UInt64Array,
+};
+use arrow::datatypes::{
+    DataType, Field, Int32Type, Schema, SchemaRef, TimestampMillisecondType, UInt32Type, UInt64Type,
+};
+use arrow::error::ArrowError;
+use arrow::ipc::reader::FileReader;
+use arrow::ipc::writer::FileWriter;
+use chrono::DateTime;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::{File, remove_file};
+use std::io::{BufReader, BufWriter, Error as IoError};
+use std::iter::once_with;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+/// Rows per record batch of the temporary files, read back one at a time.
+const SPILL_BATCH_SIZE: usize = 8192;
+
+/// Counter keeping the temporary files of concurrent sorts apart.
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Order of the rows, see `Sort`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortBy {
+    /// Most views first, like the ranking of `TopK`.
+    #[default]
+    ViewsDescending,
+    /// Page title, in byte order.
+    Title,
+}
+
+impl SortBy {
+    /// Compares two rows, with ties broken by domain code, page title, and
+    /// page ID, and then by language and mobile flag for residual rows,
+    /// which share the domain code and title `*`.
+    pub fn compare(self, a: &Pageviews, b: &Pageviews) -> Ordering {
+        let tie = || tie_key(a).cmp(&tie_key(b));
+        match self {
+            SortBy::ViewsDescending => b.views.cmp(&a.views).then_with(tie),
+            SortBy::Title => a.page_title.cmp(&b.page_title).then_with(tie),
+        }
+    }
+}
+
+/// Fields breaking ties between rows, see `SortBy::compare`.
+fn tie_key(row: &Pageviews) -> (&str, &str, Option<&str>, &str, bool) {
+    (
+        &row.domain_code,
+        &row.page_title,
+        row.page_id.as_deref(),
+        &row.parsed_domain_code.language,
+        row.parsed_domain_code.mobile,
+    )
+}
+
+/// Sorts the rows written by `parquet_from_source` and the other exports,
+/// see `ParquetOptions::sort`.
+///
+/// ```no_run
+/// use pvstream::sort::{Sort, SortBy};
+/// use pvstream::{ParquetOptions, filter::FilterBuilder, parquet_from_source};
+/// use pvstream::stream::{Source, StreamConfig};
+/// use std::path::PathBuf;
+///
+/// // Every page of the hour, most viewed first
+/// let options = ParquetOptions {
+///     sort: Some(Sort::new(SortBy::ViewsDescending)),
+///     ..Default::default()
+/// };
+/// parquet_from_source(
+///     &Source::File(PathBuf::from("pageviews-20240818-080000.gz")),
+///     PathBuf::from("sorted.parquet"),
+///     &FilterBuilder::new().build(),
+///     &StreamConfig::default(),
+///     &options,
+/// )?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sort {
+    /// Order of the rows.
+    pub by: SortBy,
+    /// Rows sorted in memory before they are spilled to a temporary file.
+    pub max_rows_in_memory: usize,
+    /// Directory of the temporary files, `std::env::temp_dir()` if `None`.
+    pub spill_dir: Option<PathBuf>,
+}
+
+impl Sort {
+    /// Rows kept in memory by default, a few hundred MB for typical rows.
+    pub const DEFAULT_MAX_ROWS_IN_MEMORY: usize = 1_000_000;
+
+    /// Sorts by `by`, with the default memory limit and temporary directory.
+    pub fn new(by: SortBy) -> Self {
+        Self {
+            by,
+            max_rows_in_memory: Self::DEFAULT_MAX_ROWS_IN_MEMORY,
+            spill_dir: None,
+        }
+    }
+
+    /// Sorts the rows.
+    ///
+    /// The rows are read in full before the first one is returned. Errors are
+    /// passed on ahead of the rows, and a read error, or a failure to write a
+    /// temporary file, ends the rows early, as the order would be incomplete.
+    pub(crate) fn apply(&self, rows: RowIterator) -> RowIterator {
+        let sort = self.clone();
+        Box::new(once_with(move || sort.sort(rows)).flatten())
+    }
+
+    fn sort(self, mut rows: RowIterator) -> RowIterator {
+        let max_rows = self.max_rows_in_memory.max(1);
+        let mut errors = Vec::new();
+        let mut buffer = Vec::new();
+        let mut spills = Vec::new();
+
+        for row in rows.by_ref() {
+            match row {
+                Ok(row) => buffer.push(row),
+                Err(err @ ParseError::ReadError(_)) => {
+                    errors.push(err);
+                    return Box::new(errors.into_iter().map(Err));
+                }
+                Err(err) => errors.push(err),
+            }
+            if buffer.len() >= max_rows {
+                match self.spill(&mut buffer) {
+                    Ok(spill) => spills.push(spill),
+                    Err(err) => {
+                        errors.push(ParseError::ReadError(err));
+                        return Box::new(errors.into_iter().map(Err));
+                    }
+                }
+            }
+        }
+
+        let by = self.by;
+        buffer.sort_unstable_by(|a, b| by.compare(a, b));
+        let errors = errors.into_iter().map(Err);
+        if spills.is_empty() {
+            return Box::new(errors.chain(buffer.into_iter().map(Ok)));
+        }
+
+        let mut runs: Vec<RowIterator> = Vec::with_capacity(spills.len() + 1);
+        for spill in &spills {
+            match read_spill(&spill.0) {
+                Ok(rows) => runs.push(Box::new(rows)),
+                Err(err) => {
+                    let err = ParseError::ReadError(err);
+                    return Box::new(errors.chain(once_with(|| Err(err))));
+                }
+            }
+        }
+        runs.push(Box::new(buffer.into_iter().map(Ok)));
+        Box::new(errors.chain(MergeRuns::new(runs, by, spills)))
+    }
+
+    /// Sorts the buffered rows and writes them to a temporary file.
+    fn spill(&self, buffer: &mut Vec<Pageviews>) -> Result<SpillFile, IoError> {
+        buffer.sort_unstable_by(|a, b| self.by.compare(a, b));
+        let dir = self.spill_dir.clone().unwrap_or_else(std::env::temp_dir);
+        let spill = SpillFile(dir.join(format!(
+            "pvstream-sort-{}-{}.arrow",
+            std::process::id(),
+            SPILLS.fetch_add(1, AtomicOrdering::Relaxed)
+        )));
+
+        let schema = spill_schema();
+        let file = BufWriter::new(File::create(&spill.0)?);
+        let mut writer = FileWriter::try_new(file, &schema).map_err(IoError::other)?;
+        let line_numbers: Vec<Option<u64>> = buffer.iter().map(|row| row.line_number).collect();
+        let rows = buffer.drain(..).map(Ok);
+        let chunks =
+            arrow_chunks_from_structs(rows, Some(SPILL_BATCH_SIZE), &ErrorPolicy::default());
+        let mut written = 0;
+        for chunk in chunks {
+            let chunk = chunk.map_err(IoError::other)?;
+            let numbers = &line_numbers[written..written + chunk.num_rows()];
+            written += chunk.num_rows();
+            let mut columns = chunk.columns().to_vec();
+            columns.push(Arc::new(UInt64Array::from(numbers.to_vec())) as ArrayRef);
+            let batch = RecordBatch::try_new(schema.clone(), columns).map_err(IoError::other)?;
+            writer.write(&batch).map_err(IoError::other)?;
+        }
+        writer.finish().map_err(IoError::other)?;
+        Ok(spill)
+    }
+}
+
+/// Schema of the temporary files, the columns of `arrow_schema` followed by
+/// the line number of each row.
+fn spill_schema() -> SchemaRef {
+    let mut fields = arrow_schema().fields.to_vec();
+    fields.push(Arc::new(Field::new("line_number", DataType::UInt64, true)));
+    Arc::new(Schema::new(fields))
+}
+
+/// Reads the rows of a temporary file back, one batch at a time.
+fn read_spill(
+    path: &Path,
+) -> Result<impl Iterator<Item = Result<Pageviews, ParseError>> + Send + use<>, IoError> {
+    let reader =
+        FileReader::try_new(BufReader::new(File::open(path)?), None).map_err(IoError::other)?;
+    Ok(reader.flat_map(
+        |batch| match batch.and_then(|batch| rows_from_spill(&batch)) {
+            Ok(rows) => rows.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(ParseError::ReadError(IoError::other(err)))],
+        },
+    ))
+}
+
+/// Converts a batch of a temporary file back to rows, with every field as it
+/// was written.
+fn rows_from_spill(batch: &RecordBatch) -> Result<Vec<Pageviews>, ArrowError> {
+    let column = |name: &str| {
+        batch
+            .column_by_name(name)
+            .ok_or_else(|| ArrowError::SchemaError(format!("missing column '{name}'")))
+    };
+    let dictionary = |name: &str| -> Result<_, ArrowError> {
+        column(name)?
+            .as_dictionary_opt::<Int32Type>()
+            .and_then(|array| array.downcast_dict::<StringArray>())
+            .ok_or_else(|| ArrowError::SchemaError(format!("unexpected type of '{name}'")))
+    };
+    let unexpected = |name: &str| ArrowError::SchemaError(format!("unexpected type of '{name}'"));
+
+    let domain_codes = dictionary("domain_code")?;
+    let page_titles = column("page_title")?
+        .as_string_opt::<i32>()
+        .ok_or_else(|| unexpected("page_title"))?;
+    let views = column("views")?
+        .as_primitive_opt::<UInt32Type>()
+        .ok_or_else(|| unexpected("views"))?;
+    let languages = dictionary("language")?;
+    let domains = dictionary("domain")?;
+    let mobile = column("mobile")?
+        .as_boolean_opt()
+        .ok_or_else(|| unexpected("mobile"))?;
+    let projects = dictionary("project")?;
+    let timestamps = column("timestamp")?
+        .as_primitive_opt::<TimestampMillisecondType>()
+        .ok_or_else(|| unexpected("timestamp"))?;
+    let response_sizes = column("response_size")?
+        .as_primitive_opt::<UInt64Type>()
+        .ok_or_else(|| unexpected("response_size"))?;
+    let hourly_views = column("hourly_views")?
+        .as_list_opt::<i32>()
+        .ok_or_else(|| unexpected("hourly_views"))?;
+    let page_ids = column("page_id")?
+        .as_string_opt::<i32>()
+        .ok_or_else(|| unexpected("page_id"))?;
+    let line_numbers = column("line_number")?
+        .as_primitive_opt::<UInt64Type>()
+        .ok_or_else(|| unexpected("line_number"))?;
+
+    (0..batch.num_rows())
+        .map(|i| {
+            let valid = |array: &dyn Array| array.is_valid(i);
+            let domain = valid(domains.keys())
+                .then(|| {
+                    known_domain(domains.value(i)).ok_or_else(|| {
+                        ArrowError::ParseError(format!("unknown domain '{}'", domains.value(i)))
+                    })
+                })
+                .transpose()?;
+            let project = valid(projects.keys())
+                .then(|| projects.value(i).parse().map_err(ArrowError::ParseError))
+                .transpose()?;
+            let hourly_views = valid(hourly_views)
+                .then(|| {
+                    let hours = hourly_views.value(i);
+                    let hours = hours.as_primitive_opt::<UInt32Type>();
+                    hours
+                        .map(|hours| hours.values().to_vec())
+                        .ok_or_else(|| unexpected("hourly_views"))
+                })
+                .transpose()?;
+            Ok(Pageviews {
+                domain_code: domain_codes.value(i).to_string(),
+                page_title: page_titles.value(i).to_string(),
+                views: views.value(i),
+                parsed_domain_code: DomainCode {
+                    language: languages.value(i).to_string(),
+                    domain,
+                    mobile: mobile.value(i),
+                    project,
+                },
+                timestamp: valid(timestamps)
+                    .then(|| DateTime::from_timestamp_millis(timestamps.value(i)))
+                    .flatten(),
+                response_size: valid(response_sizes).then(|| response_sizes.value(i)),
+                hourly_views,
+                page_id: valid(page_ids).then(|| page_ids.value(i).to_string()),
+                line_number: valid(line_numbers).then(|| line_numbers.value(i)),
+            })
+        })
+        .collect()
+}
+
+/// A temporary file of sorted rows, removed when dropped.
+struct SpillFile(PathBuf);
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.0);
+    }
+}
+
+/// The next row of a sorted run, ordered for a min-heap.
+struct Head {
+    row: Pageviews,
+    run: usize,
+    by: SortBy,
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed, as `BinaryHeap` pops the largest item first. Equal rows
+        // are taken from the earliest run first.
+        self.by
+            .compare(&other.row, &self.row)
+            .then_with(|| other.run.cmp(&self.run))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+/// Merges sorted runs into one sorted iterator.
+///
+/// An error from a run is returned after the rows before it and ends the
+/// merge. The temporary files are removed once the merge ends.
+struct MergeRuns {
+    runs: Vec<RowIterator>,
+    /// Next row of each run which isn't exhausted
+    heap: BinaryHeap<Head>,
+    by: SortBy,
+    /// Error ending the merge, returned on the next call
+    failed: Option<ParseError>,
+    spills: Vec<SpillFile>,
+}
+
+impl MergeRuns {
+    fn new(runs: Vec<RowIterator>, by: SortBy, spills: Vec<SpillFile>) -> Self {
+        let mut merge = Self {
+            heap: BinaryHeap::with_capacity(runs.len()),
+            runs,
+            by,
+            failed: None,
+            spills,
+        };
+        for run in 0..merge.runs.len() {
+            merge.pull(run);
+        }
+        merge
+    }
+
+    /// Reads the next row of a run into the heap.
+    fn pull(&mut self, run: usize) {
+        match self.runs[run].next() {
+            Some(Ok(row)) => self.heap.push(Head {
+                row,
+                run,
+                by: self.by,
+            }),
+            Some(Err(err)) => {
+                self.failed.get_or_insert(err);
+            }
+            None => {}
+        }
+    }
+}
+
+impl Iterator for MergeRuns {
+    type Item = Result<Pageviews, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.failed.take() {
+            self.heap.clear();
+            self.spills.clear();
+            return Some(Err(err));
+        }
+        let Some(head) = self.heap.pop() else {
+            self.spills.clear();
+            return None;
+        };
+        self.pull(head.run);
+        Some(Ok(head.row))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_line;
+    use crate::testing::TestDir;
+
+    fn rows(lines: &[&str]) -> RowIterator {
+        let rows: Vec<_> = lines
+            .iter()
+            .map(|line| parse_line(line.to_string()))
+            .collect();
+        Box::new(rows.into_iter())
+    }
+
+    fn titles(rows: RowIterator) -> Vec<String> {
+        rows.map(|row| row.unwrap().page_title).collect()
+    }
+
+    #[test]
+    fn test_sort() {
+        let lines = [
+            "en B 5 0", "de A 9 0", "en C 1 0", "en A 9 0", "fr D 7 0", "en E 3 0", "de F 5 0",
+        ];
+        let dir = TestDir::new("sort");
+
+        for max_rows_in_memory in [100, 2, 1] {
+            let sort = |by| Sort {
+                by,
+                max_rows_in_memory,
+                spill_dir: Some(dir.path().to_path_buf()),
+            };
+            let sorted: Vec<_> = sort(SortBy::ViewsDescending)
+                .apply(rows(&lines))
+                .map(|row| row.map(|r| (r.domain_code, r.page_title)).unwrap())
+                .collect();
+            let expected = [
+                ("de", "A"),
+                ("en", "A"),
+                ("fr", "D"),
+                ("de", "F"),
+                ("en", "B"),
+                ("en", "E"),
+                ("en", "C"),
+            ]
+            .map(|(code, title)| (code.to_string(), title.to_string()));
+            assert_eq!(sorted, expected);
+
+            let sorted = titles(sort(SortBy::Title).apply(rows(&lines)));
+            assert_eq!(sorted, ["A", "A", "B", "C", "D", "E", "F"]);
+        }
+
+        // The temporary files are gone once the rows are read
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_sort_spills_every_field() {
+        let lines = ["de.m.b A 1 A1", "en B 3 0", "fr C 2 0"];
+        let input: Vec<_> = lines
+            .iter()
+            .zip(1..)
+            .map(|(line, number)| {
+                let mut row = parse_line(line.to_string()).unwrap();
+                row.line_number = Some(number);
+                row.timestamp = "2024-08-18T08:00:00Z".parse().ok();
+                row.page_id = Some(format!("Q{number}"));
+                row
+            })
+            .collect();
+        let dir = TestDir::new("sort-fields");
+        let sort = Sort {
+            by: SortBy::Title,
+            max_rows_in_memory: 1,
+            spill_dir: Some(dir.path().to_path_buf()),
+        };
+
+        let sorted: Vec<_> = sort
+            .apply(Box::new(input.clone().into_iter().map(Ok)))
+            .map(Result::unwrap)
+            .collect();
+        let fields = |row: &Pageviews| format!("{row:?}");
+        assert_eq!(
+            sorted.iter().map(fields).collect::<Vec<_>>(),
+            input.iter().map(fields).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_sort_errors() {
+        let sort = Sort {
+            max_rows_in_memory: 2,
+            ..Sort::new(SortBy::ViewsDescending)
+        };
+        let sorted: Vec<_> = sort
+            .apply(rows(&["en A 1 0", "en", "en B 2 0", "en C 3 0"]))
+            .collect();
+        assert_eq!(sorted.len(), 4);
+        assert!(matches!(sorted[0], Err(ParseError::MissingField(..))));
+        assert_eq!(sorted[1].as_ref().unwrap().page_title, "C");
+        assert_eq!(sorted[3].as_ref().unwrap().page_title, "A");
+
+        let read_error: RowIterator = Box::new(rows(&["en A 1 0", "en B 2 0", "en C 3 0"]).chain(
+            once_with(|| Err(ParseError::ReadError(IoError::other("gone")))),
+        ));
+        let sorted: Vec<_> = sort.apply(read_error).collect();
+        assert_eq!(sorted.len(), 1);
+        assert!(matches!(sorted[0], Err(ParseError::ReadError(_))));
+    }
+}
@@ -1,29 +1,296 @@
-use crate::parse::{Pageviews, ParseError};
-use arrow2::array::TryPush;
-use arrow2::array::{
-    Array, MutableBooleanArray, MutableDictionaryArray, MutablePrimitiveArray, MutableUtf8Array,
+use crate::aggregate::TopK;
+#[cfg(feature = "cloud")]
+use crate::cloud::ObjectWriter;
+use crate::metrics::Metrics;
+use crate::parse::{ErrorSummary, Pageviews, ParseError, format_line, parse_domain_code};
+use crate::sort::Sort;
+#[cfg(not(feature = "cloud"))]
+use crate::stream::cloud_disabled;
+use crate::stream::{ErrorPolicy, object_store_url, write_error_line};
+use crate::trace;
+use arrow::array::{
+    Array, ArrayRef, AsArray, BooleanBuilder, ListBuilder, RecordBatch, StringBuilder,
+    StringDictionaryBuilder, StructArray, TimestampMillisecondBuilder, UInt32Builder,
+    UInt64Builder,
 };
-use arrow2::chunk::Chunk;
-use arrow2::datatypes::{DataType, Field, Schema};
-use arrow2::io::parquet::write::*;
+use arrow::compute::cast;
+use arrow::datatypes::{
+    DataType, Field, Fields, Int32Type, Schema, SchemaRef, TimeUnit, TimestampMillisecondType,
+    UInt32Type, UInt64Type,
+};
+use arrow::error::ArrowError;
+use chrono::{DateTime, SecondsFormat};
+use flate2::Compression as GzLevel;
+use flate2::write::GzEncoder;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::{ArrowSchemaConverter, ArrowWriter, ProjectionMask};
+use parquet::basic::Encoding;
+use parquet::errors::ParquetError;
+use parquet::file::properties::{EnabledStatistics, WriterProperties, WriterVersion};
+use parquet::schema::types::ColumnPath;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::path::Path;
+use std::io::{BufWriter, Error as IoError, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// Layout of the columns in a parquet file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SchemaLayout {
+    /// All fields as top level columns, as in the python bindings.
+    #[default]
+    Flat,
+    /// `language`, `domain`, `mobile`, and `project` in a `parsed_domain_code` struct
+    /// column, mirroring the `Pageviews` struct.
+    Nested,
+}
+
+/// Encoding of a column in a parquet file, see `ParquetOptions::encodings`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnEncoding {
+    /// The values one after another.
+    Plain,
+    /// A dictionary of the distinct values, with an index into it for each
+    /// row. Pays off for columns with few, repeated values, and falls back to
+    /// plain encoding within a row group if the dictionary grows too big.
+    Dictionary,
+}
+
+impl ColumnEncoding {
+    /// Name of the encoding.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColumnEncoding::Plain => "plain",
+            ColumnEncoding::Dictionary => "dictionary",
+        }
+    }
+}
+
+impl fmt::Display for ColumnEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ColumnEncoding {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "plain" => Ok(ColumnEncoding::Plain),
+            "dictionary" => Ok(ColumnEncoding::Dictionary),
+            _ => Err(format!(
+                "unknown encoding '{value}', expected plain or dictionary"
+            )),
+        }
+    }
+}
+
+/// Options for writing parquet files.
+#[derive(Clone, Debug, Default)]
+pub struct ParquetOptions {
+    /// Rows per row group, defaults to the parquet default of 122 880.
+    pub batch_size: Option<usize>,
+    /// Layout of the columns in the file.
+    pub layout: SchemaLayout,
+    /// Sidecar file listing the lines skipped because they failed to parse,
+    /// with the kind of error and the line separated by a tab. The file is
+    /// overwritten if it already exists.
+    pub error_file: Option<PathBuf>,
+    /// Only write the rows with most views, see `TopK`.
+    pub top_k: Option<TopK>,
+    /// Top level columns to write, named as in the chosen layout, all of them
+    /// if `None`. The columns keep the order of the full schema. Files
+    /// without `domain_code`, `page_title`, or `views` can't be read back
+    /// with `stream_from_parquet`.
+    pub columns: Option<Vec<String>>,
+    /// Sort the rows before writing them, see `Sort`. Applied after `top_k`.
+    pub sort: Option<Sort>,
+    /// Write min/max statistics and null counts of each column chunk and
+    /// page, letting query engines skip row groups and pages which can't
+    /// match a predicate. Off by default, as it slows down writing.
+    pub statistics: bool,
+    /// Write a bloom filter of the `page_title` and `language` values of each
+    /// row group, letting query engines skip row groups without a title or
+    /// language, which min/max statistics rarely can.
+    pub bloom_filters: bool,
+    /// Encoding of individual columns, overriding the defaults: dictionary
+    /// encoding for the top level `domain_code`, `language`, `domain`, and
+    /// `project` columns, and plain encoding for the rest. Columns are named
+    /// as in the flat layout, and the fields of `parsed_domain_code` by their
+    /// own names in the nested layout. Dictionary encoding `page_title` pays
+    /// off when the filters only match a small set of titles.
+    pub encodings: HashMap<String, ColumnEncoding>,
+}
+
+impl ParquetOptions {
+    /// Checks `columns` and `encodings` against the schema of the layout.
+    ///
+    /// Fails on an empty list of columns, and on names not in the schema.
+    pub fn validate(&self) -> Result<(), ArrowError> {
+        self.excluded_columns()?;
+        let flat = create_schema(SchemaLayout::Flat);
+        let mut names: Vec<_> = self.encodings.keys().collect();
+        names.sort();
+        if let Some(unknown) = names.iter().find(|name| flat.fields.find(name).is_none()) {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "unknown column '{unknown}' in encodings"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Encoding of the leaf column at `path`, given the defaults described
+    /// in `encodings`.
+    fn encoding(&self, path: &ColumnPath) -> ColumnEncoding {
+        let parts = path.parts();
+        let name = match parts {
+            [parent, child] if parent == "parsed_domain_code" => child,
+            parts => &parts[0],
+        };
+        let default = if parts.len() == 1 && DICTIONARY_COLUMNS.contains(&name.as_str()) {
+            ColumnEncoding::Dictionary
+        } else {
+            ColumnEncoding::Plain
+        };
+        self.encodings.get(name).copied().unwrap_or(default)
+    }
+
+    /// Top level columns left out of the file by `columns`.
+    ///
+    /// Fails on an empty list and on names not in the schema of the layout.
+    pub fn excluded_columns(&self) -> Result<Vec<String>, ArrowError> {
+        let Some(columns) = &self.columns else {
+            return Ok(Vec::new());
+        };
+        if columns.is_empty() {
+            return Err(ArrowError::InvalidArgumentError(
+                "at least one column must be written".to_string(),
+            ));
+        }
+        let schema = create_schema(self.layout);
+        if let Some(unknown) = columns
+            .iter()
+            .find(|name| schema.fields.find(name).is_none())
+        {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "unknown column '{unknown}' for the {:?} layout",
+                self.layout
+            )));
+        }
+        Ok(schema
+            .fields
+            .iter()
+            .map(|field| field.name().clone())
+            .filter(|name| !columns.contains(name))
+            .collect())
+    }
+}
+
+/// Summary of a parquet file written by `parquet_from_source`.
+///
+/// Meant for logging and alerting, e.g. on a file without any rows.
+#[derive(Debug, Default)]
+pub struct WriteReport {
+    /// Rows written to the file.
+    pub rows_written: u64,
+    /// Lines read which didn't end up as rows, because they didn't match the
+    /// filter, were rolled up, or were dropped by `ErrorPolicy::Skip`.
+    pub rows_filtered: u64,
+    /// Lines skipped because they failed to parse.
+    pub rows_errored: u64,
+    /// Rows matching the filters which were left out of the file, because
+    /// their record batch failed to convert to Arrow, e.g. when a dictionary
+    /// column overflows. Only counted when the batches are skipped, see
+    /// `ErrorPolicy::FailFast`.
+    pub rows_dropped: u64,
+    /// Compressed bytes read from the file or downloaded.
+    pub bytes_read: u64,
+    /// Time spent reading and writing the file.
+    pub elapsed: Duration,
+    /// The skipped lines by kind of error, see `rows_errored`.
+    pub errors: ErrorSummary,
+}
+
+/// Fields parsed from the domain code, used as a struct in the nested layout.
+fn domain_code_fields() -> Vec<Field> {
+    vec![
+        Field::new("language", DataType::Utf8, false),
+        Field::new("domain", DataType::Utf8, true),
+        Field::new("mobile", DataType::Boolean, false),
+        Field::new("project", DataType::Utf8, true),
+    ]
+}
+
+/// Type of the `timestamp` column, milliseconds since the epoch in UTC.
+fn timestamp_type() -> DataType {
+    DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+}
 
-/// Creates the arrow schema used for flattened structs.
+/// Type of the `hourly_views` column, a list of views per hour.
+fn hourly_views_type() -> DataType {
+    DataType::new_list(DataType::UInt32, true)
+}
+
+/// Columns after the fields parsed from the domain code, in both layouts.
+fn trailing_fields() -> Vec<Field> {
+    vec![
+        Field::new("timestamp", timestamp_type(), true),
+        Field::new("response_size", DataType::UInt64, true),
+        Field::new("hourly_views", hourly_views_type(), true),
+        Field::new("page_id", DataType::Utf8, true),
+    ]
+}
+
+/// Creates the arrow schema used for the parquet file.
 ///
-/// As in the python bindings, we flatten this to make it easier to work with.
-fn create_schema() -> Schema {
-    Schema::from(vec![
+/// As in the python bindings, the flat layout is the default to make it
+/// easier to work with. The `trailing_fields` are always last.
+fn create_schema(layout: SchemaLayout) -> Schema {
+    let mut fields = vec![
         Field::new("domain_code", DataType::Utf8, false),
         Field::new("page_title", DataType::Utf8, false),
         Field::new("views", DataType::UInt32, false),
-        Field::new("language", DataType::Utf8, false),
-        Field::new("domain", DataType::Utf8, true),
+    ];
+    match layout {
+        SchemaLayout::Flat => fields.extend(domain_code_fields()),
+        SchemaLayout::Nested => fields.push(Field::new(
+            "parsed_domain_code",
+            DataType::Struct(Fields::from(domain_code_fields())),
+            false,
+        )),
+    }
+    fields.extend(trailing_fields());
+    Schema::new(fields)
+}
+
+/// Arrow schema of the record batches from `arrow_chunks_from_structs`.
+///
+/// The string columns with few distinct values are dictionary encoded, the
+/// rest are plain arrays.
+pub fn arrow_schema() -> Schema {
+    let dictionary = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+    let mut fields = vec![
+        Field::new("domain_code", dictionary.clone(), false),
+        Field::new("page_title", DataType::Utf8, false),
+        Field::new("views", DataType::UInt32, false),
+        Field::new("language", dictionary.clone(), false),
+        Field::new("domain", dictionary.clone(), true),
         Field::new("mobile", DataType::Boolean, false),
-    ])
+        Field::new("project", dictionary, true),
+    ];
+    fields.extend(trailing_fields());
+    Schema::new(fields)
 }
 
+/// Rows per record batch and row group if no batch size is given, the
+/// parquet default.
+const DEFAULT_BATCH_SIZE: usize = 122_880;
+
 /// Batches parsed rows to output efficiently to the parquet file.
 ///
 /// Writing one row at a time is unuseably inefficient when working with
@@ -34,49 +301,92 @@ fn create_schema() -> Schema {
 struct ChunkIterator<I: Iterator<Item = Result<Pageviews, ParseError>>> {
     iter: I,
     batch_size: usize,
+    policy: ErrorPolicy,
+    schema: SchemaRef,
+}
+
+/// Appends a value to a dictionary, failing if its keys overflow.
+fn append_dictionary(
+    builder: &mut StringDictionaryBuilder<Int32Type>,
+    value: Option<&str>,
+) -> Result<(), ArrowError> {
+    match value {
+        Some(value) => builder.append(value).map(|_| ()),
+        None => {
+            builder.append_null();
+            Ok(())
+        }
+    }
 }
 
 impl<I: Iterator<Item = Result<Pageviews, ParseError>>> Iterator for ChunkIterator<I> {
-    type Item = Result<Chunk<Arc<dyn Array>>, arrow2::error::Error>;
+    type Item = Result<RecordBatch, ArrowError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut domain_code_builder: MutableDictionaryArray<i32, MutableUtf8Array<i32>> =
-            MutableDictionaryArray::new();
-        let mut page_title_builder = MutableUtf8Array::<i32>::new();
-        let mut views_builder = MutablePrimitiveArray::<u32>::new();
-        let mut language_builder: MutableDictionaryArray<i32, MutableUtf8Array<i32>> =
-            MutableDictionaryArray::new();
-        let mut domain_builder: MutableDictionaryArray<i32, MutableUtf8Array<i32>> =
-            MutableDictionaryArray::new();
-        let mut mobile_builder = MutableBooleanArray::new();
+        let mut domain_code_builder = StringDictionaryBuilder::<Int32Type>::new();
+        let mut page_title_builder = StringBuilder::new();
+        let mut views_builder = UInt32Builder::new();
+        let mut language_builder = StringDictionaryBuilder::<Int32Type>::new();
+        let mut domain_builder = StringDictionaryBuilder::<Int32Type>::new();
+        let mut mobile_builder = BooleanBuilder::new();
+        let mut project_builder = StringDictionaryBuilder::<Int32Type>::new();
+        let mut timestamp_builder = TimestampMillisecondBuilder::new().with_timezone("UTC");
+        let mut response_size_builder = UInt64Builder::new();
+        let mut hourly_views_builder = ListBuilder::new(UInt32Builder::new());
+        let mut page_id_builder = StringBuilder::new();
 
         let mut count = 0;
 
         while count < self.batch_size {
             match self.iter.next() {
                 Some(Ok(row)) => {
-                    if domain_code_builder
-                        .try_push(Some(&row.domain_code))
-                        .is_err()
-                        || language_builder
-                            .try_push(Some(&row.parsed_domain_code.language))
-                            .is_err()
-                        || domain_builder
-                            .try_push(row.parsed_domain_code.domain)
-                            .is_err()
-                    {
-                        // If `try_push` fails, the mutable builders are
+                    let appended =
+                        append_dictionary(&mut domain_code_builder, Some(&row.domain_code))
+                            .and_then(|_| {
+                                append_dictionary(
+                                    &mut language_builder,
+                                    Some(&row.parsed_domain_code.language),
+                                )
+                            })
+                            .and_then(|_| {
+                                append_dictionary(
+                                    &mut domain_builder,
+                                    row.parsed_domain_code.domain,
+                                )
+                            })
+                            .and_then(|_| {
+                                append_dictionary(
+                                    &mut project_builder,
+                                    row.parsed_domain_code.project.map(|p| p.as_str()),
+                                )
+                            });
+                    if let Err(err) = appended {
+                        // If `append` fails, the dictionary builders are
                         // potentially in a corrupted state, and we need
-                        // to abandon the entire Chunk.
-                        return None;
+                        // to abandon the entire batch. The next batch
+                        // starts with new builders.
+                        return Some(Err(err));
                     }
 
-                    page_title_builder.push(Some(&row.page_title));
-                    views_builder.push(Some(row.views));
-                    mobile_builder.push(Some(row.parsed_domain_code.mobile));
+                    page_title_builder.append_value(&row.page_title);
+                    views_builder.append_value(row.views);
+                    mobile_builder.append_value(row.parsed_domain_code.mobile);
+                    timestamp_builder.append_option(row.timestamp.map(|ts| ts.timestamp_millis()));
+                    response_size_builder.append_option(row.response_size);
+                    match &row.hourly_views {
+                        Some(hours) => {
+                            hourly_views_builder.values().append_slice(hours);
+                            hourly_views_builder.append(true);
+                        }
+                        None => hourly_views_builder.append_null(),
+                    }
+                    page_id_builder.append_option(row.page_id);
 
                     count += 1;
                 }
+                Some(Err(err)) if self.policy.is_fatal(&err) => {
+                    return Some(Err(ArrowError::ExternalError(Box::new(err))));
+                }
                 Some(Err(_)) => {
                     // Skip rows with parse errors
                     continue;
@@ -88,76 +398,649 @@ impl<I: Iterator<Item = Result<Pageviews, ParseError>>> Iterator for ChunkIterat
         if count == 0 {
             None
         } else {
-            Some(Ok(Chunk::new(vec![
-                domain_code_builder.into_arc(),
-                page_title_builder.into_arc(),
-                views_builder.into_arc(),
-                language_builder.into_arc(),
-                domain_builder.into_arc(),
-                mobile_builder.into_arc(),
-            ])))
+            let arrays: Vec<ArrayRef> = vec![
+                Arc::new(domain_code_builder.finish()),
+                Arc::new(page_title_builder.finish()),
+                Arc::new(views_builder.finish()),
+                Arc::new(language_builder.finish()),
+                Arc::new(domain_builder.finish()),
+                Arc::new(mobile_builder.finish()),
+                Arc::new(project_builder.finish()),
+                Arc::new(timestamp_builder.finish()),
+                Arc::new(response_size_builder.finish()),
+                Arc::new(hourly_views_builder.finish()),
+                Arc::new(page_id_builder.finish()),
+            ];
+            Some(RecordBatch::try_new(self.schema.clone(), arrays))
         }
     }
 }
 
-/// Converts the iterator of structs to an arrow chunk.
+/// Converts the iterator of structs to arrow record batches.
 ///
-/// By default, the function splits the row into chunks equaling the default
+/// By default, the function splits the row into batches equaling the default
 /// parquet row group size. This gives us a bigger memory overhead than if
 /// we split it into smaller groups, but the performance gain makes up for
 /// it. If you're in an extremely memory constrained environment, reduce the
 /// batch size.
-pub fn arrow_chunks_from_structs(
-    iterator: impl Iterator<Item = Result<Pageviews, ParseError>>,
+///
+/// Errors which are fatal under `policy` end the iterator with an error, the
+/// rest are skipped. A batch which fails to convert, e.g. when the keys of a
+/// dictionary column overflow, is returned as an error in its place, and the
+/// iterator goes on with the next batch.
+pub fn arrow_chunks_from_structs<I>(
+    iterator: I,
     batch_size: Option<usize>,
-) -> impl Iterator<Item = Result<Chunk<Arc<dyn Array>>, arrow2::error::Error>> {
-    // Default to parquet row group default size
-    let batch_size = batch_size.unwrap_or(122_880);
-
+    policy: &ErrorPolicy,
+) -> impl Iterator<Item = Result<RecordBatch, ArrowError>> + use<I>
+where
+    I: Iterator<Item = Result<Pageviews, ParseError>>,
+{
     ChunkIterator {
         iter: iterator,
-        batch_size,
+        batch_size: batch_size.unwrap_or(DEFAULT_BATCH_SIZE),
+        policy: policy.clone(),
+        schema: Arc::new(arrow_schema()),
     }
 }
 
-/// Writes an arrow chunk to a parquet file using an iterator.
+/// Whether a failed record batch from `arrow_chunks_from_structs` is left
+/// out of the file under `policy`, rather than stopping the writer.
 ///
-/// For each chunk provided by the input, the function will update a parquet
+/// Batches failing to convert are skipped like lines failing to parse,
+/// unless the policy is `ErrorPolicy::FailFast`. Fatal parse errors, passed
+/// on as external errors, always stop the writer.
+pub(crate) fn is_skipped_batch(policy: &ErrorPolicy, err: &ArrowError) -> bool {
+    !matches!(err, ArrowError::ExternalError(_)) && !matches!(policy, ErrorPolicy::FailFast)
+}
+
+/// Converts a dictionary array of strings to a plain string array, and
+/// returns other arrays as they are.
+///
+/// The parquet writer builds its own dictionaries, so the files are written
+/// with plain string columns, and read back as such.
+fn plain_array(array: &ArrayRef) -> Result<ArrayRef, ArrowError> {
+    match array.data_type() {
+        DataType::Dictionary(..) => cast(array, &DataType::Utf8),
+        _ => Ok(array.clone()),
+    }
+}
+
+/// Moves the fields parsed from the domain code into a struct array.
+fn nest_arrays(mut arrays: Vec<ArrayRef>) -> Result<Vec<ArrayRef>, ArrowError> {
+    let trailing = arrays.split_off(arrays.len() - trailing_fields().len());
+    let nested = arrays.split_off(3);
+
+    let children = nested.iter().map(plain_array).collect::<Result<_, _>>()?;
+    arrays.push(Arc::new(StructArray::try_new(
+        Fields::from(domain_code_fields()),
+        children,
+        None,
+    )?));
+    arrays.extend(trailing);
+
+    Ok(arrays)
+}
+
+/// Top level string columns with few, repeated values, dictionary encoded
+/// unless `ParquetOptions::encodings` says otherwise.
+const DICTIONARY_COLUMNS: [&str; 4] = ["domain_code", "language", "domain", "project"];
+
+/// Columns with bloom filters, see `ParquetOptions::bloom_filters`.
+const BLOOM_FILTER_COLUMNS: [&str; 2] = ["page_title", "language"];
+
+/// False positive rate of the bloom filters.
+const BLOOM_FILTER_FPP: f64 = 0.01;
+
+/// Upper bound of the distinct values a bloom filter is sized for, about
+/// 1 MiB per filter.
+const MAX_BLOOM_FILTER_NDV: usize = 1 << 20;
+
+/// Picks the encodings, statistics, and bloom filters of a file.
+///
+/// The encoding of each leaf column is given by `ParquetOptions::encoding`.
+/// Dictionaries fall back to plain encoding once they outgrow the dictionary
+/// page size limit, e.g. for a filter on a single page title across all
+/// wikis, where every domain code is unique.
+fn writer_properties(
+    schema: &Schema,
+    options: &ParquetOptions,
+) -> Result<WriterProperties, ParquetError> {
+    let statistics = if options.statistics {
+        EnabledStatistics::Page
+    } else {
+        EnabledStatistics::None
+    };
+    let ndv = options
+        .batch_size
+        .unwrap_or(DEFAULT_BATCH_SIZE)
+        .min(MAX_BLOOM_FILTER_NDV);
+    let mut builder = WriterProperties::builder()
+        .set_writer_version(WriterVersion::PARQUET_2_0)
+        .set_encoding(Encoding::PLAIN)
+        .set_dictionary_enabled(false)
+        .set_statistics_enabled(statistics)
+        .set_max_row_group_row_count(None);
+    for column in ArrowSchemaConverter::new().convert(schema)?.columns() {
+        let path = column.path();
+        let dictionary = options.encoding(path) == ColumnEncoding::Dictionary;
+        builder = builder.set_column_dictionary_enabled(path.clone(), dictionary);
+        if options.bloom_filters && BLOOM_FILTER_COLUMNS.contains(&column.name()) {
+            builder = builder
+                .set_column_bloom_filter_fpp(path.clone(), BLOOM_FILTER_FPP)
+                .set_column_bloom_filter_ndv(path.clone(), ndv as u64);
+        }
+    }
+    Ok(builder.build())
+}
+
+/// Errors skipped while writing a file, see `ParquetOptions::error_file`.
+pub(crate) struct SkippedErrors {
+    summary: ErrorSummary,
+    sidecar: Option<BufWriter<File>>,
+    write_error: Option<IoError>,
+}
+
+impl SkippedErrors {
+    /// Creates the sidecar file, if any.
+    pub(crate) fn new(sidecar: Option<&Path>) -> Result<Self, IoError> {
+        Ok(Self {
+            summary: ErrorSummary::default(),
+            sidecar: sidecar
+                .map(|path| File::create(path).map(BufWriter::new))
+                .transpose()?,
+            write_error: None,
+        })
+    }
+
+    /// Adds an error to the summary and the sidecar file.
+    ///
+    /// Write errors are kept until `finish`, so this can be called from
+    /// inside an iterator.
+    pub(crate) fn record(&mut self, err: ParseError) {
+        if let Some(sidecar) = &mut self.sidecar
+            && self.write_error.is_none()
+            && let Err(write_error) = write_error_line(sidecar, &err)
+        {
+            self.write_error = Some(write_error);
+        }
+        self.summary.record(err);
+    }
+
+    /// Returns the summary of the errors recorded so far, and starts a new
+    /// one, keeping the sidecar file open.
+    pub(crate) fn take_summary(&mut self) -> Result<ErrorSummary, IoError> {
+        if let Some(err) = self.write_error.take() {
+            return Err(err);
+        }
+        Ok(std::mem::take(&mut self.summary))
+    }
+
+    /// Flushes the sidecar file and returns the summary.
+    pub(crate) fn finish(self) -> Result<ErrorSummary, IoError> {
+        if let Some(err) = self.write_error {
+            return Err(err);
+        }
+        if let Some(mut sidecar) = self.sidecar {
+            sidecar.flush()?;
+        }
+        Ok(self.summary)
+    }
+}
+
+/// A parquet file being written, one record batch at a time.
+///
+/// Top level columns can be left out of the file, which is used to avoid
+/// repeating the values of the partition columns in a partitioned dataset,
+/// and for `ParquetOptions::columns`.
+pub(crate) struct ParquetFile {
+    writer: ArrowWriter<Output>,
+    layout: SchemaLayout,
+    columns: Vec<usize>,
+    schema: SchemaRef,
+    rows_written: u64,
+    span: trace::Span,
+    metrics: Option<Arc<dyn Metrics>>,
+}
+
+impl ParquetFile {
+    /// Creates the file with the layout and columns of `options`, overwriting
+    /// it if it already exists. The `excluded` columns are left out as well.
+    ///
+    /// `s3://` and `gs://` paths are uploaded to object storage with the
+    /// `cloud` feature.
+    pub(crate) fn create(
+        path: &Path,
+        options: &ParquetOptions,
+        excluded: &[&str],
+    ) -> Result<Self, ParquetError> {
+        options.validate()?;
+        let dropped = options.excluded_columns()?;
+        let excluded: Vec<&str> = excluded
+            .iter()
+            .copied()
+            .chain(dropped.iter().map(String::as_str))
+            .collect();
+        let schema = create_schema(options.layout);
+        let columns: Vec<_> = (0..schema.fields.len())
+            .filter(|&i| !excluded.contains(&schema.field(i).name().as_str()))
+            .collect();
+        let schema = Arc::new(schema.project(&columns)?);
+        let properties = writer_properties(&schema, options)?;
+        let span = trace::write_span(path, "parquet");
+        let output = span.in_scope(|| Output::create(path))?;
+        let writer = ArrowWriter::try_new(output, schema.clone(), Some(properties))?;
+
+        Ok(Self {
+            writer,
+            layout: options.layout,
+            columns,
+            schema,
+            rows_written: 0,
+            span,
+            metrics: None,
+        })
+    }
+
+    /// Counts the row groups written in `metrics`.
+    pub(crate) fn with_metrics(mut self, metrics: Option<&Arc<dyn Metrics>>) -> Self {
+        self.metrics = metrics.cloned();
+        self
+    }
+
+    /// Writes a record batch from `arrow_chunks_from_structs` as a row group.
+    pub(crate) fn write(&mut self, batch: RecordBatch) -> Result<(), ParquetError> {
+        let arrays = match self.layout {
+            SchemaLayout::Flat => batch.columns().to_vec(),
+            SchemaLayout::Nested => nest_arrays(batch.columns().to_vec())?,
+        };
+        let arrays = self
+            .columns
+            .iter()
+            .map(|&i| plain_array(&arrays[i]))
+            .collect::<Result<_, _>>()?;
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+        self.rows_written += batch.num_rows() as u64;
+        self.span.in_scope(|| {
+            self.writer.write(&batch)?;
+            self.writer.flush()
+        })?;
+        if let Some(metrics) = &self.metrics {
+            metrics.batches_written(1);
+        }
+        Ok(())
+    }
+
+    /// Bytes of the row groups written so far, without the footer.
+    pub(crate) fn bytes_written(&self) -> u64 {
+        self.writer.bytes_written() as u64
+    }
+
+    /// Writes the footer, which makes the file readable.
+    pub(crate) fn finish(self) -> Result<(), ParquetError> {
+        let span = self.span.clone();
+        span.in_scope(|| {
+            self.writer.into_inner()?.close()?;
+            trace::written(self.rows_written);
+            Ok(())
+        })
+    }
+}
+
+/// Destination of a parquet file, a local file or an object in a bucket.
+pub(crate) enum Output {
+    File(File),
+    #[cfg(feature = "cloud")]
+    Object(Box<ObjectWriter>),
+}
+
+impl Output {
+    /// Creates the file, or the object if `path` is an `s3://` or `gs://`
+    /// URL, overwriting it if it already exists.
+    pub(crate) fn create(path: &Path) -> Result<Self, IoError> {
+        match object_store_url(path) {
+            #[cfg(feature = "cloud")]
+            Some(url) => Ok(Output::Object(Box::new(ObjectWriter::create(&url)?))),
+            #[cfg(not(feature = "cloud"))]
+            Some(url) => Err(cloud_disabled(url.as_str())),
+            None => Ok(Output::File(File::create(path)?)),
+        }
+    }
+
+    /// Completes the upload of an object. Files are complete as written.
+    pub(crate) fn close(self) -> Result<(), IoError> {
+        match self {
+            Output::File(_) => Ok(()),
+            #[cfg(feature = "cloud")]
+            Output::Object(object) => (*object).close(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::File(file) => file.write(buf),
+            #[cfg(feature = "cloud")]
+            Output::Object(object) => object.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::File(file) => file.flush(),
+            #[cfg(feature = "cloud")]
+            Output::Object(object) => object.flush(),
+        }
+    }
+}
+
+/// Writes arrow record batches to a parquet file using an iterator.
+///
+/// For each batch provided by the input, the function will update a parquet
 /// file. The file will be overwritten if it already exists.
 ///
-/// RLE dictionaries are used for the string fields with few, repeated values,
-/// while plain fields are used for the rest.
-pub fn parquet_from_arrow<I>(path: &Path, chunks: I) -> arrow2::error::Result<()>
+/// Each batch becomes a row group. Dictionary encoded columns fall back to
+/// plain encoding within a row group if their cardinality is too high for a
+/// dictionary to pay off. The layout and columns are taken from `options`.
+/// The first failed batch stops the writer, and its error is returned.
+#[cfg(test)]
+pub fn parquet_from_arrow<I>(
+    path: &Path,
+    chunks: I,
+    options: &ParquetOptions,
+) -> Result<(), ParquetError>
 where
-    I: Iterator<Item = Result<Chunk<Arc<dyn Array>>, arrow2::error::Error>>,
+    I: Iterator<Item = Result<RecordBatch, ArrowError>>,
 {
-    let file = File::create(path)?;
-    let schema = create_schema();
-    let options = WriteOptions {
-        write_statistics: false,
-        compression: CompressionOptions::Uncompressed,
-        version: Version::V2,
-        data_pagesize_limit: None,
-    };
-    let encodings = vec![
-        vec![Encoding::RleDictionary], // domain_code
-        vec![Encoding::Plain],         // page_title
-        vec![Encoding::Plain],         // views
-        vec![Encoding::RleDictionary], // language
-        vec![Encoding::RleDictionary], // domain
-        vec![Encoding::Plain],         // mobile
-    ];
+    parquet_from_arrow_counted(path, chunks, options, &ErrorPolicy::FailFast, None)?;
+    Ok(())
+}
 
-    let row_groups = RowGroupIterator::try_new(chunks, &schema, options, encodings)?;
+/// `parquet_from_arrow`, skipping the failed batches allowed by `policy`,
+/// and counting the row groups written in `metrics`.
+///
+/// Returns the number of rows written.
+pub(crate) fn parquet_from_arrow_counted<I>(
+    path: &Path,
+    chunks: I,
+    options: &ParquetOptions,
+    policy: &ErrorPolicy,
+    metrics: Option<&Arc<dyn Metrics>>,
+) -> Result<u64, ParquetError>
+where
+    I: Iterator<Item = Result<RecordBatch, ArrowError>>,
+{
+    let mut file = ParquetFile::create(path, options, &[])?.with_metrics(metrics);
+    let mut rows_written = 0;
+    for chunk in chunks {
+        match chunk {
+            Ok(chunk) => {
+                rows_written += chunk.num_rows() as u64;
+                file.write(chunk)?;
+            }
+            Err(err) if is_skipped_batch(policy, &err) => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    file.finish()?;
+    Ok(rows_written)
+}
+
+/// Text format of the rows written by `write_to_writer`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Space separated lines, as in the dumps, see `parse::format_line`.
+    #[default]
+    Dump,
+    /// Tab separated values with the columns of the flat Parquet layout, and
+    /// no header. Missing values are written as `\N`, and backslashes, tabs,
+    /// and newlines are escaped with a backslash, as in the PostgreSQL text
+    /// format. `hourly_views` is written as an array literal, e.g. `{1,2}`.
+    Tsv,
+    /// One JSON object per line, with the fields of the flat Parquet layout.
+    Jsonl,
+}
 
-    let mut writer = FileWriter::try_new(file, schema, options)?;
+impl OutputFormat {
+    /// Formats a row as a line, without the line break.
+    pub fn format(self, row: &Pageviews) -> String {
+        match self {
+            OutputFormat::Dump => format_line(row),
+            OutputFormat::Tsv => format_tsv(row),
+            OutputFormat::Jsonl => format_json(row),
+        }
+    }
+}
 
-    for group in row_groups {
-        writer.write(group?)?;
+/// Formats a row as a line of `OutputFormat::Tsv`.
+fn format_tsv(row: &Pageviews) -> String {
+    fn escape(value: &str) -> String {
+        value
+            .replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
     }
-    writer.end(None)?;
+    let null = || "\\N".to_string();
+    let hourly_views = row.hourly_views.as_ref().map(|hours| {
+        let hours: Vec<_> = hours.iter().map(u32::to_string).collect();
+        format!("{{{}}}", hours.join(","))
+    });
+    [
+        escape(&row.domain_code),
+        escape(&row.page_title),
+        row.views.to_string(),
+        escape(&row.parsed_domain_code.language),
+        row.parsed_domain_code.domain.map_or_else(null, escape),
+        row.parsed_domain_code.mobile.to_string(),
+        row.parsed_domain_code
+            .project
+            .map_or_else(null, |project| project.to_string()),
+        row.timestamp.map_or_else(null, |timestamp| {
+            timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
+        }),
+        row.response_size.map_or_else(null, |size| size.to_string()),
+        hourly_views.unwrap_or_else(null),
+        row.page_id.as_deref().map_or_else(null, escape),
+    ]
+    .join("\t")
+}
 
-    Ok(())
+/// Formats a row as a line of `OutputFormat::Jsonl`.
+///
+/// Written by hand to keep the fields in the order of the Parquet columns.
+fn format_json(row: &Pageviews) -> String {
+    let string = |value: &str| Value::from(value).to_string();
+    let optional = |value: Option<Value>| value.unwrap_or(Value::Null).to_string();
+    let fields = [
+        ("domain_code", string(&row.domain_code)),
+        ("page_title", string(&row.page_title)),
+        ("views", row.views.to_string()),
+        ("language", string(&row.parsed_domain_code.language)),
+        (
+            "domain",
+            optional(row.parsed_domain_code.domain.map(Value::from)),
+        ),
+        ("mobile", row.parsed_domain_code.mobile.to_string()),
+        (
+            "project",
+            optional(
+                row.parsed_domain_code
+                    .project
+                    .map(|project| Value::from(project.as_str())),
+            ),
+        ),
+        (
+            "timestamp",
+            optional(row.timestamp.map(|timestamp| {
+                Value::from(timestamp.to_rfc3339_opts(SecondsFormat::Secs, true))
+            })),
+        ),
+        (
+            "response_size",
+            optional(row.response_size.map(Value::from)),
+        ),
+        (
+            "hourly_views",
+            optional(row.hourly_views.clone().map(Value::from)),
+        ),
+        ("page_id", optional(row.page_id.clone().map(Value::from))),
+    ];
+    let fields: Vec<_> = fields
+        .iter()
+        .map(|(name, value)| format!("\"{name}\":{value}"))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+/// Writes the rows to `writer`, one line per row, returning the number of
+/// rows written.
+///
+/// Rows failing to parse are skipped, unless fatal under `policy`. A broken
+/// pipe ends the write early without an error, as when piping into `head`.
+pub(crate) fn write_rows<W, I>(
+    writer: &mut W,
+    rows: I,
+    format: OutputFormat,
+    policy: &ErrorPolicy,
+) -> Result<u64, ParseError>
+where
+    W: Write,
+    I: Iterator<Item = Result<Pageviews, ParseError>>,
+{
+    let mut rows_written = 0;
+    let mut write = |row: &Pageviews| -> Result<(), IoError> {
+        writeln!(writer, "{}", format.format(row))?;
+        rows_written += 1;
+        Ok(())
+    };
+    for row in rows {
+        let row = match row {
+            Ok(row) => row,
+            Err(err) if policy.is_fatal(&err) => return Err(err),
+            Err(_) => continue,
+        };
+        match write(&row) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::BrokenPipe => return Ok(rows_written),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    match writer.flush() {
+        Err(err) if err.kind() != ErrorKind::BrokenPipe => Err(err.into()),
+        _ => Ok(rows_written),
+    }
+}
+
+/// Writes the rows to a gzip compressed file in the pageviews dump format.
+///
+/// Rows failing to parse are skipped, like when writing parquet files. Errors
+/// which are fatal under `policy` abort the write, e.g. read errors, as the
+/// rest of the input can't be trusted.
+pub fn pageviews_gz_from_structs<I>(
+    path: &Path,
+    rows: I,
+    policy: &ErrorPolicy,
+) -> Result<(), ParseError>
+where
+    I: Iterator<Item = Result<Pageviews, ParseError>>,
+{
+    trace::write_span(path, "gzip").in_scope(|| {
+        let file = BufWriter::new(File::create(path)?);
+        let mut writer = GzEncoder::new(file, GzLevel::default());
+        let rows_written = write_rows(&mut writer, rows, OutputFormat::Dump, policy)?;
+        writer.finish()?.flush()?;
+        trace::written(rows_written);
+        Ok(())
+    })
+}
+
+/// Converts a record batch read from a parquet file back to `Pageviews`.
+///
+/// Only the raw columns and the trailing fields are read, the rest is parsed
+/// from the domain code again, which works for both layouts. Files written
+/// before a trailing field was added are read with `None` in that field.
+fn pageviews_from_batch(batch: &RecordBatch) -> Vec<Result<Pageviews, ParseError>> {
+    let column = |name: &str| batch.column_by_name(name);
+    let (Some(domain_codes), Some(page_titles), Some(views)) = (
+        column("domain_code").and_then(|array| array.as_string_opt::<i32>()),
+        column("page_title").and_then(|array| array.as_string_opt::<i32>()),
+        column("views").and_then(|array| array.as_primitive_opt::<UInt32Type>()),
+    ) else {
+        let err = IoError::other("unexpected column types in parquet file");
+        return vec![Err(ParseError::ReadError(err))];
+    };
+    let timestamps =
+        column("timestamp").and_then(|array| array.as_primitive_opt::<TimestampMillisecondType>());
+    let response_sizes =
+        column("response_size").and_then(|array| array.as_primitive_opt::<UInt64Type>());
+    let hourly_views = column("hourly_views").and_then(|array| array.as_list_opt::<i32>());
+    let page_ids = column("page_id").and_then(|array| array.as_string_opt::<i32>());
+
+    (0..batch.num_rows())
+        .map(|i| {
+            let domain_code = domain_codes.value(i);
+            let timestamp = timestamps
+                .filter(|timestamps| timestamps.is_valid(i))
+                .and_then(|timestamps| DateTime::from_timestamp_millis(timestamps.value(i)));
+            let hourly_views = hourly_views
+                .filter(|lists| lists.is_valid(i))
+                .and_then(|lists| {
+                    let hours = lists.value(i);
+                    Some(hours.as_primitive_opt::<UInt32Type>()?.values().to_vec())
+                });
+            Ok(Pageviews {
+                domain_code: domain_code.to_string(),
+                page_title: page_titles.value(i).to_string(),
+                views: views.value(i),
+                parsed_domain_code: parse_domain_code(domain_code)?,
+                timestamp,
+                response_size: response_sizes
+                    .filter(|sizes| sizes.is_valid(i))
+                    .map(|sizes| sizes.value(i)),
+                hourly_views,
+                page_id: page_ids
+                    .filter(|ids| ids.is_valid(i))
+                    .map(|ids| ids.value(i).to_string()),
+                line_number: None,
+            })
+        })
+        .collect()
+}
+
+/// Reads `Pageviews` back from a parquet file written by this crate.
+///
+/// Works with both schema layouts. Rows are read one batch at a time.
+pub fn pageviews_from_parquet(
+    path: &Path,
+) -> Result<impl Iterator<Item = Result<Pageviews, ParseError>> + Send + use<>, ParquetError> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    let columns = builder
+        .schema()
+        .fields
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| {
+            matches!(
+                field.name().as_str(),
+                "domain_code"
+                    | "page_title"
+                    | "views"
+                    | "timestamp"
+                    | "response_size"
+                    | "hourly_views"
+                    | "page_id"
+            )
+        })
+        .map(|(i, _)| i);
+    let projection = ProjectionMask::roots(builder.parquet_schema(), columns);
+    let reader = builder.with_projection(projection).build()?;
+
+    Ok(reader.flat_map(|batch| match batch {
+        Ok(batch) => pageviews_from_batch(&batch),
+        Err(err) => vec![Err(ParseError::ReadError(IoError::other(err)))],
+    }))
 }
 
 #[cfg(test)]
@@ -165,7 +1048,12 @@ mod tests {
     use super::*;
     use crate::parse::DomainCode;
     use crate::parse::ParseError;
-    use arrow2::array::{BooleanArray, DictionaryArray, UInt32Array, Utf8Array};
+    use crate::sites::Project;
+    use crate::testing::TestDir;
+    use arrow::array::{DictionaryArray, StringArray};
+    use parquet::file::properties::ReaderProperties;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::file::serialized_reader::ReadOptionsBuilder;
 
     fn make_pageviews() -> Vec<Result<Pageviews, ParseError>> {
         let pv1 = Pageviews {
@@ -176,7 +1064,13 @@ mod tests {
                 language: "en".to_string(),
                 domain: Some("wikipedia.org"),
                 mobile: false,
+                project: Some(Project::Wikipedia),
             },
+            timestamp: None,
+            response_size: Some(0),
+            hourly_views: None,
+            page_id: None,
+            line_number: None,
         };
 
         let pv2 = Pageviews {
@@ -187,76 +1081,441 @@ mod tests {
                 language: "de".to_string(),
                 domain: Some("wikipedia.de"),
                 mobile: true,
+                project: Some(Project::Wikipedia),
             },
+            timestamp: None,
+            response_size: Some(0),
+            hourly_views: None,
+            page_id: None,
+            line_number: None,
         };
 
         vec![Ok(pv1), Ok(pv2)]
     }
 
-    fn dict_lookup(dict: &DictionaryArray<i32>, idx: usize) -> &str {
-        let key: usize = dict.keys().value(idx) as usize;
-        let values = dict
-            .values()
-            .as_any()
-            .downcast_ref::<Utf8Array<i32>>()
-            .unwrap();
-        values.value(key)
+    fn dict_lookup(array: &ArrayRef, idx: usize) -> &str {
+        let dict = array.as_dictionary::<Int32Type>();
+        let key = dict.keys().value(idx) as usize;
+        dict.values().as_string::<i32>().value(key)
+    }
+
+    fn read_metadata(path: &Path) -> parquet::file::metadata::ParquetMetaData {
+        let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+        reader.metadata().clone()
+    }
+
+    fn column_names(path: &Path) -> Vec<String> {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap();
+        let names = builder.schema().fields.iter().map(|f| f.name().clone());
+        names.collect()
     }
 
     #[test]
     fn test_arrow_from_structs() {
         let pageviews = make_pageviews().into_iter();
-        let chunk = arrow_chunks_from_structs(pageviews, None)
+        let batch = arrow_chunks_from_structs(pageviews, None, &ErrorPolicy::default())
             .next()
             .unwrap()
             .unwrap();
 
-        // Test array size (2 rows, 6 columns)
-        assert_eq!(chunk.arrays().len(), 6);
-        assert_eq!(chunk.len(), 2);
+        // Test array size (2 rows, 11 columns)
+        assert_eq!(batch.num_columns(), 11);
+        assert_eq!(batch.num_rows(), 2);
 
-        // Test values of first row
-        let domain_code_array = chunk.arrays()[0]
-            .as_any()
-            .downcast_ref::<DictionaryArray<i32>>()
-            .unwrap();
+        // The schema should describe the arrays
+        let schema = arrow_schema();
+        for (field, array) in schema.fields.iter().zip(batch.columns()) {
+            assert_eq!(field.data_type(), array.data_type(), "{}", field.name());
+        }
 
+        // Test values of first row
+        let domain_code_array = batch.column(0);
         assert_eq!(dict_lookup(domain_code_array, 0), "en");
         assert_eq!(dict_lookup(domain_code_array, 1), "de.m");
 
-        let page_title_array = chunk.arrays()[1]
-            .as_any()
-            .downcast_ref::<Utf8Array<i32>>()
-            .unwrap();
+        let page_title_array = batch.column(1).as_string::<i32>();
         assert_eq!(page_title_array.value(0), "Main_Page");
         assert_eq!(page_title_array.value(1), "Startseite");
 
-        let views_array = chunk.arrays()[2]
-            .as_any()
-            .downcast_ref::<UInt32Array>()
-            .unwrap();
+        let views_array = batch.column(2).as_primitive::<UInt32Type>();
         assert_eq!(views_array.value(0), 1000);
         assert_eq!(views_array.value(1), 500);
 
-        let language_array = chunk.arrays()[3]
-            .as_any()
-            .downcast_ref::<DictionaryArray<i32>>()
-            .unwrap();
+        let language_array = batch.column(3);
         assert_eq!(dict_lookup(language_array, 0), "en");
         assert_eq!(dict_lookup(language_array, 1), "de");
 
-        let domain_array = chunk.arrays()[4]
-            .as_any()
-            .downcast_ref::<DictionaryArray<i32>>()
-            .unwrap();
+        let domain_array = batch.column(4);
         assert_eq!(dict_lookup(domain_array, 0), "wikipedia.org");
         assert_eq!(dict_lookup(domain_array, 1), "wikipedia.de");
 
-        let mobile_array = chunk.arrays()[5]
-            .as_any()
-            .downcast_ref::<BooleanArray>()
-            .unwrap();
+        let mobile_array = batch.column(5).as_boolean();
         assert!(!mobile_array.value(0));
         assert!(mobile_array.value(1));
+
+        let project_array = batch.column(6);
+        assert_eq!(dict_lookup(project_array, 0), "wikipedia");
+        assert_eq!(dict_lookup(project_array, 1), "wikipedia");
+
+        let timestamp_array = batch.column(7);
+        assert_eq!(timestamp_array.data_type(), &timestamp_type());
+        assert!(timestamp_array.is_null(0));
+
+        let response_size_array = batch.column(8).as_primitive::<UInt64Type>();
+        assert_eq!(response_size_array.value(0), 0);
+        assert!(batch.column(9).is_null(1));
+        assert!(batch.column(10).is_null(0));
+    }
+
+    #[test]
+    fn test_dictionary_encoding() {
+        let dir = TestDir::new("dictionary-encoding");
+        let path = dir.join("pageviews.parquet");
+        let write = |options: &ParquetOptions| {
+            let pageviews = make_pageviews()
+                .into_iter()
+                .chain(make_pageviews())
+                .chain(make_pageviews());
+            parquet_from_arrow(
+                &path,
+                arrow_chunks_from_structs(pageviews, None, &ErrorPolicy::default()),
+                options,
+            )
+            .unwrap();
+            let metadata = read_metadata(&path);
+            let dictionary = |name: &str| {
+                let columns = metadata.row_group(0).columns();
+                let column = columns
+                    .iter()
+                    .find(|column| column.column_path().string() == name)
+                    .unwrap();
+                column.encodings().any(|e| e == Encoding::RLE_DICTIONARY)
+            };
+            ["domain_code", "page_title", "language"].map(dictionary)
+        };
+
+        assert_eq!(write(&ParquetOptions::default()), [true, false, true]);
+
+        // Read back as plain strings, not dictionaries
+        let domain_codes = pageviews_from_parquet(&path)
+            .unwrap()
+            .map(|row| row.unwrap().domain_code);
+        assert_eq!(domain_codes.collect::<Vec<_>>()[..2], ["en", "de.m"]);
+
+        let options = ParquetOptions {
+            encodings: HashMap::from([
+                ("domain_code".to_string(), ColumnEncoding::Plain),
+                ("page_title".to_string(), ColumnEncoding::Dictionary),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(write(&options), [false, true, true]);
+
+        // Fields of `parsed_domain_code` are plain unless asked for
+        let nested = |encodings: &[(&str, ColumnEncoding)]| ParquetOptions {
+            layout: SchemaLayout::Nested,
+            encodings: encodings
+                .iter()
+                .map(|(name, encoding)| (name.to_string(), *encoding))
+                .collect(),
+            ..Default::default()
+        };
+        let language = ColumnPath::new(vec!["parsed_domain_code".into(), "language".into()]);
+        assert_eq!(nested(&[]).encoding(&language), ColumnEncoding::Plain);
+        let options = nested(&[("language", ColumnEncoding::Dictionary)]);
+        assert_eq!(options.encoding(&language), ColumnEncoding::Dictionary);
+
+        assert!(options.validate().is_ok());
+        assert!(
+            nested(&[("title", ColumnEncoding::Plain)])
+                .validate()
+                .is_err()
+        );
+        assert_eq!("dictionary".parse(), Ok(ColumnEncoding::Dictionary));
+        assert!("rle".parse::<ColumnEncoding>().is_err());
+
+        // Dictionaries are converted before writing
+        let dictionary: DictionaryArray<Int32Type> = ["a", "b", "a"].into_iter().collect();
+        let plain = plain_array(&(Arc::new(dictionary) as ArrayRef)).unwrap();
+        assert_eq!(
+            plain.as_string::<i32>(),
+            &StringArray::from(vec!["a", "b", "a"])
+        );
+    }
+
+    #[test]
+    fn test_parquet_from_arrow() {
+        let dir = TestDir::new("parquet-from-arrow");
+        let path = dir.join("pageviews.parquet");
+        let pageviews = make_pageviews().into_iter();
+
+        parquet_from_arrow(
+            &path,
+            arrow_chunks_from_structs(pageviews, Some(1), &ErrorPolicy::default()),
+            &ParquetOptions::default(),
+        )
+        .unwrap();
+
+        let metadata = read_metadata(&path);
+        assert_eq!(metadata.file_metadata().num_rows(), 2);
+        assert_eq!(metadata.num_row_groups(), 2);
+
+        // Make sure the data can be read back
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let titles: Vec<String> = reader
+            .flat_map(|batch| {
+                let batch = batch.unwrap();
+                let titles = batch.column(1).as_string::<i32>();
+                titles
+                    .iter()
+                    .flatten()
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(titles, vec!["Main_Page", "Startseite"]);
+    }
+
+    #[test]
+    fn test_failed_batches() {
+        let dir = TestDir::new("failed-batches");
+        let path = dir.join("pageviews.parquet");
+        let chunks = || {
+            let mut chunks: Vec<_> = arrow_chunks_from_structs(
+                make_pageviews().into_iter(),
+                Some(1),
+                &ErrorPolicy::Skip,
+            )
+            .collect();
+            chunks.insert(1, Err(ArrowError::DictionaryKeyOverflowError));
+            chunks.into_iter()
+        };
+        let options = ParquetOptions::default();
+
+        let written =
+            parquet_from_arrow_counted(&path, chunks(), &options, &ErrorPolicy::Skip, None)
+                .unwrap();
+        assert_eq!(written, 2);
+        assert_eq!(read_metadata(&path).file_metadata().num_rows(), 2);
+
+        assert!(
+            parquet_from_arrow_counted(&path, chunks(), &options, &ErrorPolicy::FailFast, None)
+                .is_err()
+        );
+        assert!(parquet_from_arrow(&path, chunks(), &options).is_err());
+
+        let parse_error = ArrowError::ExternalError(Box::new(ParseError::MissingField(
+            "views",
+            "en Main_Page".to_string().into(),
+        )));
+        assert!(!is_skipped_batch(&ErrorPolicy::Skip, &parse_error));
+    }
+
+    #[test]
+    fn test_columns() {
+        let dir = TestDir::new("columns");
+        let path = dir.join("pageviews.parquet");
+        let columns = |names: &[&str]| ParquetOptions {
+            columns: Some(names.iter().map(|name| name.to_string()).collect()),
+            ..Default::default()
+        };
+
+        let options = columns(&["views", "page_title", "language"]);
+        assert_eq!(options.excluded_columns().unwrap().len(), 8);
+        parquet_from_arrow(
+            &path,
+            arrow_chunks_from_structs(make_pageviews().into_iter(), None, &ErrorPolicy::default()),
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(column_names(&path), vec!["page_title", "views", "language"]);
+
+        assert!(columns(&[]).excluded_columns().is_err());
+        assert!(columns(&["title"]).excluded_columns().is_err());
+        assert!(columns(&["language"]).excluded_columns().is_ok());
+        // Inside `parsed_domain_code` in the nested layout
+        let nested = ParquetOptions {
+            layout: SchemaLayout::Nested,
+            ..columns(&["language"])
+        };
+        assert!(nested.excluded_columns().is_err());
+    }
+
+    #[test]
+    fn test_statistics_and_bloom_filters() {
+        let dir = TestDir::new("bloom-filters");
+        let path = dir.join("pageviews.parquet");
+        for layout in [SchemaLayout::Flat, SchemaLayout::Nested] {
+            let options = ParquetOptions {
+                layout,
+                statistics: true,
+                bloom_filters: true,
+                ..Default::default()
+            };
+            parquet_from_arrow(
+                &path,
+                arrow_chunks_from_structs(
+                    make_pageviews().into_iter(),
+                    Some(1),
+                    &ErrorPolicy::Skip,
+                ),
+                &options,
+            )
+            .unwrap();
+
+            let properties = ReaderProperties::builder()
+                .set_read_bloom_filter(true)
+                .build();
+            let options = ReadOptionsBuilder::new()
+                .with_reader_properties(properties)
+                .with_page_index()
+                .build();
+            let reader =
+                SerializedFileReader::new_with_options(File::open(&path).unwrap(), options)
+                    .unwrap();
+            assert_eq!(reader.num_row_groups(), 2);
+            assert!(reader.metadata().column_index().is_some());
+            for (i, title) in ["Main_Page", "Startseite"].into_iter().enumerate() {
+                let row_group = reader.get_row_group(i).unwrap();
+                let columns = row_group.metadata().columns();
+                let column = |name: &str| {
+                    columns
+                        .iter()
+                        .position(|c| c.column_path().parts().last().unwrap() == name)
+                        .unwrap()
+                };
+                assert!(columns[column("views")].statistics().is_some());
+
+                let bloom_filter = |name: &str| row_group.get_column_bloom_filter(column(name));
+                let titles = bloom_filter("page_title").unwrap();
+                assert!(titles.check(title));
+                assert!(!titles.check("Missing_Page"));
+                assert!(bloom_filter("language").is_some());
+                assert!(bloom_filter("views").is_none());
+            }
+
+            // The file is still readable
+            let rows: Vec<_> = pageviews_from_parquet(&path).unwrap().collect();
+            assert_eq!(rows.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_output_formats() {
+        let mut row = crate::parse::parse_line("de.m.b Back\\slash 5 0".into()).unwrap();
+        row.timestamp = DateTime::from_timestamp(1_723_968_000, 0);
+        row.hourly_views = Some(vec![2, 3]);
+
+        assert_eq!(OutputFormat::Dump.format(&row), "de.m.b Back\\slash 5 A2B3");
+        assert_eq!(
+            OutputFormat::Tsv.format(&row),
+            "de.m.b\tBack\\\\slash\t5\tde\twikibooks.org\ttrue\twikibooks\t\
+             2024-08-18T08:00:00Z\t0\t{2,3}\t\\N"
+        );
+        assert_eq!(
+            OutputFormat::Jsonl.format(&row),
+            r#"{"domain_code":"de.m.b","page_title":"Back\\slash","views":5,"language":"de","#
+                .to_string()
+                + r#""domain":"wikibooks.org","mobile":true,"project":"wikibooks","timestamp":"2024-08-18T08:00:00Z","#
+                + r#""response_size":0,"hourly_views":[2,3],"page_id":null}"#
+        );
+
+        // Stops quietly when the reader goes away
+        struct Closed;
+        impl Write for Closed {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let rows = make_pageviews().into_iter();
+        let written = write_rows(&mut Closed, rows, OutputFormat::Tsv, &ErrorPolicy::Skip);
+        assert_eq!(written.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_fatal_errors() {
+        let rows = || {
+            let mut rows = make_pageviews();
+            rows.insert(1, Err(crate::parse::invalid("views", "en Main_Page x 0")));
+            rows.into_iter()
+        };
+
+        let chunks: Vec<_> =
+            arrow_chunks_from_structs(rows(), None, &ErrorPolicy::default()).collect();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].as_ref().unwrap().num_rows(), 2);
+
+        let chunks: Vec<_> =
+            arrow_chunks_from_structs(rows(), None, &ErrorPolicy::FailFast).collect();
+        assert!(chunks[0].is_err());
+
+        let dir = TestDir::new("fatal-errors");
+        let path = dir.join("pageviews.gz");
+        assert!(pageviews_gz_from_structs(&path, rows(), &ErrorPolicy::Skip).is_ok());
+        assert!(pageviews_gz_from_structs(&path, rows(), &ErrorPolicy::FailFast).is_err());
+    }
+
+    #[test]
+    fn test_nested_layout() {
+        let dir = TestDir::new("nested-layout");
+        let path = dir.join("pageviews.parquet");
+        let timestamp = DateTime::from_timestamp(1_723_968_000, 0);
+        let pageviews = ["en Main_Page 1000 0", "de.m.b Startseite 500 B2C498"].map(|line| {
+            crate::parse::parse_line(line.into()).map(|row| Pageviews {
+                timestamp,
+                page_id: (row.domain_code == "en").then(|| "Q5296".to_string()),
+                ..row
+            })
+        });
+
+        parquet_from_arrow(
+            &path,
+            arrow_chunks_from_structs(pageviews.into_iter(), None, &ErrorPolicy::default()),
+            &ParquetOptions {
+                layout: SchemaLayout::Nested,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            column_names(&path),
+            vec![
+                "domain_code",
+                "page_title",
+                "views",
+                "parsed_domain_code",
+                "timestamp",
+                "response_size",
+                "hourly_views",
+                "page_id"
+            ]
+        );
+
+        // Round trip back to `Pageviews`
+        let rows: Vec<_> = pageviews_from_parquet(&path)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].domain_code, "de.m.b");
+        assert_eq!(rows[1].page_title, "Startseite");
+        assert_eq!(rows[1].views, 500);
+        assert_eq!(rows[1].parsed_domain_code.domain, Some("wikibooks.org"));
+        assert!(rows[1].parsed_domain_code.mobile);
+        assert_eq!(rows[1].timestamp, timestamp);
+        assert_eq!(rows[0].response_size, Some(0));
+        assert_eq!(rows[1].response_size, None);
+        assert_eq!(rows[0].hourly_views, None);
+        assert_eq!(rows[1].hourly_views.as_ref().unwrap()[1..3], [2, 498]);
+        assert_eq!(rows[0].page_id.as_deref(), Some("Q5296"));
+        assert_eq!(rows[1].page_id, None);
     }
 }
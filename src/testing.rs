@@ -0,0 +1,425 @@
+//! Fake pageviews files for integration tests.
+//!
+//! Real dumps are large and change every hour, so tests depending on them
+//! are slow and brittle. `write_fake_pageviews_gz` writes a small gzipped
+//! file in the same format instead, with the edge cases of the real files:
+//! several languages and projects, quoted page titles, and
+//! lines that fail to parse. The same spec always writes the same file.
+//!
+//! `generate_test_dump` generates larger dumps as a string, with the domain
+//! codes in the proportions of a real dump. The benchmarks in `benches/` run
+//! on these, so parsing, filtering, and Parquet writing can be measured
+//! without downloading real files. The same seed always gives the same dump,
+//! on every platform, so results can be compared between runs and machines.
+
+use crate::parse::quote_string;
+use flate2::Compression as GzLevel;
+use flate2::write::GzEncoder;
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::fs::File;
+use std::io::{BufWriter, Error as IoError, Write};
+use std::path::Path;
+
+/// Domain codes, with their rough share of the rows in a real dump.
+const DOMAIN_CODES: &[(&str, u64)] = &[
+    ("commons.m", 3),
+    ("de", 6),
+    ("de.b", 1),
+    ("de.m", 6),
+    ("en", 20),
+    ("en.d", 2),
+    ("en.m", 25),
+    ("es.m", 5),
+    ("fr", 4),
+    ("fr.m", 4),
+    ("it.m", 3),
+    ("ja.m", 6),
+    ("ko", 1),
+    ("nn", 1),
+    ("nn.m", 1),
+    ("pt.m", 2),
+    ("ru.m", 4),
+    ("sv.m", 2),
+    ("www.wd", 2),
+    ("zh", 3),
+];
+
+/// Syllables the page titles are made of.
+const SYLLABLES: &[&str] = &[
+    "an", "be", "dor", "fe", "gra", "is", "ka", "lo", "mar", "ny", "on", "ren", "so", "ti", "ul",
+    "vel",
+];
+
+/// Titles with namespaces, escapes, and non-ASCII characters, as found in
+/// the dumps.
+const SPECIAL_TITLES: &[&str] = &[
+    "-",
+    "Caf%C3%A9_de_Flore",
+    "Main_Page",
+    "Special:Search",
+    "Talk:Main_Page",
+    "User:Example",
+    "Ελλάδα",
+    "東京都",
+];
+
+/// Share of the titles taken from `SPECIAL_TITLES`, in percent.
+const SPECIAL_SHARE: u64 = 2;
+
+/// Small deterministic random number generator, SplitMix64.
+///
+/// Good enough for test data, and stable across platforms and versions,
+/// unlike the generators of most crates.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// Picks an item with a probability proportional to its weight.
+    fn weighted<'a, T>(&mut self, items: &'a [(T, u64)]) -> &'a T {
+        let total = items.iter().map(|(_, weight)| weight).sum();
+        let mut pick = self.below(total);
+        for (item, weight) in items {
+            if pick < *weight {
+                return item;
+            }
+            pick -= weight;
+        }
+        unreachable!("pick is below the total weight")
+    }
+
+    /// Number of views with a long tail, mostly 1 like in the dumps.
+    fn views(&mut self) -> u64 {
+        let scale = self.next_u64().trailing_zeros().min(20);
+        1 + self.below(1 << scale)
+    }
+
+    /// A page title made of random syllables, or one of `SPECIAL_TITLES`.
+    fn title(&mut self) -> String {
+        if self.below(100) < SPECIAL_SHARE {
+            let index = self.below(SPECIAL_TITLES.len() as u64) as usize;
+            return SPECIAL_TITLES[index].to_string();
+        }
+        let words = 1 + self.below(3);
+        let mut title = String::new();
+        for word in 0..words {
+            if word > 0 {
+                title.push('_');
+            }
+            for position in 0..1 + self.below(4) {
+                let index = self.below(SYLLABLES.len() as u64) as usize;
+                let syllable = SYLLABLES[index];
+                if word == 0 && position == 0 {
+                    let mut chars = syllable.chars();
+                    title.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+                    title.push_str(chars.as_str());
+                } else {
+                    title.push_str(syllable);
+                }
+            }
+        }
+        title
+    }
+}
+
+/// Generates a dump of `rows` lines in the format of the pageviews files.
+///
+/// The lines are sorted by domain code and page title like the real dumps,
+/// with the domain codes in roughly the same proportions, and views mostly
+/// 1 with a long tail. A few titles have namespaces, percent-encoded
+/// characters, or non-ASCII characters. Write it to a file, compressed or
+/// not, to read it like a downloaded dump.
+///
+/// ```
+/// use pvstream::testing::generate_test_dump;
+/// use pvstream::parse::parse_line;
+///
+/// let dump = generate_test_dump(1000, 42);
+/// assert_eq!(dump.lines().count(), 1000);
+/// assert_eq!(dump, generate_test_dump(1000, 42));
+/// assert!(dump.lines().all(|line| parse_line(line.to_string()).is_ok()));
+/// ```
+pub fn generate_test_dump(rows: usize, seed: u64) -> String {
+    let mut rng = SplitMix64::new(seed);
+    let pages = generate_pages(&mut rng, rows, |rng| rng.weighted(DOMAIN_CODES).to_string());
+
+    let mut dump = String::new();
+    for ((domain_code, title), views) in pages {
+        dump.push_str(&format!("{domain_code} {title} {views} 0\n"));
+    }
+    dump
+}
+
+/// Generates `rows` distinct pages with their views, sorted by domain code
+/// and page title, with domain codes from `domain_code`.
+fn generate_pages(
+    rng: &mut SplitMix64,
+    rows: usize,
+    mut domain_code: impl FnMut(&mut SplitMix64) -> String,
+) -> BTreeMap<(String, String), u64> {
+    let mut pages = BTreeMap::new();
+    while pages.len() < rows {
+        let domain_code = domain_code(rng);
+        let views = rng.views();
+        *pages.entry((domain_code, rng.title())).or_insert(0) += views;
+    }
+    pages
+}
+
+/// Project suffixes added to the languages of a spec, with their rough
+/// share of the rows in a real dump.
+const SUFFIXES: &[(&str, u64)] = &[("", 8), (".m", 10), (".d", 1), (".b", 1)];
+
+/// Titles written quoted in the dumps, empty or containing quotes.
+const QUOTED_TITLES: &[&str] = &[
+    "",
+    "\"",
+    "\"Hello,_World!\"",
+    "Say_\"cheese\"",
+    "\"Weird_Al\"_Yankovic",
+];
+
+/// Contents of a fake pageviews file.
+#[derive(Clone, Debug)]
+pub struct FakeDumpSpec {
+    /// Number of valid rows with regular page titles.
+    pub rows: usize,
+    /// Seed of the random generator. The same spec always gives the same file.
+    pub seed: u64,
+    /// Languages of the domain codes, each with a mix of projects, such as
+    /// `de`, `de.m`, and `de.d` for `de`. A realistic mix of languages and
+    /// projects if `None`.
+    pub languages: Option<Vec<String>>,
+    /// Number of extra valid rows with quoted page titles, empty or
+    /// containing `"`.
+    pub quoted_rows: usize,
+    /// Number of lines that fail to parse, such as lines with missing
+    /// columns or non-numeric views, at random positions in the file.
+    pub invalid_lines: usize,
+}
+
+impl Default for FakeDumpSpec {
+    fn default() -> Self {
+        Self {
+            rows: 1000,
+            seed: 0,
+            languages: None,
+            quoted_rows: 0,
+            invalid_lines: 0,
+        }
+    }
+}
+
+/// Writes a gzipped fake pageviews file to `path`, following `spec`.
+///
+/// The file has `spec.rows + spec.quoted_rows` valid lines, sorted by domain
+/// code and page title like the real dumps, and `spec.invalid_lines` lines
+/// that fail to parse. Read it with `stream_from_file` like a downloaded
+/// dump.
+///
+/// ```
+/// use pvstream::filter::FilterBuilder;
+/// use pvstream::stream_from_file;
+/// use pvstream::testing::{FakeDumpSpec, write_fake_pageviews_gz};
+///
+/// let path = std::env::temp_dir().join("pageviews-20240101-000000.gz");
+/// let spec = FakeDumpSpec {
+///     rows: 500,
+///     languages: Some(vec!["en".into(), "nn".into()]),
+///     invalid_lines: 3,
+///     ..FakeDumpSpec::default()
+/// };
+/// write_fake_pageviews_gz(&path, &spec).unwrap();
+///
+/// let rows = stream_from_file(path, &FilterBuilder::new().build()).unwrap();
+/// assert_eq!(rows.filter(|row| row.is_err()).count(), 3);
+/// ```
+pub fn write_fake_pageviews_gz(path: &Path, spec: &FakeDumpSpec) -> Result<(), IoError> {
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(path)?), GzLevel::default());
+    for line in fake_lines(spec) {
+        writeln!(encoder, "{line}")?;
+    }
+    encoder.finish()?.flush()
+}
+
+/// Lines of the fake file described by `spec`.
+fn fake_lines(spec: &FakeDumpSpec) -> Vec<String> {
+    let mut rng = SplitMix64::new(spec.seed);
+    let mut domain_code = |rng: &mut SplitMix64| match &spec.languages {
+        Some(languages) if !languages.is_empty() => {
+            let language = &languages[rng.below(languages.len() as u64) as usize];
+            format!("{language}{}", rng.weighted(SUFFIXES))
+        }
+        _ => rng.weighted(DOMAIN_CODES).to_string(),
+    };
+
+    let mut pages = generate_pages(&mut rng, spec.rows, &mut domain_code);
+    let mut quoted = 0;
+    while quoted < spec.quoted_rows {
+        let title = QUOTED_TITLES[rng.below(QUOTED_TITLES.len() as u64) as usize];
+        let title = if title.is_empty() {
+            String::new()
+        } else {
+            format!("{title}_{}", rng.title())
+        };
+        let key = (domain_code(&mut rng), title);
+        if let Entry::Vacant(entry) = pages.entry(key) {
+            entry.insert(rng.views());
+            quoted += 1;
+        }
+    }
+
+    let mut lines: Vec<_> = pages
+        .into_iter()
+        .map(|((domain_code, title), views)| {
+            format!("{domain_code} {} {views} 0", quote_string(&title))
+        })
+        .collect();
+    for _ in 0..spec.invalid_lines {
+        let line = invalid_line(&mut rng, &mut domain_code);
+        let position = rng.below(lines.len() as u64 + 1) as usize;
+        lines.insert(position, line);
+    }
+    lines
+}
+
+/// A line that fails to parse, with missing columns or invalid views.
+fn invalid_line(
+    rng: &mut SplitMix64,
+    domain_code: &mut impl FnMut(&mut SplitMix64) -> String,
+) -> String {
+    let domain_code = domain_code(rng);
+    let title = rng.title();
+    match rng.below(4) {
+        0 => domain_code,
+        1 => format!("{domain_code} {title}"),
+        2 => format!("{domain_code} {title} many 0"),
+        _ => format!("{domain_code} {title} -{} 0", rng.views()),
+    }
+}
+
+/// Empty directory for the files of one test, removed when dropped.
+///
+/// The name of the directory includes the process id and a counter, so tests
+/// running in parallel, or in several processes at once, never share files.
+#[cfg(test)]
+pub(crate) struct TestDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    pub(crate) fn new(name: &str) -> Self {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let dir =
+            std::env::temp_dir().join(format!("pvstream-{name}-{}-{count}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub(crate) fn join(&self, path: impl AsRef<Path>) -> std::path::PathBuf {
+        self.0.join(path)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+    use crate::parse::parse_line;
+    use crate::stream_from_file;
+
+    #[test]
+    fn test_generate_test_dump() {
+        let dump = generate_test_dump(5000, 7);
+        let rows: Vec<_> = dump
+            .lines()
+            .map(|line| parse_line(line.to_string()).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 5000);
+        assert_ne!(dump, generate_test_dump(5000, 8));
+
+        let keys: Vec<_> = rows
+            .iter()
+            .map(|row| (&row.domain_code, &row.page_title))
+            .collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(rows.iter().all(|row| row.views > 0));
+        assert!(rows.iter().any(|row| row.domain_code == "en.m"));
+        assert!(rows.iter().any(|row| row.parsed_domain_code.mobile));
+    }
+
+    #[test]
+    fn test_write_fake_pageviews_gz() {
+        let dir = TestDir::new("write-fake-pageviews-gz");
+        let path = dir.join("pageviews-20240101-000000.gz");
+        let spec = FakeDumpSpec {
+            rows: 2000,
+            seed: 3,
+            languages: Some(vec!["de".into(), "nn".into()]),
+            quoted_rows: 20,
+            invalid_lines: 7,
+        };
+        write_fake_pageviews_gz(&path, &spec).unwrap();
+        let rows: Vec<_> = stream_from_file(path.clone(), &FilterBuilder::new().build())
+            .unwrap()
+            .collect();
+
+        let (valid, invalid): (Vec<_>, Vec<_>) = rows.into_iter().partition(Result::is_ok);
+        assert_eq!(valid.len(), 2020);
+        assert_eq!(invalid.len(), 7);
+
+        let valid: Vec<_> = valid.into_iter().map(Result::unwrap).collect();
+        assert!(valid.iter().all(|row| {
+            let language = &row.parsed_domain_code.language;
+            language == "de" || language == "nn"
+        }));
+        assert!(valid.iter().any(|row| row.page_title.is_empty()));
+        assert!(valid.iter().any(|row| row.page_title.contains('"')));
+        assert!(valid.iter().any(|row| row.parsed_domain_code.mobile));
+    }
+
+    #[test]
+    fn test_fake_lines_deterministic() {
+        let spec = FakeDumpSpec {
+            quoted_rows: 5,
+            invalid_lines: 5,
+            ..FakeDumpSpec::default()
+        };
+        assert_eq!(fake_lines(&spec), fake_lines(&spec));
+        assert_eq!(fake_lines(&spec).len(), 1010);
+        assert_ne!(
+            fake_lines(&spec),
+            fake_lines(&FakeDumpSpec { seed: 1, ..spec })
+        );
+    }
+}
@@ -0,0 +1,280 @@
+//! Instrumentation with the `tracing` crate.
+//!
+//! With the `tracing` feature, streams are recorded in spans, and the points
+//! where they slow down or fail are logged as events:
+//!
+//! - `parse` spans cover reading a source, with a `download` span for files
+//!   served over HTTP and a `decompress` span for the decoder within them.
+//! - `write` spans cover writing a Parquet or gzip file.
+//! - Retried requests, failovers to a mirror, and resumed downloads are
+//!   logged as warnings.
+//! - Progress is logged at the debug level every 100 000 lines, and the totals
+//!   of a stream at the info level once it's exhausted: bytes and lines read,
+//!   and rows emitted, filtered, and failing to parse.
+//!
+//! Without the feature, spans and events compile to nothing.
+
+#[cfg(feature = "tracing")]
+pub(crate) use enabled::*;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use disabled::*;
+
+#[cfg(feature = "tracing")]
+mod enabled {
+    use crate::progress::{Progress, ProgressHook};
+    use crate::stream::{Compression, Source, StreamError};
+    use std::io::{Error as IoError, Read};
+    use std::path::Path;
+    use tracing::{debug, info, info_span, warn};
+    use url::Url;
+
+    /// A span, entered whenever the readers and iterators it wraps are used.
+    ///
+    /// Streams are lazy, so most of the work happens long after the span is
+    /// created, in calls to `read` or `next`.
+    #[derive(Clone)]
+    pub(crate) struct Span(tracing::Span);
+
+    impl Span {
+        pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+            self.0.in_scope(f)
+        }
+
+        pub(crate) fn wrap<T>(&self, inner: T) -> InSpan<T> {
+            InSpan {
+                inner,
+                span: self.0.clone(),
+            }
+        }
+    }
+
+    /// Reader or iterator entering a span on each call.
+    pub(crate) struct InSpan<T> {
+        inner: T,
+        span: tracing::Span,
+    }
+
+    impl<I: Iterator> Iterator for InSpan<I> {
+        type Item = I::Item;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let _entered = self.span.enter();
+            self.inner.next()
+        }
+    }
+
+    impl<R: Read> Read for InSpan<R> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+            let _entered = self.span.enter();
+            self.inner.read(buf)
+        }
+    }
+
+    pub(crate) fn parse_span(source: &Source) -> Span {
+        let source = match source {
+            Source::File(path) => path.display().to_string(),
+            Source::Url(url) => url.to_string(),
+        };
+        Span(info_span!("parse", %source))
+    }
+
+    pub(crate) fn download_span(url: &Url) -> Span {
+        Span(info_span!("download", %url))
+    }
+
+    pub(crate) fn decompress_span(compression: Compression) -> Span {
+        Span(info_span!("decompress", ?compression))
+    }
+
+    pub(crate) fn write_span(path: &Path, format: &'static str) -> Span {
+        Span(info_span!("write", path = %path.display(), format))
+    }
+
+    pub(crate) fn retrying(url: &Url, attempt: u32, err: &StreamError) {
+        warn!(%url, attempt, error = %err, "retrying request");
+    }
+
+    pub(crate) fn failing_over(from: &Url, to: &Url, err: &StreamError) {
+        warn!(%from, %to, error = %err, "failing over to mirror");
+    }
+
+    pub(crate) fn resuming(position: u64, err: &IoError) {
+        warn!(position, error = %err, "resuming interrupted download");
+    }
+
+    pub(crate) fn written(rows_written: u64) {
+        info!(rows_written, "finished writing");
+    }
+
+    /// Hook logging the progress reports, before passing them on to `inner`.
+    pub(crate) fn logging_hook(inner: Option<ProgressHook>) -> ProgressHook {
+        ProgressHook::new(move |progress| {
+            log_progress(progress);
+            if let Some(inner) = &inner {
+                inner.call(progress);
+            }
+        })
+    }
+
+    fn log_progress(progress: &Progress) {
+        let Progress {
+            bytes_read,
+            bytes_decompressed,
+            lines_read,
+            rows_emitted,
+            rows_errored,
+            finished,
+            ..
+        } = *progress;
+        if finished {
+            let rows_filtered = lines_read.saturating_sub(rows_emitted + rows_errored);
+            info!(
+                bytes_read,
+                bytes_decompressed,
+                lines_read,
+                rows_emitted,
+                rows_filtered,
+                rows_errored,
+                "finished reading"
+            );
+        } else {
+            debug!(
+                bytes_read,
+                bytes_decompressed, lines_read, rows_emitted, rows_errored, "progress"
+            );
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod disabled {
+    use crate::stream::{Compression, Source, StreamError};
+    use std::io::Error as IoError;
+    use std::path::Path;
+    use url::Url;
+
+    #[derive(Clone)]
+    pub(crate) struct Span;
+
+    impl Span {
+        pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+            f()
+        }
+
+        pub(crate) fn wrap<T>(&self, inner: T) -> T {
+            inner
+        }
+    }
+
+    pub(crate) fn parse_span(_: &Source) -> Span {
+        Span
+    }
+
+    pub(crate) fn download_span(_: &Url) -> Span {
+        Span
+    }
+
+    pub(crate) fn decompress_span(_: Compression) -> Span {
+        Span
+    }
+
+    pub(crate) fn write_span(_: &Path, _: &'static str) -> Span {
+        Span
+    }
+
+    pub(crate) fn retrying(_: &Url, _: u32, _: &StreamError) {}
+
+    pub(crate) fn failing_over(_: &Url, _: &Url, _: &StreamError) {}
+
+    pub(crate) fn resuming(_: u64, _: &IoError) {}
+
+    pub(crate) fn written(_: u64) {}
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::filter::FilterBuilder;
+    use crate::stream::{Source, StreamConfig};
+    use crate::stream_from_source;
+    use std::fmt::Debug;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Message and fields of each event, after the names of its spans.
+    type Events = Arc<Mutex<Vec<(Vec<&'static str>, String)>>>;
+
+    /// Records the names of the spans created, and the message and fields of
+    /// each event, with the names of the spans it happened in.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        spans: Arc<Mutex<Vec<&'static str>>>,
+        stack: Arc<Mutex<Vec<u64>>>,
+        events: Events,
+    }
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.push_str(&format!(" {}={value:?}", field.name()));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(span.metadata().name());
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields(String::new());
+            event.record(&mut fields);
+            let spans = self.spans.lock().unwrap();
+            let stack = self.stack.lock().unwrap();
+            let names = stack.iter().map(|id| spans[*id as usize - 1]).collect();
+            self.events.lock().unwrap().push((names, fields.0));
+        }
+
+        fn enter(&self, span: &Id) {
+            self.stack.lock().unwrap().push(span.into_u64());
+        }
+
+        fn exit(&self, _: &Id) {
+            self.stack.lock().unwrap().pop();
+        }
+    }
+
+    #[test]
+    fn test_stream_events() {
+        let recorder = Recorder::default();
+        let source = Source::File(PathBuf::from("tests/files/pageviews-20240803-060000.gz"));
+        let filter = FilterBuilder::new().languages(["ja"]).build();
+
+        let rows = tracing::subscriber::with_default(recorder.clone(), || {
+            stream_from_source(&source, &filter, &StreamConfig::default())
+                .unwrap()
+                .count()
+        });
+
+        assert_eq!(*recorder.spans.lock().unwrap(), ["parse", "decompress"]);
+        let events = recorder.events.lock().unwrap();
+        let (spans, fields) = events.last().unwrap();
+        assert_eq!(spans, &["parse"]);
+        assert!(fields.contains("finished reading"));
+        assert!(fields.contains(&format!("rows_emitted={rows}")));
+        assert!(fields.contains("rows_errored=0"));
+    }
+}
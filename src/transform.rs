@@ -0,0 +1,867 @@
+use crate::aggregate::{Ranked, ViewsOverflow};
+use crate::filter::{CustomFilter, Filter, TitleTransform};
+use crate::parse::{DomainCode, Pageviews, ParseError, desktop_domain_code};
+use chrono::{DateTime, Utc};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+use std::io::Error as IoError;
+use std::sync::{Arc, Mutex};
+
+type RowResult = Result<Pageviews, ParseError>;
+
+/// Error for a sum of views which no longer fits in a row.
+///
+/// Row iterators can only return `ParseError`, so the overflow is smuggled
+/// through a read error, which is always fatal, and unwrapped as
+/// `StreamError::Overflow`.
+fn overflow(group: String) -> ParseError {
+    ParseError::ReadError(IoError::other(ViewsOverflow { group }))
+}
+
+/// The group of a row in `ViewsOverflow`, its domain code and title.
+fn row_group(row: &Pageviews) -> String {
+    format!("{} {}", row.domain_code, row.page_title)
+}
+
+/// Collapses rows from non-article namespaces into one row per namespace.
+///
+/// Pageviews files are sorted by domain code, so we only need to keep the
+/// running totals for the current domain code in memory. When the domain code
+/// changes, one aggregate row is emitted for each namespace seen, with the
+/// title `<namespace>:*` and the summed views.
+pub struct NamespaceRollup<I: Iterator<Item = RowResult>> {
+    iter: I,
+    namespaces: Vec<String>,
+    /// Aggregate rows for the current domain code, in order of appearance
+    buckets: Vec<Pageviews>,
+    /// Rows ready to be returned, used when flushing buckets
+    pending: VecDeque<RowResult>,
+}
+
+impl<I: Iterator<Item = RowResult>> NamespaceRollup<I> {
+    /// Returns the namespace of a title if it's one we should roll up.
+    fn namespace_of<'a>(&self, page_title: &'a str) -> Option<&'a str> {
+        let (namespace, _) = page_title.split_once(':')?;
+        self.namespaces
+            .iter()
+            .any(|ns| ns == namespace)
+            .then_some(namespace)
+    }
+
+    /// Moves the aggregate rows to the output queue.
+    fn flush(&mut self) {
+        self.pending.extend(self.buckets.drain(..).map(Ok));
+    }
+}
+
+impl<I: Iterator<Item = RowResult>> Iterator for NamespaceRollup<I> {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Some(row);
+            }
+
+            let mut row = match self.iter.next() {
+                Some(Ok(row)) => row,
+                Some(Err(err)) => return Some(Err(err)),
+                None if self.buckets.is_empty() => return None,
+                None => {
+                    self.flush();
+                    continue;
+                }
+            };
+
+            if self
+                .buckets
+                .first()
+                .is_some_and(|bucket| bucket.domain_code != row.domain_code)
+            {
+                self.flush();
+            }
+
+            let Some(namespace) = self.namespace_of(&row.page_title) else {
+                self.pending.push_back(Ok(row));
+                continue;
+            };
+
+            let title = format!("{namespace}:*");
+            match self.buckets.iter_mut().find(|b| b.page_title == title) {
+                Some(bucket) => match bucket.views.checked_add(row.views) {
+                    Some(views) => bucket.views = views,
+                    None => return Some(Err(overflow(row_group(bucket)))),
+                },
+                None => {
+                    row.page_title = title;
+                    self.buckets.push(row);
+                }
+            }
+        }
+    }
+}
+
+/// Collapses rows from the listed namespaces into aggregate rows.
+pub fn rollup_namespaces<I>(iter: I, namespaces: Vec<String>) -> NamespaceRollup<I>
+where
+    I: Iterator<Item = RowResult>,
+{
+    NamespaceRollup {
+        iter,
+        namespaces,
+        buckets: Vec::new(),
+        pending: VecDeque::new(),
+    }
+}
+
+/// Merges the rows of mobile sites into the rows of the desktop sites.
+///
+/// Rows differing only by the mobile flag, e.g. `en` and `en.m` for the same
+/// title, become one desktop row with the views summed. The mobile site of a
+/// wiki is listed after the desktop site, as the files are sorted by domain
+/// code, but before the next language. So only the rows of the current
+/// language are kept in memory, and returned once the domain codes are past
+/// it, sorted by domain code, and otherwise in order of appearance.
+pub struct MobileMerge<I: Iterator<Item = RowResult>> {
+    iter: I,
+    /// Rows of the languages still being read, by desktop domain code
+    groups: BTreeMap<String, DomainGroup>,
+    /// Domain code of the last row read, to only look for finished languages
+    /// when it changes
+    domain_code: String,
+    /// Rows ready to be returned, used when flushing groups
+    pending: VecDeque<RowResult>,
+}
+
+/// Merged rows of a desktop domain code, in order of appearance.
+#[derive(Default)]
+struct DomainGroup {
+    rows: Vec<Pageviews>,
+    titles: HashMap<String, usize>,
+}
+
+impl<I: Iterator<Item = RowResult>> MobileMerge<I> {
+    /// Moves the rows of languages the domain codes are past to the output.
+    fn flush_past(&mut self, domain_code: &str) {
+        let past: Vec<String> = self
+            .groups
+            .keys()
+            .filter(|key| past_language(domain_code, key.split('.').next().unwrap_or_default()))
+            .cloned()
+            .collect();
+        for key in past {
+            if let Some(group) = self.groups.remove(&key) {
+                self.pending.extend(group.rows.into_iter().map(Ok));
+            }
+        }
+    }
+
+    /// Adds a row to the group of its desktop domain code.
+    fn merge(&mut self, mut row: Pageviews) -> Result<(), ParseError> {
+        let domain_code = if row.parsed_domain_code.mobile {
+            desktop_domain_code(&row.domain_code)
+        } else {
+            row.domain_code.clone()
+        };
+        let group = self.groups.entry(domain_code.clone()).or_default();
+        match group.titles.get(&row.page_title) {
+            Some(&index) => merge_rows(&mut group.rows[index], row)?,
+            None => {
+                row.domain_code = domain_code;
+                row.parsed_domain_code.mobile = false;
+                group
+                    .titles
+                    .insert(row.page_title.clone(), group.rows.len());
+                group.rows.push(row);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item = RowResult>> Iterator for MobileMerge<I> {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Some(row);
+            }
+
+            let row = match self.iter.next() {
+                Some(Ok(row)) => row,
+                Some(Err(err)) => return Some(Err(err)),
+                None if self.groups.is_empty() => return None,
+                None => {
+                    let groups = std::mem::take(&mut self.groups);
+                    for group in groups.into_values() {
+                        self.pending.extend(group.rows.into_iter().map(Ok));
+                    }
+                    continue;
+                }
+            };
+
+            if row.domain_code != self.domain_code {
+                self.flush_past(&row.domain_code);
+                self.domain_code.clone_from(&row.domain_code);
+            }
+            if let Err(err) = self.merge(row) {
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+/// Checks if `domain_code` sorts after every domain code of `language`,
+/// which are the language itself and the language followed by a period.
+fn past_language(domain_code: &str, language: &str) -> bool {
+    match domain_code.strip_prefix(language) {
+        Some(rest) => rest >= "/",
+        None => domain_code > language,
+    }
+}
+
+/// Adds the views of `row` to `merged`, failing if a sum overflows.
+fn merge_rows(merged: &mut Pageviews, row: Pageviews) -> Result<(), ParseError> {
+    let views = merged.views.checked_add(row.views);
+    let response_size = match (merged.response_size, row.response_size) {
+        (Some(a), Some(b)) => a.checked_add(b).map(Some),
+        (a, b) => Some(a.or(b)),
+    };
+    let hourly_views = match (&merged.hourly_views, row.hourly_views) {
+        (Some(merged), Some(hourly)) => merged
+            .iter()
+            .zip(hourly)
+            .map(|(merged, views)| merged.checked_add(views))
+            .collect::<Option<Vec<_>>>()
+            .map(Some),
+        (merged, hourly) => Some(merged.clone().or(hourly)),
+    };
+    let (Some(views), Some(response_size), Some(hourly_views)) =
+        (views, response_size, hourly_views)
+    else {
+        return Err(overflow(row_group(merged)));
+    };
+    merged.views = views;
+    merged.response_size = response_size;
+    merged.hourly_views = hourly_views;
+    Ok(())
+}
+
+/// Merges rows of mobile sites into the rows of the desktop sites.
+pub fn merge_mobile<I>(iter: I) -> MobileMerge<I>
+where
+    I: Iterator<Item = RowResult>,
+{
+    MobileMerge {
+        iter,
+        groups: BTreeMap::new(),
+        domain_code: String::new(),
+        pending: VecDeque::new(),
+    }
+}
+
+/// Keeps the rows ranked between `min_rank` and `max_rank` by views within
+/// their domain code, see `Filter::max_rank`.
+///
+/// Pageviews files are sorted by domain code, so the rows of one domain code
+/// are ranked at a time, and returned once the next domain code starts, in
+/// their original order. With `max_rank`, only the top `max_rank` rows of the
+/// domain code are kept in memory, otherwise all of them.
+pub struct RankFilter<I: Iterator<Item = RowResult>> {
+    iter: I,
+    min_rank: usize,
+    max_rank: Option<usize>,
+    /// Best ranked rows of the current domain code, with their position
+    group: BinaryHeap<Reverse<(Ranked, usize)>>,
+    /// Position of the next row within the current domain code
+    position: usize,
+    /// Rows ready to be returned, used when flushing the group
+    pending: VecDeque<RowResult>,
+    /// Totals of the rows dropped, with `residual_rows`
+    residuals: Option<Residuals>,
+}
+
+impl<I: Iterator<Item = RowResult>> RankFilter<I> {
+    /// Adds a row to the group, dropping it if it's ranked below `max_rank`.
+    fn push(&mut self, row: Pageviews) {
+        let row = Reverse((Ranked(row), self.position));
+        self.position += 1;
+        let dropped = match self.max_rank {
+            Some(max_rank) if self.group.len() >= max_rank => {
+                // `Reverse` flips the order, so a lower value is a higher rank
+                if self.group.peek().is_some_and(|lowest| row < *lowest) {
+                    let lowest = self.group.pop();
+                    self.group.push(row);
+                    lowest
+                } else {
+                    Some(row)
+                }
+            }
+            _ => {
+                self.group.push(row);
+                None
+            }
+        };
+        if let Some(Reverse((Ranked(row), _))) = dropped {
+            self.drop_row(row);
+        }
+    }
+
+    /// Adds a row ranked outside of the range to the residuals, if any.
+    fn drop_row(&self, row: Pageviews) {
+        if let Some(residuals) = &self.residuals {
+            residuals.add(row);
+        }
+    }
+
+    /// Moves the rows ranked from `min_rank` to the output queue, in their
+    /// original order.
+    fn flush(&mut self) {
+        let mut ranked = std::mem::take(&mut self.group)
+            .into_sorted_vec()
+            .into_iter();
+        for Reverse((Ranked(row), _)) in ranked.by_ref().take(self.min_rank.saturating_sub(1)) {
+            self.drop_row(row);
+        }
+        let mut rows: Vec<_> = ranked
+            .map(|Reverse((Ranked(row), position))| (position, row))
+            .collect();
+        rows.sort_unstable_by_key(|(position, _)| *position);
+        self.pending
+            .extend(rows.into_iter().map(|(_, row)| Ok(row)));
+        self.position = 0;
+    }
+}
+
+impl<I: Iterator<Item = RowResult>> Iterator for RankFilter<I> {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Some(row);
+            }
+
+            let row = match self.iter.next() {
+                Some(Ok(row)) => row,
+                Some(Err(err)) => return Some(Err(err)),
+                None if self.group.is_empty() => return None,
+                None => {
+                    self.flush();
+                    continue;
+                }
+            };
+
+            if self
+                .group
+                .peek()
+                .is_some_and(|Reverse((Ranked(first), _))| first.domain_code != row.domain_code)
+            {
+                self.flush();
+            }
+            self.push(row);
+        }
+    }
+}
+
+/// Keeps the rows ranked between `min_rank` and `max_rank` by views within
+/// their domain code, summing the rest in `residuals` if given.
+pub fn rank_rows<I>(
+    iter: I,
+    min_rank: Option<usize>,
+    max_rank: Option<usize>,
+    residuals: Option<Residuals>,
+) -> RankFilter<I>
+where
+    I: Iterator<Item = RowResult>,
+{
+    RankFilter {
+        iter,
+        min_rank: min_rank.unwrap_or(1),
+        max_rank,
+        group: BinaryHeap::new(),
+        position: 0,
+        pending: VecDeque::new(),
+        residuals,
+    }
+}
+
+/// Running totals of the rows left out of a stream, per language and mobile
+/// flag, see `Filter::residual_rows`.
+///
+/// Shared by the steps of the pipeline which drop rows: the filters, the
+/// custom filter, and the rank filter. `residual_rows` returns the totals
+/// once the input is exhausted.
+#[derive(Clone, Default)]
+pub struct Residuals(Arc<Mutex<ResidualTotals>>);
+
+/// The views are summed in `u64`, and checked to fit in a row once taken.
+#[derive(Default)]
+struct ResidualTotals {
+    views: HashMap<(String, bool), u64>,
+    timestamp: Option<DateTime<Utc>>,
+}
+
+impl Residuals {
+    /// Adds the views of a row left out to its language and mobile flag.
+    pub fn add(&self, row: Pageviews) {
+        let mut totals = self.0.lock().unwrap();
+        totals.timestamp = totals.timestamp.or(row.timestamp);
+        let key = (
+            row.parsed_domain_code.language,
+            row.parsed_domain_code.mobile,
+        );
+        *totals.views.entry(key).or_default() += u64::from(row.views);
+    }
+
+    /// Takes the totals as residual rows, with `*` as domain code and page
+    /// title, sorted by language and mobile flag. A total which doesn't fit
+    /// in a row is returned as an error instead.
+    fn take_rows(&self) -> Vec<RowResult> {
+        let mut totals = self.0.lock().unwrap();
+        let timestamp = totals.timestamp;
+        let mut views: Vec<_> = totals.views.drain().collect();
+        views.sort_unstable();
+        views
+            .into_iter()
+            .map(|((language, mobile), views)| {
+                let Ok(views) = u32::try_from(views) else {
+                    let mobile = if mobile { " mobile" } else { "" };
+                    return Err(overflow(format!("* {language}{mobile}")));
+                };
+                Ok(Pageviews {
+                    domain_code: "*".to_string(),
+                    page_title: "*".to_string(),
+                    views,
+                    parsed_domain_code: DomainCode {
+                        language,
+                        domain: None,
+                        mobile,
+                        project: None,
+                    },
+                    timestamp,
+                    response_size: None,
+                    hourly_views: None,
+                    page_id: None,
+                    line_number: None,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Keeps the rows passing the filter, and adds the rest to `Residuals`.
+pub struct FilteredRows<I: Iterator<Item = RowResult>> {
+    iter: I,
+    filter: Filter,
+    residuals: Residuals,
+}
+
+impl<I: Iterator<Item = RowResult>> Iterator for FilteredRows<I> {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for result in self.iter.by_ref() {
+            match result {
+                Ok(row) if !self.filter.post_filter(&row) => self.residuals.add(row),
+                other => return Some(other),
+            }
+        }
+        None
+    }
+}
+
+/// Keeps the rows passing the filter, summing the rest in `residuals`.
+pub fn filter_rows<I>(iter: I, filter: Filter, residuals: Residuals) -> FilteredRows<I>
+where
+    I: Iterator<Item = RowResult>,
+{
+    FilteredRows {
+        iter,
+        filter,
+        residuals,
+    }
+}
+
+/// Returns the rows as is, followed by the residual rows of `Residuals`
+/// once the input is exhausted.
+pub struct ResidualRows<I: Iterator<Item = RowResult>> {
+    iter: I,
+    residuals: Residuals,
+    flushed: Option<std::vec::IntoIter<RowResult>>,
+}
+
+impl<I: Iterator<Item = RowResult>> Iterator for ResidualRows<I> {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(flushed) = self.flushed.as_mut() {
+            return flushed.next();
+        }
+        if let Some(row) = self.iter.next() {
+            return Some(row);
+        }
+
+        // The steps before are done, so the totals are complete
+        let flushed = self.flushed.insert(self.residuals.take_rows().into_iter());
+        flushed.next()
+    }
+}
+
+/// Appends the residual rows summed in `residuals` to the rows.
+pub fn residual_rows<I>(iter: I, residuals: Residuals) -> ResidualRows<I>
+where
+    I: Iterator<Item = RowResult>,
+{
+    ResidualRows {
+        iter,
+        residuals,
+        flushed: None,
+    }
+}
+
+/// Keeps the rows a `CustomFilter` accepts, checked in batches.
+///
+/// Errors are passed on ahead of the rows of the batch they were read in.
+pub struct CustomRows<I: Iterator<Item = RowResult>> {
+    iter: I,
+    custom: CustomFilter,
+    /// Totals of the rows rejected, with `residual_rows`
+    residuals: Option<Residuals>,
+    /// Rows ready to be returned, from the last batch checked
+    pending: VecDeque<RowResult>,
+}
+
+impl<I: Iterator<Item = RowResult>> Iterator for CustomRows<I> {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Some(row);
+            }
+
+            let mut batch = Vec::with_capacity(self.custom.batch_size());
+            while batch.len() < self.custom.batch_size() {
+                match self.iter.next() {
+                    Some(Ok(row)) => batch.push(row),
+                    Some(Err(err)) => self.pending.push_back(Err(err)),
+                    None => break,
+                }
+            }
+            if batch.is_empty() && self.pending.is_empty() {
+                return None;
+            }
+
+            match self.custom.check(&batch) {
+                Ok(keep) => {
+                    // Rows without a result are rejected
+                    let keep = keep.into_iter().chain(std::iter::repeat(false));
+                    for (row, keep) in batch.into_iter().zip(keep) {
+                        if keep {
+                            self.pending.push_back(Ok(row));
+                        } else if let Some(residuals) = &self.residuals {
+                            residuals.add(row);
+                        }
+                    }
+                }
+                Err(err) => self.pending.push_back(Err(ParseError::ReadError(err))),
+            }
+        }
+    }
+}
+
+/// Keeps the rows a `CustomFilter` accepts, summing the rest in `residuals`
+/// if given.
+pub fn custom_rows<I>(iter: I, custom: CustomFilter, residuals: Option<Residuals>) -> CustomRows<I>
+where
+    I: Iterator<Item = RowResult>,
+{
+    CustomRows {
+        iter,
+        custom,
+        residuals,
+        pending: VecDeque::new(),
+    }
+}
+
+/// Rewrites the page titles of rows with a `TitleTransform`.
+pub struct TransformedTitles<I: Iterator<Item = RowResult>> {
+    iter: I,
+    transform: TitleTransform,
+}
+
+impl<I: Iterator<Item = RowResult>> Iterator for TransformedTitles<I> {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut row = self.iter.next()?;
+        if let Ok(row) = &mut row
+            && let Cow::Owned(title) = self.transform.apply(&row.page_title)
+        {
+            row.page_title = title;
+        }
+        Some(row)
+    }
+}
+
+/// Rewrites the page titles of rows, leaving errors as they are.
+pub fn transform_titles<I>(iter: I, transform: TitleTransform) -> TransformedTitles<I>
+where
+    I: Iterator<Item = RowResult>,
+{
+    TransformedTitles { iter, transform }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+    use crate::parse::parse_line;
+
+    fn make_rows(lines: &[&str]) -> Vec<RowResult> {
+        lines
+            .iter()
+            .map(|line| parse_line(line.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rollup_namespaces() {
+        let rows = make_rows(&[
+            "de Berlin 10 0",
+            "de Spezial:Suche 5 0",
+            "de Spezial:Anmelden 3 0",
+            "de Diskussion:Berlin 1 0",
+            "en Special:Search 7 0",
+            "en London 2 0",
+        ]);
+        let namespaces = vec!["Spezial".to_string(), "Special".to_string()];
+        let result: Vec<_> = rollup_namespaces(rows.into_iter(), namespaces)
+            .map(|row| row.map(|r| (r.domain_code, r.page_title, r.views)).unwrap())
+            .collect();
+
+        assert_eq!(
+            result,
+            vec![
+                ("de".into(), "Berlin".into(), 10),
+                ("de".into(), "Diskussion:Berlin".into(), 1),
+                ("de".into(), "Spezial:*".into(), 8),
+                ("en".into(), "London".into(), 2),
+                ("en".into(), "Special:*".into(), 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rollup_passes_errors() {
+        let rows = make_rows(&["en Special:Search 7 0", "invalid"]);
+        let mut iter = rollup_namespaces(rows.into_iter(), vec!["Special".into()]);
+
+        assert!(iter.next().unwrap().is_err());
+        assert_eq!(iter.next().unwrap().unwrap().views, 7);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_merge_mobile() {
+        let rows = make_rows(&[
+            "be Minsk 1 0",
+            "be-tarask Minsk 2 0",
+            "be-tarask.m Minsk 3 0",
+            "be.b Cooking 4 0",
+            "be.m Minsk 5 0",
+            "be.m Brest 6 0",
+            "be.m.b Cooking 7 0",
+            "invalid",
+            "de Berlin 8 0",
+            "de.m Berlin 9 0",
+        ]);
+        let result: Vec<_> = merge_mobile(rows.into_iter())
+            .filter_map(Result::ok)
+            .map(|r| {
+                assert!(!r.parsed_domain_code.mobile);
+                (r.domain_code, r.page_title, r.views)
+            })
+            .collect();
+
+        assert_eq!(
+            result,
+            vec![
+                ("be-tarask".into(), "Minsk".into(), 5),
+                ("be".into(), "Minsk".into(), 6),
+                ("be".into(), "Brest".into(), 6),
+                ("be.b".into(), "Cooking".into(), 11),
+                ("de".into(), "Berlin".into(), 17),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rank_rows() {
+        let lines = [
+            "de Berlin 10 0",
+            "de Bonn 3 0",
+            "de Hamburg 7 0",
+            "de Köln 7 0",
+            "invalid",
+            "en London 20 0",
+            "en Rome 5 0",
+        ];
+        let ranked = |min_rank, max_rank| -> Vec<_> {
+            rank_rows(make_rows(&lines).into_iter(), min_rank, max_rank, None)
+                .filter_map(Result::ok)
+                .map(|r| (r.domain_code, r.page_title))
+                .collect()
+        };
+
+        // Rows keep their order, and ties go to the first title
+        assert_eq!(
+            ranked(None, Some(2)),
+            vec![
+                ("de".into(), "Berlin".into()),
+                ("de".into(), "Hamburg".into()),
+                ("en".into(), "London".into()),
+                ("en".into(), "Rome".into()),
+            ]
+        );
+        assert_eq!(
+            ranked(Some(2), Some(3)),
+            vec![
+                ("de".into(), "Hamburg".into()),
+                ("de".into(), "Köln".into()),
+                ("en".into(), "Rome".into()),
+            ]
+        );
+        assert_eq!(ranked(Some(4), None), vec![("de".into(), "Bonn".into())]);
+        assert!(ranked(None, Some(0)).is_empty());
+
+        let errors =
+            rank_rows(make_rows(&lines).into_iter(), None, Some(1), None).filter(Result::is_err);
+        assert_eq!(errors.count(), 1);
+    }
+
+    #[test]
+    fn test_custom_rows() {
+        let rows = make_rows(&[
+            "de Berlin 10 0",
+            "de Bonn 3 0",
+            "invalid",
+            "en London 20 0",
+            "en Rome 5 0",
+        ]);
+        let custom = CustomFilter::batched(2, |rows| {
+            assert!(rows.len() <= 2);
+            Ok(rows.iter().map(|row| row.page_title.len() > 4).collect())
+        });
+        let result: Vec<_> = custom_rows(rows.into_iter(), custom, None)
+            .map(|row| row.map(|r| r.page_title))
+            .collect();
+
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0].as_deref().ok(), Some("Berlin"));
+        // The error is returned ahead of the rows of its batch
+        assert!(result[1].is_err());
+        assert_eq!(result[2].as_deref().ok(), Some("London"));
+
+        let failing = CustomFilter::batched(1, |_| Err(std::io::Error::other("failed")));
+        let mut rows = custom_rows(make_rows(&["de Berlin 10 0"]).into_iter(), failing, None);
+        assert!(matches!(rows.next(), Some(Err(ParseError::ReadError(_)))));
+        assert!(rows.next().is_none());
+    }
+
+    #[test]
+    fn test_past_language() {
+        assert!(!past_language("be", "be"));
+        assert!(!past_language("be.m.b", "be"));
+        assert!(!past_language("be-tarask", "be"));
+        assert!(past_language("be-tarask", "ba"));
+        assert!(past_language("bg", "be"));
+        assert!(!past_language("ar", "be"));
+    }
+
+    #[test]
+    fn test_residual_rows() {
+        let rows = make_rows(&[
+            "de Berlin 10 0",
+            "de.m Berlin 4 0",
+            "en London 2 0",
+            "en.b Cooking 3 0",
+            "en.m Rome 5 0",
+            "invalid",
+        ]);
+        let filter = FilterBuilder::new().page_title("^Berlin$").build();
+        let residuals = Residuals::default();
+        let rows = filter_rows(rows.into_iter(), filter, residuals.clone());
+        let result: Vec<_> = residual_rows(rows, residuals)
+            .filter_map(Result::ok)
+            .map(|r| {
+                (
+                    r.domain_code,
+                    r.page_title,
+                    r.parsed_domain_code.language,
+                    r.parsed_domain_code.mobile,
+                    r.views,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            result,
+            vec![
+                ("de".into(), "Berlin".into(), "de".into(), false, 10),
+                ("de.m".into(), "Berlin".into(), "de".into(), true, 4),
+                ("*".into(), "*".into(), "en".into(), false, 5),
+                ("*".into(), "*".into(), "en".into(), true, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_views_overflow() {
+        use crate::stream::StreamError;
+
+        let overflow = |row: Option<RowResult>| match row.map(|row| row.map_err(StreamError::from))
+        {
+            Some(Err(StreamError::Overflow(err))) => err.group,
+            _ => panic!("expected an overflow"),
+        };
+
+        // Sums up to u32::MAX still fit
+        let rows = make_rows(&["de Talk:A 4294967294 0", "de Talk:B 1 0"]);
+        let mut rollup = rollup_namespaces(rows.into_iter(), vec!["Talk".into()]);
+        assert_eq!(rollup.next().unwrap().unwrap().views, u32::MAX);
+
+        let rows = make_rows(&["de Talk:A 4294967295 0", "de Talk:B 1 0"]);
+        let mut rollup = rollup_namespaces(rows.into_iter(), vec!["Talk".into()]);
+        assert_eq!(overflow(rollup.next()), "de Talk:*");
+
+        let rows = make_rows(&["de Berlin 4294967294 0", "de.m Berlin 1 0"]);
+        let mut merged = merge_mobile(rows.into_iter());
+        assert_eq!(merged.next().unwrap().unwrap().views, u32::MAX);
+
+        let rows = make_rows(&["de Berlin 4294967295 0", "de.m Berlin 1 0"]);
+        let mut merged = merge_mobile(rows.into_iter());
+        assert_eq!(overflow(merged.next()), "de Berlin");
+
+        let rows = make_rows(&["de Berlin 2 A4294967295", "de.m Berlin 2 A1"]);
+        let mut merged = merge_mobile(rows.into_iter());
+        assert_eq!(overflow(merged.next()), "de Berlin");
+
+        let rows = make_rows(&["en London 4294967294 0", "en Rome 1 0"]);
+        let filter = FilterBuilder::new().page_title("^Berlin$").build();
+        let residuals = Residuals::default();
+        let rows = filter_rows(rows.into_iter(), filter.clone(), residuals.clone());
+        let mut rows = residual_rows(rows, residuals);
+        assert_eq!(rows.next().unwrap().unwrap().views, u32::MAX);
+
+        let rows = make_rows(&["en London 4294967295 0", "en.m Rome 1 0", "en Rome 1 0"]);
+        let residuals = Residuals::default();
+        let rows = filter_rows(rows.into_iter(), filter, residuals.clone());
+        let mut rows = residual_rows(rows, residuals);
+        assert_eq!(overflow(rows.next()), "* en");
+        assert_eq!(rows.next().unwrap().unwrap().views, 1);
+    }
+}
@@ -0,0 +1,306 @@
+//! URLs of the hourly pageviews dumps.
+//!
+//! The dumps are stored in nested year and month directories, e.g.
+//! `2024/2024-08/pageviews-20240818-080000.gz`. The helpers in this module
+//! build these paths, on Wikimedia's own servers or on any mirror.
+
+use crate::stream::{StreamConfig, StreamError, timestamp_from_file_name};
+use chrono::{DateTime, NaiveDate, Utc};
+use regex::Regex;
+use reqwest::StatusCode;
+use std::sync::LazyLock;
+use url::{ParseError as UrlParseError, Url};
+
+/// Base URL of the pageviews dumps on Wikimedia's own servers.
+pub const DUMPS_BASE_URL: &str = "https://dumps.wikimedia.org/other/pageviews/";
+
+/// A server hosting the pageviews dumps.
+///
+/// Mirrors keep the same directory layout as Wikimedia, so only the base URL
+/// of the pageviews directory differs. You can find mirrors listed on
+/// [wikimedia.org](https://dumps.wikimedia.org/mirrors.html). The default is
+/// Wikimedia's own servers.
+///
+/// # Example
+///
+/// ```
+/// use chrono::NaiveDate;
+/// use pvstream::urls::Mirror;
+///
+/// let mirror = Mirror::new("https://mirror.example.org/wikimedia/other/pageviews")?;
+/// let date = NaiveDate::from_ymd_opt(2024, 8, 18).unwrap();
+///
+/// assert_eq!(
+///     mirror.pageviews_url(date, 8).as_str(),
+///     "https://mirror.example.org/wikimedia/other/pageviews/2024/2024-08/pageviews-20240818-080000.gz",
+/// );
+/// # Ok::<(), url::ParseError>(())
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mirror {
+    base: Url,
+}
+
+impl Default for Mirror {
+    fn default() -> Self {
+        Self::new(DUMPS_BASE_URL).expect("the default base url is valid")
+    }
+}
+
+impl Mirror {
+    /// Creates a mirror from the URL of its pageviews directory.
+    ///
+    /// A missing trailing slash is added, so the file paths are appended to
+    /// the directory instead of replacing its last segment.
+    pub fn new(base: &str) -> Result<Self, UrlParseError> {
+        let mut base = Url::parse(base)?;
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        Ok(Self { base })
+    }
+
+    /// Base URL of the pageviews directory.
+    pub fn base(&self) -> &Url {
+        &self.base
+    }
+
+    /// Path of `url` relative to this mirror, if it's on this mirror.
+    pub fn relative_path<'a>(&self, url: &'a Url) -> Option<&'a str> {
+        url.as_str().strip_prefix(self.base.as_str())
+    }
+
+    /// URL of the pageviews file for the hour starting at `hour`.
+    ///
+    /// Minutes and seconds are ignored, the dumps are hourly.
+    pub fn dump_url(&self, hour: &DateTime<Utc>) -> Url {
+        let path = hour
+            .format("%Y/%Y-%m/pageviews-%Y%m%d-%H0000.gz")
+            .to_string();
+        self.base.join(&path).expect("dump paths are always valid")
+    }
+
+    /// URL of the pageviews file for `hour` (0-23, UTC) on `date`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hour` is 24 or more.
+    pub fn pageviews_url(&self, date: NaiveDate, hour: u32) -> Url {
+        let hour = date
+            .and_hms_opt(hour, 0, 0)
+            .expect("hour must be between 0 and 23")
+            .and_utc();
+        self.dump_url(&hour)
+    }
+
+    /// URLs of all 24 pageviews files on `date`, in order.
+    pub fn pageviews_urls_for_day(&self, date: NaiveDate) -> Vec<Url> {
+        (0..24).map(|hour| self.pageviews_url(date, hour)).collect()
+    }
+
+    /// URL of the directory with the files of `month` (1-12) in `year`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `month` isn't between 1 and 12.
+    pub fn month_url(&self, year: i32, month: u32) -> Url {
+        assert!((1..=12).contains(&month), "month must be between 1 and 12");
+        self.base
+            .join(&format!("{year}/{year}-{month:02}/"))
+            .expect("month paths are always valid")
+    }
+
+    /// Hours of `month` (1-12) in `year` with a pageviews file on this
+    /// mirror, in order, read from the directory listing of the month.
+    ///
+    /// Months without a directory yet have no hours. The HTTP client is set
+    /// up with `config.client`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `month` isn't between 1 and 12.
+    pub fn list_available_hours(
+        &self,
+        year: i32,
+        month: u32,
+        config: &StreamConfig,
+    ) -> Result<Vec<DateTime<Utc>>, StreamError> {
+        let response = config
+            .client
+            .build()?
+            .get(self.month_url(year, month))
+            .send()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        Ok(hours_in_listing(&response.error_for_status()?.text()?))
+    }
+}
+
+/// Links to pageviews files in a directory listing.
+static LISTED_FILE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"href="(?:[^"]*/)?(pageviews-\d{8}-\d{6}\.gz)""#).expect("valid regex")
+});
+
+/// Hours with a pageviews file linked from an HTML directory listing, like
+/// the ones served by Wikimedia and its mirrors, in order.
+pub fn hours_in_listing(html: &str) -> Vec<DateTime<Utc>> {
+    let mut hours: Vec<_> = LISTED_FILE
+        .captures_iter(html)
+        .filter_map(|captures| timestamp_from_file_name(&captures[1]))
+        .collect();
+    hours.sort_unstable();
+    hours.dedup();
+    hours
+}
+
+/// Hours of `month` (1-12) in `year` with a pageviews file on Wikimedia's
+/// own servers, in order.
+///
+/// Lets a scheduler find out if a new hour has been published with a single
+/// request for the directory listing, instead of requesting each file.
+///
+/// # Panics
+///
+/// Panics if `month` isn't between 1 and 12.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::urls::{dump_url, list_available_hours};
+///
+/// let hours = list_available_hours(2024, 8)?;
+/// if let Some(latest) = hours.last() {
+///     println!("latest file: {}", dump_url(latest));
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn list_available_hours(year: i32, month: u32) -> Result<Vec<DateTime<Utc>>, StreamError> {
+    Mirror::default().list_available_hours(year, month, &StreamConfig::default())
+}
+
+/// URL of the pageviews file for the hour starting at `hour`, on Wikimedia's
+/// own servers.
+///
+/// Minutes and seconds are ignored, the dumps are hourly.
+pub fn dump_url(hour: &DateTime<Utc>) -> Url {
+    Mirror::default().dump_url(hour)
+}
+
+/// URL of the pageviews file for `hour` (0-23, UTC) on `date`, on
+/// Wikimedia's own servers.
+///
+/// # Panics
+///
+/// Panics if `hour` is 24 or more.
+///
+/// # Example
+///
+/// ```
+/// use chrono::NaiveDate;
+/// use pvstream::urls::pageviews_url;
+///
+/// let date = NaiveDate::from_ymd_opt(2024, 8, 18).unwrap();
+/// assert_eq!(
+///     pageviews_url(date, 8).as_str(),
+///     "https://dumps.wikimedia.org/other/pageviews/2024/2024-08/pageviews-20240818-080000.gz",
+/// );
+/// ```
+pub fn pageviews_url(date: NaiveDate, hour: u32) -> Url {
+    Mirror::default().pageviews_url(date, hour)
+}
+
+/// URLs of all 24 pageviews files on `date`, on Wikimedia's own servers.
+pub fn pageviews_urls_for_day(date: NaiveDate) -> Vec<Url> {
+    Mirror::default().pageviews_urls_for_day(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_dump_url() {
+        let hour = Utc.with_ymd_and_hms(2024, 1, 5, 7, 30, 0).unwrap();
+        assert_eq!(
+            dump_url(&hour).as_str(),
+            "https://dumps.wikimedia.org/other/pageviews/2024/2024-01/pageviews-20240105-070000.gz"
+        );
+    }
+
+    #[test]
+    fn test_pageviews_urls_for_day() {
+        let date = NaiveDate::from_ymd_opt(2024, 12, 31).unwrap();
+        let urls = pageviews_urls_for_day(date);
+
+        assert_eq!(urls.len(), 24);
+        assert!(
+            urls[0]
+                .as_str()
+                .ends_with("2024/2024-12/pageviews-20241231-000000.gz")
+        );
+        assert!(
+            urls[23]
+                .as_str()
+                .ends_with("2024/2024-12/pageviews-20241231-230000.gz")
+        );
+    }
+
+    #[test]
+    fn test_mirror_trailing_slash() {
+        let date = NaiveDate::from_ymd_opt(2024, 8, 18).unwrap();
+        let with_slash = Mirror::new("https://example.org/pageviews/").unwrap();
+        let without_slash = Mirror::new("https://example.org/pageviews").unwrap();
+
+        assert_eq!(with_slash, without_slash);
+        assert_eq!(
+            without_slash.pageviews_url(date, 23).as_str(),
+            "https://example.org/pageviews/2024/2024-08/pageviews-20240818-230000.gz"
+        );
+        assert!(Mirror::new("not a url").is_err());
+    }
+
+    #[test]
+    fn test_hours_in_listing() {
+        let html = r#"<html><body><h1>Index of /other/pageviews/2024/2024-08/</h1><hr><pre>
+<a href="../">../</a>
+<a href="pageviews-20240801-010000.gz">pageviews-20240801-010000.gz</a>  01-Aug-2024 02:05  45M
+<a href="pageviews-20240801-000000.gz">pageviews-20240801-000000.gz</a>  01-Aug-2024 01:04  48M
+<a href="projectviews-20240801-000000">projectviews-20240801-000000</a>  01-Aug-2024 01:04  31K
+<a href="/other/pageviews/2024/2024-08/pageviews-20240801-000000.gz">again</a>
+<a href="md5sums.txt">md5sums.txt</a>
+</pre><hr></body></html>"#;
+
+        let hours = hours_in_listing(html);
+        assert_eq!(
+            hours,
+            [
+                Utc.with_ymd_and_hms(2024, 8, 1, 0, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2024, 8, 1, 1, 0, 0).unwrap(),
+            ]
+        );
+        assert!(hours_in_listing("").is_empty());
+    }
+
+    #[test]
+    fn test_month_url() {
+        let mirror = Mirror::new("https://example.org/pageviews").unwrap();
+        assert_eq!(
+            mirror.month_url(2024, 8).as_str(),
+            "https://example.org/pageviews/2024/2024-08/"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "month must be between 1 and 12")]
+    fn test_month_url_out_of_range() {
+        Mirror::default().month_url(2024, 13);
+    }
+
+    #[test]
+    #[should_panic(expected = "hour must be between 0 and 23")]
+    fn test_pageviews_url_invalid_hour() {
+        pageviews_url(NaiveDate::from_ymd_opt(2024, 8, 18).unwrap(), 24);
+    }
+}
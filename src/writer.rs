@@ -0,0 +1,313 @@
+//! Parquet files appended to one input at a time.
+//!
+//! Long running ingestion jobs pick up a new hourly file every hour, and
+//! writing each to its own Parquet file leaves a directory of thousands of
+//! small files. A `ParquetWriter` stays open between inputs instead, adding
+//! the rows of each file to the same output as it comes, and rolls over to a
+//! new part file once the current one reaches a size limit:
+//!
+//! ```text
+//! output/part-0001.parquet
+//! output/part-0002.parquet
+//! ```
+//!
+//! Part files are numbered from 1, and each is complete once the writer
+//! moves on to the next one.
+
+use crate::filter::Filter;
+use crate::metrics::Metrics;
+use crate::progress::ProgressHook;
+use crate::store::{
+    ParquetFile, ParquetOptions, SkippedErrors, WriteReport, arrow_chunks_from_structs,
+    is_skipped_batch,
+};
+use crate::stream::{Source, StreamConfig, StreamError, object_store_url};
+use crate::stream_from_source;
+use arrow::array::RecordBatch;
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use url::Url;
+
+/// A directory of Parquet part files, open for appending rows from any
+/// number of pageviews files.
+///
+/// Rows are filtered and written as with `parquet_from_source`, with one
+/// `WriteReport` per input. `ParquetOptions::top_k` and
+/// `ParquetOptions::sort` apply to each input on its own, and
+/// `ParquetOptions::error_file` lists the skipped lines of all inputs.
+///
+/// Call `finish` once done, to write the footer of the last part file.
+/// Dropping the writer leaves that file unreadable.
+///
+/// # Example
+///
+/// ```no_run
+/// use pvstream::ParquetOptions;
+/// use pvstream::filter::FilterBuilder;
+/// use pvstream::stream::StreamConfig;
+/// use pvstream::urls::pageviews_urls_for_day;
+/// use pvstream::writer::ParquetWriter;
+/// use chrono::NaiveDate;
+/// use std::path::Path;
+///
+/// let mut writer = ParquetWriter::create(
+///     Path::new("pageviews"),
+///     &FilterBuilder::new().languages(["nn"]).build(),
+///     &StreamConfig::default(),
+///     &ParquetOptions::default(),
+/// )?
+/// .max_file_bytes(256 << 20);
+///
+/// let date = NaiveDate::from_ymd_opt(2024, 8, 18).unwrap();
+/// for url in pageviews_urls_for_day(date) {
+///     let report = writer.append_from_url(url)?;
+///     println!("{} rows written", report.rows_written);
+/// }
+/// let paths = writer.finish()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct ParquetWriter {
+    filter: Filter,
+    config: StreamConfig,
+    parts: Parts,
+    skipped: SkippedErrors,
+}
+
+impl ParquetWriter {
+    /// Creates a writer of part files in `output_dir`, creating the
+    /// directory if it doesn't exist.
+    ///
+    /// No file is created until the first rows are written. Existing part
+    /// files with the same names are overwritten.
+    pub fn create(
+        output_dir: &Path,
+        filter: &Filter,
+        config: &StreamConfig,
+        options: &ParquetOptions,
+    ) -> Result<Self, StreamError> {
+        options.validate()?;
+        // Buckets have no directories to create
+        if object_store_url(output_dir).is_none() {
+            create_dir_all(output_dir)?;
+        }
+        Ok(Self {
+            filter: filter.clone(),
+            config: config.clone(),
+            parts: Parts {
+                output_dir: output_dir.to_path_buf(),
+                options: options.clone(),
+                metrics: config.metrics.clone(),
+                max_file_bytes: None,
+                file: None,
+                paths: Vec::new(),
+            },
+            skipped: SkippedErrors::new(options.error_file.as_deref())?,
+        })
+    }
+
+    /// Rolls over to a new part file once the current one has `bytes` or
+    /// more, without a limit by default.
+    ///
+    /// The size is checked after each row group, so a part file ends up
+    /// larger than the limit by up to one row group and the footer. Lower
+    /// `ParquetOptions::batch_size` for files closer to the limit.
+    pub fn max_file_bytes(mut self, bytes: u64) -> Self {
+        self.parts.max_file_bytes = Some(bytes);
+        self
+    }
+
+    /// Appends the filtered rows of a local pageviews file.
+    pub fn append_from_file(&mut self, path: PathBuf) -> Result<WriteReport, StreamError> {
+        self.append_from_source(&Source::File(path))
+    }
+
+    /// Downloads a remote pageviews file and appends the filtered rows.
+    pub fn append_from_url(&mut self, url: Url) -> Result<WriteReport, StreamError> {
+        self.append_from_source(&Source::Url(url))
+    }
+
+    /// Appends the filtered rows of a pageviews file from any source.
+    ///
+    /// Returns a `WriteReport` of this input. On a read error, or a parse
+    /// error with `ErrorPolicy::FailFast`, the rows written before it stay
+    /// in the output, and the writer can go on with the next input.
+    pub fn append_from_source(&mut self, source: &Source) -> Result<WriteReport, StreamError> {
+        let started = Instant::now();
+        let (hook, progress) = ProgressHook::recording(self.config.progress.clone());
+        let config = StreamConfig {
+            progress: Some(hook),
+            ..self.config.clone()
+        };
+        let options = &self.parts.options;
+        let mut rows = stream_from_source(source, &self.filter, &config)?;
+        if let Some(top_k) = options.top_k {
+            rows = top_k.apply(rows);
+        }
+        if let Some(sort) = &options.sort {
+            rows = sort.apply(rows);
+        }
+
+        let policy = &config.error_policy;
+        let skipped = &mut self.skipped;
+        let mut rows_matched = 0;
+        let rows = rows.filter_map(|row| match row {
+            Err(err) if !policy.is_fatal(&err) => {
+                skipped.record(err);
+                None
+            }
+            row => {
+                rows_matched += u64::from(row.is_ok());
+                Some(row)
+            }
+        });
+        let chunks = arrow_chunks_from_structs(rows, options.batch_size, policy);
+        let parts = &mut self.parts;
+        let written = chunks
+            .map(|chunk| match chunk {
+                Ok(chunk) => {
+                    let rows = chunk.num_rows() as u64;
+                    parts.write(chunk)?;
+                    Ok(rows)
+                }
+                Err(err) if is_skipped_batch(policy, &err) => Ok(0),
+                Err(err) => Err(err.into()),
+            })
+            .sum::<Result<u64, StreamError>>();
+
+        // The errors of a failed input are only in the error file
+        let errors = self.skipped.take_summary()?;
+        let rows_written = written?;
+        let rows_errored = errors.total() as u64;
+        let progress = *progress.lock().unwrap();
+        Ok(WriteReport {
+            rows_written,
+            rows_filtered: progress
+                .lines_read
+                .saturating_sub(rows_matched + rows_errored),
+            rows_errored,
+            rows_dropped: rows_matched - rows_written,
+            bytes_read: progress.bytes_read,
+            elapsed: started.elapsed(),
+            errors,
+        })
+    }
+
+    /// Paths of the part files written so far, including the one being
+    /// written to.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.parts.paths
+    }
+
+    /// Writes the footer of the last part file, and returns the paths of
+    /// all part files, in order.
+    pub fn finish(self) -> Result<Vec<PathBuf>, StreamError> {
+        if let Some(file) = self.parts.file {
+            file.finish()?;
+        }
+        self.skipped.finish()?;
+        Ok(self.parts.paths)
+    }
+}
+
+/// The part files of a `ParquetWriter`, and the one being written to.
+struct Parts {
+    output_dir: PathBuf,
+    options: ParquetOptions,
+    metrics: Option<Arc<dyn Metrics>>,
+    max_file_bytes: Option<u64>,
+    file: Option<ParquetFile>,
+    paths: Vec<PathBuf>,
+}
+
+impl Parts {
+    /// Writes a record batch to the current part file, starting a new one
+    /// if needed, and finishes the file once it's over the size limit.
+    fn write(&mut self, batch: RecordBatch) -> Result<(), StreamError> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let path = self
+                    .output_dir
+                    .join(format!("part-{:04}.parquet", self.paths.len() + 1));
+                let file = ParquetFile::create(&path, &self.options, &[])?
+                    .with_metrics(self.metrics.as_ref());
+                self.paths.push(path);
+                self.file.insert(file)
+            }
+        };
+        file.write(batch)?;
+        if self
+            .max_file_bytes
+            .is_some_and(|max| file.bytes_written() >= max)
+            && let Some(file) = self.file.take()
+        {
+            file.finish()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterBuilder;
+    use crate::stream_from_parquet;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_parquet_writer_rollover() {
+        let source = Source::File(PathBuf::from("tests/files/pageviews-20240803-060000.gz"));
+        let dir = TestDir::new("writer");
+        let output_dir = dir.path();
+        let filter = FilterBuilder::new().languages(["de", "ja"]).build();
+        let config = StreamConfig::default();
+        let options = ParquetOptions {
+            batch_size: Some(2),
+            ..Default::default()
+        };
+
+        let expected = stream_from_source(&source, &filter, &config)
+            .unwrap()
+            .count();
+        let mut writer = ParquetWriter::create(output_dir, &filter, &config, &options)
+            .unwrap()
+            .max_file_bytes(1);
+        let first = writer.append_from_source(&source).unwrap();
+        let second = writer.append_from_source(&source).unwrap();
+        assert_eq!(first.rows_written as usize, expected);
+        assert_eq!(second.rows_written as usize, expected);
+
+        // Every row group is over the limit, so each gets its own file
+        let paths = writer.finish().unwrap();
+        assert_eq!(paths.len(), 2 * expected.div_ceil(2));
+        assert_eq!(paths[0], output_dir.join("part-0001.parquet"));
+        assert_eq!(paths[1], output_dir.join("part-0002.parquet"));
+        let rows: usize = paths
+            .iter()
+            .map(|path| stream_from_parquet(path.clone()).unwrap().count())
+            .sum();
+        assert_eq!(rows, expected * 2);
+    }
+
+    #[test]
+    fn test_parquet_writer_single_file() {
+        let source = Source::File(PathBuf::from("tests/files/pageviews-20240803-060000.gz"));
+        let dir = TestDir::new("writer-single");
+        let output_dir = dir.path();
+        let filter = FilterBuilder::new().languages(["de"]).build();
+        let config = StreamConfig::default();
+
+        let mut writer =
+            ParquetWriter::create(output_dir, &filter, &config, &ParquetOptions::default())
+                .unwrap();
+        let report = writer.append_from_source(&source).unwrap();
+        writer.append_from_source(&source).unwrap();
+        assert_eq!(writer.paths(), [output_dir.join("part-0001.parquet")]);
+
+        let paths = writer.finish().unwrap();
+        let rows = stream_from_parquet(paths[0].clone()).unwrap().count();
+        assert_eq!(rows as u64, report.rows_written * 2);
+    }
+}